@@ -449,3 +449,115 @@ pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
     };
     result.into()
 }
+
+#[cfg(feature = "std")]
+#[derive(Debug, FromMeta)]
+struct TestArgs {
+    #[darling(default)]
+    embassy_prefix: ModulePrefix,
+
+    /// Fail the test if it hasn't completed after this many seconds of real time.
+    #[darling(default)]
+    timeout_secs: Option<u64>,
+
+    #[darling(default)]
+    mock_clock: bool,
+}
+
+/// Turns an `async fn` into a `#[test]`, run on an `embassy_std::ScopedExecutor` of its
+/// own.
+///
+/// The function may take a `Spawner` to spawn tasks on the executor, which stop running
+/// when the test ends. The test fails if it hasn't completed after `timeout_secs` seconds
+/// (10 by default). With `mock_clock`, the time starts at zero and jumps ahead whenever all
+/// tasks are waiting for a timer. Only the test's thread sees the mock time.
+///
+/// ```ignore
+/// #[embassy::test(mock_clock)]
+/// async fn timer_completes_instantly() {
+///     let start = Instant::now();
+///     Timer::after(Duration::from_secs(60)).await;
+///     assert_eq!(Instant::now().duration_since(start), Duration::from_secs(60));
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[proc_macro_attribute]
+pub fn test(args: TokenStream, item: TokenStream) -> TokenStream {
+    let macro_args = syn::parse_macro_input!(args as syn::AttributeArgs);
+    let test_fn = syn::parse_macro_input!(item as syn::ItemFn);
+
+    let macro_args = match TestArgs::from_list(&macro_args) {
+        Ok(v) => v,
+        Err(e) => {
+            return TokenStream::from(e.write_errors());
+        }
+    };
+
+    let embassy_std_path = macro_args.embassy_prefix.append("embassy_std");
+
+    let mut fail = false;
+    if test_fn.sig.asyncness.is_none() {
+        test_fn
+            .sig
+            .span()
+            .unwrap()
+            .error("test functions must be async")
+            .emit();
+        fail = true;
+    }
+    if !test_fn.sig.generics.params.is_empty() {
+        test_fn
+            .sig
+            .span()
+            .unwrap()
+            .error("test functions must not be generic")
+            .emit();
+        fail = true;
+    }
+
+    let args = test_fn.sig.inputs.clone();
+
+    if args.len() > 1 {
+        test_fn
+            .sig
+            .span()
+            .unwrap()
+            .error("test functions must have at most one argument, the spawner")
+            .emit();
+        fail = true;
+    }
+
+    if fail {
+        return TokenStream::new();
+    }
+
+    let attrs = &test_fn.attrs;
+    let name = &test_fn.sig.ident;
+    let test_fn_body = test_fn.block.clone();
+    let test_fn_call = if args.is_empty() {
+        quote!(|_| __embassy_test())
+    } else {
+        quote!(|spawner| __embassy_test(spawner))
+    };
+
+    let embassy_std_path = embassy_std_path.path();
+    let timeout_secs = macro_args.timeout_secs.unwrap_or(10);
+    let mock_clock = macro_args.mock_clock;
+
+    let result = quote! {
+        #[test]
+        #(#attrs)*
+        fn #name() {
+            async fn __embassy_test(#args) {
+                #test_fn_body
+            }
+
+            #embassy_std_path::__run_test(
+                #test_fn_call,
+                ::std::time::Duration::from_secs(#timeout_secs),
+                #mock_clock,
+            )
+        }
+    };
+    result.into()
+}
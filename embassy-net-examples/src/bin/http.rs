@@ -0,0 +1,142 @@
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+//! Serves a provisioning page on port 80, and accepts firmware uploads.
+//!
+//! Try it with `curl http://192.168.69.2/` and
+//! `curl --data-binary @firmware.bin http://192.168.69.2/firmware`.
+
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::io::AsyncBufReadExt;
+use embassy::util::Forever;
+use embassy_net::http::{read_request, HttpError, Method, Router, ServerRequest};
+use embassy_net::*;
+use embassy_std::tuntap::TunTapDevice;
+use embassy_std::Executor;
+use heapless::Vec;
+use log::*;
+
+static DEVICE: Forever<TunTapDevice> = Forever::new();
+static CONFIG: Forever<StaticConfigurator> = Forever::new();
+static RESOURCES: Forever<StackResources<2>> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+
+#[derive(Clone, Copy)]
+enum Page {
+    Index,
+    Firmware,
+}
+
+static ROUTER: Router<Page> = Router::new(&[
+    (Method::Get, "/", Page::Index),
+    (Method::Post, "/firmware", Page::Firmware),
+]);
+
+const INDEX: &[u8] = b"<html><body><h1>Hello from embassy!</h1></body></html>";
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+async fn firmware(mut req: ServerRequest<'_, TcpSocket<'_>>) -> Result<(), HttpError> {
+    let mut buf = [0; 512];
+    let mut total = 0;
+    loop {
+        let n = req.body.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        total += n;
+    }
+    info!("received {} bytes of firmware", total);
+    req.respond(200, "text/plain", b"Firmware received\n").await
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap, Medium::Ethernet).unwrap();
+
+    // Static IP configuration
+    let config = StaticConfigurator::new(Config {
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+        dns_servers: Vec::new(),
+        gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        dhcp: None,
+    });
+
+    // Init network stack
+    let stack: &'static Stack = STACK.put(Stack::new(
+        DEVICE.put(device),
+        CONFIG.put(config),
+        RESOURCES.put(StackResources::new()),
+    ));
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
+    socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
+
+    let mut head_buf = [0; 1024];
+    loop {
+        if let Err(e) = socket.accept(80).await {
+            warn!("accept error: {:?}", e);
+            continue;
+        }
+        info!("connection from {:?}", socket.remote_endpoint());
+
+        let res = match read_request(&mut socket, &mut head_buf).await {
+            Ok(req) => match ROUTER.route(req.method, req.path) {
+                Some(Page::Index) => req.respond(200, "text/html", INDEX).await,
+                Some(Page::Firmware) => firmware(req).await,
+                None => req.respond(404, "text/plain", b"Not found\n").await,
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            warn!("HTTP error: {:?}", e);
+        }
+
+        if let Err(e) = socket.close().await {
+            warn!("close error: {:?}", e);
+        }
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
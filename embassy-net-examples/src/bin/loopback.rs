@@ -0,0 +1,137 @@
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+//! Runs two stacks in one process, connected by a loopback device pair. One side runs a
+//! TCP echo server, and the other connects to it.
+
+use embassy::executor::Spawner;
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy::time::{Duration, Timer};
+use embassy::util::Forever;
+use embassy_net::*;
+use embassy_std::Executor;
+use heapless::Vec;
+use log::*;
+
+static LOOPBACK: Forever<Loopback<4, 1514>> = Forever::new();
+static DEVICES: Forever<(
+    LoopbackDevice<'static, 4, 1514>,
+    LoopbackDevice<'static, 4, 1514>,
+)> = Forever::new();
+static SERVER_CONFIG: Forever<StaticConfigurator> = Forever::new();
+static CLIENT_CONFIG: Forever<StaticConfigurator> = Forever::new();
+static SERVER_RESOURCES: Forever<StackResources<2>> = Forever::new();
+static CLIENT_RESOURCES: Forever<StackResources<2>> = Forever::new();
+static SERVER_STACK: Forever<Stack> = Forever::new();
+static CLIENT_STACK: Forever<Stack> = Forever::new();
+
+const SERVER_ADDRESS: Ipv4Address = Ipv4Address([10, 0, 0, 1]);
+const CLIENT_ADDRESS: Ipv4Address = Ipv4Address([10, 0, 0, 2]);
+
+fn static_config(address: Ipv4Address) -> StaticConfigurator {
+    StaticConfigurator::new(Config {
+        address: Ipv4Cidr::new(address, 24),
+        dns_servers: Vec::new(),
+        gateway: None,
+        dhcp: None,
+    })
+}
+
+#[embassy::task(pool_size = 2)]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task]
+async fn server_task(stack: &'static Stack) {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
+        if let Err(e) = socket.accept(1234).await {
+            warn!("accept error: {:?}", e);
+            continue;
+        }
+        info!("server: connection from {:?}", socket.remote_endpoint());
+
+        let mut buf = [0; 256];
+        loop {
+            let n = match socket.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if let Err(e) = socket.write_all(&buf[..n]).await {
+                warn!("server: write error: {:?}", e);
+                break;
+            }
+        }
+        let _ = socket.close().await;
+    }
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let loopback: &'static Loopback<4, 1514> = LOOPBACK.put(Loopback::new());
+    let (server_device, client_device) = DEVICES.put(loopback.devices(Medium::Ethernet));
+
+    // Init network stacks
+    let server: &'static Stack = SERVER_STACK.put(Stack::new(
+        server_device,
+        SERVER_CONFIG.put(static_config(SERVER_ADDRESS)),
+        SERVER_RESOURCES.put(StackResources::new()),
+    ));
+    let client: &'static Stack = CLIENT_STACK.put(Stack::new(
+        client_device,
+        CLIENT_CONFIG.put(static_config(CLIENT_ADDRESS)),
+        CLIENT_RESOURCES.put(StackResources::new()),
+    ));
+
+    // Launch network tasks, and the server
+    spawner.spawn(net_task(server)).unwrap();
+    spawner.spawn(net_task(client)).unwrap();
+    spawner.spawn(server_task(server)).unwrap();
+
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = TcpSocket::new(client, &mut rx_buffer, &mut tx_buffer).unwrap();
+    client.wait_config_up().await;
+    socket.connect((SERVER_ADDRESS, 1234)).await.unwrap();
+
+    let mut count = 0u32;
+    loop {
+        let msg = format!("hello {}", count);
+        socket.write_all(msg.as_bytes()).await.unwrap();
+
+        let mut buf = [0; 32];
+        socket.read_exact(&mut buf[..msg.len()]).await.unwrap();
+        info!(
+            "client: echoed {:?}",
+            core::str::from_utf8(&buf[..msg.len()]).unwrap()
+        );
+
+        count += 1;
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
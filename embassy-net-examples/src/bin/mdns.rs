@@ -0,0 +1,106 @@
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+//! Makes the device reachable as `device-1234.local`, and advertises an HTTP service.
+//!
+//! Try it with `ping device-1234.local` and `avahi-browse -r _http._tcp`.
+
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::util::Forever;
+use embassy_net::mdns::{Mdns, MdnsConfig, Service};
+use embassy_net::*;
+use embassy_std::tuntap::TunTapDevice;
+use embassy_std::Executor;
+use heapless::Vec;
+
+static DEVICE: Forever<TunTapDevice> = Forever::new();
+static CONFIG: Forever<StaticConfigurator> = Forever::new();
+static RESOURCES: Forever<StackResources<2>> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+static MDNS: Forever<Mdns<'static>> = Forever::new();
+
+static SERVICES: [Service<'static>; 1] = [Service {
+    instance: "Embassy device",
+    service_type: "_http._tcp",
+    port: 80,
+    txt: &["path=/"],
+}];
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task]
+async fn mdns_task(mdns: &'static Mdns<'static>) {
+    mdns.run().await
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap, Medium::Ethernet).unwrap();
+
+    // Static IP configuration
+    let config = StaticConfigurator::new(Config {
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+        dns_servers: Vec::new(),
+        gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        dhcp: None,
+    });
+
+    // Init network stack
+    let stack: &'static Stack = STACK.put(Stack::new(
+        DEVICE.put(device),
+        CONFIG.put(config),
+        RESOURCES.put(StackResources::new()),
+    ));
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    // Launch mDNS task
+    let mdns: &'static Mdns<'static> = MDNS.put(Mdns::new(
+        stack,
+        MdnsConfig {
+            hostname: "device-1234",
+            services: &SERVICES,
+            interface: InterfaceId::FIRST,
+        },
+    ));
+    spawner.spawn(mdns_task(mdns)).unwrap();
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -0,0 +1,122 @@
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+//! Publishes a message to an MQTT broker, and prints the messages on `embassy/#`.
+//! Reconnects whenever the network comes back after losing its configuration.
+//!
+//! Run a broker on the host with `mosquitto -v`, and publish to it with
+//! `mosquitto_pub -t embassy/test -m hello`.
+
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Timer};
+use embassy::util::Forever;
+use embassy_net::mqtt::{ConnectOptions, Message, MqttClient, MqttError, QoS};
+use embassy_net::*;
+use embassy_std::tuntap::TunTapDevice;
+use embassy_std::Executor;
+use log::*;
+
+static DEVICE: Forever<TunTapDevice> = Forever::new();
+static CONFIG: Forever<DhcpConfigurator> = Forever::new();
+static RESOURCES: Forever<StackResources<2>> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+async fn session(stack: &'static Stack, events: &mut ConfigEvents<'_>) -> Result<(), MqttError> {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
+    socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
+
+    let remote_endpoint = (Ipv4Address::new(192, 168, 69, 1), 1883);
+    info!("connecting to {:?}...", remote_endpoint);
+    if let Err(e) = socket.connect(remote_endpoint).await {
+        warn!("connect error: {:?}", e);
+        return Err(MqttError::NotConnected);
+    }
+
+    let mut mqtt_rx = [0; 1024];
+    let mut mqtt_tx = [0; 256];
+    let mut client = MqttClient::new(&mut socket, &mut mqtt_rx, &mut mqtt_tx);
+
+    let mut opts = ConnectOptions::new("embassy-example");
+    opts.keep_alive_secs = 30;
+    client.connect(&opts).await?;
+    info!("connected to broker!");
+
+    client.subscribe("embassy/#", QoS::AtLeastOnce).await?;
+    client
+        .publish("embassy/hello", b"Hello!", QoS::AtLeastOnce, false)
+        .await?;
+
+    let mut handler = |msg: Message<'_>| {
+        info!(
+            "{}: {:?}",
+            msg.topic,
+            core::str::from_utf8(msg.payload).unwrap_or("<binary>")
+        );
+    };
+    client.run(&mut handler, events).await
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap, Medium::Ethernet).unwrap();
+
+    // Init network stack
+    let stack: &'static Stack = STACK.put(Stack::new(
+        DEVICE.put(device),
+        CONFIG.put(DhcpConfigurator::new()),
+        RESOURCES.put(StackResources::new()),
+    ));
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    let mut events = stack.config_events();
+    loop {
+        stack.wait_config_up().await;
+        let err = session(stack, &mut events).await;
+        warn!("MQTT session ended: {:?}", err);
+        Timer::after(Duration::from_secs(5)).await;
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
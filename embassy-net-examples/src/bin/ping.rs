@@ -0,0 +1,145 @@
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+//! Pings the host every second, and prints the round-trip times.
+//!
+//! With `--pcap ping.pcap`, the traffic is also captured to a file that Wireshark can open.
+
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Timer};
+use embassy::util::Forever;
+use embassy_net::pcap::{file_header, Tap};
+use embassy_net::*;
+use embassy_std::tuntap::TunTapDevice;
+use embassy_std::Executor;
+use heapless::Vec;
+use log::*;
+use std::fs::File;
+use std::io::Write;
+
+static DEVICE: Forever<TunTapDevice> = Forever::new();
+static CONFIG: Forever<StaticConfigurator> = Forever::new();
+static RESOURCES: Forever<StackResources<2>> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+static PCAP: Forever<PcapFile> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// Capture the traffic to this pcap file
+    #[clap(long)]
+    pcap: Option<String>,
+}
+
+/// Writes the frames of an interface to a pcap file.
+struct PcapFile(File);
+
+impl PcapFile {
+    fn create(path: &str) -> std::io::Result<Self> {
+        let mut file = File::create(path)?;
+        file.write_all(&file_header(Medium::Ethernet))?;
+        Ok(Self(file))
+    }
+}
+
+impl Tap for PcapFile {
+    fn record(&mut self, header: &[u8; 16], frame: &[u8]) {
+        if let Err(e) = self
+            .0
+            .write_all(header)
+            .and_then(|_| self.0.write_all(frame))
+        {
+            warn!("pcap write failed: {:?}", e);
+        }
+    }
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap, Medium::Ethernet).unwrap();
+
+    // Static IP configuration
+    let config = StaticConfigurator::new(Config {
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+        dns_servers: Vec::new(),
+        gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        dhcp: None,
+    });
+
+    // Init network stack
+    let stack: &'static Stack = STACK.put(Stack::new(
+        DEVICE.put(device),
+        CONFIG.put(config),
+        RESOURCES.put(StackResources::new()),
+    ));
+
+    if let Some(path) = &opts.pcap {
+        let pcap = PCAP.put(PcapFile::create(path).unwrap());
+        stack.set_tap(InterfaceId::FIRST, Some(pcap)).unwrap();
+    }
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    let mut rx_meta = [IcmpPacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 128];
+    let mut tx_meta = [IcmpPacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 128];
+    let mut socket = IcmpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    stack.wait_config_up().await;
+    let host = IpAddress::v4(192, 168, 69, 1);
+    loop {
+        match socket.ping(host, 56, Duration::from_secs(1)).await {
+            Ok(rtt) => info!("reply from {}: time={} us", host, rtt.as_micros()),
+            Err(e) => warn!("ping {} failed: {:?}", host, e),
+        }
+        let stats = stack.interface_stats(InterfaceId::FIRST).unwrap();
+        debug!(
+            "rx {} packets, {} bytes, tx {} packets, {} bytes",
+            stats.rx_packets, stats.rx_bytes, stats.tx_packets, stats.tx_bytes
+        );
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
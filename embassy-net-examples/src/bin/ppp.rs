@@ -0,0 +1,132 @@
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+//! Dials a PPP link over a serial port, and pings the peer every second.
+//!
+//! pppd on a pty works as the peer:
+//!
+//! ```text
+//! socat pty,raw,echo=0,link=/tmp/ppp-host pty,raw,echo=0,link=/tmp/ppp-device &
+//! sudo pppd /tmp/ppp-host 115200 nodetach noauth local 192.168.7.1:192.168.7.2 ms-dns 192.168.7.1
+//! cargo run --bin ppp -- --device /tmp/ppp-device
+//! ```
+
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Timer};
+use embassy::util::Forever;
+use embassy_net::ppp::{Ppp, PppConfig, PppConfigurator, PppDevice};
+use embassy_net::*;
+use embassy_std::Executor;
+use log::*;
+
+#[path = "../serial_port.rs"]
+mod serial_port;
+
+const MTU: usize = 1500;
+
+static PPP: Forever<Ppp<4, MTU>> = Forever::new();
+static DEVICE: Forever<PppDevice<'static, 4, MTU>> = Forever::new();
+static CONFIG: Forever<PppConfigurator<'static>> = Forever::new();
+static RESOURCES: Forever<StackResources<2>> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+static OPTS: Forever<Opts> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// Serial port connected to the peer
+    #[clap(long, default_value = "/dev/ttyUSB0")]
+    device: String,
+    /// PAP username
+    #[clap(long, default_value = "")]
+    username: String,
+    /// PAP password
+    #[clap(long, default_value = "")]
+    password: String,
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task]
+async fn ppp_task(ppp: &'static Ppp<4, MTU>, opts: &'static Opts) {
+    let config = PppConfig {
+        username: &opts.username,
+        password: &opts.password,
+    };
+    loop {
+        let mut port = serial_port::open(&opts.device).unwrap();
+        match ppp.run(&mut port, &config).await {
+            Ok(()) => info!("PPP link terminated"),
+            Err(e) => warn!("PPP link failed: {:?}", e),
+        }
+        // Redial after a while.
+        Timer::after(Duration::from_secs(5)).await;
+    }
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: &'static Opts = OPTS.put(Opts::parse());
+
+    let ppp: &'static Ppp<4, MTU> = PPP.put(Ppp::new());
+
+    // Init network stack
+    let stack: &'static Stack = STACK.put(Stack::new(
+        DEVICE.put(ppp.device()),
+        CONFIG.put(ppp.configurator()),
+        RESOURCES.put(StackResources::new()),
+    ));
+
+    // Launch network and PPP tasks
+    spawner.spawn(net_task(stack)).unwrap();
+    spawner.spawn(ppp_task(ppp, opts)).unwrap();
+
+    let mut rx_meta = [IcmpPacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 128];
+    let mut tx_meta = [IcmpPacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 128];
+    let mut socket = IcmpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    let peer = IpAddress::v4(192, 168, 7, 1);
+    loop {
+        stack.wait_config_up().await;
+        match socket.ping(peer, 56, Duration::from_secs(1)).await {
+            Ok(rtt) => info!("reply from {}: time={} us", peer, rtt.as_micros()),
+            Err(e) => warn!("ping {} failed: {:?}", peer, e),
+        }
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
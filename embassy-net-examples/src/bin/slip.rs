@@ -0,0 +1,131 @@
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+//! Runs a SLIP link over a serial port, and pings the peer every second.
+//!
+//! The Linux SLIP driver on a pty works as the peer:
+//!
+//! ```text
+//! socat pty,raw,echo=0,link=/tmp/slip-host pty,raw,echo=0,link=/tmp/slip-device &
+//! sudo slattach -L -p slip -s 115200 /tmp/slip-host &
+//! sudo ip addr add 192.168.8.1 peer 192.168.8.2 dev sl0
+//! sudo ip link set sl0 up
+//! cargo run --bin slip -- --device /tmp/slip-device
+//! ```
+
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Timer};
+use embassy::util::Forever;
+use embassy_net::slip::{Slip, SlipDevice};
+use embassy_net::*;
+use embassy_std::Executor;
+use heapless::Vec;
+use log::*;
+
+#[path = "../serial_port.rs"]
+mod serial_port;
+
+use crate::serial_port::SerialPort;
+
+const MTU: usize = 1006;
+
+static SLIP: Forever<Slip<4, MTU>> = Forever::new();
+static DEVICE: Forever<SlipDevice<'static, 4, MTU>> = Forever::new();
+static CONFIG: Forever<StaticConfigurator> = Forever::new();
+static RESOURCES: Forever<StackResources<2>> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// Serial port connected to the peer
+    #[clap(long, default_value = "/dev/ttyUSB0")]
+    device: String,
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task]
+async fn slip_task(slip: &'static Slip<4, MTU>, mut port: SerialPort) {
+    match slip.run(&mut port).await {
+        Ok(()) => info!("serial port closed"),
+        Err(e) => warn!("serial port failed: {:?}", e),
+    }
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init SLIP link
+    let port = serial_port::open(&opts.device).unwrap();
+    let slip: &'static Slip<4, MTU> = SLIP.put(Slip::new());
+
+    // Static IP configuration, on a point-to-point link
+    let config = StaticConfigurator::new(Config {
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 8, 2), 32),
+        dns_servers: Vec::new(),
+        gateway: None,
+        dhcp: None,
+    });
+
+    // Init network stack
+    let stack: &'static Stack = STACK.put(Stack::new(
+        DEVICE.put(slip.device()),
+        CONFIG.put(config),
+        RESOURCES.put(StackResources::new()),
+    ));
+
+    // Launch network and SLIP tasks
+    spawner.spawn(net_task(stack)).unwrap();
+    spawner.spawn(slip_task(slip, port)).unwrap();
+
+    let mut rx_meta = [IcmpPacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 128];
+    let mut tx_meta = [IcmpPacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 128];
+    let mut socket = IcmpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    stack.wait_config_up().await;
+    let peer = IpAddress::v4(192, 168, 8, 1);
+    loop {
+        match socket.ping(peer, 56, Duration::from_secs(1)).await {
+            Ok(rtt) => info!("reply from {}: time={} us", peer, rtt.as_micros()),
+            Err(e) => warn!("ping {} failed: {:?}", peer, e),
+        }
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -0,0 +1,118 @@
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![allow(incomplete_features)]
+
+//! Syncs with an NTP server, and prints the UTC time every few seconds.
+//!
+//! Any NTP server on the host works, for example chrony with this configuration:
+//!
+//! ```text
+//! allow 192.168.69.0/24
+//! local stratum 10
+//! ```
+
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Timer};
+use embassy::util::Forever;
+use embassy_net::sntp::{Sntp, SntpConfig};
+use embassy_net::*;
+use embassy_std::tuntap::TunTapDevice;
+use embassy_std::Executor;
+use heapless::Vec;
+use log::*;
+
+static DEVICE: Forever<TunTapDevice> = Forever::new();
+static CONFIG: Forever<StaticConfigurator> = Forever::new();
+static RESOURCES: Forever<StackResources<2>> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+static SNTP: Forever<Sntp<'static>> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task]
+async fn sntp_task(sntp: &'static Sntp<'static>) {
+    sntp.run().await
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap, Medium::Ethernet).unwrap();
+
+    // Static IP configuration
+    let config = StaticConfigurator::new(Config {
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+        dns_servers: Vec::new(),
+        gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        dhcp: None,
+    });
+
+    // Init network stack
+    let stack: &'static Stack = STACK.put(Stack::new(
+        DEVICE.put(device),
+        CONFIG.put(config),
+        RESOURCES.put(StackResources::new()),
+    ));
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    // Launch SNTP task
+    let mut config = SntpConfig::default();
+    config
+        .servers
+        .push(Ipv4Address::new(192, 168, 69, 1))
+        .unwrap();
+    config.interval = Duration::from_secs(64);
+    let sntp: &'static Sntp<'static> = SNTP.put(Sntp::new(stack, config));
+    spawner.spawn(sntp_task(sntp)).unwrap();
+
+    sntp.wait_synced().await;
+    loop {
+        if let Some(now) = sntp.now() {
+            info!(
+                "UTC: {}.{:06} (+/- {} us)",
+                now.unix_secs(),
+                now.unix_micros % 1_000_000,
+                now.error.as_micros()
+            );
+        }
+        Timer::after(Duration::from_secs(5)).await;
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -0,0 +1,169 @@
+#![feature(type_alias_impl_trait)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(generic_associated_types)]
+#![allow(incomplete_features)]
+
+//! Connects to a TLS 1.3 server with a pre-shared key, and echoes what it sends.
+//!
+//! Run a server on the host with:
+//!
+//! ```text
+//! openssl s_server -tls1_3 -nocert -accept 4433 \
+//!     -psk_identity embassy -psk 000102030405060708090a0b0c0d0e0f
+//! ```
+
+use clap::{AppSettings, Clap};
+use embassy::executor::Spawner;
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy::util::Forever;
+use embassy_net::tls::{Psk, TlsConfig, TlsConnection};
+use embassy_net::*;
+use embassy_std::tuntap::TunTapDevice;
+use embassy_std::Executor;
+use futures::future::{ready, Ready};
+use heapless::Vec;
+use log::*;
+
+static DEVICE: Forever<TunTapDevice> = Forever::new();
+static CONFIG: Forever<StaticConfigurator> = Forever::new();
+static RESOURCES: Forever<StackResources<2>> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
+
+#[derive(Clap)]
+#[clap(version = "1.0")]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+}
+
+struct OsRng;
+
+impl embassy::traits::rng::Rng for OsRng {
+    type Error = ();
+
+    type RngFuture<'a> = Ready<Result<(), ()>>;
+
+    fn fill_bytes<'a>(&'a mut self, dest: &'a mut [u8]) -> Self::RngFuture<'a> {
+        use rand_core::RngCore;
+        rand_core::OsRng.fill_bytes(dest);
+        ready(Ok(()))
+    }
+}
+
+#[embassy::task]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+#[embassy::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap, Medium::Ethernet).unwrap();
+
+    // Static IP configuration
+    let config = StaticConfigurator::new(Config {
+        address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+        dns_servers: Vec::new(),
+        gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        dhcp: None,
+    });
+
+    // Init network stack
+    let stack: &'static Stack = STACK.put(Stack::new(
+        DEVICE.put(device),
+        CONFIG.put(config),
+        RESOURCES.put(StackResources::new()),
+    ));
+
+    // Launch network task
+    spawner.spawn(net_task(stack)).unwrap();
+
+    stack.wait_config_up().await;
+
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
+    socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
+
+    let remote_endpoint = (Ipv4Address::new(192, 168, 69, 1), 4433);
+    info!("connecting to {:?}...", remote_endpoint);
+    if let Err(e) = socket.connect(remote_endpoint).await {
+        warn!("connect error: {:?}", e);
+        return;
+    }
+
+    // 4kB records are enough with max_fragment_length, which openssl supports.
+    let mut tls_rx = [0; 4096 + 512];
+    let mut tls_tx = [0; 4096 + 512];
+    let mut tls = TlsConnection::new(&mut socket, &mut tls_rx, &mut tls_tx);
+
+    let config = TlsConfig {
+        psk: Some(Psk {
+            identity: b"embassy",
+            key: &[
+                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+                0x0e, 0x0f,
+            ],
+        }),
+        ..Default::default()
+    };
+    if let Err(e) = tls.open(config, &mut OsRng).await {
+        warn!("handshake error: {:?}", e);
+        return;
+    }
+    info!("TLS handshake done!");
+
+    if let Err(e) = tls.write_all(b"Hello over TLS!\n").await {
+        warn!("write error: {:?}", e);
+        return;
+    }
+
+    let mut buf = [0; 128];
+    loop {
+        let n = match tls.read(&mut buf).await {
+            Ok(0) => {
+                info!("server closed the connection");
+                break;
+            }
+            Ok(n) => n,
+            Err(e) => {
+                warn!("read error: {:?}", e);
+                break;
+            }
+        };
+        info!("received {:?}", core::str::from_utf8(&buf[..n]));
+        if let Err(e) = tls.write_all(&buf[..n]).await {
+            warn!("write error: {:?}", e);
+            break;
+        }
+    }
+
+    if let Err(e) = tls.close().await {
+        warn!("close error: {:?}", e);
+    }
+}
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    use rand_core::{OsRng, RngCore};
+    OsRng.fill_bytes(buf);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -8,16 +8,16 @@ use embassy::executor::Spawner;
 use embassy::io::AsyncWriteExt;
 use embassy::util::Forever;
 use embassy_net::*;
+use embassy_std::tuntap::TunTapDevice;
 use embassy_std::Executor;
 use heapless::Vec;
 use log::*;
 
-mod tuntap;
-
-use crate::tuntap::TunTapDevice;
-
 static DEVICE: Forever<TunTapDevice> = Forever::new();
-static CONFIG: Forever<DhcpConfigurator> = Forever::new();
+static CONFIG: Forever<FallbackConfigurator<DhcpConfigurator, LinkLocalConfigurator>> =
+    Forever::new();
+static RESOURCES: Forever<StackResources<2>> = Forever::new();
+static STACK: Forever<Stack> = Forever::new();
 
 #[derive(Clap)]
 #[clap(version = "1.0")]
@@ -29,8 +29,8 @@ struct Opts {
 }
 
 #[embassy::task]
-async fn net_task() {
-    embassy_net::run().await
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
 }
 
 #[embassy::task]
@@ -38,28 +38,53 @@ async fn main_task(spawner: Spawner) {
     let opts: Opts = Opts::parse();
 
     // Init network device
-    let device = TunTapDevice::new(&opts.tap).unwrap();
+    let device = TunTapDevice::new(&opts.tap, Medium::Ethernet).unwrap();
 
     // Static IP configuration
     let config = StaticConfigurator::new(Config {
         address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
         dns_servers: Vec::new(),
         gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        dhcp: None,
     });
 
     // DHCP configruation
-    let config = DhcpConfigurator::new();
+    let mut dhcp = DhcpConfigurator::new();
+    dhcp.set_hostname("embassy-example").unwrap();
+
+    // Fall back to a link-local address if there's no DHCP server
+    let config = FallbackConfigurator::new(
+        dhcp,
+        LinkLocalConfigurator::new(),
+        embassy::time::Duration::from_secs(10),
+    );
 
     // Init network stack
-    embassy_net::init(DEVICE.put(device), CONFIG.put(config));
+    let stack: &'static Stack = STACK.put(Stack::new(
+        DEVICE.put(device),
+        CONFIG.put(config),
+        RESOURCES.put(StackResources::new()),
+    ));
 
     // Launch network task
-    spawner.spawn(net_task()).unwrap();
+    spawner.spawn(net_task(stack)).unwrap();
+
+    // Wait for an IP configuration before connecting.
+    stack.wait_config_up().await;
+    if let Some(dhcp) = stack
+        .interface_config(InterfaceId::FIRST)
+        .and_then(|c| c.dhcp)
+    {
+        info!(
+            "DHCP lease from {}, renewing at {}, expiring at {}",
+            dhcp.server, dhcp.renew_at, dhcp.expires_at
+        );
+    }
 
     // Then we can use it!
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
-    let mut socket = TcpSocket::new(&mut rx_buffer, &mut tx_buffer);
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer).unwrap();
 
     socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
 
@@ -74,7 +99,7 @@ async fn main_task(spawner: Spawner) {
     loop {
         let r = socket.write_all(b"Hello!\n").await;
         if let Err(e) = r {
-            warn!("write error: {:?}", e);
+            warn!("write error: {:?} ({:?})", e, socket.close_reason());
             return;
         }
     }
@@ -91,7 +116,6 @@ static EXECUTOR: Forever<Executor> = Forever::new();
 fn main() {
     env_logger::builder()
         .filter_level(log::LevelFilter::Debug)
-        .filter_module("async_io", log::LevelFilter::Info)
         .format_timestamp_nanos()
         .init();
 
@@ -0,0 +1,30 @@
+use embassy_std::AsyncFd;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+/// A serial port, or pty, in raw mode.
+pub type SerialPort = AsyncFd<File>;
+
+/// Open the serial port at `path`. This must be called from a task.
+pub fn open(path: &str) -> io::Result<SerialPort> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)?;
+
+    unsafe {
+        let mut termios = std::mem::zeroed();
+        if libc::tcgetattr(file.as_raw_fd(), &mut termios) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(file.as_raw_fd(), libc::TCSANOW, &termios) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    AsyncFd::new(file)
+}
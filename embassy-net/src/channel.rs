@@ -0,0 +1,171 @@
+use core::cell::{Cell, RefCell};
+use core::task::{Poll, Waker};
+use embassy::util::WakerRegistration;
+use smoltcp::phy::{DeviceCapabilities, Medium};
+
+use crate::device::{Device, LinkState, RxToken, TxToken};
+use crate::packet_queue::PacketQueue;
+
+/// Frames passed between the stack and a task driving a link, such as a serial line.
+///
+/// The stack sees the channel through a [`ChannelDevice`]. The task pushes the frames it
+/// receives with [`Channel::push_rx`], and takes the frames to send with
+/// [`Channel::pop_tx`].
+pub(crate) struct Channel<const N: usize, const MTU: usize> {
+    rx: RefCell<PacketQueue<N, MTU>>,
+    tx: RefCell<PacketQueue<N, MTU>>,
+    link_up: Cell<bool>,
+    /// The stack, waiting for received frames, room to send, or link changes.
+    stack_waker: RefCell<WakerRegistration>,
+    /// The link task, waiting for frames to send.
+    link_waker: RefCell<WakerRegistration>,
+}
+
+impl<const N: usize, const MTU: usize> Channel<N, MTU> {
+    pub const fn new() -> Self {
+        Self {
+            rx: RefCell::new(PacketQueue::new()),
+            tx: RefCell::new(PacketQueue::new()),
+            link_up: Cell::new(false),
+            stack_waker: RefCell::new(WakerRegistration::new()),
+            link_waker: RefCell::new(WakerRegistration::new()),
+        }
+    }
+
+    pub fn set_link_up(&self, up: bool) {
+        if self.link_up.replace(up) != up {
+            self.wake_stack();
+        }
+        if !up {
+            // Frames queued while the link was up are stale by now.
+            while !self.tx.borrow().is_empty() {
+                self.tx.borrow_mut().pop();
+            }
+        }
+    }
+
+    pub fn wake_stack(&self) {
+        self.stack_waker.borrow_mut().wake();
+    }
+
+    /// Pass a received frame to the stack. `f` writes the frame into the buffer it's given,
+    /// and returns its length, or `None` to drop it.
+    ///
+    /// Returns false if the stack hasn't consumed enough frames to make room for it.
+    pub fn push_rx(&self, f: impl FnOnce(&mut [u8; MTU]) -> Option<usize>) -> bool {
+        let mut rx = self.rx.borrow_mut();
+        let buf = match rx.back_buf() {
+            Some(buf) => buf,
+            None => return false,
+        };
+        if let Some(len) = f(buf) {
+            rx.push(len);
+            drop(rx);
+            self.wake_stack();
+        }
+        true
+    }
+
+    /// Take the next frame sent by the stack, or `None` if there's none.
+    pub fn pop_tx<R>(&self, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+        let mut tx = self.tx.borrow_mut();
+        let res = f(tx.front()?);
+        tx.pop();
+        drop(tx);
+        self.wake_stack();
+        Some(res)
+    }
+
+    /// Wait until the stack sent a frame.
+    pub fn poll_tx_ready(&self, waker: &Waker) -> Poll<()> {
+        if self.tx.borrow().is_empty() {
+            self.link_waker.borrow_mut().register(waker);
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+
+    pub fn device(&self, medium: Medium, ethernet_address: [u8; 6]) -> ChannelDevice<'_, N, MTU> {
+        ChannelDevice {
+            rx: ChannelRx { channel: self },
+            tx: ChannelTx { channel: self },
+            medium,
+            ethernet_address,
+        }
+    }
+}
+
+/// The side of a [`Channel`] handed to the stack.
+pub struct ChannelDevice<'a, const N: usize, const MTU: usize> {
+    rx: ChannelRx<'a, N, MTU>,
+    tx: ChannelTx<'a, N, MTU>,
+    medium: Medium,
+    ethernet_address: [u8; 6],
+}
+
+struct ChannelRx<'a, const N: usize, const MTU: usize> {
+    channel: &'a Channel<N, MTU>,
+}
+
+impl<'a, const N: usize, const MTU: usize> RxToken for ChannelRx<'a, N, MTU> {
+    fn consume(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        self.channel.rx.borrow_mut().consume(f)
+    }
+}
+
+struct ChannelTx<'a, const N: usize, const MTU: usize> {
+    channel: &'a Channel<N, MTU>,
+}
+
+impl<'a, const N: usize, const MTU: usize> TxToken for ChannelTx<'a, N, MTU> {
+    fn consume(&mut self, len: usize, f: &mut dyn FnMut(&mut [u8])) {
+        // Frames sent while the link is down are dropped.
+        if self.channel.link_up.get() {
+            TxToken::consume(&mut *self.channel.tx.borrow_mut(), len, f);
+            self.channel.link_waker.borrow_mut().wake();
+        }
+    }
+}
+
+impl<'a, const N: usize, const MTU: usize> Device for ChannelDevice<'a, N, MTU> {
+    fn receive(&mut self) -> Option<(&mut dyn RxToken, &mut dyn TxToken)> {
+        if self.rx.channel.rx.borrow().is_empty() {
+            return None;
+        }
+        let rx: &mut dyn RxToken = &mut self.rx;
+        let tx: &mut dyn TxToken = &mut self.tx;
+        Some((rx, tx))
+    }
+
+    fn transmit(&mut self) -> Option<&mut dyn TxToken> {
+        if self.tx.channel.tx.borrow().is_full() {
+            return None;
+        }
+        let tx: &mut dyn TxToken = &mut self.tx;
+        Some(tx)
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.rx.channel.stack_waker.borrow_mut().register(waker);
+    }
+
+    fn capabilities(&mut self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = MTU;
+        caps.medium = self.medium;
+        caps
+    }
+
+    fn link_state(&mut self) -> LinkState {
+        if self.rx.channel.link_up.get() {
+            LinkState::Up
+        } else {
+            LinkState::Down
+        }
+    }
+
+    fn ethernet_address(&mut self) -> [u8; 6] {
+        self.ethernet_address
+    }
+}
@@ -0,0 +1,457 @@
+use core::mem;
+use core::task::Context;
+use embassy::time::{Duration, Instant};
+use heapless::consts::*;
+use heapless::{String, Vec};
+use smoltcp::socket::{SocketHandle, UdpPacketMetadata, UdpSocket, UdpSocketBuffer};
+use smoltcp::time::Instant as SmolInstant;
+use smoltcp::wire::{EthernetAddress, IpAddress, IpEndpoint, Ipv4Address, Ipv4Cidr};
+
+mod packet;
+use packet::*;
+
+use super::*;
+use crate::device::LinkState;
+use crate::fmt::*;
+use crate::stack::{instant_from_smoltcp, instant_to_smoltcp, rand, wake_at};
+use crate::{Error, Interface, Result, SocketSet};
+
+const RX_META_LEN: usize = 2;
+const RX_BUFFER_LEN: usize = 2 * MAX_MESSAGE_SIZE as usize;
+const TX_BUFFER_LEN: usize = MAX_MESSAGE_SIZE as usize;
+
+/// Largest message we accept, the minimum every DHCP implementation must support.
+const MAX_MESSAGE_SIZE: u16 = 576;
+
+const DISCOVER_TIMEOUT_MIN: Duration = Duration::from_secs(4);
+const DISCOVER_TIMEOUT_MAX: Duration = Duration::from_secs(64);
+const REQUEST_RETRIES: u8 = 4;
+const RENEW_RETRY_MIN: Duration = Duration::from_secs(60);
+
+/// Lease time used when the server doesn't send one.
+const DEFAULT_LEASE_TIME: u32 = 120;
+const INFINITE_LEASE_TIME: u32 = 0xffff_ffff;
+
+/// Options always present in the parameter request list.
+const DEFAULT_PARAMETERS: [u8; 6] = [
+    OPT_SUBNET_MASK,
+    OPT_ROUTER,
+    OPT_DNS_SERVER,
+    OPT_DOMAIN_NAME,
+    OPT_INTERFACE_MTU,
+    OPT_NTP_SERVER,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Broadcasting DISCOVER, waiting for an OFFER.
+    Discovering,
+    /// Requesting an offered address, waiting for an ACK.
+    Requesting {
+        server: Ipv4Address,
+        address: Ipv4Address,
+    },
+    /// Holding a lease, until T1.
+    Bound,
+    /// Extending the lease with the server that granted it, until T2.
+    Renewing,
+    /// Extending the lease with any server, until it expires.
+    Rebinding,
+}
+
+/// DHCPv4 client.
+///
+/// By default the client identifier is the interface's MAC address, no hostname is sent,
+/// and the subnet mask, router, DNS servers, domain name, MTU and NTP servers are
+/// requested. The lease times are reported in [`Config::dhcp`].
+pub struct DhcpConfigurator {
+    handle: Option<SocketHandle>,
+    rx_meta: [UdpPacketMetadata; RX_META_LEN],
+    rx_buffer: [u8; RX_BUFFER_LEN],
+    tx_meta: [UdpPacketMetadata; 1],
+    tx_buffer: [u8; TX_BUFFER_LEN],
+
+    hostname: String<U64>,
+    client_id: Vec<u8, U32>,
+    requested_options: Vec<u8, U8>,
+
+    state: State,
+    config: Option<Config>,
+    xid: u32,
+    /// When the current transaction started, reported in the `secs` field.
+    started_at: Instant,
+    /// When to send the next message, or to move to the next state.
+    retry_at: Instant,
+    retries: u8,
+}
+
+impl DhcpConfigurator {
+    pub fn new() -> Self {
+        Self {
+            handle: None,
+            rx_meta: [UdpPacketMetadata::EMPTY; RX_META_LEN],
+            rx_buffer: [0; RX_BUFFER_LEN],
+            tx_meta: [UdpPacketMetadata::EMPTY; 1],
+            tx_buffer: [0; TX_BUFFER_LEN],
+
+            hostname: String::new(),
+            client_id: Vec::new(),
+            requested_options: Vec::new(),
+
+            state: State::Discovering,
+            config: None,
+            xid: 0,
+            started_at: Instant::MIN,
+            retry_at: Instant::MIN,
+            retries: 0,
+        }
+    }
+
+    /// Set the hostname sent to the server (option 12).
+    ///
+    /// Fails with `Error::Exhausted` if `hostname` is longer than 63 bytes.
+    pub fn set_hostname(&mut self, hostname: &str) -> Result<()> {
+        if hostname.len() > 63 {
+            return Err(Error::Exhausted);
+        }
+        self.hostname.clear();
+        unwrap!(self.hostname.push_str(hostname));
+        Ok(())
+    }
+
+    /// Set the client identifier sent to the server (option 61).
+    ///
+    /// The default is the hardware type followed by the MAC address, as recommended by
+    /// RFC 2132. Fails with `Error::Illegal` if `client_id` is empty, or `Error::Exhausted`
+    /// if it's longer than 32 bytes.
+    pub fn set_client_id(&mut self, client_id: &[u8]) -> Result<()> {
+        if client_id.is_empty() {
+            return Err(Error::Illegal);
+        }
+        if client_id.len() > self.client_id.capacity() {
+            return Err(Error::Exhausted);
+        }
+        self.client_id.clear();
+        unwrap!(self.client_id.extend_from_slice(client_id));
+        Ok(())
+    }
+
+    /// Ask the server for an option not requested by default.
+    ///
+    /// The raw value is reported in `DhcpInfo::options`, if the server sends it and it
+    /// fits in 32 bytes. Up to 8 extra options can be requested: fails with
+    /// `Error::Exhausted` after that.
+    pub fn request_option(&mut self, code: u8) -> Result<()> {
+        if !self.requested_options.contains(&code) {
+            self.requested_options
+                .push(code)
+                .map_err(|_| Error::Exhausted)?;
+        }
+        Ok(())
+    }
+
+    fn create_socket(&mut self) -> UdpSocket<'static> {
+        // Safety: configurators are `&'static mut`, so the buffers outlive the socket.
+        let rx_meta: &'static mut [UdpPacketMetadata] =
+            unsafe { mem::transmute(&mut self.rx_meta[..]) };
+        let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(&mut self.rx_buffer[..]) };
+        let tx_meta: &'static mut [UdpPacketMetadata] =
+            unsafe { mem::transmute(&mut self.tx_meta[..]) };
+        let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(&mut self.tx_buffer[..]) };
+
+        let mut socket = UdpSocket::new(
+            UdpSocketBuffer::new(rx_meta, rx_buffer),
+            UdpSocketBuffer::new(tx_meta, tx_buffer),
+        );
+        unwrap!(socket.bind(CLIENT_PORT));
+        socket
+    }
+
+    /// Start a new transaction in `state`, sending the first message right away.
+    fn start(&mut self, state: State, now: Instant) {
+        let mut xid = [0; 4];
+        rand(&mut xid);
+        self.xid = u32::from_le_bytes(xid);
+        self.state = state;
+        self.started_at = now;
+        self.retry_at = now;
+        self.retries = 0;
+    }
+
+    /// Drop the lease and start over, returning `Deconfigured` if we had one.
+    fn reset(&mut self, now: Instant) -> Event {
+        self.start(State::Discovering, now);
+        match self.config.take() {
+            Some(_) => Event::Deconfigured,
+            None => Event::NoChange,
+        }
+    }
+
+    fn handle_reply(&mut self, reply: Reply, now: Instant) -> Event {
+        match (self.state, reply.message_type) {
+            (State::Discovering, MessageType::Offer) => {
+                let server = match reply.server_id {
+                    Some(server) => server,
+                    None => return Event::NoChange,
+                };
+                if reply.your_ip.is_unspecified() {
+                    return Event::NoChange;
+                }
+                debug!("DHCP: offer of {} from {}", reply.your_ip, server);
+
+                // The REQUEST is part of the same transaction as the DISCOVER.
+                self.state = State::Requesting {
+                    server,
+                    address: reply.your_ip,
+                };
+                self.retry_at = now;
+                self.retries = 0;
+                Event::NoChange
+            }
+            (State::Requesting { .. }, MessageType::Ack)
+            | (State::Renewing, MessageType::Ack)
+            | (State::Rebinding, MessageType::Ack) => {
+                let server = match (reply.server_id, self.state, self.lease()) {
+                    (Some(server), _, _) => server,
+                    (None, State::Requesting { server, .. }, _) => server,
+                    (None, _, Some(info)) => info.server,
+                    (None, _, None) => return Event::NoChange,
+                };
+                match self.lease_config(reply, server, now) {
+                    Some(config) => {
+                        debug!("DHCP: lease of {} from {}", config.address, server);
+                        self.state = State::Bound;
+                        self.retry_at = unwrap!(config.dhcp.as_ref()).renew_at;
+                        self.config = Some(config.clone());
+                        Event::Configured(config)
+                    }
+                    None => Event::NoChange,
+                }
+            }
+            (State::Requesting { .. }, MessageType::Nak)
+            | (State::Renewing, MessageType::Nak)
+            | (State::Rebinding, MessageType::Nak) => {
+                debug!("DHCP: request refused by server");
+                self.reset(now)
+            }
+            _ => Event::NoChange,
+        }
+    }
+
+    /// Build the configuration from an ACK.
+    fn lease_config(&self, reply: Reply, server: Ipv4Address, now: Instant) -> Option<Config> {
+        let subnet_mask = match reply.subnet_mask {
+            Some(mask) => mask,
+            None => {
+                warn!("DHCP: ignoring ACK without a subnet mask");
+                return None;
+            }
+        };
+        let prefix_len = match IpAddress::Ipv4(subnet_mask).to_prefix_len() {
+            Some(len) => len,
+            None => {
+                warn!("DHCP: ignoring ACK with an invalid subnet mask");
+                return None;
+            }
+        };
+
+        let lease_time = reply.lease_time.unwrap_or(DEFAULT_LEASE_TIME);
+        let (renew_at, rebind_at, expires_at) = if lease_time == INFINITE_LEASE_TIME {
+            (Instant::MAX, Instant::MAX, Instant::MAX)
+        } else {
+            let lease = Duration::from_secs(lease_time as u64);
+            let renew = match reply.renewal_time {
+                Some(t) => Duration::from_secs(t as u64),
+                None => lease / 2,
+            };
+            let rebind = match reply.rebinding_time {
+                Some(t) => Duration::from_secs(t as u64),
+                None => lease * 7 / 8,
+            };
+            (now + renew, now + rebind, now + lease)
+        };
+
+        Some(Config {
+            address: Ipv4Cidr::new(reply.your_ip, prefix_len),
+            gateway: reply.router,
+            dns_servers: reply.dns_servers,
+            dhcp: Some(DhcpInfo {
+                server,
+                renew_at,
+                rebind_at,
+                expires_at,
+                ntp_servers: reply.ntp_servers,
+                domain_name: reply.domain_name,
+                mtu: reply.mtu,
+                options: reply.options,
+            }),
+        })
+    }
+
+    fn lease(&self) -> Option<&DhcpInfo> {
+        self.config.as_ref().and_then(|c| c.dhcp.as_ref())
+    }
+
+    /// Move on to renewing, rebinding or expiry once the lease times are reached.
+    fn poll_lease(&mut self, now: Instant) -> Event {
+        let info = match self.lease() {
+            Some(info) => info,
+            None => return Event::NoChange,
+        };
+
+        if now >= info.expires_at {
+            debug!("DHCP: lease expired");
+            return self.reset(now);
+        }
+        if now >= info.rebind_at && self.state != State::Rebinding {
+            self.start(State::Rebinding, now);
+        } else if now >= info.renew_at && self.state == State::Bound {
+            self.start(State::Renewing, now);
+        }
+        Event::NoChange
+    }
+
+    fn send(&mut self, socket: &mut UdpSocket, mac: EthernetAddress, now: Instant) {
+        let (dest, client_ip, requested_ip, server_id, deadline) = match self.state {
+            State::Bound => return,
+            State::Discovering => (Ipv4Address::BROADCAST, None, None, None, Instant::MAX),
+            State::Requesting { server, address } => {
+                if self.retries >= REQUEST_RETRIES {
+                    debug!("DHCP: no answer to request, restarting");
+                    self.start(State::Discovering, now);
+                    return self.send(socket, mac, now);
+                }
+                let dest = Ipv4Address::BROADCAST;
+                (dest, None, Some(address), Some(server), Instant::MAX)
+            }
+            State::Renewing | State::Rebinding => {
+                let config = unwrap!(self.config.as_ref());
+                let info = unwrap!(config.dhcp.as_ref());
+                let (dest, deadline) = match self.state {
+                    State::Renewing => (info.server, info.rebind_at),
+                    _ => (Ipv4Address::BROADCAST, info.expires_at),
+                };
+                (dest, Some(config.address.address()), None, None, deadline)
+            }
+        };
+
+        let retry_in = match self.state {
+            // RFC 2131 section 4.4.5: retransmit after half of the remaining time.
+            State::Renewing | State::Rebinding => {
+                (deadline.saturating_duration_since(now) / 2).max(RENEW_RETRY_MIN)
+            }
+            _ => (DISCOVER_TIMEOUT_MIN * (1 << self.retries.min(4))).min(DISCOVER_TIMEOUT_MAX),
+        };
+        self.retry_at = (now + retry_in).min(deadline);
+        self.retries = self.retries.saturating_add(1);
+
+        let default_client_id = [
+            1, mac.0[0], mac.0[1], mac.0[2], mac.0[3], mac.0[4], mac.0[5],
+        ];
+        let client_id = if self.client_id.is_empty() {
+            &default_client_id[..]
+        } else {
+            &self.client_id[..]
+        };
+
+        let mut parameters: Vec<u8, U16> = Vec::new();
+        parameters.extend_from_slice(&DEFAULT_PARAMETERS).unwrap();
+        parameters
+            .extend_from_slice(&self.requested_options)
+            .unwrap();
+
+        let message_type = match self.state {
+            State::Discovering => MessageType::Discover,
+            _ => MessageType::Request,
+        };
+        let secs = now.saturating_duration_since(self.started_at).as_secs();
+        let request = Request {
+            message_type,
+            xid: self.xid,
+            secs: secs.min(u16::MAX as u64) as u16,
+            broadcast: client_ip.is_none(),
+            client_ip: client_ip.unwrap_or(Ipv4Address::UNSPECIFIED),
+            mac,
+            client_id,
+            hostname: &self.hostname,
+            requested_ip,
+            server_id,
+            parameters: &parameters,
+            max_size: MAX_MESSAGE_SIZE,
+        };
+
+        let mut buf = [0; TX_BUFFER_LEN];
+        let len = request.emit(&mut buf);
+        let endpoint = IpEndpoint::new(dest.into(), SERVER_PORT);
+        match socket.send_slice(&buf[..len], endpoint) {
+            Ok(()) => debug!("DHCP: sent {:?} to {}", message_type, dest),
+            Err(_) => warn!("DHCP: failed to send {:?}", message_type),
+        }
+    }
+}
+
+impl Configurator for DhcpConfigurator {
+    fn poll(
+        &mut self,
+        iface: &mut Interface,
+        sockets: &mut SocketSet,
+        timestamp: SmolInstant,
+        cx: &mut Context<'_>,
+    ) -> Event {
+        let now = instant_from_smoltcp(timestamp);
+
+        let handle = match self.handle {
+            Some(handle) => handle,
+            None => {
+                let handle = sockets.add(self.create_socket());
+                self.handle = Some(handle);
+                self.start(State::Discovering, now);
+                handle
+            }
+        };
+        let mut socket = sockets.get::<UdpSocket>(handle);
+
+        let link_up = iface.device_mut().device.link_state() == LinkState::Up;
+        if !link_up {
+            // Drop anything received before the link went down.
+            while socket.recv().is_ok() {}
+            return self.reset(now);
+        }
+
+        let mac = iface.ethernet_addr();
+        let mut event = Event::NoChange;
+
+        loop {
+            let reply = match socket.recv() {
+                Ok((data, _)) => Reply::parse(data, &self.requested_options),
+                Err(_) => break,
+            };
+            if let Some(reply) = reply {
+                if reply.xid == self.xid && reply.mac == mac {
+                    match self.handle_reply(reply, now) {
+                        Event::NoChange => {}
+                        e => event = e,
+                    }
+                }
+            }
+        }
+
+        match self.poll_lease(now) {
+            Event::NoChange => {}
+            e => event = e,
+        }
+
+        if now >= self.retry_at {
+            self.send(&mut socket, mac, now);
+        }
+        if self.retry_at != Instant::MAX {
+            wake_at(cx, instant_to_smoltcp(self.retry_at));
+        }
+
+        event
+    }
+
+    fn deconfigure(&mut self, _iface: &mut Interface, _sockets: &mut SocketSet) {
+        self.reset(Instant::now());
+    }
+}
@@ -0,0 +1,304 @@
+//! DHCP message encoding and decoding (RFC 2131, RFC 2132).
+
+use heapless::consts::*;
+use heapless::{String, Vec};
+use smoltcp::wire::{EthernetAddress, Ipv4Address};
+
+use crate::config::DhcpOption;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+const OP_BOOTREQUEST: u8 = 1;
+const OP_BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [0x63, 0x82, 0x53, 0x63];
+
+/// Offsets of the sname and file fields, which may hold options too (see `OPT_OVERLOAD`).
+const SNAME_OFFSET: usize = 44;
+const FILE_OFFSET: usize = 108;
+const COOKIE_OFFSET: usize = 236;
+/// Offset of the options field, right after the magic cookie.
+const OPTIONS_OFFSET: usize = 240;
+
+/// Values of `OPT_OVERLOAD`.
+const OVERLOAD_FILE: u8 = 1;
+const OVERLOAD_SNAME: u8 = 2;
+
+pub const OPT_PAD: u8 = 0;
+pub const OPT_SUBNET_MASK: u8 = 1;
+pub const OPT_ROUTER: u8 = 3;
+pub const OPT_DNS_SERVER: u8 = 6;
+pub const OPT_HOST_NAME: u8 = 12;
+pub const OPT_DOMAIN_NAME: u8 = 15;
+pub const OPT_INTERFACE_MTU: u8 = 26;
+pub const OPT_NTP_SERVER: u8 = 42;
+pub const OPT_REQUESTED_IP: u8 = 50;
+pub const OPT_LEASE_TIME: u8 = 51;
+pub const OPT_OVERLOAD: u8 = 52;
+pub const OPT_MESSAGE_TYPE: u8 = 53;
+pub const OPT_SERVER_ID: u8 = 54;
+pub const OPT_PARAMETER_REQUEST_LIST: u8 = 55;
+pub const OPT_MAX_MESSAGE_SIZE: u8 = 57;
+pub const OPT_RENEWAL_TIME: u8 = 58;
+pub const OPT_REBINDING_TIME: u8 = 59;
+pub const OPT_CLIENT_ID: u8 = 61;
+pub const OPT_END: u8 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl MessageType {
+    fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            1 => Some(Self::Discover),
+            2 => Some(Self::Offer),
+            3 => Some(Self::Request),
+            4 => Some(Self::Decline),
+            5 => Some(Self::Ack),
+            6 => Some(Self::Nak),
+            7 => Some(Self::Release),
+            8 => Some(Self::Inform),
+            _ => None,
+        }
+    }
+}
+
+/// A message sent by the client.
+pub struct Request<'a> {
+    pub message_type: MessageType,
+    pub xid: u32,
+    pub secs: u16,
+    /// Ask the server to broadcast its reply, because we can't receive unicast yet.
+    pub broadcast: bool,
+    /// Our current address, when renewing or rebinding a lease.
+    pub client_ip: Ipv4Address,
+    pub mac: EthernetAddress,
+    pub client_id: &'a [u8],
+    pub hostname: &'a str,
+    pub requested_ip: Option<Ipv4Address>,
+    pub server_id: Option<Ipv4Address>,
+    pub parameters: &'a [u8],
+    pub max_size: u16,
+}
+
+impl<'a> Request<'a> {
+    /// Write the message to `buf`, returning its length.
+    ///
+    /// Panics if `buf` is too small.
+    pub fn emit(&self, buf: &mut [u8]) -> usize {
+        for b in buf[..OPTIONS_OFFSET].iter_mut() {
+            *b = 0;
+        }
+
+        buf[0] = OP_BOOTREQUEST;
+        buf[1] = HTYPE_ETHERNET;
+        buf[2] = 6;
+        buf[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        buf[8..10].copy_from_slice(&self.secs.to_be_bytes());
+        let flags = if self.broadcast { FLAG_BROADCAST } else { 0 };
+        buf[10..12].copy_from_slice(&flags.to_be_bytes());
+        buf[12..16].copy_from_slice(self.client_ip.as_bytes());
+        buf[28..34].copy_from_slice(self.mac.as_bytes());
+        buf[COOKIE_OFFSET..OPTIONS_OFFSET].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut w = OptionWriter {
+            buf,
+            pos: OPTIONS_OFFSET,
+        };
+        w.write(OPT_MESSAGE_TYPE, &[self.message_type as u8]);
+        w.write(OPT_CLIENT_ID, self.client_id);
+        if let Some(ip) = self.requested_ip {
+            w.write(OPT_REQUESTED_IP, ip.as_bytes());
+        }
+        if let Some(ip) = self.server_id {
+            w.write(OPT_SERVER_ID, ip.as_bytes());
+        }
+        if !self.hostname.is_empty() {
+            w.write(OPT_HOST_NAME, self.hostname.as_bytes());
+        }
+        if !self.parameters.is_empty() {
+            w.write(OPT_PARAMETER_REQUEST_LIST, self.parameters);
+        }
+        w.write(OPT_MAX_MESSAGE_SIZE, &self.max_size.to_be_bytes());
+        w.buf[w.pos] = OPT_END;
+        w.pos + 1
+    }
+}
+
+struct OptionWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> OptionWriter<'a> {
+    fn write(&mut self, code: u8, data: &[u8]) {
+        self.buf[self.pos] = code;
+        self.buf[self.pos + 1] = data.len() as u8;
+        self.buf[self.pos + 2..self.pos + 2 + data.len()].copy_from_slice(data);
+        self.pos += 2 + data.len();
+    }
+}
+
+/// A message received from a server, with the options we understand.
+pub struct Reply {
+    pub message_type: MessageType,
+    pub xid: u32,
+    pub mac: EthernetAddress,
+    pub your_ip: Ipv4Address,
+    pub server_id: Option<Ipv4Address>,
+    pub subnet_mask: Option<Ipv4Address>,
+    pub router: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address, U3>,
+    pub ntp_servers: Vec<Ipv4Address, U3>,
+    pub domain_name: Option<String<U64>>,
+    pub mtu: Option<u16>,
+    pub lease_time: Option<u32>,
+    pub renewal_time: Option<u32>,
+    pub rebinding_time: Option<u32>,
+    /// Other options listed in `extra`, in the order they appeared.
+    pub options: Vec<DhcpOption, U4>,
+}
+
+impl Reply {
+    /// Parse a reply, keeping the raw value of any option whose code is in `extra`.
+    ///
+    /// Returns `None` if the message is malformed or isn't a reply.
+    pub fn parse(buf: &[u8], extra: &[u8]) -> Option<Self> {
+        if buf.len() < OPTIONS_OFFSET
+            || buf[0] != OP_BOOTREPLY
+            || buf[1] != HTYPE_ETHERNET
+            || buf[2] != 6
+            || buf[COOKIE_OFFSET..OPTIONS_OFFSET] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut reply = Reply {
+            message_type: MessageType::Discover,
+            xid: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            mac: EthernetAddress::from_bytes(&buf[28..34]),
+            your_ip: Ipv4Address::from_bytes(&buf[16..20]),
+            server_id: None,
+            subnet_mask: None,
+            router: None,
+            dns_servers: Vec::new(),
+            ntp_servers: Vec::new(),
+            domain_name: None,
+            mtu: None,
+            lease_time: None,
+            renewal_time: None,
+            rebinding_time: None,
+            options: Vec::new(),
+        };
+        let mut message_type = None;
+
+        let overload = reply.parse_options(&buf[OPTIONS_OFFSET..], extra, &mut message_type)?;
+        // The file and sname fields hold more options, read in that order (RFC 2131 section
+        // 4.1). The overload option is only honored in the options field.
+        if overload & OVERLOAD_FILE != 0 {
+            reply.parse_options(&buf[FILE_OFFSET..COOKIE_OFFSET], extra, &mut message_type)?;
+        }
+        if overload & OVERLOAD_SNAME != 0 {
+            reply.parse_options(&buf[SNAME_OFFSET..FILE_OFFSET], extra, &mut message_type)?;
+        }
+
+        reply.message_type = message_type?;
+        Some(reply)
+    }
+
+    /// Parse the options in `opts`, keeping the raw value of any option whose code is in
+    /// `extra`.
+    ///
+    /// Returns the value of the overload option, or `None` if the options are malformed.
+    fn parse_options(
+        &mut self,
+        mut opts: &[u8],
+        extra: &[u8],
+        message_type: &mut Option<MessageType>,
+    ) -> Option<u8> {
+        let mut overload = 0;
+        loop {
+            let (code, data) = match opts {
+                [] | [OPT_END, ..] => break,
+                [OPT_PAD, rest @ ..] => {
+                    opts = rest;
+                    continue;
+                }
+                [code, len, rest @ ..] if rest.len() >= *len as usize => {
+                    let (data, rest) = rest.split_at(*len as usize);
+                    opts = rest;
+                    (*code, data)
+                }
+                _ => return None,
+            };
+
+            match code {
+                OPT_MESSAGE_TYPE if data.len() == 1 => {
+                    *message_type = MessageType::from_u8(data[0]);
+                }
+                OPT_SERVER_ID if data.len() == 4 => {
+                    self.server_id = Some(Ipv4Address::from_bytes(data));
+                }
+                OPT_SUBNET_MASK if data.len() == 4 => {
+                    self.subnet_mask = Some(Ipv4Address::from_bytes(data));
+                }
+                OPT_ROUTER if data.len() >= 4 => {
+                    self.router = Some(Ipv4Address::from_bytes(&data[..4]));
+                }
+                OPT_DNS_SERVER => parse_addresses(data, &mut self.dns_servers),
+                OPT_NTP_SERVER => parse_addresses(data, &mut self.ntp_servers),
+                OPT_DOMAIN_NAME => {
+                    let mut name = String::new();
+                    if let Ok(s) = core::str::from_utf8(data) {
+                        if name.push_str(s).is_ok() {
+                            self.domain_name = Some(name);
+                        }
+                    }
+                }
+                OPT_OVERLOAD if data.len() == 1 => overload = data[0],
+                OPT_INTERFACE_MTU if data.len() == 2 => {
+                    self.mtu = Some(u16::from_be_bytes([data[0], data[1]]));
+                }
+                OPT_LEASE_TIME if data.len() == 4 => self.lease_time = Some(parse_u32(data)),
+                OPT_RENEWAL_TIME if data.len() == 4 => self.renewal_time = Some(parse_u32(data)),
+                OPT_REBINDING_TIME if data.len() == 4 => {
+                    self.rebinding_time = Some(parse_u32(data))
+                }
+                _ => {}
+            }
+
+            if extra.contains(&code) {
+                let mut value = Vec::new();
+                // Options that don't fit are dropped rather than truncated.
+                if value.extend_from_slice(data).is_ok() {
+                    let _ = self.options.push(DhcpOption { code, data: value });
+                }
+            }
+        }
+        Some(overload)
+    }
+}
+
+fn parse_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn parse_addresses(data: &[u8], out: &mut Vec<Ipv4Address, U3>) {
+    for chunk in data.chunks_exact(4) {
+        if out.push(Ipv4Address::from_bytes(chunk)).is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use core::task::Context;
+use embassy::time::{Duration, Instant};
+use smoltcp::time::Instant as SmolInstant;
+
+use super::*;
+use crate::device::LinkState;
+use crate::stack::{instant_from_smoltcp, instant_to_smoltcp, wake_at};
+use crate::{Interface, SocketSet};
+
+/// Configurator that uses `fallback` when `primary` doesn't get a configuration in time.
+///
+/// Typically `primary` is a `DhcpConfigurator`, and `fallback` a `StaticConfigurator` or
+/// `LinkLocalConfigurator`. The primary configurator keeps running while the fallback is
+/// in use, and takes over again as soon as it gets a configuration. The fallback is then
+/// deconfigured, so that if the primary loses its configuration, the fallback starts over,
+/// probing again for a link-local address for example.
+pub struct FallbackConfigurator<P: Configurator, F: Configurator> {
+    primary: P,
+    fallback: F,
+    timeout: Duration,
+    /// When the link last came up, or `None` while it is down.
+    link_up_at: Option<Instant>,
+    primary_config: Option<Config>,
+    fallback_config: Option<Config>,
+    /// The configuration last reported to the stack.
+    current: Option<Config>,
+}
+
+impl<P: Configurator, F: Configurator> FallbackConfigurator<P, F> {
+    /// Create a configurator that switches to `fallback` if `primary` has no configuration
+    /// `timeout` after the link comes up.
+    pub fn new(primary: P, fallback: F, timeout: Duration) -> Self {
+        Self {
+            primary,
+            fallback,
+            timeout,
+            link_up_at: None,
+            primary_config: None,
+            fallback_config: None,
+            current: None,
+        }
+    }
+}
+
+impl<P: Configurator, F: Configurator> Configurator for FallbackConfigurator<P, F> {
+    fn poll(
+        &mut self,
+        iface: &mut Interface,
+        sockets: &mut SocketSet,
+        timestamp: SmolInstant,
+        cx: &mut Context<'_>,
+    ) -> Event {
+        let now = instant_from_smoltcp(timestamp);
+
+        let link_up = iface.device_mut().device.link_state() == LinkState::Up;
+        if !link_up {
+            self.link_up_at = None;
+        } else if self.link_up_at.is_none() {
+            self.link_up_at = Some(now);
+        }
+
+        let event = self.primary.poll(iface, sockets, timestamp, cx);
+        let had_primary_config = self.primary_config.is_some();
+        update(&mut self.primary_config, event);
+
+        // The fallback isn't polled while the primary has a configuration, so its own could
+        // go stale, for example another host could take its address.
+        if self.primary_config.is_some() && !had_primary_config {
+            self.fallback.deconfigure(iface, sockets);
+            self.fallback_config = None;
+        }
+
+        let fallback_at = self.link_up_at.map(|t| t + self.timeout);
+        let fallback_active = match fallback_at {
+            Some(t) if now >= t => true,
+            Some(t) => {
+                wake_at(cx, instant_to_smoltcp(t));
+                false
+            }
+            None => false,
+        };
+
+        // The fallback is also polled when the link goes down, so it can clean up.
+        if self.primary_config.is_none() && (fallback_active || !link_up) {
+            let event = self.fallback.poll(iface, sockets, timestamp, cx);
+            update(&mut self.fallback_config, event);
+        }
+
+        let config = match (&self.primary_config, &self.fallback_config) {
+            (Some(config), _) => Some(config),
+            (None, Some(config)) if fallback_active => Some(config),
+            _ => None,
+        };
+        if config == self.current.as_ref() {
+            return Event::NoChange;
+        }
+
+        self.current = config.cloned();
+        match &self.current {
+            Some(config) => Event::Configured(config.clone()),
+            None => Event::Deconfigured,
+        }
+    }
+
+    fn deconfigure(&mut self, iface: &mut Interface, sockets: &mut SocketSet) {
+        self.primary.deconfigure(iface, sockets);
+        self.fallback.deconfigure(iface, sockets);
+        self.link_up_at = None;
+        self.primary_config = None;
+        self.fallback_config = None;
+        self.current = None;
+    }
+}
+
+fn update(config: &mut Option<Config>, event: Event) {
+    match event {
+        Event::NoChange => {}
+        Event::Deconfigured => *config = None,
+        Event::Configured(c) => *config = Some(c),
+    }
+}
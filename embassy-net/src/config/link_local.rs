@@ -0,0 +1,229 @@
+use core::task::Context;
+use embassy::time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::time::Instant as SmolInstant;
+use smoltcp::wire::{ArpOperation, ArpRepr, EthernetAddress, Ipv4Address, Ipv4Cidr};
+
+use super::*;
+use crate::device::LinkState;
+use crate::fmt::*;
+use crate::stack::{instant_from_smoltcp, instant_to_smoltcp, rand, wake_at};
+use crate::{Interface, SocketSet};
+
+// Timing constants from RFC 3927 section 9.
+const PROBE_WAIT: Duration = Duration::from_secs(1);
+const PROBE_NUM: u8 = 3;
+const PROBE_MIN: Duration = Duration::from_secs(1);
+const PROBE_MAX: Duration = Duration::from_secs(2);
+const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+const ANNOUNCE_NUM: u8 = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_CONFLICTS: u8 = 10;
+const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(60);
+const DEFEND_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the link to come up.
+    Idle,
+    /// Checking that nobody else uses the address. `sent` probes have been sent.
+    Probing { sent: u8 },
+    /// Using the address, and telling the network about it.
+    Announcing { sent: u8 },
+    /// Using the address.
+    Bound,
+}
+
+/// IPv4 link-local address configuration (RFC 3927).
+///
+/// Picks a random address in 169.254.1.0 - 169.254.254.255, and checks with ARP
+/// probes that no other host uses it before configuring it. The address is defended
+/// against other hosts claiming it afterwards, and a new one is picked if that fails.
+pub struct LinkLocalConfigurator {
+    state: State,
+    address: Ipv4Address,
+    next_at: Instant,
+    conflicts: u8,
+    last_defense: Option<Instant>,
+}
+
+impl LinkLocalConfigurator {
+    pub fn new() -> Self {
+        Self {
+            state: State::Idle,
+            address: Ipv4Address::UNSPECIFIED,
+            next_at: Instant::MAX,
+            conflicts: 0,
+            last_defense: None,
+        }
+    }
+
+    /// Pick a new address, and start probing it.
+    fn restart(&mut self, iface: &mut Interface, now: Instant) {
+        let mut b = [0; 2];
+        rand(&mut b);
+        let n = u16::from_le_bytes(b) % (254 * 256);
+        self.address = Ipv4Address::new(169, 254, 1 + (n >> 8) as u8, n as u8);
+        self.state = State::Probing { sent: 0 };
+        self.last_defense = None;
+
+        // After too many conflicts, slow down so we don't flood the network.
+        let wait = if self.conflicts >= MAX_CONFLICTS {
+            RATE_LIMIT_INTERVAL
+        } else {
+            random_duration(PROBE_WAIT)
+        };
+        self.next_at = now + wait;
+
+        iface.device_mut().watch_arp(Some(self.address), true);
+    }
+
+    /// Stop using the address, and wait for the next poll to pick a new one.
+    fn stop(&mut self, iface: &mut Interface) {
+        self.state = State::Idle;
+        self.next_at = Instant::MAX;
+        iface.device_mut().watch_arp(None, false);
+    }
+
+    fn config(&self) -> Config {
+        Config {
+            address: Ipv4Cidr::new(self.address, 16),
+            gateway: None,
+            dns_servers: Vec::new(),
+            dhcp: None,
+        }
+    }
+
+    fn send_arp(&self, iface: &mut Interface, source: Ipv4Address) {
+        let mac = iface.ethernet_addr();
+        let repr = ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Request,
+            source_hardware_addr: mac,
+            source_protocol_addr: source,
+            target_hardware_addr: EthernetAddress([0; 6]),
+            target_protocol_addr: self.address,
+        };
+        if !iface.device_mut().send_arp(repr) {
+            warn!("Link-local: failed to send ARP packet");
+        }
+    }
+
+    fn handle_conflict(&mut self, iface: &mut Interface, now: Instant) -> Event {
+        match self.state {
+            State::Idle => Event::NoChange,
+            State::Probing { .. } => {
+                debug!(
+                    "Link-local: {} is in use, picking another address",
+                    self.address
+                );
+                self.conflicts = self.conflicts.saturating_add(1);
+                self.restart(iface, now);
+                Event::NoChange
+            }
+            State::Announcing { .. } | State::Bound => {
+                let recently_defended = match self.last_defense {
+                    Some(t) => now < t + DEFEND_INTERVAL,
+                    None => false,
+                };
+                if recently_defended {
+                    debug!("Link-local: lost {} to another host", self.address);
+                    self.conflicts = self.conflicts.saturating_add(1);
+                    self.restart(iface, now);
+                    Event::Deconfigured
+                } else {
+                    debug!("Link-local: defending {}", self.address);
+                    self.last_defense = Some(now);
+                    self.send_arp(iface, self.address);
+                    Event::NoChange
+                }
+            }
+        }
+    }
+}
+
+impl Configurator for LinkLocalConfigurator {
+    fn poll(
+        &mut self,
+        iface: &mut Interface,
+        _sockets: &mut SocketSet,
+        timestamp: SmolInstant,
+        cx: &mut Context<'_>,
+    ) -> Event {
+        let now = instant_from_smoltcp(timestamp);
+
+        let link_up = iface.device_mut().device.link_state() == LinkState::Up;
+        if !link_up {
+            let configured = matches!(self.state, State::Announcing { .. } | State::Bound);
+            self.stop(iface);
+            return if configured {
+                Event::Deconfigured
+            } else {
+                Event::NoChange
+            };
+        }
+
+        if self.state == State::Idle {
+            self.conflicts = 0;
+            self.restart(iface, now);
+        }
+
+        if iface.device_mut().take_arp_conflict() {
+            let event = self.handle_conflict(iface, now);
+            if let Event::Deconfigured = event {
+                wake_at(cx, instant_to_smoltcp(self.next_at));
+                return event;
+            }
+        }
+
+        let mut event = Event::NoChange;
+        if now >= self.next_at {
+            match self.state {
+                State::Idle => {}
+                State::Probing { sent } if sent < PROBE_NUM => {
+                    self.send_arp(iface, Ipv4Address::UNSPECIFIED);
+                    self.state = State::Probing { sent: sent + 1 };
+                    self.next_at = if sent + 1 == PROBE_NUM {
+                        now + ANNOUNCE_WAIT
+                    } else {
+                        now + PROBE_MIN + random_duration(PROBE_MAX - PROBE_MIN)
+                    };
+                }
+                State::Probing { .. } => {
+                    debug!("Link-local: claimed {}", self.address);
+                    self.conflicts = 0;
+                    self.state = State::Announcing { sent: 0 };
+                    self.next_at = now;
+                    iface.device_mut().watch_arp(Some(self.address), false);
+                    event = Event::Configured(self.config());
+                }
+                State::Announcing { sent } => {
+                    self.send_arp(iface, self.address);
+                    if sent + 1 == ANNOUNCE_NUM {
+                        self.state = State::Bound;
+                        self.next_at = Instant::MAX;
+                    } else {
+                        self.state = State::Announcing { sent: sent + 1 };
+                        self.next_at = now + ANNOUNCE_INTERVAL;
+                    }
+                }
+                State::Bound => {}
+            }
+        }
+
+        if self.next_at != Instant::MAX {
+            wake_at(cx, instant_to_smoltcp(self.next_at));
+        }
+        event
+    }
+
+    fn deconfigure(&mut self, iface: &mut Interface, _sockets: &mut SocketSet) {
+        self.stop(iface);
+    }
+}
+
+/// A random duration between zero and `max`.
+fn random_duration(max: Duration) -> Duration {
+    let mut b = [0; 4];
+    rand(&mut b);
+    Duration::from_millis(u32::from_le_bytes(b) as u64 % (max.as_millis() + 1))
+}
@@ -1,13 +1,21 @@
+use core::task::Context;
 use heapless::consts::*;
-use heapless::Vec;
+use heapless::{String, Vec};
 use smoltcp::time::Instant;
 use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
 
 use crate::fmt::*;
 use crate::{Interface, SocketSet};
 
+mod fallback;
 mod statik;
-pub use statik::StaticConfigurator;
+pub use fallback::FallbackConfigurator;
+pub use statik::{StaticConfigControl, StaticConfigurator};
+
+#[cfg(feature = "medium-ethernet")]
+mod link_local;
+#[cfg(feature = "medium-ethernet")]
+pub use link_local::LinkLocalConfigurator;
 
 #[cfg(feature = "dhcpv4")]
 mod dhcp;
@@ -30,9 +38,56 @@ pub struct Config {
     pub address: Ipv4Cidr,
     pub gateway: Option<Ipv4Address>,
     pub dns_servers: Vec<Ipv4Address, U3>,
+    /// Lease details, if the configuration was obtained through DHCP.
+    pub dhcp: Option<DhcpInfo>,
+}
+
+/// Information about a DHCP lease.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpInfo {
+    /// The server that granted the lease.
+    pub server: Ipv4Address,
+    /// When the client starts renewing the lease with `server` (T1).
+    pub renew_at: embassy::time::Instant,
+    /// When the client starts rebinding the lease with any server (T2).
+    pub rebind_at: embassy::time::Instant,
+    /// When the lease expires, and the configuration is lost.
+    pub expires_at: embassy::time::Instant,
+    pub ntp_servers: Vec<Ipv4Address, U3>,
+    pub domain_name: Option<String<U64>>,
+    pub mtu: Option<u16>,
+    /// Options requested with `DhcpConfigurator::request_option`.
+    pub options: Vec<DhcpOption, U4>,
+}
+
+/// A raw DHCP option, as received from the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpOption {
+    pub code: u8,
+    pub data: Vec<u8, U32>,
 }
 
 pub trait Configurator {
-    fn poll(&mut self, iface: &mut Interface, sockets: &mut SocketSet, timestamp: Instant)
-        -> Event;
+    /// Poll the configurator.
+    ///
+    /// Configurators that need to do something at a later time must arrange for `cx`
+    /// to be woken, for example with a timer.
+    fn poll(
+        &mut self,
+        iface: &mut Interface,
+        sockets: &mut SocketSet,
+        timestamp: Instant,
+        cx: &mut Context<'_>,
+    ) -> Event;
+
+    /// Drop the configuration without reporting it.
+    ///
+    /// This isn't called by the stack, which leaves it to `poll` to notice that the link
+    /// went down. It's called by composite configurators, such as `FallbackConfigurator`
+    /// when its primary configurator gets a configuration. The next `poll` starts over,
+    /// so a configuration that must be checked first, such as a link-local address, is
+    /// checked again before it's reported.
+    ///
+    /// Does nothing by default.
+    fn deconfigure(&mut self, _iface: &mut Interface, _sockets: &mut SocketSet) {}
 }
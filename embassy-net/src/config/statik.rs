@@ -1,3 +1,5 @@
+use core::task::{Context, Poll};
+use embassy::util::Signal;
 use smoltcp::time::Instant;
 
 use super::*;
@@ -5,17 +7,53 @@ use crate::fmt::*;
 use crate::{Interface, SocketSet};
 
 pub struct StaticConfigurator {
-    config: Config,
+    config: Option<Config>,
     returned: bool,
+    control: Option<&'static StaticConfigControl>,
 }
 
 impl StaticConfigurator {
     pub fn new(config: Config) -> Self {
         Self {
-            config,
+            config: Some(config),
             returned: false,
+            control: None,
         }
     }
+
+    /// Create a configurator whose configuration can be changed at runtime through `control`.
+    pub fn with_control(config: Config, control: &'static StaticConfigControl) -> Self {
+        Self {
+            config: Some(config),
+            returned: false,
+            control: Some(control),
+        }
+    }
+}
+
+/// Changes the configuration of a [`StaticConfigurator`] at runtime.
+///
+/// This is usually placed in a `static`, so that any task can reach it.
+pub struct StaticConfigControl {
+    signal: Signal<Option<Config>>,
+}
+
+impl StaticConfigControl {
+    pub const fn new() -> Self {
+        Self {
+            signal: Signal::new(),
+        }
+    }
+
+    /// Replace the configuration. It is applied the next time the stack runs.
+    pub fn set_config(&self, config: Config) {
+        self.signal.signal(Some(config))
+    }
+
+    /// Remove the configuration, leaving the interface without an address.
+    pub fn clear(&self) {
+        self.signal.signal(None)
+    }
 }
 
 impl Configurator for StaticConfigurator {
@@ -24,12 +62,29 @@ impl Configurator for StaticConfigurator {
         _iface: &mut Interface,
         _sockets: &mut SocketSet,
         _timestamp: Instant,
+        cx: &mut Context<'_>,
     ) -> Event {
+        if let Some(control) = self.control {
+            if let Poll::Ready(config) = control.signal.poll_wait(cx) {
+                if config != self.config {
+                    self.config = config;
+                    self.returned = false;
+                }
+            }
+        }
+
         if self.returned {
             Event::NoChange
         } else {
             self.returned = true;
-            Event::Configured(self.config.clone())
+            match &self.config {
+                Some(config) => Event::Configured(config.clone()),
+                None => Event::Deconfigured,
+            }
         }
     }
+
+    fn deconfigure(&mut self, _iface: &mut Interface, _sockets: &mut SocketSet) {
+        self.returned = false;
+    }
 }
@@ -1,12 +1,18 @@
+use core::cell::RefCell;
 use core::task::Waker;
+use embassy::time::Instant;
 use smoltcp::phy::Device as SmolDevice;
 use smoltcp::phy::DeviceCapabilities;
+#[cfg(feature = "medium-ethernet")]
+use smoltcp::phy::Medium;
 use smoltcp::time::Instant as SmolInstant;
+#[cfg(feature = "medium-ethernet")]
+use smoltcp::wire::{
+    ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol, Ipv4Address,
+};
 
-use crate::fmt::*;
-use crate::packet_pool::PacketBoxExt;
-use crate::Result;
-use crate::{Packet, PacketBox, PacketBuf};
+use crate::pcap::{record_header, Tap};
+use crate::{Error, Result};
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum LinkState {
@@ -14,10 +20,35 @@ pub enum LinkState {
     Up,
 }
 
+/// A received frame, lent by a [`Device`] until it's consumed.
+pub trait RxToken {
+    /// Call `f` with the frame, then give its buffer back to the device.
+    fn consume(&mut self, f: &mut dyn FnMut(&mut [u8]));
+}
+
+/// A buffer for a frame to send, lent by a [`Device`].
+pub trait TxToken {
+    /// Call `f` to fill in a frame of `len` bytes, then send it.
+    ///
+    /// If the device has no room for the frame after all, it can return without calling
+    /// `f`. The frame is then dropped.
+    fn consume(&mut self, len: usize, f: &mut dyn FnMut(&mut [u8]));
+}
+
+/// A network device.
+///
+/// Frames are read and written in place, in buffers lent by the device through tokens,
+/// so drivers can hand out their DMA buffers directly. Drivers without buffers of their
+/// own can use [`PacketQueue`](crate::PacketQueue)s, which implement both token traits.
 pub trait Device {
-    fn is_transmit_ready(&mut self) -> bool;
-    fn transmit(&mut self, pkt: PacketBuf);
-    fn receive(&mut self) -> Option<PacketBuf>;
+    /// Returns the next received frame, and a token to send a reply with.
+    ///
+    /// Returns `None` if nothing was received. The two tokens usually borrow separate
+    /// fields of the device, such as its receive and transmit descriptor rings.
+    fn receive(&mut self) -> Option<(&mut dyn RxToken, &mut dyn TxToken)>;
+
+    /// Returns a token to send a frame with, or `None` if there's no room for one.
+    fn transmit(&mut self) -> Option<&mut dyn TxToken>;
 
     fn register_waker(&mut self, waker: &Waker);
     fn capabilities(&mut self) -> DeviceCapabilities;
@@ -25,47 +56,255 @@ pub trait Device {
     fn ethernet_address(&mut self) -> [u8; 6];
 }
 
+/// Traffic counters of an interface, returned by
+/// [`Stack::interface_stats`](crate::Stack::interface_stats).
+///
+/// The counters wrap around when they overflow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceStats {
+    pub rx_packets: u32,
+    pub rx_bytes: u32,
+    pub tx_packets: u32,
+    pub tx_bytes: u32,
+    /// Received frames dropped because of a bad checksum.
+    pub rx_checksum_errors: u32,
+    /// Received frames dropped because they couldn't be parsed.
+    pub rx_parse_errors: u32,
+    /// Received frames dropped for other reasons, such as unsupported protocols.
+    pub rx_dropped: u32,
+    /// Times the device had no room for a frame to send. The frame is usually sent later.
+    pub tx_exhausted: u32,
+    /// Frames to send dropped by the device.
+    pub tx_dropped: u32,
+}
+
+/// Counts the frames going through an adapter, and mirrors them to the tap.
+///
+/// Both tokens of a received frame use it, and the transmit token is consumed while the
+/// receive token is being consumed, so it's in a `RefCell` that's only borrowed briefly.
+struct Monitor {
+    stats: InterfaceStats,
+    tap: Option<&'static mut dyn Tap>,
+}
+
+impl Monitor {
+    fn frame(&mut self, frame: &[u8]) {
+        if let Some(tap) = self.tap.as_mut() {
+            tap.record(&record_header(Instant::now(), frame.len()), frame);
+        }
+    }
+
+    fn received(&mut self, frame: &[u8]) {
+        self.stats.rx_packets = self.stats.rx_packets.wrapping_add(1);
+        self.stats.rx_bytes = self.stats.rx_bytes.wrapping_add(frame.len() as u32);
+        self.frame(frame);
+    }
+
+    fn receive_failed(&mut self, error: Error) {
+        let counter = match error {
+            Error::Checksum => &mut self.stats.rx_checksum_errors,
+            Error::Truncated | Error::Malformed => &mut self.stats.rx_parse_errors,
+            // The reply couldn't be sent. That's counted when sending.
+            Error::Exhausted => return,
+            _ => &mut self.stats.rx_dropped,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+
+    fn sent(&mut self, frame: &[u8]) {
+        self.stats.tx_packets = self.stats.tx_packets.wrapping_add(1);
+        self.stats.tx_bytes = self.stats.tx_bytes.wrapping_add(frame.len() as u32);
+        self.frame(frame);
+    }
+
+    fn send_dropped(&mut self) {
+        self.stats.tx_dropped = self.stats.tx_dropped.wrapping_add(1);
+    }
+
+    fn send_exhausted(&mut self) {
+        self.stats.tx_exhausted = self.stats.tx_exhausted.wrapping_add(1);
+    }
+}
+
 pub struct DeviceAdapter {
     pub device: &'static mut dyn Device,
     caps: DeviceCapabilities,
+    monitor: RefCell<Monitor>,
+    #[cfg(feature = "medium-ethernet")]
+    arp_watch: ArpWatch,
 }
 
 impl DeviceAdapter {
     pub(crate) fn new(device: &'static mut dyn Device) -> Self {
+        let caps = device.capabilities();
+
+        #[cfg(feature = "medium-ethernet")]
+        let mac = if caps.medium == Medium::Ethernet {
+            device.ethernet_address()
+        } else {
+            [0, 0, 0, 0, 0, 0]
+        };
+
         Self {
-            caps: device.capabilities(),
+            caps,
             device,
+            monitor: RefCell::new(Monitor {
+                stats: InterfaceStats::default(),
+                tap: None,
+            }),
+            #[cfg(feature = "medium-ethernet")]
+            arp_watch: ArpWatch {
+                mac: EthernetAddress(mac),
+                addr: None,
+                probing: false,
+                conflict: false,
+            },
+        }
+    }
+
+    pub(crate) fn stats(&self) -> InterfaceStats {
+        self.monitor.borrow().stats
+    }
+
+    pub(crate) fn set_tap(&mut self, tap: Option<&'static mut dyn Tap>) {
+        self.monitor.get_mut().tap = tap;
+    }
+
+    /// Start watching for other hosts using `addr`, or stop watching if `None`.
+    ///
+    /// While `probing`, ARP probes for `addr` from other hosts also count as conflicts,
+    /// as required by RFC 3927.
+    #[cfg(feature = "medium-ethernet")]
+    pub(crate) fn watch_arp(&mut self, addr: Option<Ipv4Address>, probing: bool) {
+        self.arp_watch.addr = addr;
+        self.arp_watch.probing = probing;
+        self.arp_watch.conflict = false;
+    }
+
+    /// Returns true if a conflict was seen on the watched address since the last call.
+    #[cfg(feature = "medium-ethernet")]
+    pub(crate) fn take_arp_conflict(&mut self) -> bool {
+        core::mem::replace(&mut self.arp_watch.conflict, false)
+    }
+
+    /// Broadcast an ARP packet, bypassing the interface.
+    ///
+    /// Returns false if the device can't transmit right now.
+    #[cfg(feature = "medium-ethernet")]
+    pub(crate) fn send_arp(&mut self, repr: ArpRepr) -> bool {
+        let mac = self.arp_watch.mac;
+        let token = match self.device.transmit() {
+            Some(token) => token,
+            None => {
+                self.monitor.get_mut().send_exhausted();
+                return false;
+            }
+        };
+        let monitor = &self.monitor;
+
+        let len = EthernetFrame::<&[u8]>::header_len() + repr.buffer_len();
+        let mut sent = false;
+        token.consume(len, &mut |buf| {
+            let mut frame = EthernetFrame::new_unchecked(buf);
+            frame.set_src_addr(mac);
+            frame.set_dst_addr(EthernetAddress::BROADCAST);
+            frame.set_ethertype(EthernetProtocol::Arp);
+            repr.emit(&mut ArpPacket::new_unchecked(frame.payload_mut()));
+            monitor.borrow_mut().sent(frame.into_inner());
+            sent = true;
+        });
+        if !sent {
+            monitor.borrow_mut().send_dropped();
+        }
+        sent
+    }
+}
+
+#[cfg(feature = "medium-ethernet")]
+struct ArpWatch {
+    mac: EthernetAddress,
+    addr: Option<Ipv4Address>,
+    probing: bool,
+    conflict: bool,
+}
+
+#[cfg(feature = "medium-ethernet")]
+impl ArpWatch {
+    fn inspect(&mut self, pkt: &[u8]) {
+        let addr = match self.addr {
+            Some(addr) => addr,
+            None => return,
+        };
+        let frame = match EthernetFrame::new_checked(pkt) {
+            Ok(frame) if frame.ethertype() == EthernetProtocol::Arp => frame,
+            _ => return,
+        };
+        let repr = match ArpPacket::new_checked(frame.payload()).and_then(|p| ArpRepr::parse(&p)) {
+            Ok(repr) => repr,
+            Err(_) => return,
+        };
+
+        if let ArpRepr::EthernetIpv4 {
+            operation,
+            source_hardware_addr,
+            source_protocol_addr,
+            target_protocol_addr,
+            ..
+        } = repr
+        {
+            if source_hardware_addr == self.mac {
+                return;
+            }
+            let probe = operation == ArpOperation::Request
+                && source_protocol_addr.is_unspecified()
+                && target_protocol_addr == addr;
+            if source_protocol_addr == addr || (self.probing && probe) {
+                self.conflict = true;
+            }
         }
     }
 }
 
 impl<'a> SmolDevice<'a> for DeviceAdapter {
-    type RxToken = RxToken;
-    type TxToken = TxToken<'a>;
+    type RxToken = RxTokenAdapter<'a>;
+    type TxToken = TxTokenAdapter<'a>;
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        let rx_pkt = self.device.receive()?;
-        let tx_pkt = PacketBox::new(Packet::new()).unwrap(); // TODO: not sure about unwrap
-        let rx_token = RxToken { pkt: rx_pkt };
-        let tx_token = TxToken {
-            device: self.device,
-            pkt: tx_pkt,
+        let (rx, tx) = self.device.receive()?;
+
+        #[cfg(feature = "medium-ethernet")]
+        let arp_watch = if self.caps.medium == Medium::Ethernet {
+            Some(&mut self.arp_watch)
+        } else {
+            None
         };
 
+        let rx_token = RxTokenAdapter {
+            token: rx,
+            monitor: &self.monitor,
+            #[cfg(feature = "medium-ethernet")]
+            arp_watch,
+        };
+        let tx_token = TxTokenAdapter {
+            token: tx,
+            monitor: &self.monitor,
+        };
         Some((rx_token, tx_token))
     }
 
     /// Construct a transmit token.
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        if !self.device.is_transmit_ready() {
-            return None;
+        match self.device.transmit() {
+            Some(token) => Some(TxTokenAdapter {
+                token,
+                monitor: &self.monitor,
+            }),
+            None => {
+                self.monitor.get_mut().send_exhausted();
+                None
+            }
         }
-
-        let tx_pkt = PacketBox::new(Packet::new())?;
-        Some(TxToken {
-            device: self.device,
-            pkt: tx_pkt,
-        })
     }
 
     /// Get a description of device capabilities.
@@ -74,32 +313,70 @@ impl<'a> SmolDevice<'a> for DeviceAdapter {
     }
 }
 
-pub struct RxToken {
-    pkt: PacketBuf,
+pub struct RxTokenAdapter<'a> {
+    token: &'a mut dyn RxToken,
+    monitor: &'a RefCell<Monitor>,
+    #[cfg(feature = "medium-ethernet")]
+    arp_watch: Option<&'a mut ArpWatch>,
 }
 
-impl smoltcp::phy::RxToken for RxToken {
-    fn consume<R, F>(mut self, _timestamp: SmolInstant, f: F) -> Result<R>
+impl<'a> smoltcp::phy::RxToken for RxTokenAdapter<'a> {
+    fn consume<R, F>(self, _timestamp: SmolInstant, f: F) -> Result<R>
     where
         F: FnOnce(&mut [u8]) -> Result<R>,
     {
-        f(&mut self.pkt)
+        #[cfg(feature = "medium-ethernet")]
+        let mut arp_watch = self.arp_watch;
+
+        let monitor = self.monitor;
+        let mut f = Some(f);
+        let mut res = None;
+        self.token.consume(&mut |buf| {
+            #[cfg(feature = "medium-ethernet")]
+            if let Some(arp_watch) = arp_watch.as_mut() {
+                arp_watch.inspect(buf);
+            }
+            monitor.borrow_mut().received(buf);
+            if let Some(f) = f.take() {
+                res = Some(f(buf));
+            }
+        });
+        if let Some(Err(e)) = &res {
+            monitor.borrow_mut().receive_failed(*e);
+        }
+        res.unwrap_or(Err(Error::Exhausted))
     }
 }
 
-pub struct TxToken<'a> {
-    device: &'a mut dyn Device,
-    pkt: PacketBox,
+pub struct TxTokenAdapter<'a> {
+    token: &'a mut dyn TxToken,
+    monitor: &'a RefCell<Monitor>,
 }
 
-impl<'a> smoltcp::phy::TxToken for TxToken<'a> {
+impl<'a> smoltcp::phy::TxToken for TxTokenAdapter<'a> {
     fn consume<R, F>(self, _timestamp: SmolInstant, len: usize, f: F) -> Result<R>
     where
         F: FnOnce(&mut [u8]) -> Result<R>,
     {
-        let mut buf = self.pkt.slice(0..len);
-        let r = f(&mut buf)?;
-        self.device.transmit(buf);
-        Ok(r)
+        let monitor = self.monitor;
+        let mut f = Some(f);
+        let mut res = None;
+        self.token.consume(len, &mut |buf| {
+            if let Some(f) = f.take() {
+                let r = f(buf);
+                if r.is_ok() {
+                    monitor.borrow_mut().sent(buf);
+                }
+                res = Some(r);
+            }
+        });
+        match res {
+            Some(res) => res,
+            // The device had no room for the frame.
+            None => {
+                monitor.borrow_mut().send_dropped();
+                Err(Error::Exhausted)
+            }
+        }
     }
 }
@@ -0,0 +1,121 @@
+use super::*;
+
+/// Most headers that can be added to a request.
+const MAX_REQUEST_HEADERS: usize = 8;
+
+/// An HTTP request, built with [`Request::new`] and sent with [`Request::send`].
+pub struct Request<'a> {
+    method: Method,
+    host: &'a str,
+    path: &'a str,
+    headers: Vec<Header<'a>, U8>,
+    body: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    /// Create a request for `path` on `host`, sent in the Host header.
+    pub fn new(method: Method, host: &'a str, path: &'a str) -> Self {
+        Self {
+            method,
+            host,
+            path,
+            headers: Vec::new(),
+            body: &[],
+        }
+    }
+
+    pub fn get(host: &'a str, path: &'a str) -> Self {
+        Self::new(Method::Get, host, path)
+    }
+
+    pub fn post(host: &'a str, path: &'a str) -> Self {
+        Self::new(Method::Post, host, path)
+    }
+
+    /// Add a header. Up to 8 headers can be added.
+    pub fn header(mut self, name: &'a str, value: &'a str) -> Self {
+        if self.headers.push(Header { name, value }).is_err() {
+            panic!("more than {} request headers", MAX_REQUEST_HEADERS);
+        }
+        self
+    }
+
+    /// Set the body. Its length is sent in the Content-Length header.
+    pub fn body(mut self, body: &'a [u8]) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Send the request on `stream`, and read the head of the response into `head_buf`.
+    ///
+    /// The response body can then be read from [`Response::body`].
+    pub async fn send<'b, S: AsyncBufRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &'b mut S,
+        head_buf: &'b mut [u8],
+    ) -> Result<Response<'b, S>, HttpError> {
+        stream.write_all(self.method.as_str().as_bytes()).await?;
+        stream.write_all(b" ").await?;
+        stream.write_all(self.path.as_bytes()).await?;
+        stream.write_all(b" HTTP/1.1\r\n").await?;
+        write_header(stream, "Host", self.host).await?;
+        write_header(stream, "Connection", "close").await?;
+        if !self.body.is_empty() || self.method == Method::Post || self.method == Method::Put {
+            write_header(stream, "Content-Length", &format_usize(self.body.len(), 10)).await?;
+        }
+        for h in &self.headers {
+            write_header(stream, h.name, h.value).await?;
+        }
+        stream.write_all(b"\r\n").await?;
+        stream.write_all(self.body).await?;
+
+        let len = read_head(stream, head_buf).await?;
+        let head: &'b [u8] = head_buf;
+        let (status_line, headers) = parse_head(&head[..len])?;
+
+        // HTTP/1.1 200 OK
+        let mut parts = status_line.splitn(3, ' ');
+        if !parts.next().unwrap_or("").starts_with("HTTP/1.") {
+            return Err(HttpError::BadMessage);
+        }
+        let status: u16 = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or(HttpError::BadMessage)?;
+        let reason = parts.next().unwrap_or("");
+
+        // These responses never have a body, whatever the headers say.
+        let no_body =
+            self.method == Method::Head || status / 100 == 1 || status == 204 || status == 304;
+        let body = if no_body {
+            Body {
+                stream,
+                framing: Framing::Length(0),
+            }
+        } else {
+            Body::new(stream, &headers, Framing::UntilClose)?
+        };
+
+        Ok(Response {
+            status,
+            reason,
+            headers,
+            body,
+        })
+    }
+}
+
+/// A response to a [`Request`].
+pub struct Response<'b, S> {
+    pub status: u16,
+    pub reason: &'b str,
+    pub headers: Headers<'b>,
+    pub body: Body<'b, S>,
+}
+
+impl<'b, S> Response<'b, S> {
+    /// Find a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'b str> {
+        find_header(&self.headers, name)
+    }
+}
@@ -0,0 +1,343 @@
+//! Minimal HTTP/1.1 client and server.
+//!
+//! Both sides work over any stream implementing [`AsyncBufRead`] and [`AsyncWrite`],
+//! usually a [`TcpSocket`](crate::TcpSocket). Nothing is allocated: the request or
+//! response head is read into a buffer provided by the caller, and headers borrow from it.
+//! Bodies are streamed, as a [`Body`] implementing [`AsyncBufRead`].
+//!
+//! Connections aren't kept alive: one request is made per connection.
+
+use core::cmp::min;
+use core::fmt::Write as _;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use embassy::io::{self, AsyncBufRead, AsyncWrite, AsyncWriteExt};
+use futures::future::poll_fn;
+use futures::ready;
+use heapless::consts::*;
+use heapless::{String, Vec};
+
+use crate::fmt::*;
+
+mod client;
+mod server;
+
+pub use client::{Request, Response};
+pub use server::{read_request, ResponseWriter, Router, ServerRequest};
+
+/// Most headers kept from a request or response. Others are ignored.
+pub type Headers<'b> = Vec<Header<'b>, U16>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HttpError {
+    /// The underlying stream failed.
+    Io(io::Error),
+    /// The head of the request or response doesn't fit in the buffer.
+    HeadTooLarge,
+    /// The request or response isn't valid HTTP/1.x.
+    BadMessage,
+}
+
+impl From<io::Error> for HttpError {
+    fn from(err: io::Error) -> Self {
+        HttpError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Method> {
+        Some(match s {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header<'b> {
+    pub name: &'b str,
+    pub value: &'b str,
+}
+
+/// Find a header by name, ignoring case.
+pub fn find_header<'b>(headers: &[Header<'b>], name: &str) -> Option<&'b str> {
+    headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .map(|h| h.value)
+}
+
+/// How the end of a body is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// Content-Length, with the bytes left.
+    Length(usize),
+    Chunked(Chunk),
+    /// The body ends when the connection is closed.
+    UntilClose,
+}
+
+/// Where we are in a chunked body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chunk {
+    /// Reading the size line. `ext` is set once past the size, in the chunk extensions.
+    Size {
+        size: usize,
+        ext: bool,
+    },
+    /// Reading chunk data, with the bytes left.
+    Data(usize),
+    /// Reading the CRLF after the data.
+    DataEnd,
+    /// Reading the trailer, with the length of the current line.
+    Trailer(usize),
+    Done,
+}
+
+impl Chunk {
+    fn step(&mut self, b: u8) -> io::Result<()> {
+        *self = match (*self, b) {
+            (Chunk::Size { size, ext: false }, b) if (b as char).is_ascii_hexdigit() => {
+                let digit = (b as char).to_digit(16).unwrap() as usize;
+                let size = size
+                    .checked_mul(16)
+                    .and_then(|s| s.checked_add(digit))
+                    .ok_or(io::Error::InvalidData)?;
+                Chunk::Size { size, ext: false }
+            }
+            (Chunk::Size { size, .. }, b';') => Chunk::Size { size, ext: true },
+            (Chunk::Size { size: 0, .. }, b'\n') => Chunk::Trailer(0),
+            (Chunk::Size { size, .. }, b'\n') => Chunk::Data(size),
+            (Chunk::Size { .. }, b'\r') | (Chunk::Size { ext: true, .. }, _) => *self,
+            (Chunk::Size { .. }, b' ') | (Chunk::Size { .. }, b'\t') => *self,
+            (Chunk::DataEnd, b'\r') => *self,
+            (Chunk::DataEnd, b'\n') => Chunk::Size {
+                size: 0,
+                ext: false,
+            },
+            (Chunk::Trailer(_), b'\r') => *self,
+            (Chunk::Trailer(0), b'\n') => Chunk::Done,
+            (Chunk::Trailer(_), b'\n') => Chunk::Trailer(0),
+            (Chunk::Trailer(len), _) => Chunk::Trailer(len + 1),
+            _ => return Err(io::Error::InvalidData),
+        };
+        Ok(())
+    }
+}
+
+/// The body of a request or response, read from the stream as it arrives.
+pub struct Body<'b, S> {
+    stream: &'b mut S,
+    framing: Framing,
+}
+
+impl<'b, S: AsyncBufRead + AsyncWrite + Unpin> Body<'b, S> {
+    fn new(stream: &'b mut S, headers: &[Header<'_>], default: Framing) -> Result<Self, HttpError> {
+        let chunked = find_header(headers, "transfer-encoding")
+            .and_then(|v| v.rsplit(',').next())
+            .map(|v| v.trim().eq_ignore_ascii_case("chunked"))
+            .unwrap_or(false);
+        let framing = if chunked {
+            Framing::Chunked(Chunk::Size {
+                size: 0,
+                ext: false,
+            })
+        } else if let Some(len) = find_header(headers, "content-length") {
+            Framing::Length(len.parse().map_err(|_| HttpError::BadMessage)?)
+        } else {
+            default
+        };
+        Ok(Self { stream, framing })
+    }
+
+    /// The length of the body, if it was announced with Content-Length.
+    pub fn content_length(&self) -> Option<usize> {
+        match self.framing {
+            Framing::Length(len) => Some(len),
+            _ => None,
+        }
+    }
+
+    /// Read and discard the rest of the body.
+    pub async fn skip(&mut self) -> Result<(), HttpError> {
+        loop {
+            let n = poll_fn(|cx| match Pin::new(&mut *self).poll_fill_buf(cx) {
+                Poll::Ready(Ok(buf)) => Poll::Ready(Ok(buf.len())),
+                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+                Poll::Pending => Poll::Pending,
+            })
+            .await?;
+            if n == 0 {
+                return Ok(());
+            }
+            Pin::new(&mut *self).consume(n);
+        }
+    }
+}
+
+impl<'b, S: AsyncBufRead + AsyncWrite + Unpin> AsyncBufRead for Body<'b, S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        loop {
+            match &mut this.framing {
+                Framing::Length(0) | Framing::Chunked(Chunk::Done) => return Poll::Ready(Ok(&[])),
+                Framing::Length(n) | Framing::Chunked(Chunk::Data(n)) => {
+                    let n = *n;
+                    let buf = ready!(Pin::new(&mut *this.stream).poll_fill_buf(cx))?;
+                    if buf.is_empty() {
+                        return Poll::Ready(Err(io::Error::UnexpectedEof));
+                    }
+                    return Poll::Ready(Ok(&buf[..min(n, buf.len())]));
+                }
+                Framing::UntilClose => return Pin::new(&mut *this.stream).poll_fill_buf(cx),
+                Framing::Chunked(chunk) => {
+                    let buf = ready!(Pin::new(&mut *this.stream).poll_fill_buf(cx))?;
+                    if buf.is_empty() {
+                        return Poll::Ready(Err(io::Error::UnexpectedEof));
+                    }
+                    let mut used = 0;
+                    for &b in buf {
+                        used += 1;
+                        chunk.step(b)?;
+                        if let Chunk::Data(_) | Chunk::Done = chunk {
+                            break;
+                        }
+                    }
+                    Pin::new(&mut *this.stream).consume(used);
+                }
+            }
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        match &mut this.framing {
+            Framing::Length(n) => *n -= amt,
+            Framing::Chunked(chunk) => {
+                if let Chunk::Data(n) = chunk {
+                    *n -= amt;
+                    if *n == 0 {
+                        *chunk = Chunk::DataEnd;
+                    }
+                }
+            }
+            Framing::UntilClose => {}
+        }
+        Pin::new(&mut *this.stream).consume(amt)
+    }
+}
+
+/// Read a request or response head, up to and including the empty line, into `buf`.
+///
+/// Returns the length of the head. Nothing after it is consumed from the stream.
+async fn read_head<S: AsyncBufRead + Unpin>(
+    stream: &mut S,
+    buf: &mut [u8],
+) -> Result<usize, HttpError> {
+    let mut len = 0;
+    loop {
+        let done = poll_fn(|cx| {
+            let data = ready!(Pin::new(&mut *stream).poll_fill_buf(cx))?;
+            if data.is_empty() {
+                return Poll::Ready(Err(HttpError::Io(io::Error::UnexpectedEof)));
+            }
+            let n = min(data.len(), buf.len() - len);
+            if n == 0 {
+                return Poll::Ready(Err(HttpError::HeadTooLarge));
+            }
+            buf[len..len + n].copy_from_slice(&data[..n]);
+
+            // The terminator may straddle what we had and what we got.
+            let from = len.saturating_sub(3);
+            let end = buf[from..len + n]
+                .windows(4)
+                .position(|w| w == b"\r\n\r\n")
+                .map(|i| from + i + 4);
+            let used = match end {
+                Some(end) => end - len,
+                None => n,
+            };
+            Pin::new(&mut *stream).consume(used);
+            len += used;
+            Poll::Ready(Ok(end.is_some()))
+        })
+        .await?;
+        if done {
+            return Ok(len);
+        }
+    }
+}
+
+/// Split a head into its start line and headers.
+fn parse_head(head: &[u8]) -> Result<(&str, Headers<'_>), HttpError> {
+    let head = core::str::from_utf8(head).map_err(|_| HttpError::BadMessage)?;
+    let mut lines = head.split("\r\n");
+    let start = lines.next().ok_or(HttpError::BadMessage)?;
+    let mut headers = Vec::new();
+    for line in lines.filter(|l| !l.is_empty()) {
+        let colon = line.find(':').ok_or(HttpError::BadMessage)?;
+        let header = Header {
+            name: line[..colon].trim(),
+            value: line[colon + 1..].trim(),
+        };
+        if headers.push(header).is_err() {
+            warn!("too many HTTP headers, ignoring some");
+        }
+    }
+    Ok((start, headers))
+}
+
+/// Write a header line.
+async fn write_header<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    name: &str,
+    value: &str,
+) -> Result<(), HttpError> {
+    stream.write_all(name.as_bytes()).await?;
+    stream.write_all(b": ").await?;
+    stream.write_all(value.as_bytes()).await?;
+    stream.write_all(b"\r\n").await?;
+    Ok(())
+}
+
+fn format_usize(n: usize, radix: u32) -> String<U20> {
+    let mut s = String::new();
+    // 20 digits are enough for any usize.
+    let _ = match radix {
+        16 => write!(s, "{:x}", n),
+        _ => write!(s, "{}", n),
+    };
+    s
+}
@@ -0,0 +1,199 @@
+use super::*;
+
+/// Maps request paths to routes, which are values chosen by the application.
+///
+/// Patterns match a path exactly, or as a prefix if they end with `*`. The query string
+/// isn't part of the path. Routes are tried in order.
+///
+/// ```ignore
+/// #[derive(Clone, Copy)]
+/// enum Page { Index, Firmware }
+///
+/// static ROUTER: Router<Page> = Router::new(&[
+///     (Method::Get, "/", Page::Index),
+///     (Method::Post, "/firmware/*", Page::Firmware),
+/// ]);
+/// ```
+pub struct Router<'r, T> {
+    routes: &'r [(Method, &'r str, T)],
+}
+
+impl<'r, T> Router<'r, T> {
+    pub const fn new(routes: &'r [(Method, &'r str, T)]) -> Self {
+        Self { routes }
+    }
+}
+
+impl<'r, T: Copy> Router<'r, T> {
+    /// Find the route for a request.
+    pub fn route(&self, method: Method, path: &str) -> Option<T> {
+        self.routes
+            .iter()
+            .find(|(m, pattern, _)| {
+                // GET routes serve HEAD requests too.
+                let method_matches = *m == method || (*m == Method::Get && method == Method::Head);
+                let path_matches = match pattern.strip_suffix('*') {
+                    Some(prefix) => path.starts_with(prefix),
+                    None => path == *pattern,
+                };
+                method_matches && path_matches
+            })
+            .map(|(_, _, route)| *route)
+    }
+}
+
+/// A request received by a server, returned by [`read_request`].
+pub struct ServerRequest<'b, S> {
+    pub method: Method,
+    pub path: &'b str,
+    pub query: Option<&'b str>,
+    pub headers: Headers<'b>,
+    pub body: Body<'b, S>,
+}
+
+/// Read a request from `stream`, usually a [`TcpSocket`](crate::TcpSocket) returned by
+/// [`TcpSocket::accept`](crate::TcpSocket::accept). Its head is read into `head_buf`.
+pub async fn read_request<'b, S: AsyncBufRead + AsyncWrite + Unpin>(
+    stream: &'b mut S,
+    head_buf: &'b mut [u8],
+) -> Result<ServerRequest<'b, S>, HttpError> {
+    let len = read_head(stream, head_buf).await?;
+    let head: &'b [u8] = head_buf;
+    let (request_line, headers) = parse_head(&head[..len])?;
+
+    // GET /path?query HTTP/1.1
+    let mut parts = request_line.split(' ');
+    let method = parts
+        .next()
+        .and_then(Method::parse)
+        .ok_or(HttpError::BadMessage)?;
+    let target = parts.next().ok_or(HttpError::BadMessage)?;
+    if !parts.next().unwrap_or("").starts_with("HTTP/1.") {
+        return Err(HttpError::BadMessage);
+    }
+    let (path, query) = match target.find('?') {
+        Some(i) => (&target[..i], Some(&target[i + 1..])),
+        None => (target, None),
+    };
+
+    let body = Body::new(stream, &headers, Framing::Length(0))?;
+    Ok(ServerRequest {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    })
+}
+
+impl<'b, S: AsyncBufRead + AsyncWrite + Unpin> ServerRequest<'b, S> {
+    /// Find a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'b str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Send a complete response.
+    pub async fn respond(
+        self,
+        status: u16,
+        content_type: &str,
+        body: &[u8],
+    ) -> Result<(), HttpError> {
+        let mut writer = self
+            .start_response(status, &[("Content-Type", content_type)], Some(body.len()))
+            .await?;
+        writer.write(body).await?;
+        writer.finish().await
+    }
+
+    /// Send the response head. The body is then written with the returned writer.
+    ///
+    /// Without a `content_length`, the body is sent with chunked encoding.
+    pub async fn start_response(
+        self,
+        status: u16,
+        headers: &[(&str, &str)],
+        content_length: Option<usize>,
+    ) -> Result<ResponseWriter<'b, S>, HttpError> {
+        let head_only = self.method == Method::Head;
+        let stream = self.body.stream;
+
+        stream.write_all(b"HTTP/1.1 ").await?;
+        stream
+            .write_all(format_usize(status as usize, 10).as_bytes())
+            .await?;
+        stream.write_all(b" ").await?;
+        stream.write_all(reason(status).as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+        write_header(stream, "Connection", "close").await?;
+        match content_length {
+            Some(len) => write_header(stream, "Content-Length", &format_usize(len, 10)).await?,
+            None => write_header(stream, "Transfer-Encoding", "chunked").await?,
+        }
+        for (name, value) in headers {
+            write_header(stream, name, value).await?;
+        }
+        stream.write_all(b"\r\n").await?;
+
+        Ok(ResponseWriter {
+            stream,
+            chunked: content_length.is_none(),
+            head_only,
+        })
+    }
+}
+
+/// Writes the body of a response, returned by [`ServerRequest::start_response`].
+pub struct ResponseWriter<'b, S> {
+    stream: &'b mut S,
+    chunked: bool,
+    /// The request was HEAD: the body isn't sent.
+    head_only: bool,
+}
+
+impl<'b, S: AsyncBufRead + AsyncWrite + Unpin> ResponseWriter<'b, S> {
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), HttpError> {
+        if self.head_only || data.is_empty() {
+            return Ok(());
+        }
+        if self.chunked {
+            self.stream
+                .write_all(format_usize(data.len(), 16).as_bytes())
+                .await?;
+            self.stream.write_all(b"\r\n").await?;
+        }
+        self.stream.write_all(data).await?;
+        if self.chunked {
+            self.stream.write_all(b"\r\n").await?;
+        }
+        Ok(())
+    }
+
+    /// End the body.
+    pub async fn finish(self) -> Result<(), HttpError> {
+        if self.chunked && !self.head_only {
+            self.stream.write_all(b"0\r\n\r\n").await?;
+        }
+        Ok(())
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
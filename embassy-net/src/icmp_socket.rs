@@ -0,0 +1,192 @@
+use core::mem;
+use core::task::Poll;
+use embassy::time::{with_timeout, Duration, Instant};
+use smoltcp::socket::IcmpSocket as SyncIcmpSocket;
+use smoltcp::socket::SocketHandle;
+use smoltcp::socket::{IcmpEndpoint, IcmpPacketMetadata, IcmpSocketBuffer};
+use smoltcp::wire::{Icmpv4Message, Icmpv4Packet, IpAddress};
+
+use super::stack::{rand, Inner, InterfaceId, Stack};
+use crate::fmt::*;
+use crate::Error;
+
+/// Length of the ICMP echo header, before the payload.
+const ECHO_HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PingError {
+    /// No interface can reach the address.
+    NoRoute,
+    /// The interface that can reach the address has no room for another socket.
+    Exhausted,
+    /// The request doesn't fit in the socket buffers.
+    TooLarge,
+    /// No reply arrived in time.
+    Timeout,
+}
+
+/// An ICMP socket, sending echo requests and receiving the replies.
+pub struct IcmpSocket<'a> {
+    stack: &'a Stack,
+    iface: InterfaceId,
+    handle: SocketHandle,
+    ident: u16,
+    seq_no: u16,
+}
+
+impl<'a> Unpin for IcmpSocket<'a> {}
+
+impl<'a> IcmpSocket<'a> {
+    /// Create a socket. Each metadata entry holds the addressing of one packet, and the
+    /// payload buffers hold their data. A buffer needs room for the payload of a ping,
+    /// plus 8 bytes.
+    pub fn new(
+        stack: &'a Stack,
+        rx_meta: &'a mut [IcmpPacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [IcmpPacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Self {
+        let mut ident = [0; 2];
+        rand(&mut ident);
+        let ident = u16::from_le_bytes(ident);
+
+        let iface = InterfaceId::FIRST;
+        let handle = stack.with(|inner| {
+            let rx_meta: &'static mut [IcmpPacketMetadata] = unsafe { mem::transmute(rx_meta) };
+            let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
+            let tx_meta: &'static mut [IcmpPacketMetadata] = unsafe { mem::transmute(tx_meta) };
+            let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
+            let mut socket = SyncIcmpSocket::new(
+                IcmpSocketBuffer::new(rx_meta, rx_buffer),
+                IcmpSocketBuffer::new(tx_meta, tx_buffer),
+            );
+            // Only echo replies carrying our identifier are received.
+            unwrap!(socket.bind(IcmpEndpoint::Ident(ident)));
+            inner.sockets(iface).add(socket)
+        });
+
+        Self {
+            stack,
+            iface,
+            handle,
+            ident,
+            seq_no: 0,
+        }
+    }
+
+    /// Send an echo request to `addr`, with `payload_len` bytes of payload, and wait for the
+    /// reply. Returns the round-trip time.
+    ///
+    /// Fails with `PingError::Timeout` if the request couldn't be sent and answered within
+    /// `timeout`.
+    pub async fn ping(
+        &mut self,
+        addr: IpAddress,
+        payload_len: usize,
+        timeout: Duration,
+    ) -> Result<Duration, PingError> {
+        let stack = self.stack;
+        stack.with(|inner| {
+            let iface = inner.route(addr).ok_or(PingError::NoRoute)?;
+            self.move_to(inner, iface).map_err(|_| PingError::Exhausted)
+        })?;
+
+        // Replies to earlier pings that timed out are dropped, since their sequence
+        // number doesn't match.
+        self.seq_no = self.seq_no.wrapping_add(1);
+        let seq_no = self.seq_no;
+        let ident = self.ident;
+
+        // The timeout also covers waiting for room in the tx buffer, since it may never
+        // drain if the link is down.
+        let exchange = async {
+            futures::future::poll_fn(|cx| {
+                self.with(|s| match s.send(ECHO_HEADER_LEN + payload_len, addr) {
+                    Ok(buf) => {
+                        let mut packet = Icmpv4Packet::new_unchecked(buf);
+                        packet.set_msg_type(Icmpv4Message::EchoRequest);
+                        packet.set_msg_code(0);
+                        packet.set_echo_ident(ident);
+                        packet.set_echo_seq_no(seq_no);
+                        for (i, b) in packet.data_mut().iter_mut().enumerate() {
+                            *b = i as u8;
+                        }
+                        packet.fill_checksum();
+                        Poll::Ready(Ok(()))
+                    }
+                    // No space in the tx buffer
+                    Err(Error::Exhausted) => {
+                        s.register_send_waker(cx.waker());
+                        Poll::Pending
+                    }
+                    Err(_) => Poll::Ready(Err(PingError::TooLarge)),
+                })
+            })
+            .await?;
+            let sent_at = Instant::now();
+
+            futures::future::poll_fn(|cx| {
+                self.with(|s| loop {
+                    let (data, from) = match s.recv() {
+                        Ok(res) => res,
+                        // Nothing received yet
+                        Err(_) => {
+                            s.register_recv_waker(cx.waker());
+                            return Poll::Pending;
+                        }
+                    };
+                    if from == addr && is_reply(data, ident, seq_no, payload_len) {
+                        return Poll::Ready(());
+                    }
+                })
+            })
+            .await;
+            Ok::<_, PingError>(Instant::now().saturating_duration_since(sent_at))
+        };
+        match with_timeout(timeout, exchange).await {
+            Ok(res) => res,
+            Err(_) => Err(PingError::Timeout),
+        }
+    }
+
+    /// Move the socket to `iface`, or leave it where it was if `iface` has no room for it.
+    fn move_to(&mut self, inner: &mut Inner, iface: InterfaceId) -> Result<(), Error> {
+        self.handle = inner.move_socket(self.iface, self.handle, iface)?;
+        self.iface = iface;
+        Ok(())
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut SyncIcmpSocket) -> R) -> R {
+        self.stack.with(|inner| {
+            let res = {
+                let mut s = inner.sockets(self.iface).get::<SyncIcmpSocket>(self.handle);
+                f(&mut *s)
+            };
+            inner.wake();
+            res
+        })
+    }
+}
+
+/// Check that `data` is the reply to our echo request.
+fn is_reply(data: &[u8], ident: u16, seq_no: u16, payload_len: usize) -> bool {
+    match Icmpv4Packet::new_checked(data) {
+        Ok(packet) => {
+            packet.msg_type() == Icmpv4Message::EchoReply
+                && packet.echo_ident() == ident
+                && packet.echo_seq_no() == seq_no
+                && packet.data().len() == payload_len
+        }
+        Err(_) => false,
+    }
+}
+
+impl<'a> Drop for IcmpSocket<'a> {
+    fn drop(&mut self) {
+        self.stack.with(|inner| {
+            inner.sockets(self.iface).remove(self.handle);
+        })
+    }
+}
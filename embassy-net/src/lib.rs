@@ -1,25 +1,66 @@
 #![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "tls", feature(generic_associated_types))]
+#![allow(incomplete_features)]
 
 // This mod MUST go first, so that the others see its macros.
 pub(crate) mod fmt;
 
+#[cfg(any(feature = "ppp", feature = "slip"))]
+mod channel;
 mod config;
 mod device;
-mod packet_pool;
+mod loopback;
+mod packet_queue;
+pub mod pcap;
 mod stack;
 
 #[cfg(feature = "dhcpv4")]
 pub use config::DhcpConfigurator;
-pub use config::{Config, Configurator, Event as ConfigEvent, StaticConfigurator};
+#[cfg(feature = "medium-ethernet")]
+pub use config::LinkLocalConfigurator;
+pub use config::{
+    Config, Configurator, DhcpInfo, DhcpOption, Event as ConfigEvent, FallbackConfigurator,
+    StaticConfigControl, StaticConfigurator,
+};
 
-pub use device::{Device, LinkState};
-pub use packet_pool::{Packet, PacketBox, PacketBoxExt, PacketBuf};
-pub use stack::{init, is_config_up, is_init, is_link_up, run};
+pub use device::{Device, InterfaceStats, LinkState, RxToken, TxToken};
+pub use loopback::{Loopback, LoopbackDevice};
+pub use packet_queue::PacketQueue;
+pub use stack::{ConfigEvents, InterfaceId, Stack, StackResources};
 
 #[cfg(feature = "tcp")]
 mod tcp_socket;
 #[cfg(feature = "tcp")]
-pub use tcp_socket::TcpSocket;
+pub use tcp_socket::{CloseReason, TcpReader, TcpSocket, TcpWriter};
+
+#[cfg(feature = "icmp")]
+mod icmp_socket;
+#[cfg(feature = "icmp")]
+pub use icmp_socket::{IcmpSocket, PingError};
+#[cfg(feature = "icmp")]
+pub use smoltcp::socket::IcmpPacketMetadata;
+
+#[cfg(feature = "udp")]
+mod udp_socket;
+#[cfg(feature = "udp")]
+pub use smoltcp::socket::UdpPacketMetadata;
+#[cfg(feature = "udp")]
+pub use udp_socket::UdpSocket;
+
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "ppp")]
+pub mod ppp;
+#[cfg(feature = "slip")]
+pub mod slip;
+#[cfg(feature = "sntp")]
+pub mod sntp;
+#[cfg(feature = "tls")]
+pub mod tls;
 
 // smoltcp reexports
 pub use smoltcp::phy::{DeviceCapabilities, Medium};
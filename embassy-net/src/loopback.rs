@@ -0,0 +1,135 @@
+use core::cell::RefCell;
+use core::task::Waker;
+use embassy::util::WakerRegistration;
+use smoltcp::phy::{DeviceCapabilities, Medium};
+
+use crate::device::{Device, LinkState, RxToken, TxToken};
+use crate::packet_queue::PacketQueue;
+
+/// Two devices connected to each other, passing up to `N` frames of up to `MTU` bytes in
+/// each direction through memory.
+///
+/// Each side is given to its own [`Stack`](crate::Stack), so that two stacks can talk in
+/// one process, for example a TCP client and server in a host test.
+pub struct Loopback<const N: usize, const MTU: usize> {
+    /// The frames sent by each side.
+    queues: [RefCell<PacketQueue<N, MTU>>; 2],
+    /// The stack of each side.
+    wakers: [RefCell<WakerRegistration>; 2],
+}
+
+impl<const N: usize, const MTU: usize> Loopback<N, MTU> {
+    pub const fn new() -> Self {
+        Self {
+            queues: [
+                RefCell::new(PacketQueue::new()),
+                RefCell::new(PacketQueue::new()),
+            ],
+            wakers: [
+                RefCell::new(WakerRegistration::new()),
+                RefCell::new(WakerRegistration::new()),
+            ],
+        }
+    }
+
+    /// Both sides, as devices carrying frames of `medium`. This is called once.
+    ///
+    /// With [`Medium::Ethernet`], the sides have the addresses 02:00:00:00:00:01 and
+    /// 02:00:00:00:00:02.
+    pub fn devices(
+        &self,
+        medium: Medium,
+    ) -> (LoopbackDevice<'_, N, MTU>, LoopbackDevice<'_, N, MTU>) {
+        (self.device(0, medium), self.device(1, medium))
+    }
+
+    fn device(&self, side: usize, medium: Medium) -> LoopbackDevice<'_, N, MTU> {
+        LoopbackDevice {
+            rx: LoopbackRx {
+                loopback: self,
+                side,
+            },
+            tx: LoopbackTx {
+                loopback: self,
+                side,
+            },
+            medium,
+            ethernet_address: [0x02, 0, 0, 0, 0, side as u8 + 1],
+        }
+    }
+}
+
+/// One side of a [`Loopback`].
+pub struct LoopbackDevice<'a, const N: usize, const MTU: usize> {
+    rx: LoopbackRx<'a, N, MTU>,
+    tx: LoopbackTx<'a, N, MTU>,
+    medium: Medium,
+    ethernet_address: [u8; 6],
+}
+
+struct LoopbackRx<'a, const N: usize, const MTU: usize> {
+    loopback: &'a Loopback<N, MTU>,
+    side: usize,
+}
+
+impl<'a, const N: usize, const MTU: usize> RxToken for LoopbackRx<'a, N, MTU> {
+    fn consume(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        let other = 1 - self.side;
+        self.loopback.queues[other].borrow_mut().consume(f);
+        // The other side may be waiting for room to send.
+        self.loopback.wakers[other].borrow_mut().wake();
+    }
+}
+
+struct LoopbackTx<'a, const N: usize, const MTU: usize> {
+    loopback: &'a Loopback<N, MTU>,
+    side: usize,
+}
+
+impl<'a, const N: usize, const MTU: usize> TxToken for LoopbackTx<'a, N, MTU> {
+    fn consume(&mut self, len: usize, f: &mut dyn FnMut(&mut [u8])) {
+        TxToken::consume(&mut *self.loopback.queues[self.side].borrow_mut(), len, f);
+        self.loopback.wakers[1 - self.side].borrow_mut().wake();
+    }
+}
+
+impl<'a, const N: usize, const MTU: usize> Device for LoopbackDevice<'a, N, MTU> {
+    fn receive(&mut self) -> Option<(&mut dyn RxToken, &mut dyn TxToken)> {
+        let other = 1 - self.rx.side;
+        if self.rx.loopback.queues[other].borrow().is_empty() {
+            return None;
+        }
+        let rx: &mut dyn RxToken = &mut self.rx;
+        let tx: &mut dyn TxToken = &mut self.tx;
+        Some((rx, tx))
+    }
+
+    fn transmit(&mut self) -> Option<&mut dyn TxToken> {
+        if self.tx.loopback.queues[self.tx.side].borrow().is_full() {
+            return None;
+        }
+        let tx: &mut dyn TxToken = &mut self.tx;
+        Some(tx)
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.rx.loopback.wakers[self.rx.side]
+            .borrow_mut()
+            .register(waker);
+    }
+
+    fn capabilities(&mut self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = MTU;
+        caps.medium = self.medium;
+        caps
+    }
+
+    fn link_state(&mut self) -> LinkState {
+        LinkState::Up
+    }
+
+    fn ethernet_address(&mut self) -> [u8; 6] {
+        self.ethernet_address
+    }
+}
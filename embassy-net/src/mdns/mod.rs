@@ -0,0 +1,646 @@
+//! mDNS responder (RFC 6762), with DNS-SD service advertisement (RFC 6763).
+//!
+//! [`Mdns::run`] makes the device reachable as `<hostname>.local`, and advertises its
+//! services so they can be browsed, for example with `avahi-browse -r _http._tcp`.
+//!
+//! Each time the interface is configured, the names are first probed to make sure no
+//! other device uses them, then announced. If another device answers a probe, the names
+//! get a number appended (`device-1234-2.local`), and are probed again.
+//!
+//! Records are answered in full every time: known answers listed in queries aren't
+//! suppressed.
+
+mod packet;
+
+use embassy::time::{with_timeout, Duration, Instant, Timer};
+use futures::future::{select, Either};
+use futures::{pin_mut, StreamExt};
+use smoltcp::wire::IpEndpoint;
+
+use packet::*;
+
+use crate::fmt::*;
+use crate::stack::rand;
+use crate::{
+    ConfigEvents, InterfaceId, IpAddress, Ipv4Address, Ipv4Cidr, Result, Stack, UdpPacketMetadata,
+    UdpSocket,
+};
+
+const MDNS_ADDR: Ipv4Address = Ipv4Address([224, 0, 0, 251]);
+const MDNS_PORT: u16 = 5353;
+
+/// Largest message sent or received. Longer incoming ones are ignored.
+const PACKET_LEN: usize = 1024;
+
+/// Most services a responder can advertise.
+pub const MAX_SERVICES: usize = 7;
+
+/// TTL of records naming the host, in seconds.
+const HOST_TTL: u32 = 120;
+/// TTL of the other records, in seconds.
+const OTHER_TTL: u32 = 4500;
+/// TTL cap for replies to legacy unicast queries (RFC 6762, section 6.7).
+const LEGACY_TTL: u32 = 10;
+
+const PROBE_WAIT: Duration = Duration::from_millis(250);
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+const PROBE_COUNT: usize = 3;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+const ANNOUNCE_COUNT: usize = 2;
+
+/// Replies with shared records are delayed by this much, plus up to `REPLY_JITTER`, so
+/// replies from several devices don't collide.
+const REPLY_DELAY: Duration = Duration::from_millis(20);
+const REPLY_JITTER: Duration = Duration::from_millis(100);
+
+/// A service advertised with DNS-SD.
+pub struct Service<'a> {
+    /// The instance name shown to users, such as `Living room sensor`.
+    pub instance: &'a str,
+    /// The service type, such as `_http._tcp`.
+    pub service_type: &'a str,
+    pub port: u16,
+    /// Entries of the TXT record, such as `path=/`.
+    pub txt: &'a [&'a str],
+}
+
+/// Settings for [`Mdns`].
+pub struct MdnsConfig<'a> {
+    /// The host name, without `.local`.
+    pub hostname: &'a str,
+    /// Services to advertise, at most [`MAX_SERVICES`].
+    pub services: &'a [Service<'a>],
+    /// The interface to respond on.
+    pub interface: InterfaceId,
+}
+
+/// mDNS responder for a host name and its services.
+pub struct Mdns<'a> {
+    stack: &'a Stack,
+    config: MdnsConfig<'a>,
+}
+
+impl<'a> Mdns<'a> {
+    pub fn new(stack: &'a Stack, config: MdnsConfig<'a>) -> Self {
+        assert!(config.services.len() <= MAX_SERVICES, "too many services");
+        Self { stack, config }
+    }
+
+    /// Answer queries on the interface. This must be called in a task for the names to
+    /// resolve.
+    pub async fn run(&self) -> ! {
+        let iface = self.config.interface;
+
+        let mut rx_meta = [UdpPacketMetadata::EMPTY; 4];
+        let mut rx_buffer = [0; 2 * PACKET_LEN];
+        let mut tx_meta = [UdpPacketMetadata::EMPTY; 2];
+        let mut tx_buffer = [0; 2 * PACKET_LEN];
+        let mut socket = UdpSocket::new(
+            self.stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        unwrap!(socket.bind_interface(iface));
+        unwrap!(socket.bind(MDNS_PORT));
+
+        let mut rx = [0; PACKET_LEN];
+        let mut tx = [0; PACKET_LEN];
+        let mut events = self.stack.config_events();
+        let mut number = 1;
+
+        loop {
+            let cidr = match self.stack.interface_config(iface) {
+                Some(config) => config.address,
+                None => {
+                    config_changed(&mut events, iface).await;
+                    continue;
+                }
+            };
+            if let Err(e) = self.stack.join_multicast_group(iface, MDNS_ADDR) {
+                warn!("mDNS: failed to join multicast group: {:?}", e);
+            }
+
+            let mut responder = Responder {
+                config: &self.config,
+                cidr,
+                number,
+            };
+            while responder.probe(&mut socket, &mut rx, &mut tx).await {
+                responder.number = responder.number.saturating_add(1);
+                warn!("mDNS: name conflict, trying number {}", responder.number);
+            }
+            number = responder.number;
+            info!("mDNS: responding on interface {}", iface.index());
+
+            let mut announcements = 0;
+            let mut next_announce = Instant::now();
+            loop {
+                let wake = {
+                    let changed = config_changed(&mut events, iface);
+                    let recv = socket.recv_from(&mut rx);
+                    let announce = Timer::at(next_announce);
+                    pin_mut!(changed);
+                    pin_mut!(recv);
+                    pin_mut!(announce);
+                    match select(select(changed, recv), announce).await {
+                        Either::Left((Either::Left(_), _)) => Wake::Changed,
+                        Either::Left((Either::Right((res, _)), _)) => Wake::Received(res),
+                        Either::Right(_) => Wake::Announce,
+                    }
+                };
+
+                match wake {
+                    Wake::Changed => break,
+                    Wake::Announce => {
+                        match responder.announcement(&mut tx) {
+                            Ok(msg) => send(&mut socket, msg, (MDNS_ADDR, MDNS_PORT)).await,
+                            Err(e) => warn!("mDNS: failed to build announcement: {:?}", e),
+                        }
+                        announcements += 1;
+                        next_announce = if announcements < ANNOUNCE_COUNT {
+                            Instant::now() + ANNOUNCE_INTERVAL
+                        } else {
+                            Instant::MAX
+                        };
+                    }
+                    Wake::Received(Ok((len, from))) => {
+                        let msg = &rx[..len];
+                        if responder.is_conflict(msg).unwrap_or(false) {
+                            warn!("mDNS: another device took our name");
+                            number = number.saturating_add(1);
+                            break;
+                        }
+                        match responder.reply(msg, from, &mut tx) {
+                            Ok(Some(reply)) => {
+                                if reply.delay {
+                                    Timer::after(REPLY_DELAY + random_duration(REPLY_JITTER)).await;
+                                }
+                                send(&mut socket, &tx[..reply.len], reply.to).await;
+                            }
+                            Ok(None) => {}
+                            Err(e) => debug!("mDNS: bad query: {:?}", e),
+                        }
+                    }
+                    Wake::Received(Err(e)) => warn!("mDNS: receive error: {:?}", e),
+                }
+            }
+        }
+    }
+}
+
+enum Wake {
+    Changed,
+    Received(Result<(usize, IpEndpoint)>),
+    Announce,
+}
+
+/// Wait for a configuration change on the interface.
+async fn config_changed(events: &mut ConfigEvents<'_>, iface: InterfaceId) {
+    loop {
+        if let Some((id, _)) = events.next().await {
+            if id == iface {
+                return;
+            }
+        }
+    }
+}
+
+async fn send(socket: &mut UdpSocket<'_>, msg: &[u8], to: impl Into<IpEndpoint>) {
+    if let Err(e) = socket.send_to(msg, to).await {
+        warn!("mDNS: send failed: {:?}", e);
+    }
+}
+
+/// A random duration between zero and `max`.
+fn random_duration(max: Duration) -> Duration {
+    let mut b = [0; 4];
+    rand(&mut b);
+    Duration::from_millis(u32::from_le_bytes(b) as u64 % (max.as_millis() + 1))
+}
+
+/// One of the records we own.
+#[derive(Clone, Copy)]
+enum Record {
+    /// The address of the host.
+    Host,
+    /// Points `_services._dns-sd._udp.local` to the type of a service.
+    ServiceType(usize),
+    /// Points the type of a service to its instance.
+    Ptr(usize),
+    /// The host and port of a service instance.
+    Srv(usize),
+    /// The attributes of a service instance.
+    Txt(usize),
+}
+
+impl Record {
+    fn bit(self) -> u32 {
+        match self {
+            Record::Host => 1,
+            Record::ServiceType(i) => 1 << (1 + 4 * i),
+            Record::Ptr(i) => 1 << (2 + 4 * i),
+            Record::Srv(i) => 1 << (3 + 4 * i),
+            Record::Txt(i) => 1 << (4 + 4 * i),
+        }
+    }
+
+    /// Shared records can be owned by several devices, so they don't flush caches.
+    fn is_shared(self) -> bool {
+        matches!(self, Record::ServiceType(_) | Record::Ptr(_))
+    }
+}
+
+/// A set of [`Record`]s.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Records(u32);
+
+impl Records {
+    const EMPTY: Records = Records(0);
+
+    fn insert(&mut self, record: Record) {
+        self.0 |= record.bit();
+    }
+
+    fn contains(self, record: Record) -> bool {
+        self.0 & record.bit() != 0
+    }
+
+    fn remove_all(self, other: Records) -> Records {
+        Records(self.0 & !other.0)
+    }
+
+    /// The records in the set, out of the ones of a responder with `services` services.
+    fn iter(self, services: usize) -> impl Iterator<Item = Record> {
+        let all = core::iter::once(Record::Host).chain((0..4 * services).map(|j| match j % 4 {
+            0 => Record::ServiceType(j / 4),
+            1 => Record::Ptr(j / 4),
+            2 => Record::Srv(j / 4),
+            _ => Record::Txt(j / 4),
+        }));
+        all.filter(move |&r| self.contains(r))
+    }
+
+    fn len(self) -> u16 {
+        self.0.count_ones() as u16
+    }
+}
+
+/// A reply to a query, in the transmit buffer.
+struct Reply {
+    len: usize,
+    to: IpEndpoint,
+    delay: bool,
+}
+
+/// Answers for the names of the configuration, and the address of the interface.
+struct Responder<'c, 'a> {
+    config: &'c MdnsConfig<'a>,
+    cidr: Ipv4Cidr,
+    /// Appended to the host and instance names after conflicts.
+    number: u8,
+}
+
+impl<'c, 'a> Responder<'c, 'a> {
+    fn host_name(&self) -> Name<'a> {
+        Name {
+            first: Some((self.config.hostname, self.number)),
+            middle: "",
+        }
+    }
+
+    fn service_type_name(&self, i: usize) -> Name<'a> {
+        Name {
+            first: None,
+            middle: self.config.services[i].service_type,
+        }
+    }
+
+    fn instance_name(&self, i: usize) -> Name<'a> {
+        let service = &self.config.services[i];
+        Name {
+            first: Some((service.instance, self.number)),
+            middle: service.service_type,
+        }
+    }
+
+    fn services_name() -> Name<'static> {
+        Name {
+            first: None,
+            middle: "_services._dns-sd._udp",
+        }
+    }
+
+    fn all_records(&self) -> Records {
+        let mut records = Records::EMPTY;
+        records.insert(Record::Host);
+        for i in 0..self.config.services.len() {
+            if self.is_first_of_type(i) {
+                records.insert(Record::ServiceType(i));
+            }
+            records.insert(Record::Ptr(i));
+            records.insert(Record::Srv(i));
+            records.insert(Record::Txt(i));
+        }
+        records
+    }
+
+    /// Whether no earlier service has the same type, so the type is only listed once.
+    fn is_first_of_type(&self, i: usize) -> bool {
+        let services = self.config.services;
+        !services[..i].iter().any(|s| {
+            s.service_type
+                .eq_ignore_ascii_case(services[i].service_type)
+        })
+    }
+
+    fn write_record(
+        &self,
+        w: &mut Writer,
+        record: Record,
+        cache_flush: bool,
+        max_ttl: u32,
+    ) -> Result<()> {
+        let class = if cache_flush && !record.is_shared() {
+            CLASS_IN | CLASS_TOP_BIT
+        } else {
+            CLASS_IN
+        };
+
+        match record {
+            Record::Host => w.record(
+                &self.host_name(),
+                TYPE_A,
+                class,
+                HOST_TTL.min(max_ttl),
+                |w| w.bytes(self.cidr.address().as_bytes()),
+            ),
+            Record::ServiceType(i) => w.record(
+                &Self::services_name(),
+                TYPE_PTR,
+                class,
+                OTHER_TTL.min(max_ttl),
+                |w| w.name(&self.service_type_name(i)),
+            ),
+            Record::Ptr(i) => w.record(
+                &self.service_type_name(i),
+                TYPE_PTR,
+                class,
+                OTHER_TTL.min(max_ttl),
+                |w| w.name(&self.instance_name(i)),
+            ),
+            Record::Srv(i) => w.record(
+                &self.instance_name(i),
+                TYPE_SRV,
+                class,
+                HOST_TTL.min(max_ttl),
+                |w| {
+                    w.u16(0)?; // priority
+                    w.u16(0)?; // weight
+                    w.u16(self.config.services[i].port)?;
+                    w.name(&self.host_name())
+                },
+            ),
+            Record::Txt(i) => w.record(
+                &self.instance_name(i),
+                TYPE_TXT,
+                class,
+                OTHER_TTL.min(max_ttl),
+                |w| {
+                    let txt = self.config.services[i].txt;
+                    // An empty TXT record still holds one empty string.
+                    if txt.is_empty() {
+                        return w.u8(0);
+                    }
+                    for entry in txt {
+                        let entry = &entry.as_bytes()[..entry.len().min(255)];
+                        w.u8(entry.len() as u8)?;
+                        w.bytes(entry)?;
+                    }
+                    Ok(())
+                },
+            ),
+        }
+    }
+
+    /// The records answering a question.
+    fn answers(&self, r: &Reader, name: usize, ty: u16) -> Result<Records> {
+        let is = |t| ty == t || ty == TYPE_ANY;
+        let mut records = Records::EMPTY;
+
+        if is(TYPE_A) && r.name_eq(name, &self.host_name())? {
+            records.insert(Record::Host);
+        }
+        if is(TYPE_PTR) && r.name_eq(name, &Self::services_name())? {
+            for i in 0..self.config.services.len() {
+                if self.is_first_of_type(i) {
+                    records.insert(Record::ServiceType(i));
+                }
+            }
+        }
+        for i in 0..self.config.services.len() {
+            if is(TYPE_PTR) && r.name_eq(name, &self.service_type_name(i))? {
+                records.insert(Record::Ptr(i));
+            }
+            if r.name_eq(name, &self.instance_name(i))? {
+                if is(TYPE_SRV) {
+                    records.insert(Record::Srv(i));
+                }
+                if is(TYPE_TXT) {
+                    records.insert(Record::Txt(i));
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// The records that will likely be asked for next, after `answers` (RFC 6763,
+    /// section 12).
+    fn additionals(&self, answers: Records) -> Records {
+        let mut records = Records::EMPTY;
+        for i in 0..self.config.services.len() {
+            if answers.contains(Record::Ptr(i)) {
+                records.insert(Record::Srv(i));
+                records.insert(Record::Txt(i));
+                records.insert(Record::Host);
+            }
+            if answers.contains(Record::Srv(i)) {
+                records.insert(Record::Host);
+            }
+        }
+        records.remove_all(answers)
+    }
+
+    /// Build the reply to a query into `tx`, if it asks for any of our records.
+    fn reply(&self, msg: &[u8], from: IpEndpoint, tx: &mut [u8]) -> Result<Option<Reply>> {
+        let header = Header::parse(msg)?;
+        if header.flags & (FLAG_RESPONSE | FLAG_OPCODE) != 0 {
+            return Ok(None);
+        }
+
+        let mut r = Reader::new(msg);
+        let mut answers = Records::EMPTY;
+        let mut unicast = false;
+        for _ in 0..header.counts[0] {
+            let name = r.name()?;
+            let ty = r.u16()?;
+            let class = r.u16()?;
+            if class & !CLASS_TOP_BIT != CLASS_IN && class & !CLASS_TOP_BIT != TYPE_ANY {
+                continue;
+            }
+            let records = self.answers(&r, name, ty)?;
+            if records != Records::EMPTY && class & CLASS_TOP_BIT != 0 {
+                unicast = true;
+            }
+            answers = Records(answers.0 | records.0);
+        }
+        if answers == Records::EMPTY {
+            return Ok(None);
+        }
+        let questions_end = r.pos();
+
+        // Only reply directly to devices on the local link.
+        let local = match from.addr {
+            IpAddress::Ipv4(addr) => self.cidr.contains_addr(&addr),
+            _ => false,
+        };
+        // Queries from plain DNS resolvers don't come from the mDNS port, and expect a
+        // regular DNS reply.
+        let legacy = local && from.port != MDNS_PORT;
+        let unicast = local && (unicast || legacy);
+
+        let mut w = Writer::new(tx);
+        let (id, questions, max_ttl) = if legacy {
+            w.bytes(&msg[HEADER_LEN..questions_end])?;
+            (header.id, header.counts[0], LEGACY_TTL)
+        } else {
+            (0, 0, u32::MAX)
+        };
+
+        let additionals = self.additionals(answers);
+        let services = self.config.services.len();
+        for record in answers.iter(services).chain(additionals.iter(services)) {
+            self.write_record(&mut w, record, !legacy, max_ttl)?;
+        }
+
+        let counts = [questions, answers.len(), 0, additionals.len()];
+        let len = w
+            .finish(id, FLAG_RESPONSE | FLAG_AUTHORITATIVE, counts)
+            .len();
+        let delay = !unicast && answers.iter(services).any(|r| r.is_shared());
+        let to = if unicast {
+            from
+        } else {
+            (MDNS_ADDR, MDNS_PORT).into()
+        };
+        Ok(Some(Reply { len, to, delay }))
+    }
+
+    /// Check whether a message is a response from another device claiming one of our
+    /// unique records with different data.
+    fn is_conflict(&self, msg: &[u8]) -> Result<bool> {
+        let header = Header::parse(msg)?;
+        if header.flags & FLAG_RESPONSE == 0 {
+            return Ok(false);
+        }
+
+        let mut r = Reader::new(msg);
+        for _ in 0..header.counts[0] {
+            r.name()?;
+            r.u16()?;
+            r.u16()?;
+        }
+        let records = &header.counts[1..];
+        for _ in 0..records.iter().map(|&n| n as usize).sum() {
+            let name = r.name()?;
+            let ty = r.u16()?;
+            r.u16()?; // class
+            r.u32()?; // TTL
+            let len = r.u16()? as usize;
+            let rdata_pos = r.pos();
+            let rdata = r.bytes(len)?;
+
+            if ty == TYPE_A
+                && r.name_eq(name, &self.host_name())?
+                && rdata != self.cidr.address().as_bytes()
+            {
+                return Ok(true);
+            }
+            if ty == TYPE_SRV && len >= 7 {
+                for i in 0..self.config.services.len() {
+                    if !r.name_eq(name, &self.instance_name(i))? {
+                        continue;
+                    }
+                    let port = u16::from_be_bytes([rdata[4], rdata[5]]);
+                    if port != self.config.services[i].port
+                        || !r.name_eq(rdata_pos + 6, &self.host_name())?
+                    {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Check that no other device uses our names. Returns true if one does.
+    async fn probe(&self, socket: &mut UdpSocket<'_>, rx: &mut [u8], tx: &mut [u8]) -> bool {
+        Timer::after(random_duration(PROBE_WAIT)).await;
+
+        for _ in 0..PROBE_COUNT {
+            match self.probe_query(tx) {
+                Ok(msg) => send(socket, msg, (MDNS_ADDR, MDNS_PORT)).await,
+                Err(e) => warn!("mDNS: failed to build probe: {:?}", e),
+            }
+
+            let deadline = Instant::now() + PROBE_INTERVAL;
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let len = match with_timeout(remaining, socket.recv_from(rx)).await {
+                    Ok(Ok((len, _))) => len,
+                    _ => break,
+                };
+                if self.is_conflict(&rx[..len]).unwrap_or(false) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// A query for our unique names, with the records we want for them in the authority
+    /// section.
+    fn probe_query<'t>(&self, tx: &'t mut [u8]) -> Result<&'t [u8]> {
+        let services = self.config.services.len();
+        let mut w = Writer::new(tx);
+        w.question(&self.host_name(), TYPE_ANY, CLASS_IN | CLASS_TOP_BIT)?;
+        for i in 0..services {
+            w.question(&self.instance_name(i), TYPE_ANY, CLASS_IN | CLASS_TOP_BIT)?;
+        }
+
+        let mut authority = Records::EMPTY;
+        authority.insert(Record::Host);
+        for i in 0..services {
+            authority.insert(Record::Srv(i));
+            authority.insert(Record::Txt(i));
+        }
+        for record in authority.iter(services) {
+            self.write_record(&mut w, record, false, u32::MAX)?;
+        }
+
+        let counts = [1 + services as u16, 0, authority.len(), 0];
+        Ok(w.finish(0, 0, counts))
+    }
+
+    /// An unsolicited response with all our records.
+    fn announcement<'t>(&self, tx: &'t mut [u8]) -> Result<&'t [u8]> {
+        let records = self.all_records();
+        let mut w = Writer::new(tx);
+        for record in records.iter(self.config.services.len()) {
+            self.write_record(&mut w, record, true, u32::MAX)?;
+        }
+        let counts = [0, records.len(), 0, 0];
+        Ok(w.finish(0, FLAG_RESPONSE | FLAG_AUTHORITATIVE, counts))
+    }
+}
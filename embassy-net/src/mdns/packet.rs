@@ -0,0 +1,281 @@
+//! DNS message encoding, as far as mDNS needs it.
+
+use crate::{Error, Result};
+
+pub const HEADER_LEN: usize = 12;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+
+pub const CLASS_IN: u16 = 1;
+/// In questions, asks for a unicast reply. In records, tells caches to flush older
+/// records of the same name and type.
+pub const CLASS_TOP_BIT: u16 = 0x8000;
+
+/// Response flag in the header.
+pub const FLAG_RESPONSE: u16 = 0x8000;
+/// Authoritative answer flag in the header.
+pub const FLAG_AUTHORITATIVE: u16 = 0x0400;
+/// Opcode bits in the header.
+pub const FLAG_OPCODE: u16 = 0x7800;
+
+/// Longest label allowed by DNS.
+const MAX_LABEL_LEN: usize = 63;
+
+/// A domain name in `.local`.
+///
+/// It is made of an optional leading label, such as a host name or service instance
+/// name, followed by the dot separated labels of `middle`, followed by `local`.
+#[derive(Clone, Copy)]
+pub struct Name<'n> {
+    /// The leading label, and the number appended to it after a name conflict. Numbers
+    /// below 2 aren't appended.
+    pub first: Option<(&'n str, u8)>,
+    pub middle: &'n str,
+}
+
+impl<'n> Name<'n> {
+    fn labels(&self) -> impl Iterator<Item = (&'n str, u8)> + 'n {
+        let middle = self.middle.split('.').filter(|l| !l.is_empty());
+        self.first
+            .into_iter()
+            .chain(middle.map(|l| (l, 0)))
+            .chain(core::iter::once(("local", 0)))
+    }
+}
+
+/// Spell out a label, with its conflict number, into `buf`.
+fn label<'b>(base: &str, number: u8, buf: &'b mut [u8; MAX_LABEL_LEN]) -> &'b [u8] {
+    let mut suffix = [0; 4];
+    let mut suffix_len = 0;
+    if number >= 2 {
+        suffix[0] = b'-';
+        suffix_len = 1;
+        let mut digits = [0; 3];
+        let mut n = number;
+        let mut count = 0;
+        while n > 0 {
+            digits[count] = b'0' + n % 10;
+            n /= 10;
+            count += 1;
+        }
+        for i in 0..count {
+            suffix[suffix_len] = digits[count - 1 - i];
+            suffix_len += 1;
+        }
+    }
+
+    // Truncate the base so the number still fits.
+    let base_len = base.len().min(MAX_LABEL_LEN - suffix_len);
+    buf[..base_len].copy_from_slice(&base.as_bytes()[..base_len]);
+    buf[base_len..base_len + suffix_len].copy_from_slice(&suffix[..suffix_len]);
+    &buf[..base_len + suffix_len]
+}
+
+/// Reads a message.
+pub struct Reader<'a> {
+    msg: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Start reading after the header.
+    pub fn new(msg: &'a [u8]) -> Self {
+        Self {
+            msg,
+            pos: HEADER_LEN,
+        }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.msg.len() - self.pos < n {
+            return Err(Error::Truncated);
+        }
+        let res = &self.msg[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(res)
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Skip a name, returning where it starts.
+    pub fn name(&mut self) -> Result<usize> {
+        let start = self.pos;
+        loop {
+            let len = self.bytes(1)?[0];
+            match len {
+                0 => return Ok(start),
+                // A compression pointer ends the name.
+                l if l & 0xc0 == 0xc0 => {
+                    self.bytes(1)?;
+                    return Ok(start);
+                }
+                l if l & 0xc0 != 0 => return Err(Error::Malformed),
+                l => {
+                    self.bytes(l as usize)?;
+                }
+            }
+        }
+    }
+
+    /// Check whether the name starting at `pos` is `name`, ignoring ASCII case.
+    pub fn name_eq(&self, mut pos: usize, name: &Name) -> Result<bool> {
+        let mut ours = name.labels();
+        // Limits the number of pointers followed, in case they loop.
+        let mut jumps = 0;
+        loop {
+            let len = *self.msg.get(pos).ok_or(Error::Truncated)? as usize;
+            if len & 0xc0 == 0xc0 {
+                let low = *self.msg.get(pos + 1).ok_or(Error::Truncated)? as usize;
+                pos = (len & 0x3f) << 8 | low;
+                jumps += 1;
+                if jumps > 16 {
+                    return Err(Error::Malformed);
+                }
+                continue;
+            }
+            if len & 0xc0 != 0 {
+                return Err(Error::Malformed);
+            }
+            if len == 0 {
+                return Ok(ours.next().is_none());
+            }
+
+            let theirs = self
+                .msg
+                .get(pos + 1..pos + 1 + len)
+                .ok_or(Error::Truncated)?;
+            let mut buf = [0; MAX_LABEL_LEN];
+            let matches = match ours.next() {
+                Some((base, number)) => label(base, number, &mut buf).eq_ignore_ascii_case(theirs),
+                None => false,
+            };
+            if !matches {
+                return Ok(false);
+            }
+            pos += 1 + len;
+        }
+    }
+}
+
+/// Writes a message into a caller-provided buffer.
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// Start writing after the header, which is filled in by [`Writer::finish`].
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            pos: HEADER_LEN,
+        }
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> Result<()> {
+        if self.buf.len() - self.pos < data.len() {
+            return Err(Error::Exhausted);
+        }
+        self.buf[self.pos..self.pos + data.len()].copy_from_slice(data);
+        self.pos += data.len();
+        Ok(())
+    }
+
+    pub fn u8(&mut self, val: u8) -> Result<()> {
+        self.bytes(&[val])
+    }
+
+    pub fn u16(&mut self, val: u16) -> Result<()> {
+        self.bytes(&val.to_be_bytes())
+    }
+
+    pub fn u32(&mut self, val: u32) -> Result<()> {
+        self.bytes(&val.to_be_bytes())
+    }
+
+    /// Write a name, without compression.
+    pub fn name(&mut self, name: &Name) -> Result<()> {
+        for (base, number) in name.labels() {
+            let mut buf = [0; MAX_LABEL_LEN];
+            let label = label(base, number, &mut buf);
+            self.u8(label.len() as u8)?;
+            self.bytes(label)?;
+        }
+        self.u8(0)
+    }
+
+    pub fn question(&mut self, name: &Name, ty: u16, class: u16) -> Result<()> {
+        self.name(name)?;
+        self.u16(ty)?;
+        self.u16(class)
+    }
+
+    /// Write a resource record. Its data is written by `rdata`.
+    pub fn record(
+        &mut self,
+        name: &Name,
+        ty: u16,
+        class: u16,
+        ttl: u32,
+        rdata: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        self.name(name)?;
+        self.u16(ty)?;
+        self.u16(class)?;
+        self.u32(ttl)?;
+        let len_pos = self.pos;
+        self.u16(0)?;
+        rdata(self)?;
+        let len = (self.pos - len_pos - 2) as u16;
+        self.buf[len_pos..len_pos + 2].copy_from_slice(&len.to_be_bytes());
+        Ok(())
+    }
+
+    /// Fill in the header, and return the message.
+    pub fn finish(self, id: u16, flags: u16, counts: [u16; 4]) -> &'a [u8] {
+        self.buf[0..2].copy_from_slice(&id.to_be_bytes());
+        self.buf[2..4].copy_from_slice(&flags.to_be_bytes());
+        for (i, count) in counts.iter().enumerate() {
+            self.buf[4 + 2 * i..6 + 2 * i].copy_from_slice(&count.to_be_bytes());
+        }
+        &self.buf[..self.pos]
+    }
+}
+
+/// The fields of a message header.
+pub struct Header {
+    pub id: u16,
+    pub flags: u16,
+    /// Questions, answers, authority records and additional records.
+    pub counts: [u16; 4],
+}
+
+impl Header {
+    pub fn parse(msg: &[u8]) -> Result<Self> {
+        if msg.len() < HEADER_LEN {
+            return Err(Error::Truncated);
+        }
+        let u16_at = |i: usize| u16::from_be_bytes([msg[i], msg[i + 1]]);
+        Ok(Self {
+            id: u16_at(0),
+            flags: u16_at(2),
+            counts: [u16_at(4), u16_at(6), u16_at(8), u16_at(10)],
+        })
+    }
+}
@@ -0,0 +1,570 @@
+//! MQTT client, for protocol versions 3.1.1 and 5.
+//!
+//! [`MqttClient`] runs over any stream implementing [`AsyncBufRead`] and [`AsyncWrite`],
+//! such as a [`TcpSocket`](crate::TcpSocket) or a TLS connection. It doesn't allocate:
+//! packets are assembled in buffers provided by the caller, so the largest message that
+//! can be sent or received is limited by their size.
+//!
+//! Messages can be published with QoS 0 or 1. Incoming messages are passed to a
+//! [`Handler`] by [`MqttClient::receive`] or [`MqttClient::run`]. While waiting for
+//! packets, the client sends pings to keep the connection alive.
+
+use core::cmp::min;
+use core::ops::Range;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use embassy::io::{self, AsyncBufRead, AsyncWrite};
+use embassy::time::{Duration, Instant, Ticker};
+use futures::future::{poll_fn, select, Either};
+use futures::{pin_mut, Stream, StreamExt};
+
+mod packet;
+
+use packet::{Reader, Writer, MAX_HEADER_LEN};
+
+use crate::config::Event;
+use crate::fmt::*;
+use crate::ConfigEvents;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MqttError {
+    /// The underlying stream failed.
+    Io(io::Error),
+    /// The broker sent an invalid or unexpected packet.
+    Protocol,
+    /// A packet doesn't fit in the buffers.
+    InsufficientBuffer,
+    /// The broker refused the connection, with this return code.
+    ConnectionRefused(u8),
+    /// The broker refused a subscription or publication, with this reason code.
+    Rejected(u8),
+    /// The broker didn't answer a ping in time.
+    KeepAliveTimeout,
+    /// The client isn't connected.
+    NotConnected,
+    /// The network stack lost its configuration, so the connection is gone.
+    Deconfigured,
+}
+
+impl From<io::Error> for MqttError {
+    fn from(err: io::Error) -> Self {
+        MqttError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtocolVersion {
+    V311 = 4,
+    V5 = 5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+}
+
+/// Message published by the broker when the client disconnects unexpectedly.
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+/// Settings for [`MqttClient::connect`].
+pub struct ConnectOptions<'a> {
+    pub version: ProtocolVersion,
+    pub client_id: &'a str,
+    /// Longest time between packets sent to the broker, 0 to disable pings.
+    pub keep_alive_secs: u16,
+    /// Start a new session, instead of resuming the previous one.
+    pub clean_session: bool,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a [u8]>,
+    pub will: Option<Will<'a>>,
+}
+
+impl<'a> ConnectOptions<'a> {
+    /// MQTT 3.1.1 with a clean session and a 60 second keep alive.
+    pub fn new(client_id: &'a str) -> Self {
+        Self {
+            version: ProtocolVersion::V311,
+            client_id,
+            keep_alive_secs: 60,
+            clean_session: true,
+            username: None,
+            password: None,
+            will: None,
+        }
+    }
+}
+
+/// A message received on a subscribed topic.
+pub struct Message<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub qos: QoS,
+    pub retain: bool,
+    /// The broker may have sent this message before.
+    pub dup: bool,
+}
+
+/// Receives the messages published on subscribed topics.
+pub trait Handler {
+    fn message(&mut self, message: Message<'_>);
+}
+
+impl<F: FnMut(Message<'_>)> Handler for F {
+    fn message(&mut self, message: Message<'_>) {
+        self(message)
+    }
+}
+
+/// An MQTT client over the stream `S`.
+pub struct MqttClient<'a, S> {
+    stream: S,
+    version: ProtocolVersion,
+    connected: bool,
+
+    rx_buf: &'a mut [u8],
+    /// Length of the PUBLISH packets at the start of `rx_buf`, received while waiting
+    /// for an acknowledgement and not passed to the handler yet.
+    stashed: usize,
+    /// Bytes received so far of the packet being read, after the stashed ones.
+    rx_len: usize,
+
+    tx_buf: &'a mut [u8],
+    /// Packets queued and not written to the stream yet.
+    tx_pending: Range<usize>,
+    next_packet_id: u16,
+
+    keep_alive: Duration,
+    ticker: Option<Ticker>,
+    last_tx: Instant,
+    /// When the ping we're waiting an answer for was sent.
+    ping_sent: Option<Instant>,
+}
+
+impl<'a, S: AsyncBufRead + AsyncWrite + Unpin> MqttClient<'a, S> {
+    /// Create a client over `stream`, which should already be connected to the broker.
+    ///
+    /// `rx_buf` must hold the largest incoming packet, plus the messages received while
+    /// waiting for acknowledgements. `tx_buf` must hold the largest outgoing packet.
+    pub fn new(stream: S, rx_buf: &'a mut [u8], tx_buf: &'a mut [u8]) -> Self {
+        Self {
+            stream,
+            version: ProtocolVersion::V311,
+            connected: false,
+            rx_buf,
+            stashed: 0,
+            rx_len: 0,
+            tx_buf,
+            tx_pending: 0..0,
+            next_packet_id: 1,
+            keep_alive: Duration::from_secs(0),
+            ticker: None,
+            last_tx: Instant::now(),
+            ping_sent: None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Get the underlying stream back, for example to close it before reconnecting.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Send CONNECT, and wait for the broker to accept it.
+    ///
+    /// Returns whether the broker resumed an existing session.
+    pub async fn connect(&mut self, opts: &ConnectOptions<'_>) -> Result<bool, MqttError> {
+        self.version = opts.version;
+        self.connected = false;
+        self.stashed = 0;
+        self.rx_len = 0;
+        self.tx_pending = 0..0;
+        self.ticker = None;
+        self.ping_sent = None;
+
+        self.queue(|w| packet::connect(w, opts))?;
+        self.flush().await?;
+
+        let body = self.wait_for(packet::CONNACK, None).await?;
+        let mut r = Reader::new(&self.rx_buf[body]);
+        let session_present = r.u8()? & 0x01 != 0;
+        let code = r.u8()?;
+        if code != 0 {
+            return Err(MqttError::ConnectionRefused(code));
+        }
+
+        self.connected = true;
+        self.keep_alive = Duration::from_secs(opts.keep_alive_secs as u64);
+        if opts.keep_alive_secs != 0 {
+            // Tick twice per period, so a ping is always sent in time.
+            self.ticker = Some(Ticker::every(Duration::from_millis(
+                opts.keep_alive_secs as u64 * 500,
+            )));
+        }
+        Ok(session_present)
+    }
+
+    /// Publish a message. With [`QoS::AtLeastOnce`], wait for the broker to acknowledge it.
+    pub async fn publish(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<(), MqttError> {
+        self.check_connected()?;
+        let packet_id = self.packet_id();
+        let version = self.version;
+        self.queue(|w| packet::publish(w, version, topic, payload, qos, retain, packet_id))?;
+        self.flush().await?;
+
+        if qos == QoS::AtLeastOnce {
+            let body = self.wait_for(packet::PUBACK, Some(packet_id)).await?;
+            // MQTT 5 brokers may add a reason code.
+            match self.rx_buf[body].get(2) {
+                Some(&code) if code >= 0x80 => return Err(MqttError::Rejected(code)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Subscribe to a topic filter. Returns the QoS granted by the broker.
+    pub async fn subscribe(&mut self, topic: &str, qos: QoS) -> Result<QoS, MqttError> {
+        self.check_connected()?;
+        let packet_id = self.packet_id();
+        let version = self.version;
+        self.queue(|w| packet::subscribe(w, version, topic, qos, packet_id))?;
+        self.flush().await?;
+
+        let body = self.wait_for(packet::SUBACK, Some(packet_id)).await?;
+        let mut r = Reader::new(&self.rx_buf[body]);
+        r.u16()?;
+        r.properties(self.version)?;
+        match r.u8()? {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            // We never ask for QoS 2, but the broker can't grant more than asked.
+            code => Err(MqttError::Rejected(code)),
+        }
+    }
+
+    /// Unsubscribe from a topic filter.
+    pub async fn unsubscribe(&mut self, topic: &str) -> Result<(), MqttError> {
+        self.check_connected()?;
+        let packet_id = self.packet_id();
+        let version = self.version;
+        self.queue(|w| packet::unsubscribe(w, version, topic, packet_id))?;
+        self.flush().await?;
+        self.wait_for(packet::UNSUBACK, Some(packet_id)).await?;
+        Ok(())
+    }
+
+    /// Wait for the next message, and pass it to `handler`.
+    ///
+    /// Pings are sent while waiting. This can be cancelled at any time without losing
+    /// data, so it can be raced against other futures, for example a [`Ticker`] that
+    /// triggers publishing.
+    pub async fn receive<H: Handler>(&mut self, handler: &mut H) -> Result<(), MqttError> {
+        self.check_connected()?;
+        loop {
+            if self.stashed != 0 {
+                let len = self.dispatch(0, handler)?;
+                // Drop the message, and move what was received after it.
+                self.rx_buf.copy_within(len..self.stashed + self.rx_len, 0);
+                self.stashed -= len;
+                return Ok(());
+            }
+
+            let (first, body) = self.read_packet().await?;
+            match first >> 4 {
+                packet::PUBLISH => {
+                    self.dispatch(0, handler)?;
+                    return Ok(());
+                }
+                _ => self.handle_other(first, body)?,
+            }
+        }
+    }
+
+    /// Pass messages to `handler` until the connection fails, or the network stack
+    /// loses its configuration on any interface.
+    ///
+    /// Returns [`MqttError::Deconfigured`] in the latter case. The stream is then
+    /// unusable, and the caller should open a new one once the stack is configured again,
+    /// and call [`MqttClient::connect`].
+    pub async fn run<H: Handler>(
+        &mut self,
+        handler: &mut H,
+        events: &mut ConfigEvents<'_>,
+    ) -> Result<(), MqttError> {
+        loop {
+            let deconfigured = {
+                let receive = self.receive(handler);
+                let deconfigured = wait_deconfigured(events);
+                pin_mut!(receive);
+                pin_mut!(deconfigured);
+                match select(receive, deconfigured).await {
+                    Either::Left((res, _)) => {
+                        res?;
+                        false
+                    }
+                    Either::Right(_) => true,
+                }
+            };
+            if deconfigured {
+                warn!("network deconfigured, MQTT connection lost");
+                self.connected = false;
+                return Err(MqttError::Deconfigured);
+            }
+        }
+    }
+
+    /// Tell the broker we're leaving, so it doesn't publish the will.
+    pub async fn disconnect(&mut self) -> Result<(), MqttError> {
+        self.check_connected()?;
+        self.connected = false;
+        self.ticker = None;
+        self.queue(|_| Ok(packet::DISCONNECT << 4))?;
+        self.flush().await
+    }
+
+    fn check_connected(&self) -> Result<(), MqttError> {
+        if self.connected {
+            Ok(())
+        } else {
+            Err(MqttError::NotConnected)
+        }
+    }
+
+    fn packet_id(&mut self) -> u16 {
+        let id = self.next_packet_id;
+        self.next_packet_id = match id.wrapping_add(1) {
+            0 => 1,
+            next => next,
+        };
+        id
+    }
+
+    /// Queue a packet after the pending ones. `build` writes the body, and returns the
+    /// first byte of the fixed header.
+    fn queue(
+        &mut self,
+        build: impl FnOnce(&mut Writer<'_>) -> Result<u8, MqttError>,
+    ) -> Result<(), MqttError> {
+        let pending = self.tx_pending.len();
+        self.tx_buf.copy_within(self.tx_pending.clone(), 0);
+
+        let buf = &mut self.tx_buf[pending..];
+        if buf.len() < MAX_HEADER_LEN {
+            return Err(MqttError::InsufficientBuffer);
+        }
+        let mut w = Writer::new(&mut buf[MAX_HEADER_LEN..]);
+        let first = build(&mut w)?;
+        let len = w.len();
+        let start = packet::write_header(buf, first, len)?;
+        buf.copy_within(start..MAX_HEADER_LEN + len, 0);
+
+        self.tx_pending = 0..pending + MAX_HEADER_LEN + len - start;
+        self.last_tx = Instant::now();
+        Ok(())
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), MqttError>> {
+        while !self.tx_pending.is_empty() {
+            let n = match Pin::new(&mut self.stream)
+                .poll_write(cx, &self.tx_buf[self.tx_pending.clone()])
+            {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::Error::WriteZero.into())),
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            };
+            self.tx_pending.start += n;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    async fn flush(&mut self) -> Result<(), MqttError> {
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    fn poll_keep_alive(&mut self, cx: &mut Context<'_>) -> Result<(), MqttError> {
+        let ticker = match &mut self.ticker {
+            Some(ticker) => ticker,
+            None => return Ok(()),
+        };
+        let mut ticked = false;
+        while let Poll::Ready(Some(())) = Pin::new(&mut *ticker).poll_next(cx) {
+            ticked = true;
+        }
+        if !ticked {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        match self.ping_sent {
+            Some(sent) if now - sent >= self.keep_alive => {
+                warn!("MQTT broker didn't answer ping");
+                self.connected = false;
+                Err(MqttError::KeepAliveTimeout)
+            }
+            Some(_) => Ok(()),
+            None if now - self.last_tx >= Duration::from_ticks(self.keep_alive.as_ticks() / 2) => {
+                debug!("MQTT ping");
+                self.queue(|_| Ok(packet::PINGREQ << 4))?;
+                self.ping_sent = Some(now);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Read a packet into `rx_buf`, after the stashed ones. Keeps the connection alive
+    /// meanwhile.
+    ///
+    /// Returns the first byte of the packet, and where its body is in `rx_buf`.
+    fn poll_read_packet(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(u8, Range<usize>), MqttError>> {
+        if let Err(e) = self.poll_keep_alive(cx) {
+            return Poll::Ready(Err(e));
+        }
+        if let Poll::Ready(Err(e)) = self.poll_flush(cx) {
+            return Poll::Ready(Err(e));
+        }
+
+        loop {
+            let buf = &mut self.rx_buf[self.stashed..];
+            let header = if self.rx_len < 2 {
+                None
+            } else {
+                packet::decode_varint(&buf[1..self.rx_len])?
+            };
+            let need = match header {
+                Some((len, n)) => 1 + n + len,
+                None => self.rx_len + 1,
+            };
+            if need > buf.len() {
+                return Poll::Ready(Err(MqttError::InsufficientBuffer));
+            }
+
+            if self.rx_len < need {
+                let data = match Pin::new(&mut self.stream).poll_fill_buf(cx) {
+                    Poll::Ready(Ok(data)) => data,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                    Poll::Pending => return Poll::Pending,
+                };
+                if data.is_empty() {
+                    self.connected = false;
+                    return Poll::Ready(Err(io::Error::UnexpectedEof.into()));
+                }
+                let n = min(data.len(), need - self.rx_len);
+                buf[self.rx_len..self.rx_len + n].copy_from_slice(&data[..n]);
+                Pin::new(&mut self.stream).consume(n);
+                self.rx_len += n;
+                continue;
+            }
+
+            if let Some((_, n)) = header {
+                self.rx_len = 0;
+                let body = self.stashed + 1 + n..self.stashed + need;
+                return Poll::Ready(Ok((buf[0], body)));
+            }
+        }
+    }
+
+    async fn read_packet(&mut self) -> Result<(u8, Range<usize>), MqttError> {
+        poll_fn(|cx| self.poll_read_packet(cx)).await
+    }
+
+    /// Wait for an acknowledgement, stashing the messages received meanwhile.
+    async fn wait_for(
+        &mut self,
+        packet_type: u8,
+        packet_id: Option<u16>,
+    ) -> Result<Range<usize>, MqttError> {
+        loop {
+            let (first, body) = self.read_packet().await?;
+            let t = first >> 4;
+            if t == packet::PUBLISH {
+                self.stashed = body.end;
+            } else if t == packet_type {
+                let id = Reader::new(&self.rx_buf[body.clone()]).u16();
+                if packet_id.is_none() || packet_id == id.ok() {
+                    return Ok(body);
+                }
+            } else {
+                self.handle_other(first, body)?;
+            }
+        }
+    }
+
+    fn handle_other(&mut self, first: u8, _body: Range<usize>) -> Result<(), MqttError> {
+        match first >> 4 {
+            packet::PINGRESP => self.ping_sent = None,
+            // Acknowledgements of requests we stopped waiting for.
+            packet::PUBACK | packet::SUBACK | packet::UNSUBACK => {}
+            packet::DISCONNECT => {
+                warn!("MQTT broker disconnected");
+                self.connected = false;
+                return Err(MqttError::NotConnected);
+            }
+            _ => return Err(MqttError::Protocol),
+        }
+        Ok(())
+    }
+
+    /// Pass the PUBLISH packet at `rx_buf[start..]` to `handler`, and queue its
+    /// acknowledgement. Returns the length of the packet.
+    fn dispatch<H: Handler>(&mut self, start: usize, handler: &mut H) -> Result<usize, MqttError> {
+        let buf = &self.rx_buf[start..];
+        let first = buf[0];
+        let (len, n) = packet::decode_varint(&buf[1..])?.ok_or(MqttError::Protocol)?;
+        let mut r = Reader::new(&buf[1 + n..1 + n + len]);
+
+        let qos = match (first >> 1) & 0x03 {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            _ => return Err(MqttError::Protocol),
+        };
+        let topic = r.str()?;
+        let packet_id = match qos {
+            QoS::AtMostOnce => 0,
+            QoS::AtLeastOnce => r.u16()?,
+        };
+        r.properties(self.version)?;
+        handler.message(Message {
+            topic,
+            payload: r.rest(),
+            qos,
+            retain: first & 0x01 != 0,
+            dup: first & 0x08 != 0,
+        });
+
+        if qos == QoS::AtLeastOnce {
+            self.queue(|w| packet::puback(w, packet_id))?;
+        }
+        Ok(1 + n + len)
+    }
+}
+
+async fn wait_deconfigured(events: &mut ConfigEvents<'_>) {
+    while let Some((_, event)) = events.next().await {
+        if let Event::Deconfigured = event {
+            return;
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! MQTT control packet encoding, for versions 3.1.1 and 5.
+
+use super::{ConnectOptions, MqttError, ProtocolVersion, QoS};
+
+pub const CONNECT: u8 = 1;
+pub const CONNACK: u8 = 2;
+pub const PUBLISH: u8 = 3;
+pub const PUBACK: u8 = 4;
+pub const SUBSCRIBE: u8 = 8;
+pub const SUBACK: u8 = 9;
+pub const UNSUBSCRIBE: u8 = 10;
+pub const UNSUBACK: u8 = 11;
+pub const PINGREQ: u8 = 12;
+pub const PINGRESP: u8 = 13;
+pub const DISCONNECT: u8 = 14;
+
+/// Room left before the body of outgoing packets, for the fixed header.
+pub const MAX_HEADER_LEN: usize = 5;
+
+/// Reads the fields of a packet body.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], MqttError> {
+        if self.buf.len() < n {
+            return Err(MqttError::Protocol);
+        }
+        let (res, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(res)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, MqttError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, MqttError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn str(&mut self) -> Result<&'a str, MqttError> {
+        let len = self.u16()? as usize;
+        core::str::from_utf8(self.bytes(len)?).map_err(|_| MqttError::Protocol)
+    }
+
+    pub fn varint(&mut self) -> Result<usize, MqttError> {
+        let (val, len) = decode_varint(self.buf)?.ok_or(MqttError::Protocol)?;
+        self.buf = &self.buf[len..];
+        Ok(val)
+    }
+
+    /// Skip the properties of an MQTT 5 packet.
+    pub fn properties(&mut self, version: ProtocolVersion) -> Result<(), MqttError> {
+        if version == ProtocolVersion::V5 {
+            let len = self.varint()?;
+            self.bytes(len)?;
+        }
+        Ok(())
+    }
+
+    pub fn rest(&mut self) -> &'a [u8] {
+        core::mem::replace(&mut self.buf, &[])
+    }
+}
+
+/// Writes the body of a packet into a caller-provided buffer.
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> Result<(), MqttError> {
+        if self.buf.len() - self.pos < data.len() {
+            return Err(MqttError::InsufficientBuffer);
+        }
+        self.buf[self.pos..self.pos + data.len()].copy_from_slice(data);
+        self.pos += data.len();
+        Ok(())
+    }
+
+    pub fn u8(&mut self, val: u8) -> Result<(), MqttError> {
+        self.bytes(&[val])
+    }
+
+    pub fn u16(&mut self, val: u16) -> Result<(), MqttError> {
+        self.bytes(&val.to_be_bytes())
+    }
+
+    /// Binary data or a string, prefixed with its length.
+    pub fn data(&mut self, data: &[u8]) -> Result<(), MqttError> {
+        if data.len() > u16::MAX as usize {
+            return Err(MqttError::InsufficientBuffer);
+        }
+        self.u16(data.len() as u16)?;
+        self.bytes(data)
+    }
+
+    /// Empty properties, for MQTT 5.
+    pub fn properties(&mut self, version: ProtocolVersion) -> Result<(), MqttError> {
+        if version == ProtocolVersion::V5 {
+            self.u8(0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode a variable byte integer. Returns `None` if `buf` doesn't hold all of it yet.
+pub fn decode_varint(buf: &[u8]) -> Result<Option<(usize, usize)>, MqttError> {
+    let mut val = 0;
+    for (i, &b) in buf.iter().enumerate() {
+        if i == 4 {
+            return Err(MqttError::Protocol);
+        }
+        val |= ((b & 0x7f) as usize) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok(Some((val, i + 1)));
+        }
+    }
+    Ok(None)
+}
+
+/// Prepend the fixed header to a body at `buf[MAX_HEADER_LEN..MAX_HEADER_LEN + len]`.
+///
+/// Returns where the packet starts in `buf`.
+pub fn write_header(buf: &mut [u8], first: u8, len: usize) -> Result<usize, MqttError> {
+    let mut varint = [0; 4];
+    let mut n = 0;
+    let mut rest = len;
+    loop {
+        if n == varint.len() {
+            return Err(MqttError::InsufficientBuffer);
+        }
+        varint[n] = (rest & 0x7f) as u8;
+        rest >>= 7;
+        if rest != 0 {
+            varint[n] |= 0x80;
+        }
+        n += 1;
+        if rest == 0 {
+            break;
+        }
+    }
+    let start = MAX_HEADER_LEN - 1 - n;
+    buf[start] = first;
+    buf[start + 1..MAX_HEADER_LEN].copy_from_slice(&varint[..n]);
+    Ok(start)
+}
+
+pub fn connect(w: &mut Writer<'_>, opts: &ConnectOptions<'_>) -> Result<u8, MqttError> {
+    w.data(b"MQTT")?;
+    w.u8(opts.version as u8)?;
+
+    let mut flags = 0;
+    if opts.clean_session {
+        flags |= 0x02;
+    }
+    if let Some(will) = &opts.will {
+        flags |= 0x04 | (will.qos as u8) << 3;
+        if will.retain {
+            flags |= 0x20;
+        }
+    }
+    if opts.password.is_some() {
+        flags |= 0x40;
+    }
+    if opts.username.is_some() {
+        flags |= 0x80;
+    }
+    w.u8(flags)?;
+    w.u16(opts.keep_alive_secs)?;
+    w.properties(opts.version)?;
+
+    w.data(opts.client_id.as_bytes())?;
+    if let Some(will) = &opts.will {
+        w.properties(opts.version)?;
+        w.data(will.topic.as_bytes())?;
+        w.data(will.payload)?;
+    }
+    if let Some(username) = opts.username {
+        w.data(username.as_bytes())?;
+    }
+    if let Some(password) = opts.password {
+        w.data(password)?;
+    }
+    Ok(CONNECT << 4)
+}
+
+pub fn publish(
+    w: &mut Writer<'_>,
+    version: ProtocolVersion,
+    topic: &str,
+    payload: &[u8],
+    qos: QoS,
+    retain: bool,
+    packet_id: u16,
+) -> Result<u8, MqttError> {
+    w.data(topic.as_bytes())?;
+    if qos != QoS::AtMostOnce {
+        w.u16(packet_id)?;
+    }
+    w.properties(version)?;
+    w.bytes(payload)?;
+    Ok(PUBLISH << 4 | (qos as u8) << 1 | retain as u8)
+}
+
+pub fn subscribe(
+    w: &mut Writer<'_>,
+    version: ProtocolVersion,
+    topic: &str,
+    qos: QoS,
+    packet_id: u16,
+) -> Result<u8, MqttError> {
+    w.u16(packet_id)?;
+    w.properties(version)?;
+    w.data(topic.as_bytes())?;
+    w.u8(qos as u8)?;
+    Ok(SUBSCRIBE << 4 | 0x02)
+}
+
+pub fn unsubscribe(
+    w: &mut Writer<'_>,
+    version: ProtocolVersion,
+    topic: &str,
+    packet_id: u16,
+) -> Result<u8, MqttError> {
+    w.u16(packet_id)?;
+    w.properties(version)?;
+    w.data(topic.as_bytes())?;
+    Ok(UNSUBSCRIBE << 4 | 0x02)
+}
+
+/// An acknowledgement with only a packet id, which is valid in both versions.
+pub fn puback(w: &mut Writer<'_>, packet_id: u16) -> Result<u8, MqttError> {
+    w.u16(packet_id)?;
+    Ok(PUBACK << 4)
+}
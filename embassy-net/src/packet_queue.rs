@@ -0,0 +1,104 @@
+use crate::device::{RxToken, TxToken};
+
+/// A FIFO of frames, for devices that can't lend buffers straight from their hardware.
+///
+/// The application chooses how many frames it holds, `N`, and how long they can be, `MTU`.
+/// A device usually has two: the driver pushes received frames into one, which the stack
+/// consumes as an [`RxToken`], and the stack fills the other as a [`TxToken`], for the
+/// driver to pop frames to send.
+pub struct PacketQueue<const N: usize, const MTU: usize> {
+    bufs: [[u8; MTU]; N],
+    lens: [usize; N],
+    /// Index of the front frame.
+    head: usize,
+    /// Number of frames in the queue.
+    len: usize,
+    dropped: u32,
+}
+
+impl<const N: usize, const MTU: usize> PacketQueue<N, MTU> {
+    pub const fn new() -> Self {
+        Self {
+            bufs: [[0; MTU]; N],
+            lens: [0; N],
+            head: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Returns how many frames were dropped because the queue was full. It wraps around
+    /// when it overflows.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Returns the free buffer at the back of the queue, to write a frame into. The frame
+    /// is added to the queue by [`PacketQueue::push`].
+    ///
+    /// Returns `None` if the queue is full, and counts the frame as dropped.
+    pub fn back_buf(&mut self) -> Option<&mut [u8; MTU]> {
+        if self.is_full() {
+            self.dropped = self.dropped.wrapping_add(1);
+            return None;
+        }
+        Some(&mut self.bufs[(self.head + self.len) % N])
+    }
+
+    /// Add the frame of `len` bytes written into [`PacketQueue::back_buf`] to the queue.
+    pub fn push(&mut self, len: usize) {
+        assert!(!self.is_full() && len <= MTU);
+        self.lens[(self.head + self.len) % N] = len;
+        self.len += 1;
+    }
+
+    /// Returns the frame at the front of the queue.
+    pub fn front(&mut self) -> Option<&mut [u8]> {
+        if self.is_empty() {
+            return None;
+        }
+        let len = self.lens[self.head];
+        Some(&mut self.bufs[self.head][..len])
+    }
+
+    /// Remove the frame at the front of the queue.
+    pub fn pop(&mut self) {
+        if !self.is_empty() {
+            self.head = (self.head + 1) % N;
+            self.len -= 1;
+        }
+    }
+}
+
+impl<const N: usize, const MTU: usize> RxToken for PacketQueue<N, MTU> {
+    fn consume(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        if let Some(buf) = self.front() {
+            f(buf);
+            self.pop();
+        }
+    }
+}
+
+impl<const N: usize, const MTU: usize> TxToken for PacketQueue<N, MTU> {
+    fn consume(&mut self, len: usize, f: &mut dyn FnMut(&mut [u8])) {
+        if len > MTU {
+            return;
+        }
+        if let Some(buf) = self.back_buf() {
+            f(&mut buf[..len]);
+            self.push(len);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Packet capture in pcap format.
+//!
+//! A [`Tap`] set with [`Stack::set_tap`](crate::Stack::set_tap) gets a copy of every frame
+//! sent or received on an interface, as a pcap record. Writing [`file_header`] followed
+//! by the records gives a capture file Wireshark can open.
+
+use embassy::time::Instant;
+use smoltcp::phy::Medium;
+
+/// Link type of Ethernet frames.
+pub const LINKTYPE_ETHERNET: u32 = 1;
+/// Link type of bare IP packets, for `Medium::Ip` devices.
+pub const LINKTYPE_RAW: u32 = 101;
+
+/// Longest frame kept in a capture.
+const SNAPLEN: u32 = 65535;
+
+/// Receives the frames of an interface, as pcap records.
+pub trait Tap {
+    /// Called for each frame sent or received, with the header of its record. The frame
+    /// itself follows the header in the record.
+    fn record(&mut self, header: &[u8; 16], frame: &[u8]);
+}
+
+/// The header starting a capture file, for an interface with the given medium.
+pub fn file_header(medium: Medium) -> [u8; 24] {
+    let link_type = match medium {
+        #[cfg(feature = "medium-ip")]
+        Medium::Ip => LINKTYPE_RAW,
+        _ => LINKTYPE_ETHERNET,
+    };
+
+    let mut header = [0; 24];
+    header[0..4].copy_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    header[4..6].copy_from_slice(&2u16.to_le_bytes()); // version 2.4
+    header[6..8].copy_from_slice(&4u16.to_le_bytes());
+    // The timezone offset and timestamp accuracy are zero.
+    header[16..20].copy_from_slice(&SNAPLEN.to_le_bytes());
+    header[20..24].copy_from_slice(&link_type.to_le_bytes());
+    header
+}
+
+/// The header of the record of a frame of `len` bytes seen at `at`.
+///
+/// Timestamps count from boot, since the stack doesn't know the time of day.
+pub(crate) fn record_header(at: Instant, len: usize) -> [u8; 16] {
+    let micros = at.duration_since(Instant::from_ticks(0)).as_micros();
+    let mut header = [0; 16];
+    header[0..4].copy_from_slice(&((micros / 1_000_000) as u32).to_le_bytes());
+    header[4..8].copy_from_slice(&((micros % 1_000_000) as u32).to_le_bytes());
+    header[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+    header[12..16].copy_from_slice(&(len as u32).to_le_bytes());
+    header
+}
@@ -0,0 +1,199 @@
+//! HDLC-like framing, as used by PPP over serial lines (RFC 1662).
+
+use embassy::io::{self, AsyncWrite, AsyncWriteExt};
+
+const FLAG: u8 = 0x7e;
+const ESCAPE: u8 = 0x7d;
+const ADDRESS: u8 = 0xff;
+const CONTROL: u8 = 0x03;
+
+const FCS_INIT: u16 = 0xffff;
+/// FCS of a frame including its own FCS, when it's received intact.
+const FCS_GOOD: u16 = 0xf0b8;
+
+fn fcs(mut fcs: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        fcs ^= b as u16;
+        for _ in 0..8 {
+            fcs = if fcs & 1 != 0 {
+                (fcs >> 1) ^ 0x8408
+            } else {
+                fcs >> 1
+            };
+        }
+    }
+    fcs
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Address,
+    Control,
+    Protocol,
+    ProtocolLow,
+    Information,
+    /// The frame is bad, skip to the next flag.
+    Discard,
+}
+
+/// Reassembles the frames received, one byte at a time.
+///
+/// The information field of a frame goes into a buffer of `MTU` bytes, and frames with a
+/// longer one are dropped. The FCS is only known to be the FCS once the closing flag
+/// arrives, so the last two bytes are held back until then.
+pub struct Decoder<const MTU: usize> {
+    buf: [u8; MTU],
+    len: usize,
+    stage: Stage,
+    protocol: u16,
+    escaped: bool,
+    /// The last two bytes received, which are the FCS if the frame ends now.
+    tail: [u8; 2],
+    tail_len: usize,
+    fcs: u16,
+    /// The protocol and length of the last complete frame.
+    frame: Option<(u16, usize)>,
+}
+
+impl<const MTU: usize> Decoder<MTU> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MTU],
+            len: 0,
+            stage: Stage::Discard,
+            protocol: 0,
+            escaped: false,
+            tail: [0; 2],
+            tail_len: 0,
+            fcs: FCS_INIT,
+            frame: None,
+        }
+    }
+
+    /// Feed received bytes. Stops after a frame is complete.
+    ///
+    /// Returns how many bytes were used, and whether a frame is complete. Its protocol and
+    /// information field are then returned by [`Decoder::frame`], until the next call.
+    pub fn feed(&mut self, data: &[u8]) -> (usize, bool) {
+        self.frame = None;
+        for (i, &b) in data.iter().enumerate() {
+            if b == FLAG {
+                if self.end_frame() {
+                    return (i + 1, true);
+                }
+                continue;
+            }
+            if self.stage == Stage::Discard {
+                continue;
+            }
+            match b {
+                ESCAPE => self.escaped = true,
+                // Control characters are always escaped by the peer, since we never ask
+                // otherwise. Raw ones were inserted by the line, for example XON/XOFF.
+                0..=0x1f => {}
+                b if self.escaped => {
+                    self.escaped = false;
+                    self.push(b ^ 0x20)
+                }
+                b => self.push(b),
+            }
+        }
+        (data.len(), false)
+    }
+
+    pub fn frame(&self) -> Option<(u16, &[u8])> {
+        self.frame
+            .map(|(protocol, len)| (protocol, &self.buf[..len]))
+    }
+
+    fn push(&mut self, b: u8) {
+        self.fcs = fcs(self.fcs, &[b]);
+        if self.tail_len < 2 {
+            self.tail[self.tail_len] = b;
+            self.tail_len += 1;
+            return;
+        }
+        let out = self.tail[0];
+        self.tail = [self.tail[1], b];
+        self.content(out);
+    }
+
+    fn content(&mut self, b: u8) {
+        match self.stage {
+            Stage::Address if b == ADDRESS => self.stage = Stage::Control,
+            // The address and control fields are compressed away.
+            Stage::Address => self.protocol_byte(b),
+            Stage::Control if b == CONTROL => self.stage = Stage::Protocol,
+            Stage::Control => self.stage = Stage::Discard,
+            Stage::Protocol => self.protocol_byte(b),
+            Stage::ProtocolLow => {
+                self.protocol = self.protocol << 8 | b as u16;
+                self.stage = Stage::Information;
+            }
+            Stage::Information if self.len < MTU => {
+                self.buf[self.len] = b;
+                self.len += 1;
+            }
+            Stage::Information | Stage::Discard => self.stage = Stage::Discard,
+        }
+    }
+
+    fn protocol_byte(&mut self, b: u8) {
+        self.protocol = b as u16;
+        // The low byte of a protocol number is odd, so an odd first byte means the
+        // protocol field is compressed to one byte.
+        self.stage = if b & 1 != 0 {
+            Stage::Information
+        } else {
+            Stage::ProtocolLow
+        };
+    }
+
+    /// Handle a flag. Returns whether it ends a valid frame.
+    fn end_frame(&mut self) -> bool {
+        let valid = self.stage == Stage::Information && self.tail_len == 2 && self.fcs == FCS_GOOD;
+        if valid {
+            self.frame = Some((self.protocol, self.len));
+        }
+        self.len = 0;
+        self.stage = Stage::Address;
+        self.escaped = false;
+        self.tail_len = 0;
+        self.fcs = FCS_INIT;
+        valid
+    }
+}
+
+/// Write a frame to `stream`, escaping every control character.
+pub async fn write_frame<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    protocol: u16,
+    information: &[u8],
+) -> io::Result<()> {
+    let header = [ADDRESS, CONTROL, (protocol >> 8) as u8, protocol as u8];
+    let trailer = (!fcs(fcs(FCS_INIT, &header), information)).to_le_bytes();
+
+    let mut buf = [0; 64];
+    buf[0] = FLAG;
+    let mut len = 1;
+    for &b in header.iter().chain(information).chain(&trailer) {
+        if len + 2 > buf.len() {
+            stream.write_all(&buf[..len]).await?;
+            len = 0;
+        }
+        if b < 0x20 || b == FLAG || b == ESCAPE {
+            buf[len] = ESCAPE;
+            buf[len + 1] = b ^ 0x20;
+            len += 2;
+        } else {
+            buf[len] = b;
+            len += 1;
+        }
+    }
+    if len == buf.len() {
+        stream.write_all(&buf[..len]).await?;
+        len = 0;
+    }
+    buf[len] = FLAG;
+    stream.write_all(&buf[..len + 1]).await
+}
@@ -0,0 +1,802 @@
+//! PPP over serial lines, such as a cellular modem in data mode.
+//!
+//! [`Ppp`] runs over any stream implementing [`AsyncBufRead`] and [`AsyncWrite`].
+//! [`Ppp::run`] establishes the link with LCP, authenticates with PAP if the peer asks for
+//! it, and gets an address and DNS servers with IPCP. The stack uses [`Ppp::device`] as its
+//! device, carrying IP packets with [`Medium::Ip`], and [`Ppp::configurator`] as its
+//! configurator, which hands it the negotiated address.
+//!
+//! The link is down until IPCP completes, and goes down again when `run` returns, for
+//! example because the peer hung up. Calling `run` again redials.
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use embassy::io::{self, AsyncBufRead, AsyncWrite};
+use embassy::time::{Duration, Instant, Timer};
+use futures::future::poll_fn;
+use heapless::Vec;
+use smoltcp::phy::Medium;
+use smoltcp::time::Instant as SmolInstant;
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+
+mod hdlc;
+
+use hdlc::{write_frame, Decoder};
+
+use crate::channel::{Channel, ChannelDevice};
+use crate::config::{self, Config, Configurator};
+use crate::fmt::*;
+use crate::stack::rand;
+use crate::{Interface, SocketSet};
+
+const PROTO_IPV4: u16 = 0x0021;
+const PROTO_IPCP: u16 = 0x8021;
+const PROTO_LCP: u16 = 0xc021;
+const PROTO_PAP: u16 = 0xc023;
+
+// Control packet codes. IPCP only uses the first seven.
+const CONFIGURE_REQUEST: u8 = 1;
+const CONFIGURE_ACK: u8 = 2;
+const CONFIGURE_NAK: u8 = 3;
+const CONFIGURE_REJECT: u8 = 4;
+const TERMINATE_REQUEST: u8 = 5;
+const TERMINATE_ACK: u8 = 6;
+const CODE_REJECT: u8 = 7;
+const PROTOCOL_REJECT: u8 = 8;
+const ECHO_REQUEST: u8 = 9;
+const ECHO_REPLY: u8 = 10;
+const DISCARD_REQUEST: u8 = 11;
+
+const LCP_MRU: u8 = 1;
+const LCP_ACCM: u8 = 2;
+const LCP_AUTH: u8 = 3;
+const LCP_MAGIC: u8 = 5;
+
+const IPCP_ADDRESS: u8 = 3;
+const IPCP_DNS1: u8 = 129;
+const IPCP_DNS2: u8 = 131;
+
+const PAP_REQUEST: u8 = 1;
+const PAP_ACK: u8 = 2;
+const PAP_NAK: u8 = 3;
+
+/// The value of the LCP authentication option asking for PAP.
+const AUTH_PAP: [u8; 2] = PROTO_PAP.to_be_bytes();
+
+/// MRU assumed when none is negotiated.
+const DEFAULT_MRU: usize = 1500;
+/// Longest control packet handled. Longer ones are dropped.
+const MAX_CONTROL_LEN: usize = 128;
+/// How long to wait for an answer before sending a request again.
+const RESTART_INTERVAL: Duration = Duration::from_secs(3);
+/// How many times a request is sent before giving up.
+const MAX_REQUESTS: u8 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PppError {
+    /// The underlying stream failed or was closed.
+    Io(io::Error),
+    /// The peer didn't complete negotiation.
+    Timeout,
+    /// The peer refused the credentials.
+    AuthenticationFailed,
+}
+
+impl From<io::Error> for PppError {
+    fn from(err: io::Error) -> Self {
+        PppError::Io(err)
+    }
+}
+
+/// Credentials sent with PAP, if the peer asks for them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PppConfig<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+}
+
+/// The [`Device`](crate::Device) returned by [`Ppp::device`].
+pub type PppDevice<'a, const N: usize, const MTU: usize> = ChannelDevice<'a, N, MTU>;
+
+/// A PPP link, queueing up to `N` packets of up to `MTU` bytes in each direction.
+///
+/// It isn't `Sync`, so it can't be placed in a `static` directly. It's usually put in a
+/// [`Forever`](embassy::util::Forever) instead, so that the stack and the task calling
+/// [`Ppp::run`] can both borrow it for `'static`.
+pub struct Ppp<const N: usize, const MTU: usize> {
+    channel: Channel<N, MTU>,
+    config: RefCell<Option<Config>>,
+}
+
+impl<const N: usize, const MTU: usize> Ppp<N, MTU> {
+    pub const fn new() -> Self {
+        Self {
+            channel: Channel::new(),
+            config: RefCell::new(None),
+        }
+    }
+}
+
+impl<const N: usize, const MTU: usize> Ppp<N, MTU> {
+    /// The device to pass to the stack.
+    pub fn device(&self) -> PppDevice<'_, N, MTU> {
+        self.channel.device(Medium::Ip, [0; 6])
+    }
+
+    /// The configurator to pass to the stack.
+    pub fn configurator(&self) -> PppConfigurator<'_> {
+        PppConfigurator {
+            config: &self.config,
+            returned: None,
+        }
+    }
+
+    /// Run the link over `stream` until the peer terminates it, which returns `Ok`, or
+    /// until it fails.
+    pub async fn run<S: AsyncBufRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        config: &PppConfig<'_>,
+    ) -> Result<(), PppError> {
+        // Takes the link down however `run` ends, including when it's dropped.
+        let _guard = LinkGuard { ppp: self };
+        let mut runner = Runner {
+            ppp: self,
+            stream,
+            credentials: config,
+            decoder: Decoder::new(),
+            tx_buf: [0; MTU],
+            phase: Phase::Establish,
+            id: 0,
+            retries: 0,
+            deadline: None,
+            send_mru: MTU != DEFAULT_MRU,
+            magic: Some(new_magic()),
+            authenticate: false,
+            lcp: Negotiation::default(),
+            ipcp: Negotiation::default(),
+            address: Ipv4Address::UNSPECIFIED,
+            peer_address: None,
+            dns: [Some(Ipv4Address::UNSPECIFIED); 2],
+        };
+        runner.run().await
+    }
+
+    fn set_config(&self, config: Option<Config>) {
+        self.channel.set_link_up(config.is_some());
+        *self.config.borrow_mut() = config;
+        self.channel.wake_stack();
+    }
+}
+
+struct LinkGuard<'a, const N: usize, const MTU: usize> {
+    ppp: &'a Ppp<N, MTU>,
+}
+
+impl<'a, const N: usize, const MTU: usize> Drop for LinkGuard<'a, N, MTU> {
+    fn drop(&mut self) {
+        self.ppp.set_config(None);
+    }
+}
+
+/// Configures the stack with the address negotiated by a [`Ppp`] link.
+pub struct PppConfigurator<'a> {
+    config: &'a RefCell<Option<Config>>,
+    returned: Option<Config>,
+}
+
+impl<'a> Configurator for PppConfigurator<'a> {
+    fn poll(
+        &mut self,
+        _iface: &mut Interface,
+        _sockets: &mut SocketSet,
+        _timestamp: SmolInstant,
+        _cx: &mut Context<'_>,
+    ) -> config::Event {
+        // The stack is woken through the device when the configuration changes.
+        let config = self.config.borrow();
+        if *config == self.returned {
+            return config::Event::NoChange;
+        }
+        self.returned = config.clone();
+        match &*config {
+            Some(config) => config::Event::Configured(config.clone()),
+            None => config::Event::Deconfigured,
+        }
+    }
+
+    fn deconfigure(&mut self, _iface: &mut Interface, _sockets: &mut SocketSet) {
+        self.returned = None;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Phase {
+    /// Negotiating the link with LCP.
+    Establish,
+    /// Sending our credentials with PAP.
+    Authenticate,
+    /// Negotiating addresses with IPCP.
+    Network,
+    /// Passing IP packets.
+    Opened,
+}
+
+/// The progress of a Configure-Request exchange in each direction.
+#[derive(Default)]
+struct Negotiation {
+    /// The peer acknowledged our options.
+    ack_received: bool,
+    /// We acknowledged the peer's options.
+    ack_sent: bool,
+}
+
+impl Negotiation {
+    fn opened(&self) -> bool {
+        self.ack_received && self.ack_sent
+    }
+}
+
+enum Input {
+    /// The decoder has a frame.
+    Frame,
+    /// The stack sent a packet.
+    Transmit,
+    /// The restart timer expired.
+    Timeout,
+}
+
+struct Runner<'a, S, const N: usize, const MTU: usize> {
+    ppp: &'a Ppp<N, MTU>,
+    stream: &'a mut S,
+    credentials: &'a PppConfig<'a>,
+    decoder: Decoder<MTU>,
+    tx_buf: [u8; MTU],
+
+    phase: Phase,
+    /// Identifier of our last request.
+    id: u8,
+    /// Requests sent in the current phase.
+    retries: u8,
+    /// When to send the last request again.
+    deadline: Option<Instant>,
+
+    /// Whether to ask for an MRU of `MTU`, until the peer refuses.
+    send_mru: bool,
+    magic: Option<u32>,
+    /// Whether the peer asked us to authenticate.
+    authenticate: bool,
+    lcp: Negotiation,
+
+    ipcp: Negotiation,
+    /// Our address, and DNS servers, as suggested by the peer. The DNS servers are `None`
+    /// once the peer refuses to give them.
+    address: Ipv4Address,
+    peer_address: Option<Ipv4Address>,
+    dns: [Option<Ipv4Address>; 2],
+}
+
+impl<'a, S: AsyncBufRead + AsyncWrite + Unpin, const N: usize, const MTU: usize>
+    Runner<'a, S, N, MTU>
+{
+    async fn run(&mut self) -> Result<(), PppError> {
+        self.send_lcp_request().await?;
+        loop {
+            let mut timer = self.deadline.map(Timer::at);
+            match poll_fn(|cx| self.poll_input(cx, &mut timer)).await? {
+                Input::Frame => {
+                    if self.handle_frame().await? {
+                        info!("PPP: link terminated by peer");
+                        return Ok(());
+                    }
+                }
+                Input::Transmit => self.transmit().await?,
+                Input::Timeout => self.timeout().await?,
+            }
+        }
+    }
+
+    fn poll_input(
+        &mut self,
+        cx: &mut Context<'_>,
+        timer: &mut Option<Timer>,
+    ) -> Poll<Result<Input, PppError>> {
+        loop {
+            let data = match Pin::new(&mut *self.stream).poll_fill_buf(cx) {
+                Poll::Ready(Ok(data)) => data,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => break,
+            };
+            if data.is_empty() {
+                return Poll::Ready(Err(io::Error::UnexpectedEof.into()));
+            }
+            let (n, done) = self.decoder.feed(data);
+            Pin::new(&mut *self.stream).consume(n);
+            if done {
+                return Poll::Ready(Ok(Input::Frame));
+            }
+        }
+
+        if self.phase == Phase::Opened && self.ppp.channel.poll_tx_ready(cx.waker()).is_ready() {
+            return Poll::Ready(Ok(Input::Transmit));
+        }
+        if let Some(timer) = timer {
+            if Pin::new(timer).poll(cx).is_ready() {
+                return Poll::Ready(Ok(Input::Timeout));
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Handle the frame in the decoder. Returns whether the peer terminated the link.
+    async fn handle_frame(&mut self) -> Result<bool, PppError> {
+        let (protocol, information) = match self.decoder.frame() {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+
+        if protocol == PROTO_IPV4 {
+            if self.phase == Phase::Opened {
+                let pushed = self.ppp.channel.push_rx(|buf| {
+                    buf[..information.len()].copy_from_slice(information);
+                    Some(information.len())
+                });
+                if !pushed {
+                    debug!("PPP: rx queue full, dropping packet");
+                }
+            }
+            return Ok(false);
+        }
+
+        // Copied out of the decoder, since answering needs `self`.
+        let mut packet = [0; MAX_CONTROL_LEN];
+        let len = information.len().min(MAX_CONTROL_LEN);
+        packet[..len].copy_from_slice(&information[..len]);
+        let truncated = information.len() > MAX_CONTROL_LEN;
+        let packet = &packet[..len];
+
+        match protocol {
+            PROTO_LCP | PROTO_PAP | PROTO_IPCP if truncated => {
+                warn!("PPP: control packet too long, dropping it");
+                Ok(false)
+            }
+            PROTO_LCP => self.handle_lcp(packet).await,
+            PROTO_PAP => self.handle_pap(packet).await.map(|_| false),
+            PROTO_IPCP => self.handle_ipcp(packet).await.map(|_| false),
+            _ => {
+                if self.phase != Phase::Establish {
+                    let mut data = [0; MAX_CONTROL_LEN - 4];
+                    data[..2].copy_from_slice(&protocol.to_be_bytes());
+                    let len = packet.len().min(data.len() - 2);
+                    data[2..2 + len].copy_from_slice(&packet[..len]);
+                    let id = self.next_id();
+                    self.send_control(PROTO_LCP, PROTOCOL_REJECT, id, &data[..2 + len])
+                        .await?;
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    async fn handle_lcp(&mut self, packet: &[u8]) -> Result<bool, PppError> {
+        let (code, id, data) = match parse_control(packet) {
+            Some(parsed) => parsed,
+            None => return Ok(false),
+        };
+        match code {
+            CONFIGURE_REQUEST if options_valid(data) => {
+                if self.phase != Phase::Establish {
+                    // Everything above LCP is gone when the peer renegotiates the link.
+                    info!("PPP: peer renegotiating link");
+                    self.restart().await?;
+                }
+                let (code, reply) = answer(data, |ty, value| match ty {
+                    LCP_MRU | LCP_ACCM | LCP_MAGIC => Verdict::Ack,
+                    LCP_AUTH if value == &AUTH_PAP[..] => Verdict::Ack,
+                    LCP_AUTH => Verdict::Nak(&AUTH_PAP),
+                    _ => Verdict::Reject,
+                });
+                self.lcp.ack_sent = code == CONFIGURE_ACK;
+                if self.lcp.ack_sent {
+                    self.authenticate = options(data).any(|(ty, _, _)| ty == LCP_AUTH);
+                }
+                self.send_control(PROTO_LCP, code, id, reply.as_slice())
+                    .await?;
+                self.advance().await?;
+            }
+            CONFIGURE_ACK if id == self.id && self.phase == Phase::Establish => {
+                self.lcp.ack_received = true;
+                self.advance().await?;
+            }
+            CONFIGURE_NAK | CONFIGURE_REJECT if id == self.id && self.phase == Phase::Establish => {
+                for (ty, _, _) in options(data) {
+                    match (ty, code) {
+                        (LCP_MRU, _) => self.send_mru = false,
+                        // The peer thinks it sees its own magic number, pick another.
+                        (LCP_MAGIC, CONFIGURE_NAK) => self.magic = Some(new_magic()),
+                        (LCP_MAGIC, _) => self.magic = None,
+                        _ => {}
+                    }
+                }
+                self.send_lcp_request().await?;
+            }
+            TERMINATE_REQUEST => {
+                self.send_control(PROTO_LCP, TERMINATE_ACK, id, &[]).await?;
+                return Ok(true);
+            }
+            ECHO_REQUEST if self.phase != Phase::Establish => {
+                let mut reply = [0; MAX_CONTROL_LEN - 4];
+                let magic = self.magic.unwrap_or(0).to_be_bytes();
+                reply[..4].copy_from_slice(&magic);
+                let len = data.len().max(4);
+                reply[4..len].copy_from_slice(&data[4.min(data.len())..]);
+                self.send_control(PROTO_LCP, ECHO_REPLY, id, &reply[..len])
+                    .await?;
+            }
+            CONFIGURE_REQUEST | CONFIGURE_ACK | CONFIGURE_NAK | CONFIGURE_REJECT
+            | TERMINATE_ACK | CODE_REJECT | PROTOCOL_REJECT | ECHO_REQUEST | ECHO_REPLY
+            | DISCARD_REQUEST => {}
+            _ => {
+                self.send_control(PROTO_LCP, CODE_REJECT, id, packet)
+                    .await?
+            }
+        }
+        Ok(false)
+    }
+
+    async fn handle_pap(&mut self, packet: &[u8]) -> Result<(), PppError> {
+        let (code, id, _) = match parse_control(packet) {
+            Some(parsed) => parsed,
+            None => return Ok(()),
+        };
+        if self.phase != Phase::Authenticate || id != self.id {
+            return Ok(());
+        }
+        match code {
+            PAP_ACK => self.start_network().await,
+            PAP_NAK => Err(PppError::AuthenticationFailed),
+            _ => Ok(()),
+        }
+    }
+
+    async fn handle_ipcp(&mut self, packet: &[u8]) -> Result<(), PppError> {
+        // Network protocols are only negotiated once the link is established.
+        if self.phase < Phase::Network {
+            return Ok(());
+        }
+        let (code, id, data) = match parse_control(packet) {
+            Some(parsed) => parsed,
+            None => return Ok(()),
+        };
+        match code {
+            CONFIGURE_REQUEST if options_valid(data) => {
+                if self.phase == Phase::Opened {
+                    info!("PPP: peer renegotiating addresses");
+                    self.start_network().await?;
+                }
+                let (code, reply) = answer(data, |ty, value| match ty {
+                    IPCP_ADDRESS if value.len() == 4 => Verdict::Ack,
+                    _ => Verdict::Reject,
+                });
+                self.ipcp.ack_sent = code == CONFIGURE_ACK;
+                if self.ipcp.ack_sent {
+                    self.peer_address = options(data)
+                        .find(|(ty, _, _)| *ty == IPCP_ADDRESS)
+                        .map(|(_, value, _)| Ipv4Address::from_bytes(value));
+                }
+                self.send_control(PROTO_IPCP, code, id, reply.as_slice())
+                    .await?;
+                self.advance().await?;
+            }
+            CONFIGURE_ACK if id == self.id && self.phase == Phase::Network => {
+                self.ipcp.ack_received = true;
+                self.advance().await?;
+            }
+            CONFIGURE_NAK | CONFIGURE_REJECT if id == self.id && self.phase == Phase::Network => {
+                for (ty, value, _) in options(data) {
+                    let slot = match ty {
+                        IPCP_DNS1 => &mut self.dns[0],
+                        IPCP_DNS2 => &mut self.dns[1],
+                        IPCP_ADDRESS if code == CONFIGURE_NAK && value.len() == 4 => {
+                            self.address = Ipv4Address::from_bytes(value);
+                            continue;
+                        }
+                        _ => continue,
+                    };
+                    *slot = match code {
+                        CONFIGURE_NAK if value.len() == 4 => Some(Ipv4Address::from_bytes(value)),
+                        _ => None,
+                    };
+                }
+                self.send_ipcp_request().await?;
+            }
+            TERMINATE_REQUEST => {
+                self.send_control(PROTO_IPCP, TERMINATE_ACK, id, &[])
+                    .await?;
+                if self.phase == Phase::Opened {
+                    self.start_network().await?;
+                }
+            }
+            CONFIGURE_REQUEST | CONFIGURE_ACK | CONFIGURE_NAK | CONFIGURE_REJECT
+            | TERMINATE_ACK | CODE_REJECT => {}
+            _ => {
+                self.send_control(PROTO_IPCP, CODE_REJECT, id, packet)
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    /// Move on to the next phase, if the current one is complete.
+    async fn advance(&mut self) -> Result<(), PppError> {
+        if self.phase == Phase::Establish && self.lcp.opened() {
+            if self.authenticate {
+                debug!("PPP: link established, authenticating");
+                self.phase = Phase::Authenticate;
+                self.retries = 0;
+                self.send_pap_request().await?;
+            } else {
+                self.start_network().await?;
+            }
+        }
+        if self.phase == Phase::Network && self.ipcp.opened() {
+            info!("PPP: up, address {}", self.address);
+            self.phase = Phase::Opened;
+            self.deadline = None;
+
+            let mut dns_servers = Vec::new();
+            for dns in self.dns.iter().flatten() {
+                if !dns.is_unspecified() {
+                    let _ = dns_servers.push(*dns);
+                }
+            }
+            self.ppp.set_config(Some(Config {
+                address: Ipv4Cidr::new(self.address, 32),
+                gateway: self.peer_address,
+                dns_servers,
+                dhcp: None,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Start negotiating the link from scratch.
+    async fn restart(&mut self) -> Result<(), PppError> {
+        self.ppp.set_config(None);
+        self.phase = Phase::Establish;
+        self.lcp = Negotiation::default();
+        self.ipcp = Negotiation::default();
+        self.retries = 0;
+        self.send_lcp_request().await
+    }
+
+    /// Start negotiating addresses, from scratch.
+    async fn start_network(&mut self) -> Result<(), PppError> {
+        debug!("PPP: negotiating addresses");
+        self.ppp.set_config(None);
+        self.phase = Phase::Network;
+        self.ipcp = Negotiation::default();
+        self.retries = 0;
+        self.send_ipcp_request().await
+    }
+
+    async fn timeout(&mut self) -> Result<(), PppError> {
+        if self.retries >= MAX_REQUESTS {
+            warn!("PPP: no answer from peer");
+            return Err(PppError::Timeout);
+        }
+        match self.phase {
+            Phase::Establish if !self.lcp.ack_received => self.send_lcp_request().await,
+            Phase::Authenticate => self.send_pap_request().await,
+            Phase::Network if !self.ipcp.ack_received => self.send_ipcp_request().await,
+            // Waiting for the peer's request.
+            _ => {
+                self.retries += 1;
+                self.restart_timer();
+                Ok(())
+            }
+        }
+    }
+
+    async fn transmit(&mut self) -> Result<(), PppError> {
+        let tx_buf = &mut self.tx_buf;
+        let len = self.ppp.channel.pop_tx(|packet| {
+            tx_buf[..packet.len()].copy_from_slice(packet);
+            packet.len()
+        });
+        if let Some(len) = len {
+            write_frame(&mut *self.stream, PROTO_IPV4, &self.tx_buf[..len]).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_lcp_request(&mut self) -> Result<(), PppError> {
+        let mut options = Options::new();
+        if self.send_mru {
+            options.push(LCP_MRU, &(MTU as u16).to_be_bytes());
+        }
+        if let Some(magic) = self.magic {
+            options.push(LCP_MAGIC, &magic.to_be_bytes());
+        }
+        self.send_request(PROTO_LCP, CONFIGURE_REQUEST, options.as_slice())
+            .await
+    }
+
+    async fn send_pap_request(&mut self) -> Result<(), PppError> {
+        let mut data = [0; MAX_CONTROL_LEN - 4];
+        // Overlong credentials are truncated to fit.
+        let username = self.credentials.username.as_bytes();
+        let username = &username[..username.len().min(data.len() / 2 - 1)];
+        let password = self.credentials.password.as_bytes();
+        let password = &password[..password.len().min(data.len() / 2 - 1)];
+
+        data[0] = username.len() as u8;
+        data[1..1 + username.len()].copy_from_slice(username);
+        let pos = 1 + username.len();
+        data[pos] = password.len() as u8;
+        data[pos + 1..pos + 1 + password.len()].copy_from_slice(password);
+        let len = pos + 1 + password.len();
+
+        self.send_request(PROTO_PAP, PAP_REQUEST, &data[..len])
+            .await
+    }
+
+    async fn send_ipcp_request(&mut self) -> Result<(), PppError> {
+        let mut options = Options::new();
+        options.push(IPCP_ADDRESS, &self.address.0);
+        if let Some(dns) = self.dns[0] {
+            options.push(IPCP_DNS1, &dns.0);
+        }
+        if let Some(dns) = self.dns[1] {
+            options.push(IPCP_DNS2, &dns.0);
+        }
+        self.send_request(PROTO_IPCP, CONFIGURE_REQUEST, options.as_slice())
+            .await
+    }
+
+    /// Send a request, to be sent again if there's no answer in time.
+    async fn send_request(&mut self, protocol: u16, code: u8, data: &[u8]) -> Result<(), PppError> {
+        self.retries += 1;
+        self.restart_timer();
+        let id = self.next_id();
+        self.send_control(protocol, code, id, data).await
+    }
+
+    async fn send_control(
+        &mut self,
+        protocol: u16,
+        code: u8,
+        id: u8,
+        data: &[u8],
+    ) -> Result<(), PppError> {
+        let mut packet = [0; MAX_CONTROL_LEN];
+        let len = 4 + data.len().min(MAX_CONTROL_LEN - 4);
+        packet[0] = code;
+        packet[1] = id;
+        packet[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        packet[4..len].copy_from_slice(&data[..len - 4]);
+        write_frame(&mut *self.stream, protocol, &packet[..len]).await?;
+        Ok(())
+    }
+
+    fn next_id(&mut self) -> u8 {
+        self.id = self.id.wrapping_add(1);
+        self.id
+    }
+
+    fn restart_timer(&mut self) {
+        self.deadline = Some(Instant::now() + RESTART_INTERVAL);
+    }
+}
+
+fn new_magic() -> u32 {
+    let mut magic = [0; 4];
+    rand(&mut magic);
+    u32::from_le_bytes(magic)
+}
+
+/// Split a control packet into its code, identifier and data.
+fn parse_control(packet: &[u8]) -> Option<(u8, u8, &[u8])> {
+    if packet.len() < 4 {
+        return None;
+    }
+    let len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if len < 4 || len > packet.len() {
+        return None;
+    }
+    Some((packet[0], packet[1], &packet[4..len]))
+}
+
+/// Iterate over the options of a Configure packet, as their type, value, and the whole
+/// option. Stops at the first malformed option.
+fn options(data: &[u8]) -> impl Iterator<Item = (u8, &[u8], &[u8])> {
+    let mut rest = data;
+    core::iter::from_fn(move || {
+        if rest.len() < 2 {
+            return None;
+        }
+        let len = rest[1] as usize;
+        if len < 2 || len > rest.len() {
+            return None;
+        }
+        let (option, tail) = rest.split_at(len);
+        rest = tail;
+        Some((option[0], &option[2..], option))
+    })
+}
+
+fn options_valid(data: &[u8]) -> bool {
+    options(data)
+        .map(|(_, _, option)| option.len())
+        .sum::<usize>()
+        == data.len()
+}
+
+/// Options of a control packet being written.
+struct Options {
+    buf: [u8; MAX_CONTROL_LEN - 4],
+    len: usize,
+}
+
+impl Options {
+    fn new() -> Self {
+        Self {
+            buf: [0; MAX_CONTROL_LEN - 4],
+            len: 0,
+        }
+    }
+
+    /// Append raw bytes, such as whole options. They're dropped if they don't fit.
+    fn extend(&mut self, data: &[u8]) {
+        if let Some(buf) = self.buf.get_mut(self.len..self.len + data.len()) {
+            buf.copy_from_slice(data);
+            self.len += data.len();
+        }
+    }
+
+    fn push(&mut self, ty: u8, value: &[u8]) {
+        if self.len + 2 + value.len() <= self.buf.len() {
+            self.extend(&[ty, 2 + value.len() as u8]);
+            self.extend(value);
+        }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+enum Verdict {
+    Ack,
+    /// Suggest this value instead.
+    Nak(&'static [u8]),
+    Reject,
+}
+
+/// Work out the answer to a Configure-Request from the verdict on each option: a
+/// Configure-Reject with the options we don't support, else a Configure-Nak with the
+/// values we'd rather see, else a Configure-Ack.
+fn answer(request: &[u8], mut verdict: impl FnMut(u8, &[u8]) -> Verdict) -> (u8, Options) {
+    let mut rejected = Options::new();
+    let mut naked = Options::new();
+    for (ty, value, option) in options(request) {
+        match verdict(ty, value) {
+            Verdict::Ack => {}
+            Verdict::Nak(value) => naked.push(ty, value),
+            Verdict::Reject => rejected.extend(option),
+        }
+    }
+
+    if rejected.len > 0 {
+        (CONFIGURE_REJECT, rejected)
+    } else if naked.len > 0 {
+        (CONFIGURE_NAK, naked)
+    } else {
+        let mut acked = Options::new();
+        acked.extend(request);
+        (CONFIGURE_ACK, acked)
+    }
+}
@@ -0,0 +1,231 @@
+//! SLIP (RFC 1055), carrying IP packets over a byte stream such as a UART.
+//!
+//! [`Slip`] runs over any stream implementing [`AsyncBufRead`] and [`AsyncWrite`]. SLIP
+//! has no negotiation: the stack uses [`Slip::device`] as its device, carrying IP packets
+//! with [`Medium::Ip`], and is configured like with any other device, usually with a
+//! [`StaticConfigurator`](crate::StaticConfigurator). The link is up while [`Slip::run`]
+//! runs.
+
+use core::pin::Pin;
+use core::task::Poll;
+use embassy::io::{self, AsyncBufRead, AsyncWrite, AsyncWriteExt};
+use futures::future::poll_fn;
+use smoltcp::phy::Medium;
+
+use crate::channel::{Channel, ChannelDevice};
+use crate::fmt::*;
+
+const END: u8 = 0xc0;
+const ESC: u8 = 0xdb;
+const ESC_END: u8 = 0xdc;
+const ESC_ESC: u8 = 0xdd;
+
+/// The [`Device`](crate::Device) returned by [`Slip::device`].
+pub type SlipDevice<'a, const N: usize, const MTU: usize> = ChannelDevice<'a, N, MTU>;
+
+/// A SLIP link, queueing up to `N` packets of up to `MTU` bytes in each direction.
+///
+/// It isn't `Sync`, so it can't be placed in a `static` directly. It's usually put in a
+/// [`Forever`](embassy::util::Forever) instead, so that the stack and the task calling
+/// [`Slip::run`] can both borrow it for `'static`.
+pub struct Slip<const N: usize, const MTU: usize> {
+    channel: Channel<N, MTU>,
+}
+
+impl<const N: usize, const MTU: usize> Slip<N, MTU> {
+    pub const fn new() -> Self {
+        Self {
+            channel: Channel::new(),
+        }
+    }
+}
+
+enum Input {
+    /// The decoder has a packet of this length.
+    Packet(usize),
+    /// The stack sent a packet.
+    Transmit,
+    /// The stream was closed.
+    Closed,
+}
+
+impl<const N: usize, const MTU: usize> Slip<N, MTU> {
+    /// The device to pass to the stack.
+    pub fn device(&self) -> SlipDevice<'_, N, MTU> {
+        self.channel.device(Medium::Ip, [0; 6])
+    }
+
+    /// Pass packets over `stream`, until it's closed, which returns `Ok`, or until it fails.
+    pub async fn run<S: AsyncBufRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+    ) -> io::Result<()> {
+        self.channel.set_link_up(true);
+        // Takes the link down however `run` ends, including when it's dropped.
+        let _guard = LinkGuard {
+            channel: &self.channel,
+        };
+
+        let mut decoder = Decoder::<MTU>::new();
+        let mut tx_buf = [0; MTU];
+        loop {
+            let input = poll_fn(|cx| {
+                loop {
+                    let data = match Pin::new(&mut *stream).poll_fill_buf(cx) {
+                        Poll::Ready(Ok(data)) => data,
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                        Poll::Pending => break,
+                    };
+                    if data.is_empty() {
+                        return Poll::Ready(Ok(Input::Closed));
+                    }
+                    let (n, packet) = decoder.feed(data);
+                    Pin::new(&mut *stream).consume(n);
+                    if let Some(len) = packet {
+                        return Poll::Ready(Ok(Input::Packet(len)));
+                    }
+                }
+                if self.channel.poll_tx_ready(cx.waker()).is_ready() {
+                    return Poll::Ready(Ok(Input::Transmit));
+                }
+                Poll::Pending
+            })
+            .await?;
+
+            match input {
+                Input::Packet(len) => {
+                    let packet = &decoder.buf[..len];
+                    let pushed = self.channel.push_rx(|buf| {
+                        buf[..len].copy_from_slice(packet);
+                        Some(len)
+                    });
+                    if !pushed {
+                        debug!("SLIP: rx queue full, dropping packet");
+                    }
+                }
+                Input::Transmit => {
+                    let len = self.channel.pop_tx(|packet| {
+                        tx_buf[..packet.len()].copy_from_slice(packet);
+                        packet.len()
+                    });
+                    if let Some(len) = len {
+                        write_packet(stream, &tx_buf[..len]).await?;
+                    }
+                }
+                Input::Closed => return Ok(()),
+            }
+        }
+    }
+}
+
+struct LinkGuard<'a, const N: usize, const MTU: usize> {
+    channel: &'a Channel<N, MTU>,
+}
+
+impl<'a, const N: usize, const MTU: usize> Drop for LinkGuard<'a, N, MTU> {
+    fn drop(&mut self) {
+        self.channel.set_link_up(false);
+    }
+}
+
+/// Reassembles the packets received. Packets longer than `MTU`, or with a bad escape
+/// sequence, are dropped.
+struct Decoder<const MTU: usize> {
+    buf: [u8; MTU],
+    len: usize,
+    escaped: bool,
+    /// The packet is bad, skip to the next END.
+    discard: bool,
+}
+
+impl<const MTU: usize> Decoder<MTU> {
+    fn new() -> Self {
+        Self {
+            buf: [0; MTU],
+            len: 0,
+            escaped: false,
+            discard: false,
+        }
+    }
+
+    /// Feed received bytes. Stops after a packet is complete.
+    ///
+    /// Returns how many bytes were used, and the length of the packet if one is complete.
+    /// It's then at the start of `buf`, until the next call.
+    fn feed(&mut self, data: &[u8]) -> (usize, Option<usize>) {
+        for (i, &b) in data.iter().enumerate() {
+            if b == END {
+                let len = self.len;
+                let valid = !self.discard && !self.escaped && len > 0;
+                self.len = 0;
+                self.escaped = false;
+                self.discard = false;
+                // Empty packets come from the END sent before each packet, to flush line
+                // noise.
+                if valid {
+                    return (i + 1, Some(len));
+                }
+                continue;
+            }
+            if self.discard {
+                continue;
+            }
+
+            let b = match (self.escaped, b) {
+                (false, ESC) => {
+                    self.escaped = true;
+                    continue;
+                }
+                (false, b) => b,
+                (true, ESC_END) => END,
+                (true, ESC_ESC) => ESC,
+                (true, _) => {
+                    self.discard = true;
+                    continue;
+                }
+            };
+            self.escaped = false;
+            if self.len == MTU {
+                self.discard = true;
+            } else {
+                self.buf[self.len] = b;
+                self.len += 1;
+            }
+        }
+        (data.len(), None)
+    }
+}
+
+async fn write_packet<S: AsyncWrite + Unpin>(stream: &mut S, packet: &[u8]) -> io::Result<()> {
+    let mut buf = [0; 64];
+    buf[0] = END;
+    let mut len = 1;
+    for &b in packet {
+        if len + 2 > buf.len() {
+            stream.write_all(&buf[..len]).await?;
+            len = 0;
+        }
+        match b {
+            END => {
+                buf[len] = ESC;
+                buf[len + 1] = ESC_END;
+                len += 2;
+            }
+            ESC => {
+                buf[len] = ESC;
+                buf[len + 1] = ESC_ESC;
+                len += 2;
+            }
+            b => {
+                buf[len] = b;
+                len += 1;
+            }
+        }
+    }
+    if len == buf.len() {
+        stream.write_all(&buf[..len]).await?;
+        len = 0;
+    }
+    buf[len] = END;
+    stream.write_all(&buf[..len + 1]).await
+}
@@ -0,0 +1,328 @@
+//! SNTP client (RFC 4330).
+//!
+//! [`Sntp::run`] periodically asks a time server for the current time, and works out the
+//! offset between UTC and [`Instant`]. Other tasks can then get the current UTC time, with
+//! error bounds, from [`Sntp::now`].
+
+use core::cell::RefCell;
+use core::cmp::min;
+use core::task::Poll;
+use embassy::time::{with_timeout, Duration, Instant, Timer};
+use embassy::util::MultiWakerRegistration;
+use heapless::consts::*;
+use heapless::Vec;
+
+use crate::fmt::*;
+use crate::{IpAddress, Ipv4Address, Stack, UdpPacketMetadata, UdpSocket};
+
+const NTP_PORT: u16 = 123;
+const PACKET_LEN: usize = 48;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// How fast the local clock is assumed to drift, in parts per million. The error bound
+/// grows by this much after each sync.
+const DRIFT_PPM: u64 = 100;
+
+/// Tasks that can wait for the first sync at once.
+const WAITERS_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SntpError {
+    /// No server is configured, or learned from DHCP.
+    NoServer,
+    /// The request couldn't be sent.
+    Network,
+    /// The server didn't answer in time.
+    Timeout,
+    /// The server asked us to go away, or to slow down (a "kiss-o'-death" reply).
+    KissOfDeath,
+    /// The server isn't synchronized itself.
+    Unsynchronized,
+}
+
+/// Settings for [`Sntp`].
+pub struct SntpConfig {
+    /// Servers to ask, in order of preference.
+    pub servers: Vec<Ipv4Address, U3>,
+    /// Also ask the NTP servers learned from DHCP, after the ones above.
+    pub use_dhcp_servers: bool,
+    /// Time between syncs.
+    pub interval: Duration,
+    /// Time before retrying after a failed sync. It doubles after each failure, up to
+    /// `interval`.
+    pub min_retry: Duration,
+    /// How long to wait for each server to answer.
+    pub timeout: Duration,
+}
+
+impl Default for SntpConfig {
+    fn default() -> Self {
+        Self {
+            servers: Vec::new(),
+            use_dhcp_servers: true,
+            interval: Duration::from_secs(1024),
+            min_retry: Duration::from_secs(4),
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// The result of a successful sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SntpSync {
+    /// The server that answered.
+    pub server: Ipv4Address,
+    /// When the answer was received.
+    pub at: Instant,
+    /// UTC time minus the time since boot, in microseconds.
+    pub offset_micros: i64,
+    /// Round-trip time of the request, minus the time spent on the server.
+    pub delay: Duration,
+    /// Error bound of the offset at the time of the sync.
+    pub error: Duration,
+}
+
+/// An estimate of the current UTC time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UtcTime {
+    /// Microseconds since the Unix epoch, ignoring leap seconds.
+    pub unix_micros: u64,
+    /// The real time is within `error` of `unix_micros`.
+    pub error: Duration,
+}
+
+impl UtcTime {
+    pub fn unix_secs(&self) -> u64 {
+        self.unix_micros / 1_000_000
+    }
+}
+
+/// SNTP client, keeping track of the UTC time.
+pub struct Sntp<'a> {
+    stack: &'a Stack,
+    config: SntpConfig,
+    state: RefCell<State>,
+}
+
+struct State {
+    last_sync: Option<SntpSync>,
+    wakers: MultiWakerRegistration<WAITERS_LEN>,
+}
+
+impl<'a> Sntp<'a> {
+    pub fn new(stack: &'a Stack, config: SntpConfig) -> Self {
+        Self {
+            stack,
+            config,
+            state: RefCell::new(State {
+                last_sync: None,
+                wakers: MultiWakerRegistration::new(),
+            }),
+        }
+    }
+
+    /// Returns the last successful sync, if any.
+    pub fn last_sync(&self) -> Option<SntpSync> {
+        self.state.borrow().last_sync
+    }
+
+    /// Returns the current UTC time, or `None` if no sync succeeded yet.
+    pub fn now(&self) -> Option<UtcTime> {
+        let sync = self.last_sync()?;
+        let now = Instant::now();
+        let drift = now.saturating_duration_since(sync.at).as_micros() * DRIFT_PPM / 1_000_000;
+        Some(UtcTime {
+            unix_micros: (micros(now) + sync.offset_micros) as u64,
+            error: sync.error + Duration::from_micros(drift),
+        })
+    }
+
+    /// Wait until a sync succeeds, and return the current UTC time.
+    pub async fn wait_synced(&self) -> UtcTime {
+        futures::future::poll_fn(|cx| match self.now() {
+            Some(now) => Poll::Ready(now),
+            None => {
+                self.state.borrow_mut().wakers.register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Keep syncing with the servers. This must be called in a task for the time to be known.
+    pub async fn run(&self) -> ! {
+        let mut rx_meta = [UdpPacketMetadata::EMPTY; 2];
+        let mut rx_buffer = [0; 2 * PACKET_LEN];
+        let mut tx_meta = [UdpPacketMetadata::EMPTY; 1];
+        let mut tx_buffer = [0; PACKET_LEN];
+        let mut socket = UdpSocket::new(
+            self.stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        unwrap!(socket.bind(0));
+
+        let mut retry = self.config.min_retry;
+        loop {
+            self.stack.wait_config_up().await;
+
+            match self.sync(&mut socket).await {
+                Ok(sync) => {
+                    debug!(
+                        "SNTP sync with {}: offset {} us, delay {} us",
+                        sync.server,
+                        sync.offset_micros,
+                        sync.delay.as_micros()
+                    );
+                    let mut state = self.state.borrow_mut();
+                    state.last_sync = Some(sync);
+                    state.wakers.wake();
+                    drop(state);
+
+                    retry = self.config.min_retry;
+                    Timer::after(self.config.interval).await;
+                }
+                Err(e) => {
+                    warn!("SNTP sync failed: {:?}", e);
+                    Timer::after(retry).await;
+                    retry = min(retry * 2, self.config.interval);
+                }
+            }
+        }
+    }
+
+    /// Try each server in turn until one answers.
+    async fn sync(&self, socket: &mut UdpSocket<'_>) -> Result<SntpSync, SntpError> {
+        let mut servers: Vec<Ipv4Address, U8> = Vec::new();
+        for &server in &self.config.servers {
+            let _ = servers.push(server);
+        }
+        if self.config.use_dhcp_servers {
+            for id in self.stack.interfaces() {
+                if let Some(dhcp) = self.stack.interface_config(id).and_then(|c| c.dhcp) {
+                    for &server in &dhcp.ntp_servers {
+                        if !servers.contains(&server) {
+                            let _ = servers.push(server);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut res = Err(SntpError::NoServer);
+        for &server in &servers {
+            res = self.query(socket, server).await;
+            if res.is_ok() {
+                break;
+            }
+        }
+        res
+    }
+
+    async fn query(
+        &self,
+        socket: &mut UdpSocket<'_>,
+        server: Ipv4Address,
+    ) -> Result<SntpSync, SntpError> {
+        // Drop stale replies from earlier queries.
+        let mut buf = [0; PACKET_LEN];
+        while let Ok(Ok(_)) =
+            with_timeout(Duration::from_ticks(0), socket.recv_from(&mut buf)).await
+        {}
+
+        // The transmit timestamp is only used to match the reply, since we don't know the
+        // time yet. The server echoes it in the originate timestamp.
+        let t1 = Instant::now();
+        let nonce = t1.as_ticks().to_be_bytes();
+        let mut request = [0; PACKET_LEN];
+        request[0] = 0x23; // Version 4, client mode
+        request[40..48].copy_from_slice(&nonce);
+        socket
+            .send_to(&request, (IpAddress::Ipv4(server), NTP_PORT))
+            .await
+            .map_err(|_| SntpError::Network)?;
+
+        let deadline = t1 + self.config.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let (len, from) = match with_timeout(remaining, socket.recv_from(&mut buf)).await {
+                Ok(Ok(res)) => res,
+                Ok(Err(_)) => return Err(SntpError::Network),
+                Err(_) => return Err(SntpError::Timeout),
+            };
+            let t4 = Instant::now();
+            if from.addr != IpAddress::Ipv4(server) || len < PACKET_LEN || buf[24..32] != nonce {
+                continue;
+            }
+            return parse_reply(&buf, server, t1, t4);
+        }
+    }
+}
+
+fn parse_reply(
+    buf: &[u8; PACKET_LEN],
+    server: Ipv4Address,
+    t1: Instant,
+    t4: Instant,
+) -> Result<SntpSync, SntpError> {
+    let leap = buf[0] >> 6;
+    let mode = buf[0] & 0x07;
+    let stratum = buf[1];
+    if mode != 4 && mode != 5 {
+        return Err(SntpError::Unsynchronized);
+    }
+    if stratum == 0 {
+        return Err(SntpError::KissOfDeath);
+    }
+    if leap == 3 || buf[40..48] == [0; 8] {
+        return Err(SntpError::Unsynchronized);
+    }
+
+    let root_delay = short_to_micros(&buf[4..8]);
+    let root_dispersion = short_to_micros(&buf[8..12]);
+    let t2 = timestamp_to_micros(&buf[32..40]);
+    let t3 = timestamp_to_micros(&buf[40..48]);
+    let at = t4;
+    let t1 = micros(t1);
+    let t4 = micros(t4);
+
+    let offset = ((t2 - t1) + (t3 - t4)) / 2;
+    let delay = ((t4 - t1) - (t3 - t2)).max(0) as u64;
+    Ok(SntpSync {
+        server,
+        at,
+        offset_micros: offset,
+        delay: Duration::from_micros(delay),
+        error: Duration::from_micros(delay / 2 + root_delay / 2 + root_dispersion),
+    })
+}
+
+/// Microseconds since boot.
+fn micros(t: Instant) -> i64 {
+    t.duration_since(Instant::from_ticks(0)).as_micros() as i64
+}
+
+/// Convert an NTP timestamp to microseconds since the Unix epoch.
+fn timestamp_to_micros(b: &[u8]) -> i64 {
+    let secs = u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64;
+    let frac = u32::from_be_bytes([b[4], b[5], b[6], b[7]]) as u64;
+    // Timestamps wrap in 2036. Ones with the top bit clear are taken to be after that.
+    let secs = if secs & 0x8000_0000 != 0 {
+        secs - NTP_UNIX_OFFSET
+    } else {
+        secs + (1 << 32) - NTP_UNIX_OFFSET
+    };
+    (secs * 1_000_000 + (frac * 1_000_000 >> 32)) as i64
+}
+
+/// Convert an NTP short format duration (16.16 fixed point seconds) to microseconds.
+fn short_to_micros(b: &[u8]) -> u64 {
+    let val = u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64;
+    val * 1_000_000 >> 16
+}
@@ -1,64 +1,329 @@
 use core::cell::RefCell;
 use core::future::Future;
+use core::pin::Pin;
 use core::task::Context;
 use core::task::Poll;
 use embassy::time::{Instant, Timer};
-use embassy::util::ThreadModeMutex;
-use embassy::util::{Forever, WakerRegistration};
+use embassy::util::{MultiWakerRegistration, WakerRegistration};
 use futures::pin_mut;
+use futures::Stream;
+use heapless::consts::*;
+use heapless::Vec;
 use smoltcp::iface::InterfaceBuilder;
 #[cfg(feature = "medium-ethernet")]
 use smoltcp::iface::{Neighbor, NeighborCache, Route, Routes};
 use smoltcp::phy::Device as _;
 use smoltcp::phy::Medium;
-use smoltcp::socket::SocketSetItem;
+use smoltcp::socket::{Socket, SocketHandle, SocketSetItem};
 use smoltcp::time::Instant as SmolInstant;
 #[cfg(feature = "medium-ethernet")]
 use smoltcp::wire::EthernetAddress;
 use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address, Ipv4Cidr};
 
 use crate::config::Configurator;
-use crate::config::Event;
-use crate::device::{Device, DeviceAdapter, LinkState};
+use crate::config::{Config, Event};
+use crate::device::{Device, DeviceAdapter, InterfaceStats, LinkState};
 use crate::fmt::*;
-use crate::{Interface, SocketSet};
+use crate::pcap::Tap;
+use crate::{Error, Interface, Result, SocketSet};
 
 const ADDRESSES_LEN: usize = 1;
 const NEIGHBOR_CACHE_LEN: usize = 8;
-const SOCKETS_LEN: usize = 2;
+#[cfg(feature = "igmp")]
+const MULTICAST_GROUPS_LEN: usize = 4;
 const LOCAL_PORT_MIN: u16 = 1025;
 const LOCAL_PORT_MAX: u16 = 65535;
-
-struct StackResources {
+const EVENT_QUEUE_LEN: usize = 4;
+const STATE_WAITERS_LEN: usize = 4;
+
+/// Memory used by one interface of a [`Stack`].
+///
+/// `SOCK` is the maximum number of sockets on the interface. Configurators may
+/// need sockets of their own, for example `DhcpConfigurator` uses one.
+pub struct StackResources<const SOCK: usize> {
     addresses: [IpCidr; ADDRESSES_LEN],
-    sockets: [Option<SocketSetItem<'static>>; SOCKETS_LEN],
+    sockets: [Option<SocketSetItem<'static>>; SOCK],
 
     #[cfg(feature = "medium-ethernet")]
     routes: [Option<(IpCidr, Route)>; 1],
     #[cfg(feature = "medium-ethernet")]
     neighbor_cache: [Option<(IpAddress, Neighbor)>; NEIGHBOR_CACHE_LEN],
+
+    #[cfg(feature = "igmp")]
+    multicast_groups: [Option<(Ipv4Address, ())>; MULTICAST_GROUPS_LEN],
+}
+
+impl<const SOCK: usize> StackResources<SOCK> {
+    pub fn new() -> Self {
+        const NONE_SOCKET: Option<SocketSetItem<'static>> = None;
+        Self {
+            addresses: [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 32)],
+            sockets: [NONE_SOCKET; SOCK],
+
+            #[cfg(feature = "medium-ethernet")]
+            routes: [None; 1],
+            #[cfg(feature = "medium-ethernet")]
+            neighbor_cache: [None; NEIGHBOR_CACHE_LEN],
+
+            #[cfg(feature = "igmp")]
+            multicast_groups: [None; MULTICAST_GROUPS_LEN],
+        }
+    }
 }
 
-static STACK_RESOURCES: Forever<StackResources> = Forever::new();
-static STACK: ThreadModeMutex<RefCell<Option<Stack>>> = ThreadModeMutex::new(RefCell::new(None));
+/// Identifies one of the network interfaces added to the stack.
+///
+/// Interfaces are numbered in the order they were added. When several interfaces
+/// could reach a destination, the one with the lowest id is preferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceId(u8);
+
+impl InterfaceId {
+    /// The interface created by [`Stack::new`].
+    pub const FIRST: InterfaceId = InterfaceId(0);
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
 
-pub(crate) struct Stack {
+pub(crate) struct Iface {
     iface: Interface,
     pub sockets: SocketSet,
+    /// The number of sockets `sockets` has room for, since adding one more panics.
+    socket_capacity: usize,
     link_up: bool,
-    config_up: bool,
-    next_local_port: u16,
+    config: Option<Config>,
     configurator: &'static mut dyn Configurator,
+}
+
+/// A network stack.
+///
+/// The stack owns one or more interfaces, and must be driven by awaiting
+/// [`Stack::run`] in a task. Sockets borrow the stack they were created on.
+pub struct Stack {
+    inner: RefCell<Inner>,
+}
+
+pub(crate) struct Inner {
+    ifaces: Vec<Iface, U4>,
+    next_local_port: u16,
     waker: WakerRegistration,
+    events: Events,
+}
+
+/// The most recent configuration events, kept so every [`ConfigEvents`] stream sees them.
+struct Events {
+    queue: [Option<(InterfaceId, Event)>; EVENT_QUEUE_LEN],
+    /// Number of events pushed so far. The latest one is at `queue[(seq - 1) % EVENT_QUEUE_LEN]`.
+    seq: u32,
+    /// Tasks waiting for link or configuration state changes.
+    wakers: MultiWakerRegistration<STATE_WAITERS_LEN>,
+}
+
+impl Events {
+    fn new() -> Self {
+        const NONE_EVENT: Option<(InterfaceId, Event)> = None;
+        Self {
+            queue: [NONE_EVENT; EVENT_QUEUE_LEN],
+            seq: 0,
+            wakers: MultiWakerRegistration::new(),
+        }
+    }
+
+    fn push(&mut self, id: InterfaceId, event: Event) {
+        self.queue[self.seq as usize % EVENT_QUEUE_LEN] = Some((id, event));
+        self.seq = self.seq.wrapping_add(1);
+        self.wakers.wake();
+    }
 }
 
 impl Stack {
-    pub(crate) fn with<R>(f: impl FnOnce(&mut Stack) -> R) -> R {
-        let mut stack = STACK.borrow().borrow_mut();
-        let stack = stack.as_mut().unwrap();
-        f(stack)
+    /// Create a new stack with a single interface.
+    ///
+    /// More interfaces can be added afterwards with [`Stack::add_interface`].
+    pub fn new<const SOCK: usize>(
+        device: &'static mut dyn Device,
+        configurator: &'static mut dyn Configurator,
+        resources: &'static mut StackResources<SOCK>,
+    ) -> Self {
+        let local_port = loop {
+            let mut res = [0u8; 2];
+            rand(&mut res);
+            let port = u16::from_le_bytes(res);
+            if port >= LOCAL_PORT_MIN && port <= LOCAL_PORT_MAX {
+                break port;
+            }
+        };
+
+        let stack = Self {
+            inner: RefCell::new(Inner {
+                ifaces: Vec::new(),
+                next_local_port: local_port,
+                waker: WakerRegistration::new(),
+                events: Events::new(),
+            }),
+        };
+        stack.add_interface(device, configurator, resources);
+        stack
+    }
+
+    /// Add another network interface to the stack.
+    ///
+    /// Each interface has its own device, configurator and sockets. Up to 4 interfaces
+    /// are supported.
+    pub fn add_interface<const SOCK: usize>(
+        &self,
+        device: &'static mut dyn Device,
+        configurator: &'static mut dyn Configurator,
+        resources: &'static mut StackResources<SOCK>,
+    ) -> InterfaceId {
+        self.with(|inner| inner.add_interface(device, configurator, resources))
+    }
+
+    /// Returns the ids of all the interfaces, in the order they were added.
+    pub fn interfaces(&self) -> impl Iterator<Item = InterfaceId> {
+        let count = self.with(|inner| inner.ifaces.len());
+        (0..count).map(|i| InterfaceId(i as u8))
+    }
+
+    /// Returns true if the link is up on any interface.
+    pub fn is_link_up(&self) -> bool {
+        self.with(|inner| inner.ifaces.iter().any(|i| i.link_up))
+    }
+
+    /// Returns true if any interface has an IP configuration.
+    pub fn is_config_up(&self) -> bool {
+        self.with(|inner| inner.ifaces.iter().any(|i| i.config.is_some()))
+    }
+
+    /// Returns true if the link is up on the given interface.
+    ///
+    /// Returns false for an interface of another stack.
+    pub fn interface_link_up(&self, id: InterfaceId) -> bool {
+        self.with(|inner| inner.iface(id).map_or(false, |i| i.link_up))
     }
 
+    /// Returns the current IP configuration of the given interface, if any.
+    pub fn interface_config(&self, id: InterfaceId) -> Option<Config> {
+        self.with(|inner| inner.iface(id).ok().and_then(|i| i.config.clone()))
+    }
+
+    /// Returns the traffic counters of the given interface.
+    ///
+    /// Fails with `Error::Illegal` for an interface of another stack.
+    pub fn interface_stats(&self, id: InterfaceId) -> Result<InterfaceStats> {
+        self.with(|inner| Ok(inner.iface(id)?.iface.device().stats()))
+    }
+
+    /// Give `tap` a copy of every frame sent or received on the given interface, or stop
+    /// if `None`.
+    ///
+    /// The tap is called while the stack is busy, so it must not use the stack. Fails
+    /// with `Error::Illegal` for an interface of another stack.
+    pub fn set_tap(&self, id: InterfaceId, tap: Option<&'static mut dyn Tap>) -> Result<()> {
+        self.with(|inner| {
+            inner.iface(id)?.iface.device_mut().set_tap(tap);
+            Ok(())
+        })
+    }
+
+    /// Returns the interface that would be used to reach `addr`, if any.
+    pub fn route(&self, addr: IpAddress) -> Option<InterfaceId> {
+        self.with(|inner| inner.route(addr))
+    }
+
+    /// Join an IPv4 multicast group on the given interface, so datagrams sent to it are
+    /// received by the sockets there. Up to 4 groups can be joined on each interface.
+    ///
+    /// Returns `Ok(false)` if the group was already joined. The device must pass up
+    /// frames sent to the group's multicast MAC address.
+    #[cfg(feature = "igmp")]
+    pub fn join_multicast_group(&self, id: InterfaceId, addr: Ipv4Address) -> Result<bool> {
+        self.with(|inner| {
+            let timestamp = instant_to_smoltcp(Instant::now());
+            let res = inner.iface(id)?.iface.join_multicast_group(addr, timestamp);
+            inner.wake();
+            res
+        })
+    }
+
+    /// Leave an IPv4 multicast group on the given interface.
+    ///
+    /// Returns `Ok(false)` if the group wasn't joined.
+    #[cfg(feature = "igmp")]
+    pub fn leave_multicast_group(&self, id: InterfaceId, addr: Ipv4Address) -> Result<bool> {
+        self.with(|inner| {
+            let timestamp = instant_to_smoltcp(Instant::now());
+            let res = inner
+                .iface(id)?
+                .iface
+                .leave_multicast_group(addr, timestamp);
+            inner.wake();
+            res
+        })
+    }
+
+    /// Wait until the link is up on any interface.
+    pub async fn wait_link_up(&self) {
+        futures::future::poll_fn(|cx| {
+            self.with(|inner| {
+                if inner.ifaces.iter().any(|i| i.link_up) {
+                    Poll::Ready(())
+                } else {
+                    inner.events.wakers.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Wait until any interface has an IP configuration.
+    pub async fn wait_config_up(&self) {
+        futures::future::poll_fn(|cx| {
+            self.with(|inner| {
+                if inner.ifaces.iter().any(|i| i.config.is_some()) {
+                    Poll::Ready(())
+                } else {
+                    inner.events.wakers.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    /// Returns a stream of the configuration changes on all interfaces.
+    ///
+    /// The stream yields `Configured` and `Deconfigured` events that happen after it was
+    /// created. Only the 4 most recent events are kept: a stream that falls further behind
+    /// skips the older ones.
+    pub fn config_events(&self) -> ConfigEvents<'_> {
+        ConfigEvents {
+            stack: self,
+            next_seq: self.with(|inner| inner.events.seq),
+        }
+    }
+
+    /// Run the network stack.
+    ///
+    /// This must be called in a task for the stack to make progress. It never returns.
+    pub async fn run(&self) {
+        futures::future::poll_fn(|cx| {
+            self.with(|inner| inner.poll(cx));
+            Poll::<()>::Pending
+        })
+        .await
+    }
+
+    pub(crate) fn with<R>(&self, f: impl FnOnce(&mut Inner) -> R) -> R {
+        f(&mut *self.inner.borrow_mut())
+    }
+}
+
+impl Inner {
     pub fn get_local_port(&mut self) -> u16 {
         let res = self.next_local_port;
         self.next_local_port = if res >= LOCAL_PORT_MAX {
@@ -69,20 +334,186 @@ impl Stack {
         res
     }
 
+    /// Returns the interface `id`, or `Error::Illegal` if it's an id from another stack.
+    pub(crate) fn iface(&mut self, id: InterfaceId) -> Result<&mut Iface> {
+        self.ifaces.get_mut(id.index()).ok_or(Error::Illegal)
+    }
+
+    /// Returns the sockets of the interface a socket is on.
+    ///
+    /// Sockets are only ever on interfaces of their own stack, so unlike `iface`, this
+    /// doesn't check `id`.
+    pub(crate) fn sockets(&mut self, id: InterfaceId) -> &mut SocketSet {
+        &mut self.ifaces[id.index()].sockets
+    }
+
+    /// Move the socket `handle` from interface `from` to interface `to`, returning its
+    /// handle there.
+    ///
+    /// Fails with `Error::Exhausted` if `to` has no room for another socket, leaving the
+    /// socket where it was.
+    pub(crate) fn move_socket(
+        &mut self,
+        from: InterfaceId,
+        handle: SocketHandle,
+        to: InterfaceId,
+    ) -> Result<SocketHandle> {
+        if from == to {
+            return Ok(handle);
+        }
+        self.check_room(to)?;
+        let socket = self.sockets(from).remove(handle);
+        Ok(self.sockets(to).add(socket))
+    }
+
+    /// Add `socket` to interface `id`, returning its handle.
+    ///
+    /// Fails with `Error::Exhausted` if the interface has no room for another socket.
+    pub(crate) fn add_socket(
+        &mut self,
+        id: InterfaceId,
+        socket: impl Into<Socket<'static>>,
+    ) -> Result<SocketHandle> {
+        self.check_room(id)?;
+        Ok(self.sockets(id).add(socket))
+    }
+
+    /// Fails with `Error::Exhausted` if interface `id` has no room for another socket.
+    fn check_room(&mut self, id: InterfaceId) -> Result<()> {
+        let iface = self.iface(id)?;
+        if iface.sockets.iter().count() >= iface.socket_capacity {
+            return Err(Error::Exhausted);
+        }
+        Ok(())
+    }
+
+    /// Choose the interface used to reach `addr`.
+    ///
+    /// Interfaces whose configured subnet contains `addr` win over interfaces that
+    /// merely have a default route (a gateway, or a point-to-point `Medium::Ip` link).
+    pub(crate) fn route(&self, addr: IpAddress) -> Option<InterfaceId> {
+        let addr = match addr {
+            IpAddress::Ipv4(addr) => addr,
+            _ => return None,
+        };
+
+        for (i, iface) in self.ifaces.iter().enumerate() {
+            if let Some(config) = &iface.config {
+                if config.address.contains_addr(&addr) {
+                    return Some(InterfaceId(i as u8));
+                }
+            }
+        }
+
+        for (i, iface) in self.ifaces.iter().enumerate() {
+            if let Some(config) = &iface.config {
+                let has_default_route = config.gateway.is_some();
+                #[cfg(feature = "medium-ip")]
+                let has_default_route =
+                    has_default_route || iface.iface.device().capabilities().medium == Medium::Ip;
+                if has_default_route {
+                    return Some(InterfaceId(i as u8));
+                }
+            }
+        }
+
+        None
+    }
+
     pub(crate) fn wake(&mut self) {
         self.waker.wake()
     }
 
-    fn poll_configurator(&mut self, timestamp: SmolInstant) {
+    fn add_interface<const SOCK: usize>(
+        &mut self,
+        device: &'static mut dyn Device,
+        configurator: &'static mut dyn Configurator,
+        resources: &'static mut StackResources<SOCK>,
+    ) -> InterfaceId {
+        let index = self.ifaces.len();
+        let iface = Iface::new(device, configurator, resources);
+        if self.ifaces.push(iface).is_err() {
+            panic!("too many interfaces");
+        }
+        self.waker.wake();
+
+        InterfaceId(index as u8)
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) {
+        self.waker.register(cx.waker());
+
+        let timestamp = instant_to_smoltcp(Instant::now());
+        let mut poll_at: Option<SmolInstant> = None;
+
+        for (i, iface) in self.ifaces.iter_mut().enumerate() {
+            if let Some(t) = iface.poll(i, cx, timestamp, &mut self.events) {
+                poll_at = Some(poll_at.map_or(t, |p| p.min(t)));
+            }
+        }
+
+        if let Some(poll_at) = poll_at {
+            wake_at(cx, poll_at);
+        }
+    }
+}
+
+impl Iface {
+    fn new<const SOCK: usize>(
+        device: &'static mut dyn Device,
+        configurator: &'static mut dyn Configurator,
+        res: &'static mut StackResources<SOCK>,
+    ) -> Self {
+        let medium = device.capabilities().medium;
+
+        #[cfg(feature = "medium-ethernet")]
+        let ethernet_addr = if medium == Medium::Ethernet {
+            device.ethernet_address()
+        } else {
+            [0, 0, 0, 0, 0, 0]
+        };
+
+        let mut b = InterfaceBuilder::new(DeviceAdapter::new(device));
+        b = b.ip_addrs(&mut res.addresses[..]);
+
+        #[cfg(feature = "medium-ethernet")]
+        if medium == Medium::Ethernet {
+            b = b.ethernet_addr(EthernetAddress(ethernet_addr));
+            b = b.neighbor_cache(NeighborCache::new(&mut res.neighbor_cache[..]));
+            b = b.routes(Routes::new(&mut res.routes[..]));
+        }
+
+        #[cfg(feature = "igmp")]
+        {
+            b = b.ipv4_multicast_groups(&mut res.multicast_groups[..]);
+        }
+
+        Self {
+            iface: b.finalize(),
+            sockets: SocketSet::new(&mut res.sockets[..]),
+            socket_capacity: SOCK,
+            link_up: false,
+            config: None,
+            configurator,
+        }
+    }
+
+    fn poll_configurator(
+        &mut self,
+        index: usize,
+        cx: &mut Context<'_>,
+        timestamp: SmolInstant,
+        events: &mut Events,
+    ) {
         let medium = self.iface.device().capabilities().medium;
 
-        match self
+        let event = self
             .configurator
-            .poll(&mut self.iface, &mut self.sockets, timestamp)
-        {
-            Event::NoChange => {}
+            .poll(&mut self.iface, &mut self.sockets, timestamp, cx);
+        match &event {
+            Event::NoChange => return,
             Event::Configured(config) => {
-                debug!("Acquired IP configuration:");
+                debug!("Acquired IP configuration on interface {}:", index);
 
                 debug!("   IP address:      {}", config.address);
                 set_ipv4_addr(&mut self.iface, config.address);
@@ -104,29 +535,36 @@ impl Stack {
                     debug!("   DNS server {}:    {}", i, s);
                 }
 
-                self.config_up = true;
+                self.config = Some(config.clone());
             }
             Event::Deconfigured => {
-                debug!("Lost IP configuration");
+                debug!("Lost IP configuration on interface {}", index);
                 set_ipv4_addr(&mut self.iface, Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0));
                 #[cfg(feature = "medium-ethernet")]
                 if medium == Medium::Ethernet {
                     self.iface.routes_mut().remove_default_ipv4_route();
                 }
-                self.config_up = false;
+                self.config = None;
             }
         }
+
+        events.push(InterfaceId(index as u8), event);
     }
 
-    fn poll(&mut self, cx: &mut Context<'_>) {
+    /// Poll the interface, returning when it next wants to be polled.
+    fn poll(
+        &mut self,
+        index: usize,
+        cx: &mut Context<'_>,
+        timestamp: SmolInstant,
+        events: &mut Events,
+    ) -> Option<SmolInstant> {
         self.iface.device_mut().device.register_waker(cx.waker());
-        self.waker.register(cx.waker());
 
-        let timestamp = instant_to_smoltcp(Instant::now());
         if let Err(_) = self.iface.poll(&mut self.sockets, timestamp) {
             // If poll() returns error, it may not be done yet, so poll again later.
             cx.waker().wake_by_ref();
-            return;
+            return None;
         }
 
         // Update link up
@@ -136,124 +574,82 @@ impl Stack {
         // Print when changed
         if old_link_up != self.link_up {
             if self.link_up {
-                info!("Link up!");
+                info!("Link up on interface {}!", index);
             } else {
-                info!("Link down!");
+                info!("Link down on interface {}!", index);
             }
+            events.wakers.wake();
         }
 
         if old_link_up || self.link_up {
-            self.poll_configurator(timestamp)
+            self.poll_configurator(index, cx, timestamp, events)
         }
 
-        if let Some(poll_at) = self.iface.poll_at(&mut self.sockets, timestamp) {
-            let t = Timer::at(instant_from_smoltcp(poll_at));
-            pin_mut!(t);
-            if t.poll(cx).is_ready() {
-                cx.waker().wake_by_ref();
-            }
-        }
+        self.iface.poll_at(&mut self.sockets, timestamp)
     }
 }
 
-fn set_ipv4_addr(iface: &mut Interface, cidr: Ipv4Cidr) {
-    iface.update_ip_addrs(|addrs| {
-        let dest = addrs.iter_mut().next().unwrap();
-        *dest = IpCidr::Ipv4(cidr);
-    });
+/// Stream of configuration events, returned by [`Stack::config_events`].
+pub struct ConfigEvents<'a> {
+    stack: &'a Stack,
+    next_seq: u32,
 }
 
-/// Initialize embassy_net.
-/// This function must be called from thread mode.
-pub fn init(device: &'static mut dyn Device, configurator: &'static mut dyn Configurator) {
-    const NONE_SOCKET: Option<SocketSetItem<'static>> = None;
-    let res = STACK_RESOURCES.put(StackResources {
-        addresses: [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 32)],
-        sockets: [NONE_SOCKET; SOCKETS_LEN],
-
-        #[cfg(feature = "medium-ethernet")]
-        routes: [None; 1],
-        #[cfg(feature = "medium-ethernet")]
-        neighbor_cache: [None; NEIGHBOR_CACHE_LEN],
-    });
+impl<'a> Unpin for ConfigEvents<'a> {}
 
-    let medium = device.capabilities().medium;
+impl<'a> Stream for ConfigEvents<'a> {
+    type Item = (InterfaceId, Event);
 
-    #[cfg(feature = "medium-ethernet")]
-    let ethernet_addr = if medium == Medium::Ethernet {
-        device.ethernet_address()
-    } else {
-        [0, 0, 0, 0, 0, 0]
-    };
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        this.stack.with(|inner| {
+            let events = &mut inner.events;
+            let pending = events.seq.wrapping_sub(this.next_seq);
+            if pending == 0 {
+                events.wakers.register(cx.waker());
+                return Poll::Pending;
+            }
 
-    let mut b = InterfaceBuilder::new(DeviceAdapter::new(device));
-    b = b.ip_addrs(&mut res.addresses[..]);
+            // Skip events that have already been overwritten.
+            if pending as usize > EVENT_QUEUE_LEN {
+                this.next_seq = events.seq.wrapping_sub(EVENT_QUEUE_LEN as u32);
+            }
 
-    #[cfg(feature = "medium-ethernet")]
-    if medium == Medium::Ethernet {
-        b = b.ethernet_addr(EthernetAddress(ethernet_addr));
-        b = b.neighbor_cache(NeighborCache::new(&mut res.neighbor_cache[..]));
-        b = b.routes(Routes::new(&mut res.routes[..]));
+            let event = events.queue[this.next_seq as usize % EVENT_QUEUE_LEN].clone();
+            this.next_seq = this.next_seq.wrapping_add(1);
+            Poll::Ready(event)
+        })
     }
-
-    let iface = b.finalize();
-
-    let sockets = SocketSet::new(&mut res.sockets[..]);
-
-    let local_port = loop {
-        let mut res = [0u8; 2];
-        rand(&mut res);
-        let port = u16::from_le_bytes(res);
-        if port >= LOCAL_PORT_MIN && port <= LOCAL_PORT_MAX {
-            break port;
-        }
-    };
-
-    let stack = Stack {
-        iface,
-        sockets,
-        link_up: false,
-        config_up: false,
-        configurator,
-        next_local_port: local_port,
-        waker: WakerRegistration::new(),
-    };
-
-    *STACK.borrow().borrow_mut() = Some(stack);
 }
 
-pub fn is_init() -> bool {
-    STACK.borrow().borrow().is_some()
-}
-
-pub fn is_link_up() -> bool {
-    STACK.borrow().borrow().as_ref().unwrap().link_up
-}
-
-pub fn is_config_up() -> bool {
-    STACK.borrow().borrow().as_ref().unwrap().config_up
-}
-
-pub async fn run() {
-    futures::future::poll_fn(|cx| {
-        Stack::with(|stack| stack.poll(cx));
-        Poll::<()>::Pending
-    })
-    .await
+fn set_ipv4_addr(iface: &mut Interface, cidr: Ipv4Cidr) {
+    iface.update_ip_addrs(|addrs| {
+        let dest = addrs.iter_mut().next().unwrap();
+        *dest = IpCidr::Ipv4(cidr);
+    });
 }
 
-fn instant_to_smoltcp(instant: Instant) -> SmolInstant {
+pub(crate) fn instant_to_smoltcp(instant: Instant) -> SmolInstant {
     SmolInstant::from_millis(instant.as_millis() as i64)
 }
 
-fn instant_from_smoltcp(instant: SmolInstant) -> Instant {
+pub(crate) fn instant_from_smoltcp(instant: SmolInstant) -> Instant {
     Instant::from_millis(instant.total_millis() as u64)
 }
 
+/// Arrange for the task polling `cx` to be woken at `at`.
+pub(crate) fn wake_at(cx: &mut Context<'_>, at: SmolInstant) {
+    let t = Timer::at(instant_from_smoltcp(at));
+    pin_mut!(t);
+    if t.poll(cx).is_ready() {
+        cx.waker().wake_by_ref();
+    }
+}
+
 extern "Rust" {
     fn _embassy_rand(buf: &mut [u8]);
 }
 
-fn rand(buf: &mut [u8]) {
+pub(crate) fn rand(buf: &mut [u8]) {
     unsafe { _embassy_rand(buf) }
 }
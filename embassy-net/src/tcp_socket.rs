@@ -1,48 +1,146 @@
-use core::marker::PhantomData;
+use core::cell::Cell;
 use core::mem;
 use core::pin::Pin;
 use core::task::{Context, Poll};
 use embassy::io;
 use embassy::io::{AsyncBufRead, AsyncWrite};
+use embassy::time::Instant;
 use smoltcp::socket::SocketHandle;
 use smoltcp::socket::TcpSocket as SyncTcpSocket;
 use smoltcp::socket::{TcpSocketBuffer, TcpState};
 use smoltcp::time::Duration;
 use smoltcp::wire::IpEndpoint;
 
-use super::stack::Stack;
+use super::stack::{Inner, InterfaceId, Stack};
 use crate::fmt::*;
 use crate::{Error, Result};
 
 pub struct TcpSocket<'a> {
+    stack: &'a Stack,
+    iface: InterfaceId,
     handle: SocketHandle,
-    ghost: PhantomData<&'a mut [u8]>,
+    bound: bool,
+    track: Cell<Track>,
+}
+
+/// Why a connection was closed, returned by [`TcpSocket::close_reason`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CloseReason {
+    /// The connection was closed gracefully.
+    Finished,
+    /// The connection was aborted locally, with [`TcpSocket::abort`].
+    Aborted,
+    /// The remote host reset the connection, or refused it.
+    Reset,
+    /// The remote host didn't respond within the socket timeout. This is a best guess, see
+    /// [`TcpSocket::close_reason`].
+    TimedOut,
+}
+
+/// What we last saw of the socket, to tell how it was closed.
+///
+/// smoltcp doesn't report why a socket went to `Closed`. Unless it closed gracefully, it
+/// was either reset by the remote host, or closed by smoltcp when nothing was heard from
+/// the remote host within the socket timeout. This keeps track of the last time the socket
+/// saw the remote host do something, to tell which.
+#[derive(Clone, Copy)]
+struct Track {
+    state: TcpState,
+    recv_queue: usize,
+    send_queue: usize,
+    /// Last time the remote host was seen sending data, acking ours, or changing the
+    /// connection state.
+    last_activity: Instant,
+    /// `close()` or `abort()` was called.
+    closed: bool,
+    aborted: bool,
+    reason: Option<CloseReason>,
+}
+
+impl Track {
+    fn new() -> Self {
+        Self {
+            state: TcpState::Closed,
+            recv_queue: 0,
+            send_queue: 0,
+            last_activity: Instant::now(),
+            closed: false,
+            aborted: false,
+            reason: None,
+        }
+    }
 }
 
 impl<'a> Unpin for TcpSocket<'a> {}
 
 impl<'a> TcpSocket<'a> {
-    pub fn new(rx_buffer: &'a mut [u8], tx_buffer: &'a mut [u8]) -> Self {
-        let handle = Stack::with(|stack| {
+    /// Create a socket using the given buffers.
+    ///
+    /// The socket starts out on the first interface of the stack, and is moved to the
+    /// right one once the remote address is known. Fails with `Error::Exhausted` if the
+    /// first interface has no room for another socket, even if the socket would end up
+    /// on another interface.
+    pub fn new(stack: &'a Stack, rx_buffer: &'a mut [u8], tx_buffer: &'a mut [u8]) -> Result<Self> {
+        let iface = InterfaceId::FIRST;
+        let handle = stack.with(|inner| {
             let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
             let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-            stack.sockets.add(SyncTcpSocket::new(
-                TcpSocketBuffer::new(rx_buffer),
-                TcpSocketBuffer::new(tx_buffer),
-            ))
-        });
+            inner.add_socket(
+                iface,
+                SyncTcpSocket::new(
+                    TcpSocketBuffer::new(rx_buffer),
+                    TcpSocketBuffer::new(tx_buffer),
+                ),
+            )
+        })?;
 
-        Self {
+        Ok(Self {
+            stack,
+            iface,
             handle,
-            ghost: PhantomData,
-        }
+            bound: false,
+            track: Cell::new(Track::new()),
+        })
+    }
+
+    /// Bind this socket to an interface.
+    ///
+    /// By default, the interface is chosen on `connect` by routing the remote address.
+    /// A bound socket always uses the given interface instead.
+    ///
+    /// Fails with `Error::Exhausted` if the interface has no room for another socket, or
+    /// `Error::Illegal` if it's an interface of another stack.
+    pub fn bind_interface(&mut self, iface: InterfaceId) -> Result<()> {
+        let stack = self.stack;
+        stack.with(|inner| self.move_to(inner, iface))?;
+        self.bound = true;
+        Ok(())
+    }
+
+    /// Returns the interface this socket is currently on.
+    pub fn interface(&self) -> InterfaceId {
+        self.iface
     }
 
     pub async fn connect<T>(&mut self, remote_endpoint: T) -> Result<()>
     where
         T: Into<IpEndpoint>,
     {
-        let local_port = Stack::with(|stack| stack.get_local_port());
+        let remote_endpoint = remote_endpoint.into();
+        let stack = self.stack;
+        let local_port = stack.with(|inner| {
+            let iface = if self.bound {
+                self.iface
+            } else {
+                inner
+                    .route(remote_endpoint.addr)
+                    .ok_or(Error::Unaddressable)?
+            };
+            self.move_to(inner, iface)?;
+            Ok(inner.get_local_port())
+        })?;
+        self.track.set(Track::new());
         self.with(|s| s.connect(remote_endpoint, local_port))?;
 
         futures::future::poll_fn(|cx| {
@@ -59,6 +157,30 @@ impl<'a> TcpSocket<'a> {
         .await
     }
 
+    /// Listen on `local_endpoint`, and wait for a remote host to connect.
+    ///
+    /// The socket listens on its current interface: the first one, unless
+    /// [`TcpSocket::bind_interface`] was called. Once the connection is closed, `accept`
+    /// can be called again to wait for the next one.
+    pub async fn accept<T>(&mut self, local_endpoint: T) -> Result<()>
+    where
+        T: Into<IpEndpoint>,
+    {
+        self.track.set(Track::new());
+        self.with(|s| s.listen(local_endpoint))?;
+
+        futures::future::poll_fn(|cx| {
+            self.with(|s| match s.state() {
+                TcpState::Listen | TcpState::SynSent | TcpState::SynReceived => {
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+                _ => Poll::Ready(Ok(())),
+            })
+        })
+        .await
+    }
+
     pub fn set_timeout(&mut self, duration: Option<Duration>) {
         self.with(|s| s.set_timeout(duration))
     }
@@ -83,14 +205,59 @@ impl<'a> TcpSocket<'a> {
         self.with(|s| s.state())
     }
 
-    pub fn close(&mut self) {
-        self.with(|s| s.close())
+    /// Close the write side of the connection, and wait until the connection is closed.
+    ///
+    /// This waits for all the data written so far to be acknowledged, and for the remote
+    /// host to close its side too. Returns an error if the connection broke instead.
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.with(|s| s.close());
+        self.update_track(|t| t.closed = true);
+
+        futures::future::poll_fn(|cx| {
+            self.with(|s| match s.state() {
+                TcpState::Closed | TcpState::TimeWait => Poll::Ready(()),
+                _ => {
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await;
+
+        match self.close_reason() {
+            Some(CloseReason::Reset) => Err(io::Error::ConnectionReset),
+            Some(CloseReason::TimedOut) => Err(io::Error::TimedOut),
+            _ => Ok(()),
+        }
     }
 
     pub fn abort(&mut self) {
+        self.update_track(|t| {
+            t.closed = true;
+            t.aborted = true;
+        });
         self.with(|s| s.abort())
     }
 
+    /// Split the socket into a read half and a write half, which can be used concurrently.
+    pub fn split(&mut self) -> (TcpReader<'_, 'a>, TcpWriter<'_, 'a>) {
+        let socket = &*self;
+        (TcpReader { socket }, TcpWriter { socket })
+    }
+
+    /// Returns why the connection was closed, or `None` if it hasn't been closed yet.
+    ///
+    /// smoltcp doesn't say why it closed a connection abruptly, so telling a timeout from a
+    /// reset is best-effort. A connection is reported as timed out if a timeout is set (see
+    /// [`TcpSocket::set_timeout`]) and the socket saw nothing from the remote host for that
+    /// long before it closed, and as reset otherwise. Since the socket only sees data and
+    /// acknowledgements, not keep-alives, a connection reset after being idle for longer
+    /// than the timeout is reported as timed out too.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.with(|_| ());
+        self.track.get().reason
+    }
+
     pub fn may_send(&self) -> bool {
         self.with(|s| s.may_send())
     }
@@ -99,37 +266,94 @@ impl<'a> TcpSocket<'a> {
         self.with(|s| s.may_recv())
     }
 
+    /// Move the socket to `iface`, or leave it where it was if `iface` has no room for it.
+    fn move_to(&mut self, inner: &mut Inner, iface: InterfaceId) -> Result<()> {
+        self.handle = inner.move_socket(self.iface, self.handle, iface)?;
+        self.iface = iface;
+        Ok(())
+    }
+
     fn with<R>(&self, f: impl FnOnce(&mut SyncTcpSocket) -> R) -> R {
-        Stack::with(|stack| {
+        self.stack.with(|inner| {
             let res = {
-                let mut s = stack.sockets.get::<SyncTcpSocket>(self.handle);
-                f(&mut *s)
+                let mut s = inner.sockets(self.iface).get::<SyncTcpSocket>(self.handle);
+                self.observe(&s);
+                let res = f(&mut *s);
+                self.observe(&s);
+                res
             };
-            stack.wake();
+            inner.wake();
             res
         })
     }
-}
 
-fn to_ioerr(_err: Error) -> io::Error {
-    // todo
-    io::Error::Other
-}
+    fn update_track(&self, f: impl FnOnce(&mut Track)) {
+        let mut t = self.track.get();
+        f(&mut t);
+        self.track.set(t);
+    }
 
-impl<'a> Drop for TcpSocket<'a> {
-    fn drop(&mut self) {
-        Stack::with(|stack| {
-            stack.sockets.remove(self.handle);
-        })
+    /// Record the socket state, and work out why it closed if it just did.
+    fn observe(&self, s: &SyncTcpSocket) {
+        let mut t = self.track.get();
+        let now = Instant::now();
+        let state = s.state();
+
+        if state == TcpState::Closed {
+            if t.state != TcpState::Closed && t.reason.is_none() {
+                let timed_out = match s.timeout() {
+                    Some(timeout) => now >= t.last_activity + duration_from_smoltcp(timeout),
+                    None => false,
+                };
+                t.reason = Some(if t.aborted {
+                    CloseReason::Aborted
+                } else if t.closed || t.state == TcpState::TimeWait {
+                    CloseReason::Finished
+                } else if timed_out {
+                    CloseReason::TimedOut
+                } else {
+                    CloseReason::Reset
+                });
+            }
+        } else if state != t.state || s.recv_queue() > t.recv_queue || s.send_queue() < t.send_queue
+        {
+            t.last_activity = now;
+        }
+
+        t.state = state;
+        t.recv_queue = s.recv_queue();
+        t.send_queue = s.send_queue();
+        self.track.set(t);
+    }
+
+    /// Map a smoltcp error to an io error, taking into account how the connection closed.
+    fn io_error(&self, err: Error) -> io::Error {
+        let t = self.track.get();
+        match (t.reason, err) {
+            (Some(CloseReason::Reset), _) => io::Error::ConnectionReset,
+            (Some(CloseReason::TimedOut), _) => io::Error::TimedOut,
+            (Some(CloseReason::Finished), _) | (Some(CloseReason::Aborted), _) => {
+                io::Error::BrokenPipe
+            }
+            // The socket refuses to send or receive: either we closed it, or it was never
+            // connected.
+            (None, Error::Illegal) if t.closed => io::Error::BrokenPipe,
+            (None, Error::Illegal) => io::Error::NotConnected,
+            (None, _) => io::Error::Other,
+        }
     }
 }
 
-impl<'a> AsyncBufRead for TcpSocket<'a> {
-    fn poll_fill_buf<'z>(
-        self: Pin<&'z mut Self>,
-        cx: &mut Context<'_>,
-    ) -> Poll<io::Result<&'z [u8]>> {
-        self.with(|socket| match socket.peek(1 << 30) {
+fn duration_from_smoltcp(duration: Duration) -> embassy::time::Duration {
+    embassy::time::Duration::from_millis(duration.total_millis())
+}
+
+impl<'a> TcpSocket<'a> {
+    // The io trait implementations, shared by the socket and its split halves. They only
+    // need `&self`, since the read and write halves use separate wakers and buffers.
+
+    fn poll_fill_buf_shared<'z>(&'z self, cx: &mut Context<'_>) -> Poll<io::Result<&'z [u8]>> {
+        let res = self.with(|socket| match socket.peek(1 << 30) {
             // No data ready
             Ok(buf) if buf.len() == 0 => {
                 socket.register_recv_waker(cx.waker());
@@ -141,27 +365,27 @@ impl<'a> AsyncBufRead for TcpSocket<'a> {
                 // - User can't touch the inner TcpSocket directly at all.
                 // - The socket itself won't touch these bytes until consume() is called, which
                 //   requires the user to release this borrow.
+                // - Only the read half, which holds this borrow, can call consume().
                 let buf: &'z [u8] = unsafe { core::mem::transmute(&*buf) };
                 Poll::Ready(Ok(buf))
             }
             // EOF
             Err(Error::Finished) => Poll::Ready(Ok(&[][..])),
             // Error
-            Err(e) => Poll::Ready(Err(to_ioerr(e))),
-        })
+            Err(e) => Poll::Ready(Err(e)),
+        });
+        res.map_err(|e| self.io_error(e))
     }
 
-    fn consume(self: Pin<&mut Self>, amt: usize) {
-        self.with(|s| s.recv(|_| (amt, ()))).unwrap()
+    fn consume_shared(&self, amt: usize) {
+        // This only fails if the connection broke since `poll_fill_buf`, in which case the
+        // next read reports the error.
+        if let Err(_) = self.with(|s| s.recv(|_| (amt, ()))) {
+            debug!("TcpSocket: consume failed");
+        }
     }
-}
 
-impl<'a> AsyncWrite for TcpSocket<'a> {
-    fn poll_write(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<io::Result<usize>> {
+    fn poll_write_shared(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
         self.with(|s| match s.send_slice(buf) {
             // Not ready to send (no space in the tx buffer)
             Ok(0) => {
@@ -171,7 +395,75 @@ impl<'a> AsyncWrite for TcpSocket<'a> {
             // Some data sent
             Ok(n) => Poll::Ready(Ok(n)),
             // Error
-            Err(e) => Poll::Ready(Err(to_ioerr(e))),
+            Err(e) => Poll::Ready(Err(e)),
         })
+        .map_err(|e| self.io_error(e))
+    }
+}
+
+impl<'a> Drop for TcpSocket<'a> {
+    fn drop(&mut self) {
+        self.stack.with(|inner| {
+            inner.sockets(self.iface).remove(self.handle);
+        })
+    }
+}
+
+impl<'a> AsyncBufRead for TcpSocket<'a> {
+    fn poll_fill_buf<'z>(
+        self: Pin<&'z mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<&'z [u8]>> {
+        self.into_ref().get_ref().poll_fill_buf_shared(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.consume_shared(amt)
+    }
+}
+
+impl<'a> AsyncWrite for TcpSocket<'a> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_shared(cx, buf)
+    }
+}
+
+/// Read half of a [`TcpSocket`], returned by [`TcpSocket::split`].
+pub struct TcpReader<'b, 'a> {
+    socket: &'b TcpSocket<'a>,
+}
+
+/// Write half of a [`TcpSocket`], returned by [`TcpSocket::split`].
+pub struct TcpWriter<'b, 'a> {
+    socket: &'b TcpSocket<'a>,
+}
+
+impl<'b, 'a> Unpin for TcpReader<'b, 'a> {}
+impl<'b, 'a> Unpin for TcpWriter<'b, 'a> {}
+
+impl<'b, 'a> AsyncBufRead for TcpReader<'b, 'a> {
+    fn poll_fill_buf<'z>(
+        self: Pin<&'z mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<&'z [u8]>> {
+        self.socket.poll_fill_buf_shared(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.socket.consume_shared(amt)
+    }
+}
+
+impl<'b, 'a> AsyncWrite for TcpWriter<'b, 'a> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.socket.poll_write_shared(cx, buf)
     }
 }
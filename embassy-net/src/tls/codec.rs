@@ -0,0 +1,115 @@
+use super::TlsError;
+
+/// Reads the big-endian, length-prefixed fields used by TLS.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], TlsError> {
+        if self.buf.len() < n {
+            return Err(TlsError::DecodeError);
+        }
+        let (res, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(res)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, TlsError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, TlsError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u24(&mut self) -> Result<usize, TlsError> {
+        let b = self.bytes(3)?;
+        Ok((b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize)
+    }
+
+    /// A vector with a 1-byte length.
+    pub fn vec8(&mut self) -> Result<Reader<'a>, TlsError> {
+        let len = self.u8()? as usize;
+        Ok(Reader::new(self.bytes(len)?))
+    }
+
+    /// A vector with a 2-byte length.
+    pub fn vec16(&mut self) -> Result<Reader<'a>, TlsError> {
+        let len = self.u16()? as usize;
+        Ok(Reader::new(self.bytes(len)?))
+    }
+
+    /// A vector with a 3-byte length.
+    pub fn vec24(&mut self) -> Result<Reader<'a>, TlsError> {
+        let len = self.u24()?;
+        Ok(Reader::new(self.bytes(len)?))
+    }
+
+    pub fn rest(&mut self) -> &'a [u8] {
+        core::mem::replace(&mut self.buf, &[])
+    }
+}
+
+/// Writes TLS fields into a caller-provided buffer.
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    /// The bytes written so far.
+    pub fn written(&self) -> &[u8] {
+        &self.buf[..self.pos]
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> Result<(), TlsError> {
+        if self.buf.len() - self.pos < data.len() {
+            return Err(TlsError::InsufficientBuffer);
+        }
+        self.buf[self.pos..self.pos + data.len()].copy_from_slice(data);
+        self.pos += data.len();
+        Ok(())
+    }
+
+    pub fn u8(&mut self, val: u8) -> Result<(), TlsError> {
+        self.bytes(&[val])
+    }
+
+    pub fn u16(&mut self, val: u16) -> Result<(), TlsError> {
+        self.bytes(&val.to_be_bytes())
+    }
+
+    /// Start a vector with a `size`-byte length, to be filled in by [`Writer::end`].
+    pub fn begin(&mut self, size: usize) -> Result<usize, TlsError> {
+        let mark = self.pos;
+        self.bytes(&[0, 0, 0][..size])?;
+        Ok(mark)
+    }
+
+    pub fn end(&mut self, mark: usize, size: usize) {
+        let len = (self.pos - mark - size).to_be_bytes();
+        self.buf[mark..mark + size].copy_from_slice(&len[len.len() - size..]);
+    }
+
+    pub fn into_inner(self) -> &'a mut [u8] {
+        self.buf
+    }
+}
@@ -0,0 +1,431 @@
+//! The client side of the TLS 1.3 handshake.
+
+use core::ops::Range;
+use embassy::io::{AsyncBufRead, AsyncWrite};
+use embassy::traits::rng::Rng;
+use heapless::consts::*;
+use heapless::Vec;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::codec::{Reader, Writer};
+use super::key_schedule::{finished, Hash, KeySchedule, Transcript, HASH_LEN};
+use super::record::*;
+use super::*;
+
+const HS_CLIENT_HELLO: u8 = 1;
+const HS_SERVER_HELLO: u8 = 2;
+const HS_ENCRYPTED_EXTENSIONS: u8 = 8;
+const HS_CERTIFICATE: u8 = 11;
+const HS_CERTIFICATE_REQUEST: u8 = 13;
+const HS_CERTIFICATE_VERIFY: u8 = 15;
+const HS_FINISHED: u8 = 20;
+
+const EXT_SERVER_NAME: u16 = 0;
+const EXT_MAX_FRAGMENT_LENGTH: u16 = 1;
+const EXT_SUPPORTED_GROUPS: u16 = 10;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 13;
+const EXT_PRE_SHARED_KEY: u16 = 41;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+const EXT_PSK_KEY_EXCHANGE_MODES: u16 = 45;
+const EXT_KEY_SHARE: u16 = 51;
+
+const LEGACY_VERSION: u16 = 0x0303;
+const TLS13: u16 = 0x0304;
+const TLS_AES_128_GCM_SHA256: u16 = 0x1301;
+const GROUP_X25519: u16 = 0x001d;
+const PSK_DHE_KE: u8 = 1;
+
+/// Length of the binders list at the end of a ClientHello offering one PSK.
+const BINDERS_LEN: usize = 2 + 1 + HASH_LEN;
+
+/// ServerHello.random of a HelloRetryRequest.
+const HRR_RANDOM: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+const SERVER_SIGNATURE_CONTEXT: &[u8] = b"TLS 1.3, server CertificateVerify";
+
+struct ServerHello {
+    key_share: [u8; 32],
+    psk_accepted: bool,
+}
+
+impl<'a, S: AsyncBufRead + AsyncWrite + Unpin> TlsConnection<'a, S> {
+    pub(super) async fn handshake<R: Rng>(
+        &mut self,
+        mut config: TlsConfig<'_>,
+        rng: &mut R,
+    ) -> Result<(), TlsError> {
+        let mut random = [0; 32];
+        let mut secret = [0; 32];
+        rng.fill_bytes(&mut random)
+            .await
+            .map_err(|_| TlsError::Rng)?;
+        rng.fill_bytes(&mut secret)
+            .await
+            .map_err(|_| TlsError::Rng)?;
+        let secret = StaticSecret::from(secret);
+        let public = PublicKey::from(&secret);
+
+        let mut transcript = Transcript::new();
+        let mut schedule = KeySchedule::new(config.psk.map(|psk| psk.key));
+
+        let len = self.write_client_hello(&config, &random, public.as_bytes(), &schedule)?;
+        transcript.update(&self.tx_buf[HEADER_LEN..HEADER_LEN + len]);
+        self.queue_record(CONTENT_HANDSHAKE, len)?;
+        self.flush().await?;
+
+        // Handshake messages not processed yet, in `rx_buf`.
+        let mut hs = 0..0;
+
+        let msg = self.read_handshake(&mut hs).await?;
+        let sh = self.parse_server_hello(msg.clone(), config.psk.is_some())?;
+        transcript.update(&self.rx_buf[msg]);
+        if !hs.is_empty() {
+            // Keys change here, so the next message must start a new record.
+            return Err(TlsError::UnexpectedMessage);
+        }
+
+        if config.psk.is_some() && !sh.psk_accepted {
+            schedule = KeySchedule::new(None);
+        }
+        let shared = secret.diffie_hellman(&PublicKey::from(sh.key_share));
+        schedule.advance(shared.as_bytes());
+        let (client_hs, server_hs) = schedule.traffic_secrets(b"hs", &transcript.hash());
+        self.rx_cipher = Some(Cipher::new(&server_hs));
+
+        let mut certificate_request: Option<Vec<u8, U32>> = None;
+        let mut certificate_seen = false;
+        let mut verified = sh.psk_accepted;
+        loop {
+            let msg = self.read_handshake(&mut hs).await?;
+            let data = &self.rx_buf[msg];
+            let mut r = Reader::new(data);
+            let msg_type = r.u8()?;
+            let mut body = r.vec24()?;
+            match msg_type {
+                HS_ENCRYPTED_EXTENSIONS if !certificate_seen => {
+                    body.vec16()?;
+                }
+                HS_CERTIFICATE_REQUEST if !sh.psk_accepted && !certificate_seen => {
+                    let context = body.vec8()?.rest();
+                    let context =
+                        Vec::from_slice(context).map_err(|_| TlsError::InsufficientBuffer)?;
+                    certificate_request = Some(context);
+                }
+                HS_CERTIFICATE if !sh.psk_accepted && !certificate_seen => {
+                    body.vec8()?;
+                    let chain = CertificateChain::parse(body.vec24()?.rest())?;
+                    let verifier = config.verifier.as_mut().ok_or(TlsError::NoVerifier)?;
+                    verifier.verify_certificate(config.server_name, chain)?;
+                    certificate_seen = true;
+                }
+                HS_CERTIFICATE_VERIFY if certificate_seen && !verified => {
+                    let scheme = body.u16()?;
+                    let signature = body.vec16()?.rest();
+
+                    let mut message = [0x20; 64 + SERVER_SIGNATURE_CONTEXT.len() + 1 + HASH_LEN];
+                    message[64..64 + SERVER_SIGNATURE_CONTEXT.len()]
+                        .copy_from_slice(SERVER_SIGNATURE_CONTEXT);
+                    message[64 + SERVER_SIGNATURE_CONTEXT.len()] = 0;
+                    message[64 + SERVER_SIGNATURE_CONTEXT.len() + 1..]
+                        .copy_from_slice(&transcript.hash());
+
+                    let verifier = config.verifier.as_mut().ok_or(TlsError::NoVerifier)?;
+                    verifier.verify_signature(scheme, &message, signature)?;
+                    verified = true;
+                }
+                HS_FINISHED if verified => {
+                    let expected = finished(&server_hs, &transcript.hash());
+                    if !constant_time_eq(body.rest(), &expected) {
+                        return Err(TlsError::DecryptError);
+                    }
+                    transcript.update(data);
+                    break;
+                }
+                HS_FINISHED => return Err(TlsError::HandshakeFailure),
+                _ => return Err(TlsError::UnexpectedMessage),
+            }
+            transcript.update(data);
+        }
+        if !hs.is_empty() {
+            return Err(TlsError::UnexpectedMessage);
+        }
+
+        // Application secrets are derived from the transcript up to the server Finished.
+        schedule.advance(&[0; HASH_LEN]);
+        let (client_ap, server_ap) = schedule.traffic_secrets(b"ap", &transcript.hash());
+
+        // Our Finished, preceded by an empty Certificate if the server asked for one.
+        let mut w = Writer::new(&mut self.tx_buf[HEADER_LEN..]);
+        if let Some(context) = &certificate_request {
+            let start = w.len();
+            w.u8(HS_CERTIFICATE)?;
+            let m = w.begin(3)?;
+            let c = w.begin(1)?;
+            w.bytes(context)?;
+            w.end(c, 1);
+            w.begin(3)?;
+            w.end(m, 3);
+            transcript.update(&w.written()[start..]);
+        }
+        let verify_data = finished(&client_hs, &transcript.hash());
+        w.u8(HS_FINISHED)?;
+        let m = w.begin(3)?;
+        w.bytes(&verify_data)?;
+        w.end(m, 3);
+        let len = w.len();
+
+        self.tx_cipher = Some(Cipher::new(&client_hs));
+        self.queue_record(CONTENT_HANDSHAKE, len)?;
+        self.flush().await?;
+
+        self.rx_cipher = Some(Cipher::new(&server_ap));
+        self.tx_cipher = Some(Cipher::new(&client_ap));
+        self.rx_secret = server_ap;
+        self.tx_secret = client_ap;
+        Ok(())
+    }
+
+    /// Write a ClientHello at `tx_buf[HEADER_LEN..]`, returning its length.
+    fn write_client_hello(
+        &mut self,
+        config: &TlsConfig<'_>,
+        random: &[u8; 32],
+        key_share: &[u8; 32],
+        schedule: &KeySchedule,
+    ) -> Result<usize, TlsError> {
+        let max_fragment_length = max_fragment_length(self.rx_buf.len())?;
+        let schemes = match &config.verifier {
+            Some(verifier) => verifier.signature_schemes(),
+            None => &[],
+        };
+
+        let mut w = Writer::new(&mut self.tx_buf[HEADER_LEN..]);
+        w.u8(HS_CLIENT_HELLO)?;
+        let msg = w.begin(3)?;
+        w.u16(LEGACY_VERSION)?;
+        w.bytes(random)?;
+        w.u8(0)?; // legacy_session_id
+        let m = w.begin(2)?;
+        w.u16(TLS_AES_128_GCM_SHA256)?;
+        w.end(m, 2);
+        w.bytes(&[1, 0])?; // legacy_compression_methods: null
+
+        let exts = w.begin(2)?;
+
+        if let Some(name) = config.server_name {
+            w.u16(EXT_SERVER_NAME)?;
+            let e = w.begin(2)?;
+            let list = w.begin(2)?;
+            w.u8(0)?; // host_name
+            let n = w.begin(2)?;
+            w.bytes(name.as_bytes())?;
+            w.end(n, 2);
+            w.end(list, 2);
+            w.end(e, 2);
+        }
+
+        w.u16(EXT_SUPPORTED_VERSIONS)?;
+        let e = w.begin(2)?;
+        let list = w.begin(1)?;
+        w.u16(TLS13)?;
+        w.end(list, 1);
+        w.end(e, 2);
+
+        w.u16(EXT_SUPPORTED_GROUPS)?;
+        let e = w.begin(2)?;
+        let list = w.begin(2)?;
+        w.u16(GROUP_X25519)?;
+        w.end(list, 2);
+        w.end(e, 2);
+
+        // Required by servers even when only a PSK is used.
+        w.u16(EXT_SIGNATURE_ALGORITHMS)?;
+        let e = w.begin(2)?;
+        let list = w.begin(2)?;
+        for &scheme in schemes {
+            w.u16(scheme)?;
+        }
+        if schemes.is_empty() {
+            w.u16(0x0403)?;
+        }
+        w.end(list, 2);
+        w.end(e, 2);
+
+        w.u16(EXT_KEY_SHARE)?;
+        let e = w.begin(2)?;
+        let list = w.begin(2)?;
+        w.u16(GROUP_X25519)?;
+        let k = w.begin(2)?;
+        w.bytes(key_share)?;
+        w.end(k, 2);
+        w.end(list, 2);
+        w.end(e, 2);
+
+        if let Some(code) = max_fragment_length {
+            w.u16(EXT_MAX_FRAGMENT_LENGTH)?;
+            let e = w.begin(2)?;
+            w.u8(code)?;
+            w.end(e, 2);
+        }
+
+        if let Some(psk) = &config.psk {
+            w.u16(EXT_PSK_KEY_EXCHANGE_MODES)?;
+            let e = w.begin(2)?;
+            let list = w.begin(1)?;
+            w.u8(PSK_DHE_KE)?;
+            w.end(list, 1);
+            w.end(e, 2);
+
+            // pre_shared_key must be the last extension.
+            w.u16(EXT_PRE_SHARED_KEY)?;
+            let e = w.begin(2)?;
+            let ids = w.begin(2)?;
+            let id = w.begin(2)?;
+            w.bytes(psk.identity)?;
+            w.end(id, 2);
+            w.bytes(&[0; 4])?; // obfuscated_ticket_age, 0 for external PSKs
+            w.end(ids, 2);
+            let binders = w.begin(2)?;
+            let b = w.begin(1)?;
+            w.bytes(&[0; HASH_LEN])?;
+            w.end(b, 1);
+            w.end(binders, 2);
+            w.end(e, 2);
+        }
+
+        w.end(exts, 2);
+        w.end(msg, 3);
+        let len = w.len();
+        let buf = w.into_inner();
+
+        // The binder covers the ClientHello up to the binders list, and is filled in last.
+        if config.psk.is_some() {
+            let mut partial = Transcript::new();
+            partial.update(&buf[..len - BINDERS_LEN]);
+            let binder = finished(&schedule.binder_key(), &partial.hash());
+            buf[len - HASH_LEN..len].copy_from_slice(&binder);
+        }
+
+        Ok(len)
+    }
+
+    fn parse_server_hello(
+        &self,
+        msg: Range<usize>,
+        psk_offered: bool,
+    ) -> Result<ServerHello, TlsError> {
+        let mut r = Reader::new(&self.rx_buf[msg]);
+        if r.u8()? != HS_SERVER_HELLO {
+            return Err(TlsError::UnexpectedMessage);
+        }
+        let mut body = r.vec24()?;
+        body.u16()?; // legacy_version
+        if body.bytes(32)? == HRR_RANDOM {
+            // We only offer x25519, there's nothing else to retry with.
+            return Err(TlsError::HandshakeFailure);
+        }
+        body.vec8()?; // legacy_session_id_echo
+        if body.u16()? != TLS_AES_128_GCM_SHA256 || body.u8()? != 0 {
+            return Err(TlsError::HandshakeFailure);
+        }
+
+        let mut version = None;
+        let mut key_share = None;
+        let mut psk_accepted = false;
+        let mut exts = body.vec16()?;
+        while !exts.is_empty() {
+            let ext_type = exts.u16()?;
+            let mut data = exts.vec16()?;
+            match ext_type {
+                EXT_SUPPORTED_VERSIONS => version = Some(data.u16()?),
+                EXT_KEY_SHARE => {
+                    if data.u16()? != GROUP_X25519 {
+                        return Err(TlsError::HandshakeFailure);
+                    }
+                    let mut key = [0; 32];
+                    let k = data.vec16()?.rest();
+                    if k.len() != key.len() {
+                        return Err(TlsError::DecodeError);
+                    }
+                    key.copy_from_slice(k);
+                    key_share = Some(key);
+                }
+                EXT_PRE_SHARED_KEY => {
+                    if !psk_offered || data.u16()? != 0 {
+                        return Err(TlsError::HandshakeFailure);
+                    }
+                    psk_accepted = true;
+                }
+                _ => {}
+            }
+        }
+
+        // Without supported_versions, the server wants an older TLS version.
+        if version != Some(TLS13) {
+            return Err(TlsError::HandshakeFailure);
+        }
+        // We only offer psk_dhe_ke, so a key share is always needed.
+        let key_share = key_share.ok_or(TlsError::HandshakeFailure)?;
+        Ok(ServerHello {
+            key_share,
+            psk_accepted,
+        })
+    }
+
+    /// Read the next handshake message, reassembling it from records if needed.
+    ///
+    /// `hs` holds the received handshake bytes not returned yet. The message returned stays
+    /// valid until the next call.
+    async fn read_handshake(&mut self, hs: &mut Range<usize>) -> Result<Range<usize>, TlsError> {
+        loop {
+            let avail = &self.rx_buf[hs.clone()];
+            if avail.len() >= 4 {
+                let len = 4 + Reader::new(&avail[1..4]).u24()?;
+                if avail.len() >= len {
+                    let msg = hs.start..hs.start + len;
+                    hs.start += len;
+                    return Ok(msg);
+                }
+            }
+
+            // Make room for the rest of the message after what we have.
+            self.rx_buf.copy_within(hs.clone(), 0);
+            *hs = 0..hs.len();
+
+            let (content_type, range) = self.read_record(hs.end).await?;
+            match content_type {
+                CONTENT_HANDSHAKE => {
+                    let len = range.len();
+                    self.rx_buf.copy_within(range, hs.end);
+                    hs.end += len;
+                }
+                CONTENT_ALERT => {
+                    return Err(alert(&self.rx_buf[range])?.unwrap_or(TlsError::UnexpectedMessage))
+                }
+                _ => return Err(TlsError::UnexpectedMessage),
+            }
+        }
+    }
+}
+
+/// The max_fragment_length code to request so records fit in a buffer of `len` bytes,
+/// or `None` if any record fits.
+fn max_fragment_length(len: usize) -> Result<Option<u8>, TlsError> {
+    if len >= MAX_RECORD_LEN {
+        return Ok(None);
+    }
+    // Codes 1 to 4 ask for 2^9 to 2^12 bytes.
+    for code in (1..=4).rev() {
+        if HEADER_LEN + (1 << (8 + code)) + 256 <= len {
+            return Ok(Some(code as u8));
+        }
+    }
+    Err(TlsError::InsufficientBuffer)
+}
+
+fn constant_time_eq(a: &[u8], b: &Hash) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,116 @@
+//! TLS 1.3 key derivation (RFC 8446 section 7.1), for SHA-256 cipher suites.
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+
+pub const HASH_LEN: usize = 32;
+pub type Hash = [u8; HASH_LEN];
+
+pub fn hkdf_extract(salt: &[u8], ikm: &[u8]) -> Hash {
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), ikm);
+    prk.into()
+}
+
+pub fn hkdf_expand_label(secret: &Hash, label: &[u8], context: &[u8], out: &mut [u8]) {
+    let hk = Hkdf::<Sha256>::from_prk(secret).unwrap();
+    let len = (out.len() as u16).to_be_bytes();
+    let label_len = [(6 + label.len()) as u8];
+    let context_len = [context.len() as u8];
+    hk.expand_multi_info(
+        &[&len, &label_len, b"tls13 ", label, &context_len, context],
+        out,
+    )
+    .unwrap();
+}
+
+pub fn derive_secret(secret: &Hash, label: &[u8], transcript_hash: &Hash) -> Hash {
+    let mut out = [0; HASH_LEN];
+    hkdf_expand_label(secret, label, transcript_hash, &mut out);
+    out
+}
+
+pub fn hmac(key: &Hash, data: &[u8]) -> Hash {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// The `verify_data` of a Finished message, or a PSK binder.
+pub fn finished(base_key: &Hash, transcript_hash: &Hash) -> Hash {
+    let mut finished_key = [0; HASH_LEN];
+    hkdf_expand_label(base_key, b"finished", &[], &mut finished_key);
+    hmac(&finished_key, transcript_hash)
+}
+
+pub fn empty_hash() -> Hash {
+    Sha256::digest(&[]).into()
+}
+
+/// Running hash of the handshake messages.
+#[derive(Clone)]
+pub struct Transcript(Sha256);
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self(Sha256::new())
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data)
+    }
+
+    /// Hash of the messages so far.
+    pub fn hash(&self) -> Hash {
+        self.0.clone().finalize().into()
+    }
+}
+
+/// The secrets of a connection, as they evolve through the handshake.
+pub struct KeySchedule {
+    secret: Hash,
+}
+
+impl KeySchedule {
+    /// Start with the early secret, from the PSK if any.
+    pub fn new(psk: Option<&[u8]>) -> Self {
+        let zeros = [0; HASH_LEN];
+        Self {
+            secret: hkdf_extract(&zeros, psk.unwrap_or(&zeros)),
+        }
+    }
+
+    /// The key used to compute PSK binders, from the early secret.
+    pub fn binder_key(&self) -> Hash {
+        derive_secret(&self.secret, b"ext binder", &empty_hash())
+    }
+
+    /// Move on to the next secret (handshake, then master), mixing in `ikm`.
+    pub fn advance(&mut self, ikm: &[u8]) {
+        let derived = derive_secret(&self.secret, b"derived", &empty_hash());
+        self.secret = hkdf_extract(&derived, ikm);
+    }
+
+    /// Client and server traffic secrets for the current stage.
+    pub fn traffic_secrets(&self, stage: &[u8; 2], transcript_hash: &Hash) -> (Hash, Hash) {
+        let mut client_label = [0; 12];
+        let mut server_label = [0; 12];
+        client_label[..2].copy_from_slice(b"c ");
+        server_label[..2].copy_from_slice(b"s ");
+        for label in [&mut client_label, &mut server_label].iter_mut() {
+            label[2..4].copy_from_slice(stage);
+            label[4..].copy_from_slice(b" traffic");
+        }
+        (
+            derive_secret(&self.secret, &client_label, transcript_hash),
+            derive_secret(&self.secret, &server_label, transcript_hash),
+        )
+    }
+}
+
+/// The next traffic secret, after a KeyUpdate.
+pub fn next_traffic_secret(secret: &Hash) -> Hash {
+    let mut out = [0; HASH_LEN];
+    hkdf_expand_label(secret, b"traffic upd", &[], &mut out);
+    out
+}
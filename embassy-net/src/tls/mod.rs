@@ -0,0 +1,540 @@
+//! TLS 1.3 client (RFC 8446).
+//!
+//! [`TlsConnection`] runs TLS over any stream implementing [`AsyncBufRead`] and [`AsyncWrite`],
+//! usually a [`TcpSocket`](crate::TcpSocket), and implements the same traits for the
+//! plaintext. It doesn't allocate: records are assembled in buffers provided by the caller.
+//!
+//! Only the TLS_AES_128_GCM_SHA256 cipher suite and the x25519 key exchange are supported.
+//! The server is authenticated either with an external pre-shared key, or with its
+//! certificate. Certificates are checked by a [`Verifier`] provided by the application,
+//! since parsing X.509 and checking signatures depends on what the application trusts.
+
+use core::cmp::min;
+use core::ops::Range;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use embassy::io::{self, AsyncBufRead, AsyncWrite};
+use embassy::traits::rng::Rng;
+use futures::future::poll_fn;
+
+mod codec;
+mod handshake;
+mod key_schedule;
+mod record;
+
+use codec::Reader;
+use key_schedule::{next_traffic_secret, Hash, HASH_LEN};
+use record::*;
+
+use crate::fmt::*;
+
+const HS_KEY_UPDATE: u8 = 24;
+const HS_NEW_SESSION_TICKET: u8 = 4;
+
+const ALERT_LEVEL_WARNING: u8 = 1;
+const ALERT_CLOSE_NOTIFY: u8 = 0;
+
+/// Record buffer size that can hold any record. Smaller buffers work with servers
+/// that honor the max_fragment_length extension (RFC 6066).
+pub const MAX_RECORD_LEN: usize = HEADER_LEN + MAX_FRAGMENT_LEN + 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TlsError {
+    /// The underlying stream failed.
+    Io(io::Error),
+    /// The random number generator failed.
+    Rng,
+    /// A record or handshake message doesn't fit in the buffers.
+    InsufficientBuffer,
+    /// The server sent a record longer than allowed.
+    RecordOverflow,
+    /// A message couldn't be parsed.
+    DecodeError,
+    /// A record failed authentication.
+    DecryptError,
+    /// A message arrived that isn't allowed at this point.
+    UnexpectedMessage,
+    /// The server chose parameters we don't support, or failed to authenticate.
+    HandshakeFailure,
+    /// The verifier rejected the server's certificate or signature.
+    BadCertificate,
+    /// The server sent a certificate, but no verifier was configured.
+    NoVerifier,
+    /// The server sent a fatal alert, with this description.
+    Alert(u8),
+    /// The connection isn't open, or has failed.
+    NotConnected,
+    InternalError,
+}
+
+impl From<io::Error> for TlsError {
+    fn from(err: io::Error) -> Self {
+        TlsError::Io(err)
+    }
+}
+
+impl From<TlsError> for io::Error {
+    fn from(err: TlsError) -> Self {
+        match err {
+            TlsError::Io(err) => err,
+            TlsError::Alert(_) => io::Error::ConnectionAborted,
+            TlsError::NotConnected => io::Error::NotConnected,
+            TlsError::RecordOverflow
+            | TlsError::DecodeError
+            | TlsError::DecryptError
+            | TlsError::UnexpectedMessage => io::Error::InvalidData,
+            _ => io::Error::Other,
+        }
+    }
+}
+
+/// An external pre-shared key, agreed on with the server out of band.
+#[derive(Clone, Copy)]
+pub struct Psk<'a> {
+    pub identity: &'a [u8],
+    pub key: &'a [u8],
+}
+
+/// Connection settings, used by [`TlsConnection::open`].
+#[derive(Default)]
+pub struct TlsConfig<'a> {
+    /// Name of the server. Sent with the server_name extension, and passed to the verifier.
+    pub server_name: Option<&'a str>,
+    /// Pre-shared key to offer. If the server accepts it, no certificate is needed.
+    pub psk: Option<Psk<'a>>,
+    /// Checks the server's certificate. Needed unless the server accepts the PSK.
+    pub verifier: Option<&'a mut dyn Verifier>,
+}
+
+/// Checks the identity of the server.
+pub trait Verifier {
+    /// Signature schemes we can check, in order of preference.
+    fn signature_schemes(&self) -> &[u16] {
+        &[
+            0x0403, // ecdsa_secp256r1_sha256
+            0x0807, // ed25519
+            0x0804, // rsa_pss_rsae_sha256
+        ]
+    }
+
+    /// Check the certificate chain sent by the server.
+    ///
+    /// The verifier must remember the public key of the end-entity certificate, to check
+    /// the signature passed to [`Verifier::verify_signature`].
+    fn verify_certificate(
+        &mut self,
+        server_name: Option<&str>,
+        chain: CertificateChain<'_>,
+    ) -> Result<(), TlsError>;
+
+    /// Check that `signature` is a valid signature of `message` with `scheme`, made by the
+    /// key of the end-entity certificate.
+    fn verify_signature(
+        &mut self,
+        scheme: u16,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), TlsError>;
+}
+
+/// The certificates sent by the server, end-entity certificate first.
+#[derive(Clone, Copy)]
+pub struct CertificateChain<'a> {
+    list: &'a [u8],
+}
+
+impl<'a> CertificateChain<'a> {
+    /// Parse and validate a certificate_list.
+    fn parse(list: &'a [u8]) -> Result<Self, TlsError> {
+        let mut r = Reader::new(list);
+        while !r.is_empty() {
+            r.vec24()?;
+            r.vec16()?;
+        }
+        Ok(Self { list })
+    }
+
+    /// The DER encoding of each certificate.
+    pub fn iter(&self) -> impl Iterator<Item = &'a [u8]> {
+        let mut r = Reader::new(self.list);
+        core::iter::from_fn(move || {
+            if r.is_empty() {
+                return None;
+            }
+            let cert = r.vec24().ok()?.rest();
+            r.vec16().ok()?;
+            Some(cert)
+        })
+    }
+
+    /// The DER encoding of the server's own certificate.
+    pub fn end_entity(&self) -> Option<&'a [u8]> {
+        self.iter().next()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    Open,
+    Failed,
+}
+
+/// A TLS connection over the stream `S`.
+pub struct TlsConnection<'a, S> {
+    stream: S,
+    state: State,
+
+    rx_buf: &'a mut [u8],
+    /// Bytes received so far of the record being read.
+    rx_len: usize,
+    /// Application data received and not consumed yet.
+    rx_plain: Range<usize>,
+    rx_cipher: Option<Cipher>,
+    rx_secret: Hash,
+    /// The server sent close_notify.
+    rx_closed: bool,
+
+    tx_buf: &'a mut [u8],
+    /// Protected bytes not written to the stream yet.
+    tx_pending: Range<usize>,
+    /// Application data in the pending record, reported once it has been written.
+    tx_pending_plain: usize,
+    tx_cipher: Option<Cipher>,
+    tx_secret: Hash,
+    /// We sent close_notify.
+    tx_closed: bool,
+    /// The server asked us to update our keys.
+    key_update_requested: bool,
+}
+
+impl<'a, S: AsyncBufRead + AsyncWrite + Unpin> TlsConnection<'a, S> {
+    /// Create a connection over `stream`, which should already be connected.
+    ///
+    /// `rx_buf` holds incoming records. It must be large enough for the largest handshake
+    /// message (usually the server certificate chain) plus one record. `tx_buf` holds one
+    /// outgoing record, and must be large enough for the ClientHello.
+    pub fn new(stream: S, rx_buf: &'a mut [u8], tx_buf: &'a mut [u8]) -> Self {
+        Self {
+            stream,
+            state: State::Start,
+
+            rx_buf,
+            rx_len: 0,
+            rx_plain: 0..0,
+            rx_cipher: None,
+            rx_secret: [0; HASH_LEN],
+            rx_closed: false,
+
+            tx_buf,
+            tx_pending: 0..0,
+            tx_pending_plain: 0,
+            tx_cipher: None,
+            tx_secret: [0; HASH_LEN],
+            tx_closed: false,
+            key_update_requested: false,
+        }
+    }
+
+    /// Perform the handshake.
+    pub async fn open<R: Rng>(
+        &mut self,
+        config: TlsConfig<'_>,
+        rng: &mut R,
+    ) -> Result<(), TlsError> {
+        if self.state != State::Start {
+            return Err(TlsError::InternalError);
+        }
+        match self.handshake(config, rng).await {
+            Ok(()) => {
+                self.state = State::Open;
+                Ok(())
+            }
+            Err(e) => {
+                warn!("TLS handshake failed: {:?}", e);
+                self.state = State::Failed;
+                Err(e)
+            }
+        }
+    }
+
+    /// Tell the server we won't send more data, with a close_notify alert.
+    ///
+    /// Data can still be read until the server closes its side.
+    pub async fn close(&mut self) -> Result<(), TlsError> {
+        if self.state != State::Open {
+            return Err(TlsError::NotConnected);
+        }
+        self.flush().await?;
+        if !self.tx_closed {
+            self.tx_buf[HEADER_LEN] = ALERT_LEVEL_WARNING;
+            self.tx_buf[HEADER_LEN + 1] = ALERT_CLOSE_NOTIFY;
+            self.queue_record(CONTENT_ALERT, 2)?;
+            self.tx_closed = true;
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Get the underlying stream back.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Queue a record whose plaintext is at `tx_buf[HEADER_LEN..HEADER_LEN + len]`.
+    fn queue_record(&mut self, content_type: u8, len: usize) -> Result<(), TlsError> {
+        let total = match &mut self.tx_cipher {
+            Some(cipher) => cipher.encrypt(self.tx_buf, len, content_type)?,
+            None => {
+                if self.tx_buf.len() < HEADER_LEN + len {
+                    return Err(TlsError::InsufficientBuffer);
+                }
+                write_header(self.tx_buf, content_type, len);
+                HEADER_LEN + len
+            }
+        };
+        self.tx_pending = 0..total;
+        Ok(())
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), TlsError>> {
+        while !self.tx_pending.is_empty() {
+            let n = match Pin::new(&mut self.stream)
+                .poll_write(cx, &self.tx_buf[self.tx_pending.clone()])
+            {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::Error::WriteZero.into())),
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            };
+            self.tx_pending.start += n;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    async fn flush(&mut self) -> Result<(), TlsError> {
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    /// Read one record into `rx_buf[offset..]`, unprotecting it if keys are installed.
+    ///
+    /// Returns the content type, and where the content is in `rx_buf`.
+    fn poll_read_record(
+        &mut self,
+        cx: &mut Context<'_>,
+        offset: usize,
+    ) -> Poll<Result<(u8, Range<usize>), TlsError>> {
+        loop {
+            let buf = &mut self.rx_buf[offset..];
+            let need = if self.rx_len < HEADER_LEN {
+                HEADER_LEN
+            } else {
+                HEADER_LEN + u16::from_be_bytes([buf[3], buf[4]]) as usize
+            };
+            if need > buf.len() {
+                return Poll::Ready(Err(if self.rx_len < HEADER_LEN {
+                    TlsError::InsufficientBuffer
+                } else {
+                    TlsError::RecordOverflow
+                }));
+            }
+
+            if self.rx_len < need {
+                let data = match Pin::new(&mut self.stream).poll_fill_buf(cx) {
+                    Poll::Ready(Ok(data)) => data,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                    Poll::Pending => return Poll::Pending,
+                };
+                if data.is_empty() {
+                    return Poll::Ready(Err(io::Error::UnexpectedEof.into()));
+                }
+                let n = min(data.len(), need - self.rx_len);
+                buf[self.rx_len..self.rx_len + n].copy_from_slice(&data[..n]);
+                Pin::new(&mut self.stream).consume(n);
+                self.rx_len += n;
+                continue;
+            }
+
+            self.rx_len = 0;
+            let record = &mut buf[..need];
+            let content_type = record[0];
+            let res = match (&mut self.rx_cipher, content_type) {
+                // Sent by servers in middlebox compatibility mode, ignored.
+                (_, CONTENT_CHANGE_CIPHER_SPEC) if self.state == State::Start => continue,
+                (Some(cipher), CONTENT_APPLICATION_DATA) => {
+                    let (content_type, len) = cipher.decrypt(record)?;
+                    (content_type, offset + HEADER_LEN..offset + HEADER_LEN + len)
+                }
+                (None, CONTENT_HANDSHAKE) | (None, CONTENT_ALERT) => {
+                    (content_type, offset + HEADER_LEN..offset + need)
+                }
+                _ => return Poll::Ready(Err(TlsError::UnexpectedMessage)),
+            };
+            return Poll::Ready(Ok(res));
+        }
+    }
+
+    async fn read_record(&mut self, offset: usize) -> Result<(u8, Range<usize>), TlsError> {
+        poll_fn(|cx| self.poll_read_record(cx, offset)).await
+    }
+
+    /// Handle a record other than application data received after the handshake.
+    fn handle_record(&mut self, content_type: u8, range: Range<usize>) -> Result<(), TlsError> {
+        match content_type {
+            CONTENT_ALERT => match alert(&self.rx_buf[range])? {
+                None => self.rx_closed = true,
+                Some(e) => return Err(e),
+            },
+            CONTENT_HANDSHAKE => {
+                let mut r = Reader::new(&self.rx_buf[range]);
+                while !r.is_empty() {
+                    let msg_type = r.u8()?;
+                    let mut body = r.vec24()?;
+                    match msg_type {
+                        // We don't do resumption, so tickets are of no use.
+                        HS_NEW_SESSION_TICKET => {}
+                        HS_KEY_UPDATE => {
+                            let update_requested = body.u8()?;
+                            self.rx_secret = next_traffic_secret(&self.rx_secret);
+                            self.rx_cipher = Some(Cipher::new(&self.rx_secret));
+                            if update_requested == 1 {
+                                self.key_update_requested = true;
+                            }
+                        }
+                        _ => return Err(TlsError::UnexpectedMessage),
+                    }
+                }
+            }
+            _ => return Err(TlsError::UnexpectedMessage),
+        }
+        Ok(())
+    }
+
+    /// Send a KeyUpdate, and switch to new keys.
+    fn queue_key_update(&mut self) -> Result<(), TlsError> {
+        self.tx_buf[HEADER_LEN..HEADER_LEN + 5].copy_from_slice(&[HS_KEY_UPDATE, 0, 0, 1, 0]);
+        self.queue_record(CONTENT_HANDSHAKE, 5)?;
+        self.tx_secret = next_traffic_secret(&self.tx_secret);
+        self.tx_cipher = Some(Cipher::new(&self.tx_secret));
+        self.key_update_requested = false;
+        Ok(())
+    }
+
+    fn poll_fill_buf_inner(&mut self, cx: &mut Context<'_>) -> Poll<Result<&[u8], TlsError>> {
+        if self.state != State::Open {
+            return Poll::Ready(Err(TlsError::NotConnected));
+        }
+        loop {
+            if !self.rx_plain.is_empty() {
+                return Poll::Ready(Ok(&self.rx_buf[self.rx_plain.clone()]));
+            }
+            if self.rx_closed {
+                return Poll::Ready(Ok(&[]));
+            }
+            match self.poll_read_record(cx, 0) {
+                Poll::Ready(Ok((CONTENT_APPLICATION_DATA, range))) => self.rx_plain = range,
+                Poll::Ready(Ok((content_type, range))) => {
+                    if let Err(e) = self.handle_record(content_type, range) {
+                        self.state = State::Failed;
+                        return Poll::Ready(Err(e));
+                    }
+                }
+                Poll::Ready(Err(e)) => {
+                    self.state = State::Failed;
+                    return Poll::Ready(Err(e));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn poll_write_inner(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, TlsError>> {
+        if self.state != State::Open {
+            return Poll::Ready(Err(TlsError::NotConnected));
+        }
+        if self.tx_closed {
+            return Poll::Ready(Err(TlsError::Io(io::Error::BrokenPipe)));
+        }
+        loop {
+            match self.poll_flush(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => {
+                    self.state = State::Failed;
+                    return Poll::Ready(Err(e));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+
+            // The last record has been written: the data in it is now really sent.
+            if self.tx_pending_plain != 0 {
+                let n = min(self.tx_pending_plain, buf.len());
+                self.tx_pending_plain = 0;
+                return Poll::Ready(Ok(n));
+            }
+
+            if self.key_update_requested {
+                if let Err(e) = self.queue_key_update() {
+                    self.state = State::Failed;
+                    return Poll::Ready(Err(e));
+                }
+                continue;
+            }
+
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            if self.tx_buf.len() <= OVERHEAD {
+                return Poll::Ready(Err(TlsError::InsufficientBuffer));
+            }
+            let n = min(
+                buf.len(),
+                min(self.tx_buf.len() - OVERHEAD, MAX_FRAGMENT_LEN),
+            );
+            self.tx_buf[HEADER_LEN..HEADER_LEN + n].copy_from_slice(&buf[..n]);
+            self.queue_record(CONTENT_APPLICATION_DATA, n)?;
+            self.tx_pending_plain = n;
+        }
+    }
+}
+
+/// Parse an alert. Returns `None` for close_notify, and an error for fatal alerts.
+fn alert(data: &[u8]) -> Result<Option<TlsError>, TlsError> {
+    let mut r = Reader::new(data);
+    let _level = r.u8()?;
+    match r.u8()? {
+        ALERT_CLOSE_NOTIFY => Ok(None),
+        description => Ok(Some(TlsError::Alert(description))),
+    }
+}
+
+impl<'a, S: AsyncBufRead + AsyncWrite + Unpin> AsyncBufRead for TlsConnection<'a, S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        match self.get_mut().poll_fill_buf_inner(cx) {
+            Poll::Ready(Ok(buf)) => Poll::Ready(Ok(buf)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.rx_plain.start = min(this.rx_plain.start + amt, this.rx_plain.end);
+    }
+}
+
+impl<'a, S: AsyncBufRead + AsyncWrite + Unpin> AsyncWrite for TlsConnection<'a, S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_write_inner(cx, buf)
+            .map_err(|e| e.into())
+    }
+}
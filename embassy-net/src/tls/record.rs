@@ -0,0 +1,111 @@
+//! TLS 1.3 record protection, with TLS_AES_128_GCM_SHA256.
+
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{AeadInPlace, NewAead};
+use aes_gcm::Aes128Gcm;
+
+use super::key_schedule::{hkdf_expand_label, Hash};
+use super::TlsError;
+
+pub const HEADER_LEN: usize = 5;
+pub const TAG_LEN: usize = 16;
+/// Largest plaintext allowed in a record.
+pub const MAX_FRAGMENT_LEN: usize = 1 << 14;
+/// Bytes added to the plaintext by protection: header, content type and tag.
+pub const OVERHEAD: usize = HEADER_LEN + 1 + TAG_LEN;
+
+pub const CONTENT_CHANGE_CIPHER_SPEC: u8 = 20;
+pub const CONTENT_ALERT: u8 = 21;
+pub const CONTENT_HANDSHAKE: u8 = 22;
+pub const CONTENT_APPLICATION_DATA: u8 = 23;
+
+/// Keys protecting one direction of a connection.
+pub struct Cipher {
+    aead: Aes128Gcm,
+    iv: [u8; 12],
+    seq: u64,
+}
+
+impl Cipher {
+    pub fn new(traffic_secret: &Hash) -> Self {
+        let mut key = [0; 16];
+        let mut iv = [0; 12];
+        hkdf_expand_label(traffic_secret, b"key", &[], &mut key);
+        hkdf_expand_label(traffic_secret, b"iv", &[], &mut iv);
+        Self {
+            aead: Aes128Gcm::new(GenericArray::from_slice(&key)),
+            iv,
+            seq: 0,
+        }
+    }
+
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[4..].iter_mut().zip(self.seq.to_be_bytes().iter()) {
+            *n ^= s;
+        }
+        self.seq += 1;
+        nonce
+    }
+
+    /// Protect a record whose plaintext is at `buf[HEADER_LEN..HEADER_LEN + len]`.
+    ///
+    /// Returns the length of the whole record, written at the start of `buf`.
+    pub fn encrypt(
+        &mut self,
+        buf: &mut [u8],
+        len: usize,
+        content_type: u8,
+    ) -> Result<usize, TlsError> {
+        let total = len + OVERHEAD;
+        if buf.len() < total {
+            return Err(TlsError::InsufficientBuffer);
+        }
+
+        buf[HEADER_LEN + len] = content_type;
+        write_header(buf, CONTENT_APPLICATION_DATA, total - HEADER_LEN);
+
+        let nonce = self.next_nonce();
+        let (header, rest) = buf.split_at_mut(HEADER_LEN);
+        let (payload, tag) = rest[..len + 1 + TAG_LEN].split_at_mut(len + 1);
+        let t = self
+            .aead
+            .encrypt_in_place_detached(GenericArray::from_slice(&nonce), header, payload)
+            .map_err(|_| TlsError::InternalError)?;
+        tag.copy_from_slice(&t);
+        Ok(total)
+    }
+
+    /// Unprotect a whole record in place.
+    ///
+    /// Returns the real content type, and the length of the plaintext at `buf[HEADER_LEN..]`.
+    pub fn decrypt(&mut self, buf: &mut [u8]) -> Result<(u8, usize), TlsError> {
+        if buf.len() < HEADER_LEN + 1 + TAG_LEN {
+            return Err(TlsError::DecodeError);
+        }
+
+        let nonce = self.next_nonce();
+        let (header, rest) = buf.split_at_mut(HEADER_LEN);
+        let (payload, tag) = rest.split_at_mut(rest.len() - TAG_LEN);
+        self.aead
+            .decrypt_in_place_detached(
+                GenericArray::from_slice(&nonce),
+                header,
+                payload,
+                GenericArray::from_slice(tag),
+            )
+            .map_err(|_| TlsError::DecryptError)?;
+
+        // Strip the padding. The content type is the last non-zero byte.
+        match payload.iter().rposition(|&b| b != 0) {
+            Some(pos) => Ok((payload[pos], pos)),
+            None => Err(TlsError::UnexpectedMessage),
+        }
+    }
+}
+
+pub fn write_header(buf: &mut [u8], content_type: u8, len: usize) {
+    buf[0] = content_type;
+    buf[1..3].copy_from_slice(&[0x03, 0x03]);
+    buf[3..5].copy_from_slice(&(len as u16).to_be_bytes());
+}
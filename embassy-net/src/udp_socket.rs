@@ -0,0 +1,159 @@
+use core::mem;
+use core::task::Poll;
+use smoltcp::socket::SocketHandle;
+use smoltcp::socket::UdpSocket as SyncUdpSocket;
+use smoltcp::socket::{UdpPacketMetadata, UdpSocketBuffer};
+use smoltcp::wire::IpEndpoint;
+
+use super::stack::{Inner, InterfaceId, Stack};
+use crate::{Error, Result};
+
+pub struct UdpSocket<'a> {
+    stack: &'a Stack,
+    iface: InterfaceId,
+    handle: SocketHandle,
+    bound: bool,
+}
+
+impl<'a> Unpin for UdpSocket<'a> {}
+
+impl<'a> UdpSocket<'a> {
+    /// Create a socket. Each metadata entry holds the addressing of one datagram, and
+    /// the payload buffers hold their data.
+    pub fn new(
+        stack: &'a Stack,
+        rx_meta: &'a mut [UdpPacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [UdpPacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Self {
+        // Like TCP sockets, the socket starts out on the first interface, and is moved to
+        // the right one when sending.
+        let iface = InterfaceId::FIRST;
+        let handle = stack.with(|inner| {
+            let rx_meta: &'static mut [UdpPacketMetadata] = unsafe { mem::transmute(rx_meta) };
+            let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
+            let tx_meta: &'static mut [UdpPacketMetadata] = unsafe { mem::transmute(tx_meta) };
+            let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
+            inner.sockets(iface).add(SyncUdpSocket::new(
+                UdpSocketBuffer::new(rx_meta, rx_buffer),
+                UdpSocketBuffer::new(tx_meta, tx_buffer),
+            ))
+        });
+
+        Self {
+            stack,
+            iface,
+            handle,
+            bound: false,
+        }
+    }
+
+    /// Bind this socket to an interface.
+    ///
+    /// By default, the interface is chosen when sending by routing the remote address,
+    /// and datagrams are received on the interface the last one was sent on. A bound
+    /// socket always uses the given interface instead.
+    ///
+    /// Fails with `Error::Exhausted` if the interface has no room for another socket, or
+    /// `Error::Illegal` if it's an interface of another stack.
+    pub fn bind_interface(&mut self, iface: InterfaceId) -> Result<()> {
+        let stack = self.stack;
+        stack.with(|inner| self.move_to(inner, iface))?;
+        self.bound = true;
+        Ok(())
+    }
+
+    /// Returns the interface this socket is currently on.
+    pub fn interface(&self) -> InterfaceId {
+        self.iface
+    }
+
+    /// Bind the socket to a local endpoint. A port of 0 picks an ephemeral port.
+    pub fn bind<T>(&mut self, local_endpoint: T) -> Result<()>
+    where
+        T: Into<IpEndpoint>,
+    {
+        let mut endpoint = local_endpoint.into();
+        if endpoint.port == 0 {
+            endpoint.port = self.stack.with(|inner| inner.get_local_port());
+        }
+        self.with(|s| s.bind(endpoint))
+    }
+
+    pub fn local_endpoint(&self) -> IpEndpoint {
+        self.with(|s| s.endpoint())
+    }
+
+    /// Send a datagram to `remote_endpoint`, waiting for room in the transmit buffer.
+    pub async fn send_to<T>(&mut self, buf: &[u8], remote_endpoint: T) -> Result<()>
+    where
+        T: Into<IpEndpoint>,
+    {
+        let remote_endpoint = remote_endpoint.into();
+        if !self.bound {
+            let stack = self.stack;
+            stack.with(|inner| {
+                let iface = inner
+                    .route(remote_endpoint.addr)
+                    .ok_or(Error::Unaddressable)?;
+                self.move_to(inner, iface)
+            })?;
+        }
+
+        futures::future::poll_fn(|cx| {
+            self.with(|s| match s.send_slice(buf, remote_endpoint) {
+                // No space in the tx buffer
+                Err(Error::Exhausted) => {
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+                res => Poll::Ready(res),
+            })
+        })
+        .await
+    }
+
+    /// Wait for a datagram. Returns its length and where it came from.
+    ///
+    /// Datagrams longer than `buf` are truncated.
+    pub async fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, IpEndpoint)> {
+        futures::future::poll_fn(|cx| {
+            self.with(|s| match s.recv_slice(buf) {
+                // Nothing received yet
+                Err(Error::Exhausted) => {
+                    s.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+                res => Poll::Ready(res),
+            })
+        })
+        .await
+    }
+
+    /// Move the socket to `iface`, or leave it where it was if `iface` has no room for it.
+    fn move_to(&mut self, inner: &mut Inner, iface: InterfaceId) -> Result<()> {
+        self.handle = inner.move_socket(self.iface, self.handle, iface)?;
+        self.iface = iface;
+        Ok(())
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut SyncUdpSocket) -> R) -> R {
+        self.stack.with(|inner| {
+            let res = {
+                let mut s = inner.sockets(self.iface).get::<SyncUdpSocket>(self.handle);
+                f(&mut *s)
+            };
+            inner.wake();
+            res
+        })
+    }
+}
+
+impl<'a> Drop for UdpSocket<'a> {
+    fn drop(&mut self) {
+        self.stack.with(|inner| {
+            inner.sockets(self.iface).remove(self.handle);
+        })
+    }
+}
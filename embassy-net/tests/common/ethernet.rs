@@ -0,0 +1,108 @@
+//! A stack on one side of an Ethernet [`Loopback`], and a raw peer on the other, for tests
+//! that need to see and craft frames.
+
+use embassy::executor::Spawner;
+use embassy::util::Forever;
+use embassy_net::*;
+use smoltcp::wire::{ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol};
+use std::task::Poll;
+
+pub const STACK_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 1]);
+pub const PEER_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 2]);
+
+type LoopbackSide = LoopbackDevice<'static, 8, 1500>;
+
+/// The memory of a stack and its peer. Each test puts its own in a `static`, since the
+/// stack is `'static` and can't be set up twice.
+pub struct EthernetNet {
+    loopback: Forever<Loopback<8, 1500>>,
+    devices: Forever<(LoopbackSide, LoopbackSide)>,
+    resources: Forever<StackResources<4>>,
+    stack: Forever<Stack>,
+}
+
+#[embassy::task(pool_size = 4)]
+async fn stack_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+impl EthernetNet {
+    pub const fn new() -> Self {
+        Self {
+            loopback: Forever::new(),
+            devices: Forever::new(),
+            resources: Forever::new(),
+            stack: Forever::new(),
+        }
+    }
+
+    /// Create the stack, configured by `configurator`, and start running it.
+    pub fn start(
+        &'static self,
+        spawner: Spawner,
+        configurator: &'static mut dyn Configurator,
+    ) -> (&'static Stack, Peer) {
+        let loopback: &'static Loopback<8, 1500> = self.loopback.put(Loopback::new());
+        let (device, peer) = self.devices.put(loopback.devices(Medium::Ethernet));
+
+        let stack: &'static Stack = self.stack.put(Stack::new(
+            device,
+            configurator,
+            self.resources.put(StackResources::new()),
+        ));
+        spawner.spawn(stack_task(stack)).unwrap();
+        (stack, Peer { device: peer })
+    }
+}
+
+/// The other side of the loopback, with the address [`PEER_MAC`].
+pub struct Peer {
+    device: &'static mut LoopbackSide,
+}
+
+impl Peer {
+    /// Wait for the next frame sent by the stack.
+    pub async fn recv(&mut self) -> Vec<u8> {
+        futures::future::poll_fn(|cx| {
+            self.device.register_waker(cx.waker());
+            match self.device.receive() {
+                Some((rx, _)) => {
+                    let mut frame = Vec::new();
+                    rx.consume(&mut |buf| frame.extend_from_slice(buf));
+                    Poll::Ready(frame)
+                }
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Send `frame` to the stack.
+    pub fn send(&mut self, frame: &[u8]) {
+        let tx = self.device.transmit().expect("loopback full");
+        tx.consume(frame.len(), &mut |buf| buf.copy_from_slice(frame));
+    }
+
+    /// Wait for the next ARP packet sent by the stack, skipping other frames.
+    pub async fn recv_arp(&mut self) -> ArpRepr {
+        loop {
+            let frame = self.recv().await;
+            let frame = EthernetFrame::new_checked(&frame[..]).unwrap();
+            if frame.ethertype() == EthernetProtocol::Arp {
+                let packet = ArpPacket::new_checked(frame.payload()).unwrap();
+                return ArpRepr::parse(&packet).unwrap();
+            }
+        }
+    }
+
+    /// Broadcast an ARP packet.
+    pub fn send_arp(&mut self, repr: ArpRepr) {
+        let mut frame = vec![0; EthernetFrame::<&[u8]>::header_len() + repr.buffer_len()];
+        let mut ethernet = EthernetFrame::new_unchecked(&mut frame[..]);
+        ethernet.set_src_addr(PEER_MAC);
+        ethernet.set_dst_addr(EthernetAddress::BROADCAST);
+        ethernet.set_ethertype(EthernetProtocol::Arp);
+        repr.emit(&mut ArpPacket::new_unchecked(ethernet.payload_mut()));
+        self.send(&frame);
+    }
+}
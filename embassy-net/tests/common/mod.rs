@@ -0,0 +1,109 @@
+//! Helpers shared by the tests.
+
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Read;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use embassy::io::{self, AsyncBufRead, AsyncWrite};
+
+#[cfg(feature = "medium-ethernet")]
+pub mod ethernet;
+#[cfg(feature = "medium-ip")]
+pub mod net;
+
+#[no_mangle]
+fn _embassy_rand(buf: &mut [u8]) {
+    File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(buf))
+        .unwrap();
+}
+
+/// The bytes written to one end of a [`pipe`], until the other end reads them.
+struct Buffer {
+    data: VecDeque<u8>,
+    /// The writing end was dropped.
+    closed: bool,
+    reader: Option<Waker>,
+}
+
+/// Two byte streams connected to each other in memory, like a socket pair.
+pub fn pipe() -> (PipeEnd, PipeEnd) {
+    let new_buffer = || {
+        Rc::new(RefCell::new(Buffer {
+            data: VecDeque::new(),
+            closed: false,
+            reader: None,
+        }))
+    };
+    let (a, b) = (new_buffer(), new_buffer());
+    (
+        PipeEnd {
+            rx: a.clone(),
+            tx: b.clone(),
+            pending: Vec::new(),
+        },
+        PipeEnd {
+            rx: b,
+            tx: a,
+            pending: Vec::new(),
+        },
+    )
+}
+
+/// One end of a [`pipe`]. Dropping it ends the stream read by the other end.
+pub struct PipeEnd {
+    rx: Rc<RefCell<Buffer>>,
+    tx: Rc<RefCell<Buffer>>,
+    /// The bytes taken from `rx` and returned by `poll_fill_buf`, not consumed yet.
+    pending: Vec<u8>,
+}
+
+impl AsyncBufRead for PipeEnd {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.pending.is_empty() {
+            let mut rx = this.rx.borrow_mut();
+            if rx.data.is_empty() && !rx.closed {
+                rx.reader = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            this.pending.extend(rx.data.drain(..));
+        }
+        Poll::Ready(Ok(&this.pending))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().pending.drain(..amt);
+    }
+}
+
+impl AsyncWrite for PipeEnd {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut tx = self.tx.borrow_mut();
+        tx.data.extend(buf);
+        if let Some(waker) = tx.reader.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        let mut tx = self.tx.borrow_mut();
+        tx.closed = true;
+        if let Some(waker) = tx.reader.take() {
+            waker.wake();
+        }
+    }
+}
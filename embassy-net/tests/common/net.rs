@@ -0,0 +1,71 @@
+//! Two stacks connected by a [`Loopback`].
+
+use embassy::executor::Spawner;
+use embassy::util::Forever;
+use embassy_net::*;
+use heapless::Vec;
+
+pub const SERVER_ADDRESS: Ipv4Address = Ipv4Address([192, 168, 69, 1]);
+pub const CLIENT_ADDRESS: Ipv4Address = Ipv4Address([192, 168, 69, 2]);
+
+type LoopbackSide = LoopbackDevice<'static, 4, 1500>;
+
+/// The memory of a server and a client stack. Each test puts its own in a `static`, since
+/// the stacks are `'static` and can't be set up twice.
+pub struct Net {
+    loopback: Forever<Loopback<4, 1500>>,
+    devices: Forever<(LoopbackSide, LoopbackSide)>,
+    configs: [Forever<StaticConfigurator>; 2],
+    resources: [Forever<StackResources<4>>; 2],
+    stacks: [Forever<Stack>; 2],
+}
+
+#[embassy::task(pool_size = 4)]
+async fn net_task(stack: &'static Stack) {
+    stack.run().await
+}
+
+fn static_config(address: Ipv4Address) -> StaticConfigurator {
+    StaticConfigurator::new(Config {
+        address: Ipv4Cidr::new(address, 24),
+        dns_servers: Vec::new(),
+        gateway: None,
+        dhcp: None,
+    })
+}
+
+impl Net {
+    pub const fn new() -> Self {
+        Self {
+            loopback: Forever::new(),
+            devices: Forever::new(),
+            configs: [Forever::new(), Forever::new()],
+            resources: [Forever::new(), Forever::new()],
+            stacks: [Forever::new(), Forever::new()],
+        }
+    }
+
+    /// Create the server and client stacks, with the addresses [`SERVER_ADDRESS`] and
+    /// [`CLIENT_ADDRESS`], and wait until both are configured.
+    pub async fn start(&'static self, spawner: Spawner) -> (&'static Stack, &'static Stack) {
+        let loopback: &'static Loopback<4, 1500> = self.loopback.put(Loopback::new());
+        let (server_device, client_device) = self.devices.put(loopback.devices(Medium::Ip));
+
+        let server: &'static Stack = self.stacks[0].put(Stack::new(
+            server_device,
+            self.configs[0].put(static_config(SERVER_ADDRESS)),
+            self.resources[0].put(StackResources::new()),
+        ));
+        let client: &'static Stack = self.stacks[1].put(Stack::new(
+            client_device,
+            self.configs[1].put(static_config(CLIENT_ADDRESS)),
+            self.resources[1].put(StackResources::new()),
+        ));
+
+        spawner.spawn(net_task(server)).unwrap();
+        spawner.spawn(net_task(client)).unwrap();
+        server.wait_config_up().await;
+        client.wait_config_up().await;
+        (server, client)
+    }
+}
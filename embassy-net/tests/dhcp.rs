@@ -0,0 +1,460 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::ethernet::{EthernetNet, Peer, PEER_MAC, STACK_MAC};
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Instant, Timer};
+use embassy::util::Forever;
+use embassy_net::*;
+use futures::StreamExt;
+use smoltcp::wire::{ArpOperation, ArpPacket, ArpRepr, EthernetFrame, EthernetProtocol};
+
+static MALFORMED_NET: EthernetNet = EthernetNet::new();
+static MALFORMED_DHCP: Forever<DhcpConfigurator> = Forever::new();
+static OVERLONG_NET: EthernetNet = EthernetNet::new();
+static OVERLONG_DHCP: Forever<DhcpConfigurator> = Forever::new();
+static OVERLOAD_NET: EthernetNet = EthernetNet::new();
+static OVERLOAD_DHCP: Forever<DhcpConfigurator> = Forever::new();
+static RENEW_NET: EthernetNet = EthernetNet::new();
+static RENEW_DHCP: Forever<DhcpConfigurator> = Forever::new();
+static REBIND_NET: EthernetNet = EthernetNet::new();
+static REBIND_DHCP: Forever<DhcpConfigurator> = Forever::new();
+static EXPIRE_NET: EthernetNet = EthernetNet::new();
+static EXPIRE_DHCP: Forever<DhcpConfigurator> = Forever::new();
+
+const SERVER_IP: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
+const CLIENT_IP: Ipv4Address = Ipv4Address([192, 168, 1, 50]);
+/// An address the client must not take, offered in messages it must ignore.
+const DECOY_IP: Ipv4Address = Ipv4Address([192, 168, 1, 66]);
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS_SERVER: u8 = 6;
+const OPT_DOMAIN_NAME: u8 = 15;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_OVERLOAD: u8 = 52;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPT_END: u8 = 255;
+
+/// A message sent by the client.
+struct Message {
+    /// The IP address it was sent to.
+    dest: Ipv4Address,
+    xid: [u8; 4],
+    client_ip: Ipv4Address,
+    options: Vec<(u8, Vec<u8>)>,
+}
+
+impl Message {
+    fn parse(dest: Ipv4Address, msg: &[u8]) -> Self {
+        assert_eq!(msg[0], 1, "not a request");
+        assert_eq!(&msg[28..34], STACK_MAC.as_bytes());
+
+        let mut options = Vec::new();
+        let mut opts = &msg[240..];
+        while opts[0] != OPT_END {
+            let len = opts[1] as usize;
+            options.push((opts[0], opts[2..2 + len].to_vec()));
+            opts = &opts[2 + len..];
+        }
+
+        Self {
+            dest,
+            xid: [msg[4], msg[5], msg[6], msg[7]],
+            client_ip: Ipv4Address::from_bytes(&msg[12..16]),
+            options,
+        }
+    }
+
+    fn option(&self, code: u8) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map(|(_, data)| &data[..])
+    }
+
+    fn message_type(&self) -> u8 {
+        self.option(OPT_MESSAGE_TYPE).unwrap()[0]
+    }
+}
+
+/// A DHCP server stand-in, on the peer side of the loopback.
+struct Server {
+    peer: Peer,
+}
+
+impl Server {
+    /// Wait for the next DHCP message from the client, answering ARP requests for
+    /// [`SERVER_IP`] meanwhile.
+    async fn recv(&mut self) -> Message {
+        loop {
+            let frame = self.peer.recv().await;
+            let frame = EthernetFrame::new_checked(&frame[..]).unwrap();
+            match frame.ethertype() {
+                EthernetProtocol::Arp => {
+                    let packet = ArpPacket::new_checked(frame.payload()).unwrap();
+                    if let ArpRepr::EthernetIpv4 {
+                        operation: ArpOperation::Request,
+                        source_hardware_addr,
+                        source_protocol_addr,
+                        target_protocol_addr,
+                        ..
+                    } = ArpRepr::parse(&packet).unwrap()
+                    {
+                        if target_protocol_addr == SERVER_IP {
+                            self.peer.send_arp(ArpRepr::EthernetIpv4 {
+                                operation: ArpOperation::Reply,
+                                source_hardware_addr: PEER_MAC,
+                                source_protocol_addr: SERVER_IP,
+                                target_hardware_addr: source_hardware_addr,
+                                target_protocol_addr: source_protocol_addr,
+                            });
+                        }
+                    }
+                }
+                EthernetProtocol::Ipv4 => {
+                    let ip = frame.payload();
+                    let udp = &ip[(ip[0] & 0x0f) as usize * 4..];
+                    let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+                    if ip[9] == 17 && udp[2..4] == 67u16.to_be_bytes() {
+                        let dest = Ipv4Address::from_bytes(&ip[16..20]);
+                        return Message::parse(dest, &udp[8..udp_len]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Answer `request` with a message of `message_type` offering [`CLIENT_IP`], with the
+    /// server id and `options`.
+    async fn reply(&mut self, request: &Message, message_type: u8, options: &[u8]) {
+        let mut msg = reply_header(request.xid, CLIENT_IP);
+        msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, message_type, OPT_SERVER_ID, 4]);
+        msg.extend_from_slice(SERVER_IP.as_bytes());
+        msg.extend_from_slice(options);
+        msg.push(OPT_END);
+        self.send(&msg).await;
+    }
+
+    /// Broadcast a raw DHCP message, and give the stack time to handle it, since the
+    /// client only buffers a couple of them.
+    async fn send(&mut self, msg: &[u8]) {
+        self.peer.send(&udp_frame(msg));
+        Timer::after(Duration::from_millis(1)).await;
+    }
+}
+
+/// The fixed part of a reply to `xid` offering `your_ip`, up to the magic cookie.
+fn reply_header(xid: [u8; 4], your_ip: Ipv4Address) -> Vec<u8> {
+    let mut msg = vec![0; 240];
+    msg[0] = 2; // BOOTREPLY
+    msg[1] = 1; // Ethernet
+    msg[2] = 6;
+    msg[4..8].copy_from_slice(&xid);
+    msg[16..20].copy_from_slice(your_ip.as_bytes());
+    msg[28..34].copy_from_slice(STACK_MAC.as_bytes());
+    msg[236..240].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+    msg
+}
+
+/// Wrap `payload` in a broadcast UDP datagram from the server port to the client port.
+fn udp_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0xff; 6];
+    frame.extend_from_slice(PEER_MAC.as_bytes());
+    frame.extend_from_slice(&[0x08, 0x00]);
+
+    let total_len = (20 + 8 + payload.len()) as u16;
+    let mut ip = vec![0x45, 0];
+    ip.extend_from_slice(&total_len.to_be_bytes());
+    ip.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+    ip.extend_from_slice(SERVER_IP.as_bytes());
+    ip.extend_from_slice(&[255, 255, 255, 255]);
+    let sum = ip.chunks(2).fold(0u32, |sum, word| {
+        let sum = sum + u16::from_be_bytes([word[0], word[1]]) as u32;
+        (sum & 0xffff) + (sum >> 16)
+    });
+    ip[10..12].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+    frame.extend_from_slice(&ip);
+
+    // No UDP checksum, which IPv4 allows.
+    frame.extend_from_slice(&67u16.to_be_bytes());
+    frame.extend_from_slice(&68u16.to_be_bytes());
+    frame.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The options of an ACK for a lease of `lease_secs`, on a /24 with the server as router.
+fn lease_options(lease_secs: u32) -> Vec<u8> {
+    let mut options = vec![OPT_SUBNET_MASK, 4, 255, 255, 255, 0, OPT_ROUTER, 4];
+    options.extend_from_slice(SERVER_IP.as_bytes());
+    options.extend_from_slice(&[OPT_LEASE_TIME, 4]);
+    options.extend_from_slice(&lease_secs.to_be_bytes());
+    options
+}
+
+fn start(
+    net: &'static EthernetNet,
+    dhcp: &'static mut DhcpConfigurator,
+    spawner: Spawner,
+) -> (&'static Stack, Server) {
+    let (stack, peer) = net.start(spawner, dhcp);
+    (stack, Server { peer })
+}
+
+/// Wait for the client to discover the server and request the offered address.
+async fn expect_request(server: &mut Server) -> Message {
+    let discover = server.recv().await;
+    assert_eq!(discover.message_type(), DISCOVER);
+    assert_eq!(discover.dest, Ipv4Address::BROADCAST);
+    server.reply(&discover, OFFER, &[]).await;
+
+    let request = server.recv().await;
+    assert_eq!(request.message_type(), REQUEST);
+    assert_eq!(request.option(OPT_REQUESTED_IP), Some(CLIENT_IP.as_bytes()));
+    assert_eq!(request.option(OPT_SERVER_ID), Some(SERVER_IP.as_bytes()));
+    request
+}
+
+/// Grant the client a lease of `lease_secs`, and return when it was granted.
+async fn lease(server: &mut Server, stack: &Stack, lease_secs: u32) -> Instant {
+    let request = expect_request(server).await;
+    let granted = Instant::now();
+    server
+        .reply(&request, ACK, &lease_options(lease_secs))
+        .await;
+    stack.wait_config_up().await;
+
+    let config = stack.interface_config(InterfaceId::FIRST).unwrap();
+    assert_eq!(config.address, Ipv4Cidr::new(CLIENT_IP, 24));
+    assert_eq!(config.gateway, Some(SERVER_IP));
+    granted
+}
+
+/// Check that `a` and `b` are the same, give or take the rounding of the stack's clock to
+/// milliseconds.
+fn assert_near(a: Instant, b: Instant) {
+    let diff = if a > b { a - b } else { b - a };
+    assert!(diff < Duration::from_millis(10), "{:?} != {:?}", a, b);
+}
+
+#[embassy::test(mock_clock)]
+async fn ignores_malformed_replies(spawner: Spawner) {
+    let (_stack, mut server) = start(
+        &MALFORMED_NET,
+        MALFORMED_DHCP.put(DhcpConfigurator::new()),
+        spawner,
+    );
+    let discover = server.recv().await;
+    let decoy_offer = || {
+        let mut msg = reply_header(discover.xid, DECOY_IP);
+        msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, OFFER, OPT_SERVER_ID, 4]);
+        msg.extend_from_slice(SERVER_IP.as_bytes());
+        msg
+    };
+
+    // Truncated before the options.
+    let mut msg = decoy_offer();
+    msg.truncate(200);
+    server.send(&msg).await;
+
+    // An option running past the end of the message.
+    let mut msg = decoy_offer();
+    msg.extend_from_slice(&[OPT_ROUTER, 8, 192, 168]);
+    server.send(&msg).await;
+
+    // A server id of the wrong length, without which the offer can't be used.
+    let mut msg = reply_header(discover.xid, DECOY_IP);
+    msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, OFFER, OPT_SERVER_ID, 3, 192, 168, 1]);
+    msg.push(OPT_END);
+    server.send(&msg).await;
+
+    // A bad magic cookie.
+    let mut msg = decoy_offer();
+    msg[239] = 0;
+    msg.push(OPT_END);
+    server.send(&msg).await;
+
+    // Another transaction.
+    let mut msg = decoy_offer();
+    msg[4] ^= 0xff;
+    msg.push(OPT_END);
+    server.send(&msg).await;
+
+    // None of them was taken, so this offer is the one requested.
+    server.reply(&discover, OFFER, &[]).await;
+    let request = server.recv().await;
+    assert_eq!(request.message_type(), REQUEST);
+    assert_eq!(request.option(OPT_REQUESTED_IP), Some(CLIENT_IP.as_bytes()));
+}
+
+#[embassy::test(mock_clock)]
+async fn drops_overlong_options(spawner: Spawner) {
+    let mut dhcp = DhcpConfigurator::new();
+    assert_eq!(dhcp.set_hostname(&"h".repeat(64)), Err(Error::Exhausted));
+    assert_eq!(dhcp.set_client_id(&[]), Err(Error::Illegal));
+    assert_eq!(dhcp.set_client_id(&[1; 33]), Err(Error::Exhausted));
+    for code in 224..232 {
+        dhcp.request_option(code).unwrap();
+    }
+    assert_eq!(dhcp.request_option(232), Err(Error::Exhausted));
+    let (stack, mut server) = start(&OVERLONG_NET, OVERLONG_DHCP.put(dhcp), spawner);
+
+    let request = expect_request(&mut server).await;
+    let parameters = request.option(OPT_PARAMETER_REQUEST_LIST).unwrap();
+    assert!((224..232).all(|code| parameters.contains(&code)));
+    assert!(!parameters.contains(&232));
+
+    let mut options = lease_options(3600);
+    // Five DNS servers, of which three are kept.
+    options.extend_from_slice(&[OPT_DNS_SERVER, 20]);
+    for i in 1..=5 {
+        options.extend_from_slice(&[10, 0, 0, i]);
+    }
+    // A domain name too long to keep.
+    options.extend_from_slice(&[OPT_DOMAIN_NAME, 70]);
+    options.extend_from_slice("d".repeat(70).as_bytes());
+    // A requested option too long to keep, and one that fits.
+    options.extend_from_slice(&[224, 40]);
+    options.extend_from_slice(&[0xaa; 40]);
+    options.extend_from_slice(&[225, 2, 0x12, 0x34]);
+    server.reply(&request, ACK, &options).await;
+    stack.wait_config_up().await;
+
+    let config = stack.interface_config(InterfaceId::FIRST).unwrap();
+    assert_eq!(config.address, Ipv4Cidr::new(CLIENT_IP, 24));
+    assert_eq!(
+        config.dns_servers[..],
+        [
+            Ipv4Address::new(10, 0, 0, 1),
+            Ipv4Address::new(10, 0, 0, 2),
+            Ipv4Address::new(10, 0, 0, 3)
+        ]
+    );
+    let info = config.dhcp.unwrap();
+    assert_eq!(info.domain_name, None);
+    assert_eq!(info.options.len(), 1);
+    assert_eq!(info.options[0].code, 225);
+    assert_eq!(info.options[0].data[..], [0x12, 0x34]);
+}
+
+#[embassy::test(mock_clock)]
+async fn reads_overloaded_options(spawner: Spawner) {
+    let (stack, mut server) = start(
+        &OVERLOAD_NET,
+        OVERLOAD_DHCP.put(DhcpConfigurator::new()),
+        spawner,
+    );
+    let request = expect_request(&mut server).await;
+
+    // The subnet mask is in the file field, and the router in the sname field.
+    let mut msg = reply_header(request.xid, CLIENT_IP);
+    msg[108..115].copy_from_slice(&[OPT_SUBNET_MASK, 4, 255, 255, 255, 0, OPT_END]);
+    msg[44..51].copy_from_slice(&[OPT_ROUTER, 4, 192, 168, 1, 1, OPT_END]);
+    msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, ACK, OPT_SERVER_ID, 4]);
+    msg.extend_from_slice(SERVER_IP.as_bytes());
+    msg.extend_from_slice(&[OPT_OVERLOAD, 1, 3, OPT_END]);
+    server.send(&msg).await;
+
+    stack.wait_config_up().await;
+    let config = stack.interface_config(InterfaceId::FIRST).unwrap();
+    assert_eq!(config.address, Ipv4Cidr::new(CLIENT_IP, 24));
+    assert_eq!(config.gateway, Some(SERVER_IP));
+}
+
+#[embassy::test(mock_clock)]
+async fn renews_lease(spawner: Spawner) {
+    let (stack, mut server) = start(&RENEW_NET, RENEW_DHCP.put(DhcpConfigurator::new()), spawner);
+    let granted = lease(&mut server, stack, 100).await;
+
+    // At T1, half of the lease, the client asks the server that granted it.
+    let renew = server.recv().await;
+    assert_near(Instant::now(), granted + Duration::from_secs(50));
+    assert_eq!(renew.message_type(), REQUEST);
+    assert_eq!(renew.dest, SERVER_IP);
+    assert_eq!(renew.client_ip, CLIENT_IP);
+    assert_eq!(renew.option(OPT_REQUESTED_IP), None);
+
+    let mut events = stack.config_events();
+    let renewed = Instant::now();
+    server.reply(&renew, ACK, &lease_options(100)).await;
+    let config = match events.next().await {
+        Some((_, ConfigEvent::Configured(config))) => config,
+        event => panic!("expected a new lease, got {:?}", event),
+    };
+    assert_eq!(config.address, Ipv4Cidr::new(CLIENT_IP, 24));
+    let info = config.dhcp.unwrap();
+    assert_near(info.renew_at, renewed + Duration::from_secs(50));
+    assert_near(info.expires_at, renewed + Duration::from_secs(100));
+}
+
+#[embassy::test(mock_clock)]
+async fn rebinds_lease(spawner: Spawner) {
+    let (stack, mut server) = start(
+        &REBIND_NET,
+        REBIND_DHCP.put(DhcpConfigurator::new()),
+        spawner,
+    );
+    let granted = lease(&mut server, stack, 100).await;
+
+    // The server doesn't answer the renewal, so at T2, 7/8 of the lease, the client asks
+    // any server.
+    let renew = server.recv().await;
+    assert_eq!(renew.dest, SERVER_IP);
+    let rebind = server.recv().await;
+    assert_near(Instant::now(), granted + Duration::from_millis(87_500));
+    assert_eq!(rebind.message_type(), REQUEST);
+    assert_eq!(rebind.dest, Ipv4Address::BROADCAST);
+    assert_eq!(rebind.client_ip, CLIENT_IP);
+
+    let mut events = stack.config_events();
+    let rebound = Instant::now();
+    server.reply(&rebind, ACK, &lease_options(100)).await;
+    match events.next().await {
+        Some((_, ConfigEvent::Configured(config))) => {
+            let info = config.dhcp.unwrap();
+            assert_near(info.expires_at, rebound + Duration::from_secs(100));
+        }
+        event => panic!("expected a new lease, got {:?}", event),
+    }
+}
+
+#[embassy::test(mock_clock)]
+async fn lease_expires(spawner: Spawner) {
+    let (stack, mut server) = start(
+        &EXPIRE_NET,
+        EXPIRE_DHCP.put(DhcpConfigurator::new()),
+        spawner,
+    );
+    let granted = lease(&mut server, stack, 100).await;
+    let mut events = stack.config_events();
+
+    // Neither the renewal nor the rebinding is answered.
+    assert_eq!(server.recv().await.dest, SERVER_IP);
+    assert_eq!(server.recv().await.dest, Ipv4Address::BROADCAST);
+
+    match events.next().await {
+        Some((_, ConfigEvent::Deconfigured)) => {}
+        event => panic!("expected the lease to expire, got {:?}", event),
+    }
+    assert_near(Instant::now(), granted + Duration::from_secs(100));
+    assert_eq!(stack.interface_config(InterfaceId::FIRST), None);
+
+    // The client starts over.
+    let discover = server.recv().await;
+    assert_eq!(discover.message_type(), DISCOVER);
+}
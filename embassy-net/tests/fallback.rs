@@ -0,0 +1,92 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::ethernet::EthernetNet;
+use core::task::{Context, Poll};
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Instant};
+use embassy::util::{Forever, Signal};
+use embassy_net::*;
+use futures::StreamExt;
+use heapless::Vec;
+
+static TIMEOUT_NET: EthernetNet = EthernetNet::new();
+static TIMEOUT_CONFIG: Forever<FallbackConfigurator<Later, StaticConfigurator>> = Forever::new();
+static TIMEOUT_PRIMARY: Signal<Config> = Signal::new();
+static TAKEOVER_NET: EthernetNet = EthernetNet::new();
+static TAKEOVER_CONFIG: Forever<FallbackConfigurator<Later, StaticConfigurator>> = Forever::new();
+static TAKEOVER_PRIMARY: Signal<Config> = Signal::new();
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A primary configurator that gets its configuration when the test signals it.
+struct Later(&'static Signal<Config>);
+
+impl Configurator for Later {
+    fn poll(
+        &mut self,
+        _iface: &mut Interface,
+        _sockets: &mut SocketSet,
+        _timestamp: SmolInstant,
+        cx: &mut Context<'_>,
+    ) -> ConfigEvent {
+        match self.0.poll_wait(cx) {
+            Poll::Ready(config) => ConfigEvent::Configured(config),
+            Poll::Pending => ConfigEvent::NoChange,
+        }
+    }
+}
+
+fn config(address: [u8; 4]) -> Config {
+    Config {
+        address: Ipv4Cidr::new(Ipv4Address(address), 24),
+        gateway: None,
+        dns_servers: Vec::new(),
+        dhcp: None,
+    }
+}
+
+fn fallback(primary: &'static Signal<Config>) -> FallbackConfigurator<Later, StaticConfigurator> {
+    FallbackConfigurator::new(
+        Later(primary),
+        StaticConfigurator::new(config([10, 0, 0, 2])),
+        TIMEOUT,
+    )
+}
+
+#[embassy::test(mock_clock)]
+async fn falls_back_after_timeout(spawner: Spawner) {
+    let start = Instant::now();
+    let (stack, _peer) = TIMEOUT_NET.start(spawner, TIMEOUT_CONFIG.put(fallback(&TIMEOUT_PRIMARY)));
+
+    stack.wait_config_up().await;
+    let elapsed = Instant::now() - start;
+    assert!(elapsed >= TIMEOUT);
+    assert!(elapsed < TIMEOUT + Duration::from_secs(1));
+    assert_eq!(
+        stack.interface_config(InterfaceId::FIRST),
+        Some(config([10, 0, 0, 2]))
+    );
+}
+
+#[embassy::test(mock_clock)]
+async fn primary_takes_over(spawner: Spawner) {
+    let (stack, _peer) =
+        TAKEOVER_NET.start(spawner, TAKEOVER_CONFIG.put(fallback(&TAKEOVER_PRIMARY)));
+    stack.wait_config_up().await;
+    let mut events = stack.config_events();
+
+    TAKEOVER_PRIMARY.signal(config([192, 168, 1, 2]));
+    match events.next().await {
+        Some((_, ConfigEvent::Configured(c))) => assert_eq!(c, config([192, 168, 1, 2])),
+        event => panic!("expected the primary configuration, got {:?}", event),
+    }
+    assert_eq!(
+        stack.interface_config(InterfaceId::FIRST),
+        Some(config([192, 168, 1, 2]))
+    );
+}
@@ -0,0 +1,222 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::net::{Net, SERVER_ADDRESS};
+use embassy::executor::Spawner;
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy_net::http::{read_request, Method, Request};
+use embassy_net::TcpSocket;
+
+static LENGTH_NET: Net = Net::new();
+static LENGTH_EXCESS_NET: Net = Net::new();
+static CHUNKED_NET: Net = Net::new();
+static CHUNKED_RAW_NET: Net = Net::new();
+
+const PORT: u16 = 80;
+
+#[embassy::test]
+async fn content_length(spawner: Spawner) {
+    let (server, client) = LENGTH_NET.start(spawner).await;
+
+    let server = async {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let mut socket = TcpSocket::new(server, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.accept(PORT).await.unwrap();
+
+        let mut head_buf = [0; 512];
+        let mut req = read_request(&mut socket, &mut head_buf).await.unwrap();
+        assert_eq!(req.method, Method::Post);
+        assert_eq!(req.path, "/echo");
+        assert_eq!(req.query, Some("x=1"));
+        assert_eq!(req.header("x-test"), Some("yes"));
+        assert_eq!(req.body.content_length(), Some(11));
+
+        let mut body = [0; 64];
+        let n = req.body.read_to_end(&mut body).await.unwrap();
+        assert_eq!(&body[..n], b"hello world");
+        req.respond(200, "text/plain", &body[..n]).await.unwrap();
+        socket.close().await.unwrap();
+    };
+
+    let client = async {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let mut socket = TcpSocket::new(client, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.connect((SERVER_ADDRESS, PORT)).await.unwrap();
+
+        let mut head_buf = [0; 512];
+        let mut resp = Request::post("server", "/echo?x=1")
+            .header("X-Test", "yes")
+            .body(b"hello world")
+            .send(&mut socket, &mut head_buf)
+            .await
+            .unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.reason, "OK");
+        assert_eq!(resp.header("content-type"), Some("text/plain"));
+        assert_eq!(resp.body.content_length(), Some(11));
+
+        let mut body = [0; 64];
+        let n = resp.body.read_to_end(&mut body).await.unwrap();
+        assert_eq!(&body[..n], b"hello world");
+    };
+
+    futures::join!(server, client);
+}
+
+#[embassy::test]
+async fn content_length_ends_body(spawner: Spawner) {
+    let (server, client) = LENGTH_EXCESS_NET.start(spawner).await;
+
+    let server = async {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let mut socket = TcpSocket::new(server, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.accept(PORT).await.unwrap();
+
+        let mut head_buf = [0; 512];
+        let mut req = read_request(&mut socket, &mut head_buf).await.unwrap();
+        req.body.skip().await.unwrap();
+        drop(req);
+
+        // Bytes past the announced length aren't part of the body.
+        socket
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhelloEXTRA")
+            .await
+            .unwrap();
+        socket.close().await.unwrap();
+    };
+
+    let client = async {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let mut socket = TcpSocket::new(client, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.connect((SERVER_ADDRESS, PORT)).await.unwrap();
+
+        let mut head_buf = [0; 512];
+        let mut resp = Request::get("server", "/")
+            .send(&mut socket, &mut head_buf)
+            .await
+            .unwrap();
+        let mut body = [0; 64];
+        let n = resp.body.read_to_end(&mut body).await.unwrap();
+        assert_eq!(&body[..n], b"hello");
+        drop(resp);
+
+        let mut rest = [0; 64];
+        let n = socket.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(&rest[..n], b"EXTRA");
+    };
+
+    futures::join!(server, client);
+}
+
+#[embassy::test]
+async fn chunked(spawner: Spawner) {
+    let (server, client) = CHUNKED_NET.start(spawner).await;
+
+    let server = async {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let mut socket = TcpSocket::new(server, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.accept(PORT).await.unwrap();
+
+        let mut head_buf = [0; 512];
+        let req = read_request(&mut socket, &mut head_buf).await.unwrap();
+        assert_eq!(req.body.content_length(), Some(0));
+
+        let mut writer = req
+            .start_response(200, &[("Content-Type", "text/plain")], None)
+            .await
+            .unwrap();
+        writer.write(b"first ").await.unwrap();
+        writer.write(&[b'x'; 300]).await.unwrap();
+        writer.write(b"").await.unwrap();
+        writer.write(b" last").await.unwrap();
+        writer.finish().await.unwrap();
+        socket.close().await.unwrap();
+    };
+
+    let client = async {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let mut socket = TcpSocket::new(client, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.connect((SERVER_ADDRESS, PORT)).await.unwrap();
+
+        let mut head_buf = [0; 512];
+        let mut resp = Request::get("server", "/")
+            .send(&mut socket, &mut head_buf)
+            .await
+            .unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.header("transfer-encoding"), Some("chunked"));
+        assert_eq!(resp.body.content_length(), None);
+
+        let mut body = [0; 512];
+        let n = resp.body.read_to_end(&mut body).await.unwrap();
+        let mut expected = b"first ".to_vec();
+        expected.extend_from_slice(&[b'x'; 300]);
+        expected.extend_from_slice(b" last");
+        assert_eq!(&body[..n], &expected[..]);
+    };
+
+    futures::join!(server, client);
+}
+
+#[embassy::test]
+async fn chunked_extensions_and_trailer(spawner: Spawner) {
+    let (server, client) = CHUNKED_RAW_NET.start(spawner).await;
+
+    let server = async {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let mut socket = TcpSocket::new(server, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.accept(PORT).await.unwrap();
+
+        let mut head_buf = [0; 512];
+        let mut req = read_request(&mut socket, &mut head_buf).await.unwrap();
+        req.body.skip().await.unwrap();
+        drop(req);
+
+        socket
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  5;name=value\r\nhello\r\n\
+                  A \r\n, chunked!\r\n\
+                  0\r\nX-Trailer: 1\r\n\r\n\
+                  after",
+            )
+            .await
+            .unwrap();
+        socket.close().await.unwrap();
+    };
+
+    let client = async {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let mut socket = TcpSocket::new(client, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.connect((SERVER_ADDRESS, PORT)).await.unwrap();
+
+        let mut head_buf = [0; 512];
+        let mut resp = Request::get("server", "/")
+            .send(&mut socket, &mut head_buf)
+            .await
+            .unwrap();
+        let mut body = [0; 64];
+        let n = resp.body.read_to_end(&mut body).await.unwrap();
+        assert_eq!(&body[..n], b"hello, chunked!");
+        drop(resp);
+
+        // The body ends after the trailer, leaving the rest of the stream.
+        let mut rest = [0; 64];
+        let n = socket.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(&rest[..n], b"after");
+    };
+
+    futures::join!(server, client);
+}
@@ -0,0 +1,79 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::net::{Net, SERVER_ADDRESS};
+use embassy::executor::Spawner;
+use embassy::time::Duration;
+use embassy_net::{IcmpPacketMetadata, IcmpSocket, IpAddress, Ipv4Address, PingError};
+
+static REPLY_NET: Net = Net::new();
+static TIMEOUT_NET: Net = Net::new();
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[embassy::test]
+async fn ping_replies(spawner: Spawner) {
+    let (_server, client) = REPLY_NET.start(spawner).await;
+
+    let mut rx_meta = [IcmpPacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 256];
+    let mut tx_meta = [IcmpPacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 256];
+    let mut socket = IcmpSocket::new(
+        client,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    // The server stack answers echo requests by itself.
+    for payload_len in [0, 1, 56].iter() {
+        let rtt = socket
+            .ping(IpAddress::Ipv4(SERVER_ADDRESS), *payload_len, TIMEOUT)
+            .await
+            .unwrap();
+        assert!(rtt < TIMEOUT);
+    }
+
+    assert_eq!(
+        socket
+            .ping(IpAddress::Ipv4(SERVER_ADDRESS), 1000, TIMEOUT)
+            .await,
+        Err(PingError::TooLarge)
+    );
+}
+
+#[embassy::test(mock_clock)]
+async fn ping_times_out(spawner: Spawner) {
+    let (_server, client) = TIMEOUT_NET.start(spawner).await;
+
+    let mut rx_meta = [IcmpPacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 256];
+    let mut tx_meta = [IcmpPacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 256];
+    let mut socket = IcmpSocket::new(
+        client,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    // On the link, but nobody has the address.
+    let absent = IpAddress::Ipv4(Ipv4Address([192, 168, 69, 3]));
+    assert_eq!(
+        socket.ping(absent, 8, TIMEOUT).await,
+        Err(PingError::Timeout)
+    );
+
+    // The socket still works after a timeout.
+    socket
+        .ping(IpAddress::Ipv4(SERVER_ADDRESS), 8, TIMEOUT)
+        .await
+        .unwrap();
+}
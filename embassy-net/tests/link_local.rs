@@ -0,0 +1,172 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::ethernet::{EthernetNet, Peer, PEER_MAC, STACK_MAC};
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Instant, Timer};
+use embassy::util::Forever;
+use embassy_net::{ConfigEvent, InterfaceId, Ipv4Address, Ipv4Cidr, LinkLocalConfigurator, Stack};
+use futures::StreamExt;
+use smoltcp::wire::{ArpOperation, ArpRepr, EthernetAddress};
+
+static CLAIM_NET: EthernetNet = EthernetNet::new();
+static CLAIM_CONFIG: Forever<LinkLocalConfigurator> = Forever::new();
+static PROBE_CONFLICT_NET: EthernetNet = EthernetNet::new();
+static PROBE_CONFLICT_CONFIG: Forever<LinkLocalConfigurator> = Forever::new();
+static DEFEND_NET: EthernetNet = EthernetNet::new();
+static DEFEND_CONFIG: Forever<LinkLocalConfigurator> = Forever::new();
+static LOSE_NET: EthernetNet = EthernetNet::new();
+static LOSE_CONFIG: Forever<LinkLocalConfigurator> = Forever::new();
+
+/// Wait for a probe from the stack, and return the address it probes.
+async fn expect_probe(peer: &mut Peer) -> Ipv4Address {
+    match peer.recv_arp().await {
+        ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Request,
+            source_hardware_addr,
+            source_protocol_addr,
+            target_protocol_addr,
+            ..
+        } if source_protocol_addr.is_unspecified() => {
+            assert_eq!(source_hardware_addr, STACK_MAC);
+            target_protocol_addr
+        }
+        repr => panic!("expected a probe, got {:?}", repr),
+    }
+}
+
+/// Wait for the stack to announce, or defend, `addr`.
+async fn expect_announcement(peer: &mut Peer, addr: Ipv4Address) {
+    match peer.recv_arp().await {
+        ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Request,
+            source_protocol_addr,
+            target_protocol_addr,
+            ..
+        } if source_protocol_addr == addr && target_protocol_addr == addr => {}
+        repr => panic!("expected an announcement of {}, got {:?}", addr, repr),
+    }
+}
+
+/// Another host, using `addr`.
+fn conflict(addr: Ipv4Address) -> ArpRepr {
+    ArpRepr::EthernetIpv4 {
+        operation: ArpOperation::Reply,
+        source_hardware_addr: PEER_MAC,
+        source_protocol_addr: addr,
+        target_hardware_addr: STACK_MAC,
+        target_protocol_addr: addr,
+    }
+}
+
+/// Wait until the stack has probed and announced its address, and return it.
+async fn claim(stack: &Stack, peer: &mut Peer) -> Ipv4Address {
+    let addr = expect_probe(peer).await;
+    for _ in 1..3 {
+        assert_eq!(expect_probe(peer).await, addr);
+    }
+    for _ in 0..2 {
+        expect_announcement(peer, addr).await;
+    }
+    assert_eq!(address(stack), Some(addr));
+    addr
+}
+
+fn address(stack: &Stack) -> Option<Ipv4Address> {
+    let config = stack.interface_config(InterfaceId::FIRST)?;
+    assert_eq!(config.address.prefix_len(), 16);
+    Some(config.address.address())
+}
+
+#[embassy::test(mock_clock)]
+async fn claims_address(spawner: Spawner) {
+    let (stack, mut peer) =
+        CLAIM_NET.start(spawner, CLAIM_CONFIG.put(LinkLocalConfigurator::new()));
+
+    let addr = expect_probe(&mut peer).await;
+    let first_probe = Instant::now();
+    let octets = addr.as_bytes();
+    assert_eq!(octets[..2], [169, 254]);
+    assert!(octets[2] >= 1 && octets[2] <= 254);
+
+    for _ in 1..3 {
+        assert_eq!(expect_probe(&mut peer).await, addr);
+        assert_eq!(address(stack), None);
+    }
+    expect_announcement(&mut peer, addr).await;
+    // Two probes at least a second apart, then two seconds without a reply.
+    assert!(Instant::now() - first_probe >= Duration::from_secs(4));
+    assert_eq!(
+        stack.interface_config(InterfaceId::FIRST).unwrap().address,
+        Ipv4Cidr::new(addr, 16)
+    );
+    expect_announcement(&mut peer, addr).await;
+}
+
+#[embassy::test(mock_clock)]
+async fn probes_again_on_conflict(spawner: Spawner) {
+    let (stack, mut peer) = PROBE_CONFLICT_NET.start(
+        spawner,
+        PROBE_CONFLICT_CONFIG.put(LinkLocalConfigurator::new()),
+    );
+
+    // Another host replies that it uses the address.
+    let addr = expect_probe(&mut peer).await;
+    peer.send_arp(conflict(addr));
+
+    // Another host probes for the same address.
+    let addr = expect_probe(&mut peer).await;
+    peer.send_arp(ArpRepr::EthernetIpv4 {
+        operation: ArpOperation::Request,
+        source_hardware_addr: PEER_MAC,
+        source_protocol_addr: Ipv4Address::UNSPECIFIED,
+        target_hardware_addr: EthernetAddress([0; 6]),
+        target_protocol_addr: addr,
+    });
+
+    // Each conflict starts the probes over, so it's only claimed after three more.
+    let addr = claim(stack, &mut peer).await;
+    assert_eq!(address(stack), Some(addr));
+}
+
+#[embassy::test(mock_clock)]
+async fn defends_address(spawner: Spawner) {
+    let (stack, mut peer) =
+        DEFEND_NET.start(spawner, DEFEND_CONFIG.put(LinkLocalConfigurator::new()));
+    let addr = claim(stack, &mut peer).await;
+
+    peer.send_arp(conflict(addr));
+    expect_announcement(&mut peer, addr).await;
+
+    // Conflicts further apart than DEFEND_INTERVAL (10 seconds) are each defended.
+    Timer::after(Duration::from_secs(11)).await;
+    peer.send_arp(conflict(addr));
+    expect_announcement(&mut peer, addr).await;
+    assert_eq!(address(stack), Some(addr));
+}
+
+#[embassy::test(mock_clock)]
+async fn gives_up_address_on_repeated_conflicts(spawner: Spawner) {
+    let (stack, mut peer) = LOSE_NET.start(spawner, LOSE_CONFIG.put(LinkLocalConfigurator::new()));
+    let addr = claim(stack, &mut peer).await;
+    let mut events = stack.config_events();
+
+    peer.send_arp(conflict(addr));
+    expect_announcement(&mut peer, addr).await;
+
+    // A second conflict within DEFEND_INTERVAL isn't defended.
+    Timer::after(Duration::from_secs(5)).await;
+    peer.send_arp(conflict(addr));
+    match events.next().await {
+        Some((_, ConfigEvent::Deconfigured)) => {}
+        event => panic!("expected Deconfigured, got {:?}", event),
+    }
+    assert_eq!(address(stack), None);
+
+    // And a new address is claimed.
+    claim(stack, &mut peer).await;
+}
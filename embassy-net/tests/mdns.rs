@@ -0,0 +1,264 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::net::{Net, SERVER_ADDRESS};
+use embassy::executor::Spawner;
+use embassy::time::{with_timeout, Duration, Timer};
+use embassy::util::Forever;
+use embassy_net::mdns::{Mdns, MdnsConfig, Service};
+use embassy_net::{InterfaceId, IpAddress, Stack, UdpPacketMetadata, UdpSocket};
+
+static HOST_NET: Net = Net::new();
+static HOST_MDNS: Forever<Mdns<'static>> = Forever::new();
+static SERVICE_NET: Net = Net::new();
+static SERVICE_MDNS: Forever<Mdns<'static>> = Forever::new();
+
+const MDNS_PORT: u16 = 5353;
+const HOSTNAME: &str = "device";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+/// In a question, asks for a unicast reply. In a record, flushes caches.
+const CLASS_TOP_BIT: u16 = 0x8000;
+const FLAG_RESPONSE: u16 = 0x8000;
+
+static SERVICES: [Service<'static>; 1] = [Service {
+    instance: "Sensor",
+    service_type: "_http._tcp",
+    port: 8080,
+    txt: &["path=/"],
+}];
+
+#[embassy::task(pool_size = 2)]
+async fn mdns_task(mdns: &'static Mdns<'static>) {
+    mdns.run().await
+}
+
+/// Start a responder on `stack`, and wait until it's done probing its names.
+async fn start_responder(
+    spawner: Spawner,
+    mdns: &'static Forever<Mdns<'static>>,
+    stack: &'static Stack,
+) {
+    let mdns: &'static Mdns<'static> = mdns.put(Mdns::new(
+        stack,
+        MdnsConfig {
+            hostname: HOSTNAME,
+            services: &SERVICES,
+            interface: InterfaceId::FIRST,
+        },
+    ));
+    spawner.spawn(mdns_task(mdns)).unwrap();
+    Timer::after(Duration::from_secs(2)).await;
+}
+
+/// Encode a dot separated name.
+fn name(name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for label in name.split('.') {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+    out
+}
+
+fn query(id: u16, qname: &str, ty: u16, class: u16) -> Vec<u8> {
+    let mut msg = Vec::new();
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    msg.extend_from_slice(&name(qname));
+    msg.extend_from_slice(&ty.to_be_bytes());
+    msg.extend_from_slice(&class.to_be_bytes());
+    msg
+}
+
+/// A resource record from a reply.
+#[derive(Debug)]
+struct Record {
+    name: Vec<u8>,
+    ty: u16,
+    class: u16,
+    ttl: u32,
+    rdata: Vec<u8>,
+}
+
+/// A reply, with its questions skipped.
+#[derive(Debug)]
+struct Reply {
+    id: u16,
+    flags: u16,
+    counts: [u16; 4],
+    questions: Vec<u8>,
+    records: Vec<Record>,
+}
+
+fn u16_at(msg: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([msg[pos], msg[pos + 1]])
+}
+
+/// The end of the uncompressed name at `pos`.
+fn name_end(msg: &[u8], mut pos: usize) -> usize {
+    while msg[pos] != 0 {
+        assert!(msg[pos] < 0xc0, "compressed name");
+        pos += 1 + msg[pos] as usize;
+    }
+    pos + 1
+}
+
+fn parse(msg: &[u8]) -> Reply {
+    let counts = [
+        u16_at(msg, 4),
+        u16_at(msg, 6),
+        u16_at(msg, 8),
+        u16_at(msg, 10),
+    ];
+    let mut pos = 12;
+    for _ in 0..counts[0] {
+        pos = name_end(msg, pos) + 4;
+    }
+    let questions = msg[12..pos].to_vec();
+
+    let mut records = Vec::new();
+    for _ in 0..counts[1] + counts[2] + counts[3] {
+        let end = name_end(msg, pos);
+        let len = u16_at(msg, end + 8) as usize;
+        records.push(Record {
+            name: msg[pos..end].to_vec(),
+            ty: u16_at(msg, end),
+            class: u16_at(msg, end + 2),
+            ttl: u32::from_be_bytes([msg[end + 4], msg[end + 5], msg[end + 6], msg[end + 7]]),
+            rdata: msg[end + 10..end + 10 + len].to_vec(),
+        });
+        pos = end + 10 + len;
+    }
+    assert_eq!(pos, msg.len(), "trailing bytes");
+
+    Reply {
+        id: u16_at(msg, 0),
+        flags: u16_at(msg, 2),
+        counts,
+        questions,
+        records,
+    }
+}
+
+#[embassy::test(mock_clock)]
+async fn resolves_host(spawner: Spawner) {
+    let (server, client) = HOST_NET.start(spawner).await;
+    start_responder(spawner, &HOST_MDNS, server).await;
+
+    let mut rx_meta = [UdpPacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [UdpPacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = UdpSocket::new(
+        client,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(1234).unwrap();
+
+    // A plain DNS resolver asking directly, not from the mDNS port.
+    let msg = query(0x4242, "device.local", TYPE_A, CLASS_IN);
+    socket
+        .send_to(&msg, (SERVER_ADDRESS, MDNS_PORT))
+        .await
+        .unwrap();
+
+    let mut buf = [0; 1024];
+    let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+    assert_eq!(from.addr, IpAddress::Ipv4(SERVER_ADDRESS));
+    assert_eq!(from.port, MDNS_PORT);
+
+    let reply = parse(&buf[..len]);
+    assert_eq!(reply.id, 0x4242);
+    assert_ne!(reply.flags & FLAG_RESPONSE, 0);
+    assert_eq!(reply.counts, [1, 1, 0, 0]);
+    assert_eq!(reply.questions, &msg[12..]);
+
+    let a = &reply.records[0];
+    assert_eq!(a.name, name("device.local"));
+    assert_eq!(a.ty, TYPE_A);
+    // Legacy replies don't flush caches, and have short TTLs.
+    assert_eq!(a.class, CLASS_IN);
+    assert_eq!(a.ttl, 10);
+    assert_eq!(a.rdata, SERVER_ADDRESS.as_bytes());
+
+    // Names that aren't ours get no reply.
+    let msg = query(0x4343, "other.local", TYPE_A, CLASS_IN);
+    socket
+        .send_to(&msg, (SERVER_ADDRESS, MDNS_PORT))
+        .await
+        .unwrap();
+    let recv = socket.recv_from(&mut buf);
+    assert!(with_timeout(Duration::from_secs(1), recv).await.is_err());
+}
+
+#[embassy::test(mock_clock)]
+async fn browses_service(spawner: Spawner) {
+    let (server, client) = SERVICE_NET.start(spawner).await;
+    start_responder(spawner, &SERVICE_MDNS, server).await;
+
+    let mut rx_meta = [UdpPacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [UdpPacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = UdpSocket::new(
+        client,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(MDNS_PORT).unwrap();
+
+    // An mDNS query asking for a unicast reply.
+    let msg = query(0, "_http._tcp.local", TYPE_PTR, CLASS_IN | CLASS_TOP_BIT);
+    socket
+        .send_to(&msg, (SERVER_ADDRESS, MDNS_PORT))
+        .await
+        .unwrap();
+
+    let mut buf = [0; 1024];
+    let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+    assert_eq!(from.addr, IpAddress::Ipv4(SERVER_ADDRESS));
+
+    let reply = parse(&buf[..len]);
+    assert_eq!(reply.id, 0);
+    assert_ne!(reply.flags & FLAG_RESPONSE, 0);
+    assert_eq!(reply.counts, [0, 1, 0, 3]);
+
+    let instance = name("Sensor._http._tcp.local");
+    let ptr = &reply.records[0];
+    assert_eq!(ptr.name, name("_http._tcp.local"));
+    assert_eq!(ptr.ty, TYPE_PTR);
+    // PTR records are shared, so they never flush caches.
+    assert_eq!(ptr.class, CLASS_IN);
+    assert_eq!(ptr.rdata, instance);
+
+    // The records needed to reach the service come along as additionals.
+    let find = |ty| reply.records.iter().find(|r| r.ty == ty).unwrap();
+    let srv = find(TYPE_SRV);
+    assert_eq!(srv.name, instance);
+    assert_eq!(srv.class, CLASS_IN | CLASS_TOP_BIT);
+    assert_eq!(u16_at(&srv.rdata, 4), 8080);
+    assert_eq!(&srv.rdata[6..], &name("device.local")[..]);
+
+    let txt = find(TYPE_TXT);
+    assert_eq!(txt.name, instance);
+    assert_eq!(txt.rdata, b"\x06path=/");
+
+    let a = find(TYPE_A);
+    assert_eq!(a.name, name("device.local"));
+    assert_eq!(a.rdata, SERVER_ADDRESS.as_bytes());
+}
@@ -0,0 +1,197 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::{pipe, PipeEnd};
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy::time::{Duration, Instant};
+use embassy_net::mqtt::{ConnectOptions, Message, MqttClient, MqttError, QoS};
+
+const CONNECT: u8 = 0x10;
+const PUBLISH_QOS0: u8 = 0x30;
+const PUBLISH_QOS1: u8 = 0x32;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xc0;
+const DISCONNECT: u8 = 0xe0;
+
+/// Read a packet sent by the client. Returns the first byte of the header, and the body.
+async fn read_packet(stream: &mut PipeEnd) -> (u8, Vec<u8>) {
+    let mut first = [0; 1];
+    stream.read_exact(&mut first).await.unwrap();
+
+    let mut len = 0;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte).await.unwrap();
+        len |= (byte[0] as usize & 0x7f) << shift;
+        shift += 7;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).await.unwrap();
+    (first[0], body)
+}
+
+/// Read a length-prefixed string from the start of `body`, and remove it.
+fn take_str(body: &mut Vec<u8>) -> String {
+    let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    let s = String::from_utf8(body[2..2 + len].to_vec()).unwrap();
+    body.drain(..2 + len);
+    s
+}
+
+fn publish_qos0(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![PUBLISH_QOS0, (2 + topic.len() + payload.len()) as u8];
+    packet.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+    packet.extend_from_slice(topic.as_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Accept the connection of the client, checking its id.
+async fn accept(stream: &mut PipeEnd, client_id: &str) {
+    let (first, mut body) = read_packet(stream).await;
+    assert_eq!(first, CONNECT);
+    assert_eq!(take_str(&mut body), "MQTT");
+    assert_eq!(body[0], 4, "not MQTT 3.1.1");
+    body.drain(..4); // Level, flags and keep alive
+    assert_eq!(take_str(&mut body), client_id);
+    stream.write_all(&[0x20, 2, 0, 0]).await.unwrap();
+}
+
+#[embassy::test]
+async fn publish_and_receive() {
+    let (client_stream, mut broker_stream) = pipe();
+
+    let broker = async {
+        let stream = &mut broker_stream;
+        accept(stream, "embassy-test").await;
+
+        let (first, mut body) = read_packet(stream).await;
+        assert_eq!(first, SUBSCRIBE);
+        let id = [body[0], body[1]];
+        body.drain(..2);
+        assert_eq!(take_str(&mut body), "sensors/#");
+        assert_eq!(body, [1]);
+        stream.write_all(&[0x90, 3, id[0], id[1], 1]).await.unwrap();
+
+        let (first, mut body) = read_packet(stream).await;
+        assert_eq!(first, PUBLISH_QOS1);
+        let topic = take_str(&mut body);
+        assert_eq!(topic, "sensors/temp");
+        let id = [body[0], body[1]];
+        assert_eq!(&body[2..], b"21.5");
+        stream.write_all(&[0x40, 2, id[0], id[1]]).await.unwrap();
+        stream
+            .write_all(&publish_qos0(&topic, &body[2..]))
+            .await
+            .unwrap();
+
+        let (first, _) = read_packet(stream).await;
+        assert_eq!(first, DISCONNECT);
+    };
+
+    let client = async {
+        let mut rx_buf = [0; 256];
+        let mut tx_buf = [0; 256];
+        let mut client = MqttClient::new(client_stream, &mut rx_buf, &mut tx_buf);
+        assert_eq!(
+            client.connect(&ConnectOptions::new("embassy-test")).await,
+            Ok(false)
+        );
+        assert_eq!(
+            client.subscribe("sensors/#", QoS::AtLeastOnce).await,
+            Ok(QoS::AtLeastOnce)
+        );
+        client
+            .publish("sensors/temp", b"21.5", QoS::AtLeastOnce, false)
+            .await
+            .unwrap();
+
+        let mut received = Vec::new();
+        client
+            .receive(&mut |msg: Message<'_>| {
+                assert_eq!(msg.qos, QoS::AtMostOnce);
+                received.push((msg.topic.to_string(), msg.payload.to_vec()));
+            })
+            .await
+            .unwrap();
+        assert_eq!(received, [("sensors/temp".to_string(), b"21.5".to_vec())]);
+
+        client.disconnect().await.unwrap();
+    };
+
+    futures::join!(broker, client);
+}
+
+#[embassy::test(mock_clock)]
+async fn pings_while_waiting() {
+    let (client_stream, mut broker_stream) = pipe();
+
+    let broker = async {
+        let stream = &mut broker_stream;
+        accept(stream, "embassy-test").await;
+
+        // Nothing is sent until the client pings.
+        let (first, body) = read_packet(stream).await;
+        assert_eq!(first, PINGREQ);
+        assert!(body.is_empty());
+        stream.write_all(&[0xd0, 0]).await.unwrap();
+        stream
+            .write_all(&publish_qos0("status", b"alive"))
+            .await
+            .unwrap();
+    };
+
+    let client = async {
+        let mut rx_buf = [0; 256];
+        let mut tx_buf = [0; 256];
+        let mut client = MqttClient::new(client_stream, &mut rx_buf, &mut tx_buf);
+        let mut opts = ConnectOptions::new("embassy-test");
+        opts.keep_alive_secs = 10;
+        client.connect(&opts).await.unwrap();
+
+        let start = Instant::now();
+        let mut payload = Vec::new();
+        client
+            .receive(&mut |msg: Message<'_>| payload.extend_from_slice(msg.payload))
+            .await
+            .unwrap();
+        assert_eq!(payload, b"alive");
+        assert!(Instant::now() - start >= Duration::from_secs(5));
+    };
+
+    futures::join!(broker, client);
+}
+
+#[embassy::test]
+async fn connection_refused() {
+    let (client_stream, mut broker_stream) = pipe();
+
+    let broker = async {
+        let (first, _) = read_packet(&mut broker_stream).await;
+        assert_eq!(first, CONNECT);
+        // Not authorized
+        broker_stream.write_all(&[0x20, 2, 0, 5]).await.unwrap();
+    };
+
+    let client = async {
+        let mut rx_buf = [0; 256];
+        let mut tx_buf = [0; 256];
+        let mut client = MqttClient::new(client_stream, &mut rx_buf, &mut tx_buf);
+        assert_eq!(
+            client.connect(&ConnectOptions::new("embassy-test")).await,
+            Err(MqttError::ConnectionRefused(5))
+        );
+        assert!(!client.is_connected());
+    };
+
+    futures::join!(broker, client);
+}
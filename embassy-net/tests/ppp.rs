@@ -0,0 +1,137 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::{pipe, PipeEnd};
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy_net::ppp::{Ppp, PppConfig};
+
+const FLAG: u8 = 0x7e;
+const ESCAPE: u8 = 0x7d;
+const FCS_GOOD: u16 = 0xf0b8;
+
+const PROTO_LCP: u16 = 0xc021;
+const CONFIGURE_REQUEST: u8 = 1;
+const CONFIGURE_ACK: u8 = 2;
+const TERMINATE_REQUEST: u8 = 5;
+const TERMINATE_ACK: u8 = 6;
+const LCP_MAGIC: u8 = 5;
+
+/// The FCS of RFC 1662, section C.2, bit by bit rather than from a table.
+fn fcs(data: &[u8]) -> u16 {
+    let mut fcs = 0xffff;
+    for &b in data {
+        for i in 0..8 {
+            let bit = (b >> i) & 1;
+            let carry = (fcs & 1) as u8 ^ bit;
+            fcs >>= 1;
+            if carry != 0 {
+                fcs ^= 0x8408;
+            }
+        }
+    }
+    fcs
+}
+
+/// Frame a packet, escaping flags, escapes and every control character.
+fn encode(protocol: u16, information: &[u8]) -> Vec<u8> {
+    let mut content = vec![0xff, 0x03];
+    content.extend_from_slice(&protocol.to_be_bytes());
+    content.extend_from_slice(information);
+    let trailer = !fcs(&content);
+    content.extend_from_slice(&trailer.to_le_bytes());
+
+    let mut frame = vec![FLAG];
+    for b in content {
+        if b < 0x20 || b == FLAG || b == ESCAPE {
+            frame.extend_from_slice(&[ESCAPE, b ^ 0x20]);
+        } else {
+            frame.push(b);
+        }
+    }
+    frame.push(FLAG);
+    frame
+}
+
+fn control(code: u8, id: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![code, id];
+    packet.extend_from_slice(&(4 + data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+/// Read the next frame sent by the link, checking its framing. Returns its protocol and
+/// information field.
+async fn recv(stream: &mut PipeEnd) -> (u16, Vec<u8>) {
+    let mut raw = Vec::new();
+    loop {
+        let b = stream.read_byte().await.unwrap();
+        if b != FLAG {
+            raw.push(b);
+        } else if !raw.is_empty() {
+            break;
+        }
+    }
+
+    let mut content = Vec::new();
+    let mut bytes = raw.iter();
+    while let Some(&b) = bytes.next() {
+        // Nothing that a serial line could mangle goes out unescaped.
+        assert!(b >= 0x20, "unescaped control character {:#04x}", b);
+        if b == ESCAPE {
+            content.push(bytes.next().unwrap() ^ 0x20);
+        } else {
+            content.push(b);
+        }
+    }
+    assert_eq!(fcs(&content), FCS_GOOD, "bad FCS");
+    assert_eq!(&content[..2], &[0xff, 0x03]);
+    let protocol = u16::from_be_bytes([content[2], content[3]]);
+    (protocol, content[4..content.len() - 2].to_vec())
+}
+
+#[embassy::test]
+async fn hdlc_round_trip() {
+    let ppp: Ppp<4, 1500> = Ppp::new();
+    let (mut link_end, mut peer) = pipe();
+    let config = PppConfig::default();
+
+    let peer = async {
+        let (protocol, request) = recv(&mut peer).await;
+        assert_eq!(protocol, PROTO_LCP);
+        assert_eq!(request[0], CONFIGURE_REQUEST);
+
+        // Every byte of the identifier and magic number needs escaping.
+        let options = [LCP_MAGIC, 6, FLAG, ESCAPE, 0x00, 0x11];
+        let request = control(CONFIGURE_REQUEST, FLAG, &options);
+
+        // A frame damaged on the line is dropped.
+        let mut damaged = encode(PROTO_LCP, &control(CONFIGURE_REQUEST, 1, &options));
+        let last = damaged.len() - 2;
+        damaged[last] ^= 0x01;
+        peer.write_all(&damaged).await.unwrap();
+
+        // Raw control characters, such as XON/XOFF inserted by the line, are ignored.
+        let mut frame = encode(PROTO_LCP, &request);
+        frame.insert(3, 0x11);
+        frame.insert(8, 0x13);
+        peer.write_all(&frame).await.unwrap();
+
+        let (protocol, ack) = recv(&mut peer).await;
+        assert_eq!(protocol, PROTO_LCP);
+        assert_eq!(ack, control(CONFIGURE_ACK, FLAG, &options));
+
+        peer.write_all(&encode(PROTO_LCP, &control(TERMINATE_REQUEST, 2, &[])))
+            .await
+            .unwrap();
+        let (protocol, ack) = recv(&mut peer).await;
+        assert_eq!(protocol, PROTO_LCP);
+        assert_eq!(ack, control(TERMINATE_ACK, 2, &[]));
+    };
+
+    let (res, ()) = futures::join!(ppp.run(&mut link_end, &config), peer);
+    assert_eq!(res, Ok(()));
+}
@@ -0,0 +1,87 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::net::{Net, SERVER_ADDRESS};
+use embassy::executor::Spawner;
+use embassy::time::Duration;
+use embassy_net::sntp::{Sntp, SntpConfig};
+use embassy_net::{Stack, UdpPacketMetadata, UdpSocket};
+use futures::future::{select, Either};
+use futures::pin_mut;
+use heapless::Vec;
+
+static NET: Net = Net::new();
+
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+/// The time told by the server, 2020-09-13 12:26:40 UTC.
+const SERVER_UNIX_SECS: u64 = 1_600_000_000;
+
+/// Answer one SNTP request with [`SERVER_UNIX_SECS`].
+async fn serve_one(stack: &Stack) {
+    let mut rx_meta = [UdpPacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 64];
+    let mut tx_meta = [UdpPacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 64];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(123).unwrap();
+
+    let mut request = [0; 48];
+    let (len, from) = socket.recv_from(&mut request).await.unwrap();
+    assert_eq!(len, 48);
+    assert_eq!(request[0] & 0x07, 3, "not a client request");
+
+    let timestamp = (SERVER_UNIX_SECS + NTP_UNIX_OFFSET) << 32;
+    let mut reply = [0; 48];
+    reply[0] = 0x24; // Version 4, server mode
+    reply[1] = 1; // Stratum 1
+    reply[24..32].copy_from_slice(&request[40..48]);
+    reply[32..40].copy_from_slice(&timestamp.to_be_bytes());
+    reply[40..48].copy_from_slice(&timestamp.to_be_bytes());
+    socket.send_to(&reply, from).await.unwrap();
+}
+
+#[embassy::test]
+async fn syncs_with_server(spawner: Spawner) {
+    let (server, client) = NET.start(spawner).await;
+
+    let mut servers = Vec::new();
+    servers.push(SERVER_ADDRESS).unwrap();
+    let sntp = Sntp::new(
+        client,
+        SntpConfig {
+            servers,
+            use_dhcp_servers: false,
+            timeout: Duration::from_secs(5),
+            ..SntpConfig::default()
+        },
+    );
+
+    let client = async {
+        let run = sntp.run();
+        let synced = sntp.wait_synced();
+        pin_mut!(run, synced);
+        match select(run, synced).await {
+            Either::Left((never, _)) => never,
+            Either::Right((now, _)) => now,
+        }
+    };
+    let ((), now) = futures::join!(serve_one(server), client);
+
+    let expected = SERVER_UNIX_SECS * 1_000_000;
+    assert!(now.unix_micros >= expected);
+    assert!(now.unix_micros - expected < 1_000_000);
+    assert!(now.error < Duration::from_secs(1));
+
+    let sync = sntp.last_sync().unwrap();
+    assert_eq!(sync.server, SERVER_ADDRESS);
+}
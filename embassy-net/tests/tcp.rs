@@ -0,0 +1,95 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::net::{Net, SERVER_ADDRESS};
+use embassy::executor::Spawner;
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy_net::{CloseReason, Error, TcpSocket};
+
+static ECHO_NET: Net = Net::new();
+static REFUSED_NET: Net = Net::new();
+static FULL_NET: Net = Net::new();
+
+#[embassy::test]
+async fn echo(spawner: Spawner) {
+    let (server, client) = ECHO_NET.start(spawner).await;
+
+    let server = async {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let mut socket = TcpSocket::new(server, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.accept(1234).await.unwrap();
+
+        let mut buf = [0; 256];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            socket.write_all(&buf[..n]).await.unwrap();
+        }
+        socket.close().await.unwrap();
+        assert_eq!(socket.close_reason(), Some(CloseReason::Finished));
+    };
+
+    let client = async {
+        let mut rx_buffer = [0; 1024];
+        let mut tx_buffer = [0; 1024];
+        let mut socket = TcpSocket::new(client, &mut rx_buffer, &mut tx_buffer).unwrap();
+        socket.connect((SERVER_ADDRESS, 1234)).await.unwrap();
+
+        for i in 0..10 {
+            let msg = format!("hello {}", i);
+            socket.write_all(msg.as_bytes()).await.unwrap();
+            let mut buf = [0; 32];
+            socket.read_exact(&mut buf[..msg.len()]).await.unwrap();
+            assert_eq!(&buf[..msg.len()], msg.as_bytes());
+        }
+        socket.close().await.unwrap();
+    };
+
+    futures::join!(server, client);
+}
+
+#[embassy::test]
+async fn connect_refused(spawner: Spawner) {
+    let (_server, client) = REFUSED_NET.start(spawner).await;
+
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = TcpSocket::new(client, &mut rx_buffer, &mut tx_buffer).unwrap();
+    assert_eq!(
+        socket.connect((SERVER_ADDRESS, 1234)).await,
+        Err(Error::Unaddressable)
+    );
+    assert_eq!(socket.close_reason(), Some(CloseReason::Reset));
+}
+
+#[embassy::test]
+async fn new_fails_when_full(spawner: Spawner) {
+    let (_server, client) = FULL_NET.start(spawner).await;
+
+    let mut buffers = [[0; 64]; 10];
+    let mut buffers = buffers.iter_mut();
+    let mut sockets = Vec::new();
+    let err = loop {
+        let rx_buffer = buffers.next().unwrap();
+        let tx_buffer = buffers.next().unwrap();
+        match TcpSocket::new(client, rx_buffer, tx_buffer) {
+            Ok(socket) => sockets.push(socket),
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(err, Error::Exhausted);
+    assert_eq!(sockets.len(), 4);
+
+    // Dropping a socket makes room for another.
+    sockets.pop();
+    let mut rx_buffer = [0; 64];
+    let mut tx_buffer = [0; 64];
+    assert!(TcpSocket::new(client, &mut rx_buffer, &mut tx_buffer).is_ok());
+}
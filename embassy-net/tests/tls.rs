@@ -0,0 +1,213 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![feature(generic_associated_types)]
+#![allow(incomplete_features)]
+
+mod common;
+
+use common::{pipe, PipeEnd};
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy_net::tls::{
+    CertificateChain, TlsConfig, TlsConnection, TlsError, Verifier, MAX_RECORD_LEN,
+};
+use embassy_std::rng::OsRng;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, UnparsedPublicKey};
+use ring::signature::{ECDSA_P256_SHA256_ASN1, ECDSA_P256_SHA256_ASN1_SIGNING};
+use rustls::{NoClientAuth, ServerConfig, ServerSession, Session};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+
+const ECDSA_SECP256R1_SHA256: u16 = 0x0403;
+
+/// A self-signed certificate for "localhost", with its key.
+struct Identity {
+    cert_der: Vec<u8>,
+    key_der: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+impl Identity {
+    fn generate() -> Self {
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &SystemRandom::new())
+                .unwrap();
+        let public_key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref())
+            .unwrap()
+            .public_key()
+            .as_ref()
+            .to_vec();
+
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+        params.key_pair = Some(rcgen::KeyPair::from_der(pkcs8.as_ref()).unwrap());
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+
+        Self {
+            cert_der: cert.serialize_der().unwrap(),
+            key_der: pkcs8.as_ref().to_vec(),
+            public_key,
+        }
+    }
+}
+
+/// Trusts a single certificate.
+struct PinnedVerifier {
+    cert_der: Vec<u8>,
+    public_key: Vec<u8>,
+    signature_checked: bool,
+}
+
+impl Verifier for PinnedVerifier {
+    fn verify_certificate(
+        &mut self,
+        server_name: Option<&str>,
+        chain: CertificateChain<'_>,
+    ) -> Result<(), TlsError> {
+        assert_eq!(server_name, Some("localhost"));
+        if chain.end_entity() == Some(&self.cert_der[..]) {
+            Ok(())
+        } else {
+            Err(TlsError::BadCertificate)
+        }
+    }
+
+    fn verify_signature(
+        &mut self,
+        scheme: u16,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), TlsError> {
+        assert_eq!(scheme, ECDSA_SECP256R1_SHA256);
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, &self.public_key)
+            .verify(message, signature)
+            .map_err(|_| TlsError::BadCertificate)?;
+        self.signature_checked = true;
+        Ok(())
+    }
+}
+
+/// Run a rustls server over `stream`, echoing what the client sends until it closes the
+/// connection. Returns whether it closed it with close_notify.
+async fn echo_server(mut stream: PipeEnd, identity: &Identity) -> bool {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.versions = vec![rustls::ProtocolVersion::TLSv1_3];
+    config
+        .set_single_cert(
+            vec![rustls::Certificate(identity.cert_der.clone())],
+            rustls::PrivateKey(identity.key_der.clone()),
+        )
+        .unwrap();
+    let mut session = ServerSession::new(&Arc::new(config));
+
+    let mut buf = [0; 4096];
+    loop {
+        while session.wants_write() {
+            let mut out = Vec::new();
+            session.write_tls(&mut out).unwrap();
+            stream.write_all(&out).await.unwrap();
+        }
+
+        let n = stream.read(&mut buf).await.unwrap();
+        if n == 0 {
+            return false;
+        }
+        let mut received = &buf[..n];
+        while !received.is_empty() {
+            session.read_tls(&mut received).unwrap();
+            if session.process_new_packets().is_err() {
+                return false;
+            }
+        }
+
+        let mut plain = [0; 4096];
+        loop {
+            match session.read(&mut plain) {
+                Ok(0) => break,
+                Ok(n) => session.write_all(&plain[..n]).unwrap(),
+                // The client sent close_notify.
+                Err(e) if e.kind() == ErrorKind::ConnectionAborted => {
+                    session.send_close_notify();
+                    let mut out = Vec::new();
+                    session.write_tls(&mut out).unwrap();
+                    stream.write_all(&out).await.unwrap();
+                    return true;
+                }
+                Err(e) => panic!("read error: {:?}", e),
+            }
+        }
+    }
+}
+
+#[embassy::test]
+async fn echo_with_certificate() {
+    let identity = Identity::generate();
+    let (client_stream, server_stream) = pipe();
+
+    let client = async {
+        let mut verifier = PinnedVerifier {
+            cert_der: identity.cert_der.clone(),
+            public_key: identity.public_key.clone(),
+            signature_checked: false,
+        };
+        let mut rx_buf = vec![0; 2 * MAX_RECORD_LEN];
+        let mut tx_buf = vec![0; MAX_RECORD_LEN];
+        let mut tls = TlsConnection::new(client_stream, &mut rx_buf, &mut tx_buf);
+        let config = TlsConfig {
+            server_name: Some("localhost"),
+            psk: None,
+            verifier: Some(&mut verifier),
+        };
+        tls.open(config, &mut OsRng::new().unwrap()).await.unwrap();
+
+        for i in 0..3 {
+            let msg = format!("hello {}", i);
+            tls.write_all(msg.as_bytes()).await.unwrap();
+            let mut buf = [0; 32];
+            tls.read_exact(&mut buf[..msg.len()]).await.unwrap();
+            assert_eq!(&buf[..msg.len()], msg.as_bytes());
+        }
+
+        // The server answers close_notify with its own, which ends the stream.
+        tls.close().await.unwrap();
+        let mut buf = [0; 32];
+        assert_eq!(tls.read(&mut buf).await, Ok(0));
+        drop(tls);
+
+        assert!(verifier.signature_checked);
+    };
+
+    let (closed, ()) = futures::join!(echo_server(server_stream, &identity), client);
+    assert!(closed);
+}
+
+#[embassy::test]
+async fn rejects_unknown_certificate() {
+    let identity = Identity::generate();
+    let other = Identity::generate();
+    let (client_stream, server_stream) = pipe();
+
+    let client = async {
+        let mut verifier = PinnedVerifier {
+            cert_der: other.cert_der.clone(),
+            public_key: other.public_key.clone(),
+            signature_checked: false,
+        };
+        let mut rx_buf = vec![0; 2 * MAX_RECORD_LEN];
+        let mut tx_buf = vec![0; MAX_RECORD_LEN];
+        let mut tls = TlsConnection::new(client_stream, &mut rx_buf, &mut tx_buf);
+        let config = TlsConfig {
+            server_name: Some("localhost"),
+            psk: None,
+            verifier: Some(&mut verifier),
+        };
+        assert_eq!(
+            tls.open(config, &mut OsRng::new().unwrap()).await,
+            Err(TlsError::BadCertificate)
+        );
+    };
+
+    let (closed, ()) = futures::join!(echo_server(server_stream, &identity), client);
+    assert!(!closed);
+}
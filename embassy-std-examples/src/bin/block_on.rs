@@ -0,0 +1,54 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+//! Runs a few steps on an executor that returns, like a host tool would, then exits
+//! normally.
+
+use embassy::time::{Duration, Timer};
+use embassy_std::ScopedExecutor;
+use log::*;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+
+#[embassy::task]
+async fn tick() {
+    loop {
+        Timer::after(Duration::from_millis(100)).await;
+        TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+async fn collect(samples: &mut Vec<u32>) {
+    for _ in 0..3 {
+        Timer::after(Duration::from_millis(250)).await;
+        samples.push(TICKS.load(Ordering::Relaxed));
+    }
+}
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let mut executor = ScopedExecutor::new();
+    executor.spawner().spawn(tick()).unwrap();
+
+    // The future borrows `samples`, and the tick task keeps running alongside it.
+    let mut samples = Vec::new();
+    executor.block_on(collect(&mut samples));
+    info!("ticks sampled: {:?}", samples);
+
+    executor.run_until(|| TICKS.load(Ordering::Relaxed) >= 10);
+    info!("done after {} ticks", TICKS.load(Ordering::Relaxed));
+
+    // A one-off future, on the executor that `block_on` keeps for this thread.
+    let answer = embassy_std::block_on(async {
+        Timer::after(Duration::from_millis(100)).await;
+        42
+    });
+    info!("answer: {}", answer);
+}
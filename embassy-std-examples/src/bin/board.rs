@@ -0,0 +1,98 @@
+#![feature(generic_associated_types)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+//! Runs application code, written against `embassy::traits`, on the simulated board.
+//!
+//! The console echoes the lines typed, the LED on pin 1 follows the button on pin 0, and
+//! the number of boots is kept in flash. Press the button with:
+//!
+//! ```text
+//! echo "0 high" | socat - UNIX-CONNECT:embassy-board.sock
+//! ```
+
+use embassy::executor::Spawner;
+use embassy::traits::flash::Flash;
+use embassy::traits::gpio::WaitForAnyEdge;
+use embassy::traits::uart::{ReadUntilIdle, Write};
+use embassy::util::Forever;
+use embassy_std::board::{self, config::Config};
+use embassy_std::gpio::Pin;
+use embassy_std::uart::Uart;
+use embassy_std::Executor;
+use log::*;
+use std::os::unix::net::UnixStream;
+
+const BOOT_COUNT_ADDRESS: usize = 0;
+
+async fn echo(uart: &mut (impl ReadUntilIdle + Write)) {
+    let mut buf = [0; 64];
+    loop {
+        uart.write(b"> ").await.unwrap();
+        let n = match uart.read_until_idle(&mut buf).await {
+            Ok(n) => n,
+            Err(_) => return,
+        };
+        uart.write(b"echo: ").await.unwrap();
+        uart.write(&buf[..n]).await.unwrap();
+    }
+}
+
+#[embassy::task]
+async fn console(mut uart: Uart<UnixStream>) {
+    echo(&mut uart).await;
+    info!("console closed");
+}
+
+#[embassy::task]
+async fn button(mut button: Pin, led: Pin) {
+    loop {
+        button.wait_for_any_edge().await;
+        let pressed = button.is_set_high();
+        info!("button {}", if pressed { "pressed" } else { "released" });
+        led.set(pressed);
+    }
+}
+
+async fn count_boot(flash: &mut impl Flash) -> u32 {
+    let mut count = [0; 4];
+    flash.read(BOOT_COUNT_ADDRESS, &mut count).await.unwrap();
+    let count = match u32::from_le_bytes(count) {
+        u32::MAX => 1,
+        count => count + 1,
+    };
+    flash.erase(BOOT_COUNT_ADDRESS).await.unwrap();
+    flash
+        .write(BOOT_COUNT_ADDRESS, &count.to_le_bytes())
+        .await
+        .unwrap();
+    count
+}
+
+#[embassy::task]
+async fn run(spawner: Spawner) {
+    let mut p = board::init(Config::default()).unwrap();
+
+    info!("boot number {}", count_boot(&mut p.flash).await);
+
+    spawner
+        .spawn(button(p.gpio[0].clone(), p.gpio[1].clone()))
+        .unwrap();
+    spawner.spawn(console(p.console)).unwrap();
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(run(spawner)).unwrap();
+    });
+}
@@ -0,0 +1,109 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+//! Runs tasks at different priority levels, on executors pended through simulated
+//! interrupts, like on hardware. Each executor has its own alarm.
+//!
+//! The low priority executor runs in "thread mode", on the main thread. The medium and
+//! high priority executors run in the simulated interrupts SWI0 and SWI1. Long computations
+//! block the thread of their executor, but high priority ticks still run on time:
+//!
+//! ```not_rust
+//!         [high] tick!
+//!     [med] Starting long computation
+//! [low] Starting long computation
+//!         [high] tick!
+//!     [med] done in 1000 ms
+//!         [high] tick!
+//!         [high] tick!
+//!     [med] Starting long computation
+//! [low] done in 2000 ms
+//! ```
+
+use embassy::executor::InterruptExecutor;
+use embassy::time::{Duration, Instant, Timer};
+use embassy::util::Forever;
+use embassy_std::interrupt::{self, InterruptExt, Priority};
+use embassy_std::{Executor, StdAlarm};
+use log::*;
+
+#[embassy::task]
+async fn run_high() {
+    loop {
+        info!("        [high] tick!");
+        Timer::after(Duration::from_millis(800)).await;
+    }
+}
+
+#[embassy::task]
+async fn run_med() {
+    loop {
+        let start = Instant::now();
+        info!("    [med] Starting long computation");
+
+        // Block the thread to simulate a long CPU computation
+        std::thread::sleep(std::time::Duration::from_secs(1));
+
+        let ms = Instant::now().duration_since(start).as_millis();
+        info!("    [med] done in {} ms", ms);
+
+        Timer::after(Duration::from_millis(700)).await;
+    }
+}
+
+#[embassy::task]
+async fn run_low() {
+    loop {
+        let start = Instant::now();
+        info!("[low] Starting long computation");
+
+        // Block the thread to simulate a long CPU computation
+        std::thread::sleep(std::time::Duration::from_secs(2));
+
+        let ms = Instant::now().duration_since(start).as_millis();
+        info!("[low] done in {} ms", ms);
+
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
+
+static ALARM_HIGH: Forever<StdAlarm> = Forever::new();
+static EXECUTOR_HIGH: Forever<InterruptExecutor<interrupt::SWI1>> = Forever::new();
+static ALARM_MED: Forever<StdAlarm> = Forever::new();
+static EXECUTOR_MED: Forever<InterruptExecutor<interrupt::SWI0>> = Forever::new();
+static EXECUTOR_LOW: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    // High-priority executor: SWI1, priority level 6
+    let irq = interrupt::take!(SWI1);
+    irq.set_priority(Priority::P6);
+    let alarm = ALARM_HIGH.put(StdAlarm::new());
+    let executor = EXECUTOR_HIGH.put(InterruptExecutor::new(irq));
+    executor.set_alarm(alarm);
+    executor.start(|spawner| {
+        spawner.spawn(run_high()).unwrap();
+    });
+
+    // Medium-priority executor: SWI0, priority level 7
+    let irq = interrupt::take!(SWI0);
+    irq.set_priority(Priority::P7);
+    let alarm = ALARM_MED.put(StdAlarm::new());
+    let executor = EXECUTOR_MED.put(InterruptExecutor::new(irq));
+    executor.set_alarm(alarm);
+    executor.start(|spawner| {
+        spawner.spawn(run_med()).unwrap();
+    });
+
+    // Low priority executor: runs in thread mode, with its own alarm
+    let executor = EXECUTOR_LOW.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(run_low()).unwrap();
+    });
+}
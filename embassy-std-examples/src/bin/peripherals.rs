@@ -0,0 +1,119 @@
+#![feature(generic_associated_types)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+//! Runs drivers written against `embassy::traits` on simulated peripherals.
+
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Timer};
+use embassy::traits::flash::Flash;
+use embassy::traits::gpio::WaitForHigh;
+use embassy::traits::i2c::I2c;
+use embassy::traits::rng::Rng;
+use embassy::traits::spi::FullDuplex;
+use embassy::traits::uart::{Read, Write};
+use embassy::util::Forever;
+use embassy_std::flash::RamFlash;
+use embassy_std::gpio::Pin;
+use embassy_std::i2c::{self, RegisterMap};
+use embassy_std::rng::OsRng;
+use embassy_std::spi::SpiLoopback;
+use embassy_std::uart::Uart;
+use embassy_std::Executor;
+use log::*;
+
+const SENSOR_ADDRESS: u8 = 0x48;
+const REG_WHO_AM_I: u8 = 0x0f;
+const REG_TEMPERATURE: u8 = 0x10;
+
+/// A driver for a temperature sensor, which raises its data ready pin when a measurement
+/// is available.
+async fn read_temperature<I: I2c<Error = i2c::Error>, P: WaitForHigh>(
+    i2c: &mut I,
+    data_ready: &mut P,
+) -> Result<i16, i2c::Error> {
+    let mut who_am_i = [0];
+    i2c.write_read(SENSOR_ADDRESS, &[REG_WHO_AM_I], &mut who_am_i)
+        .await?;
+    info!("sensor id: {:#04x}", who_am_i[0]);
+
+    data_ready.wait_for_high().await;
+    let mut temperature = [0; 2];
+    i2c.write_read(SENSOR_ADDRESS, &[REG_TEMPERATURE], &mut temperature)
+        .await?;
+    Ok(i16::from_be_bytes(temperature))
+}
+
+/// The simulated sensor, completing a measurement after a while.
+#[embassy::task]
+async fn sensor(registers: RegisterMap, data_ready: Pin) {
+    registers.set(REG_WHO_AM_I as usize, 0xa5);
+    Timer::after(Duration::from_millis(100)).await;
+    registers.with(|r| r[REG_TEMPERATURE as usize..][..2].copy_from_slice(&2150i16.to_be_bytes()));
+    data_ready.set_high();
+}
+
+#[embassy::task]
+async fn run(spawner: Spawner) {
+    // I2C sensor, with a data ready pin
+    let registers = RegisterMap::new(0x20);
+    let mut bus = i2c::I2c::new();
+    bus.attach(SENSOR_ADDRESS, registers.clone());
+    let data_ready = Pin::new();
+    spawner
+        .spawn(sensor(registers, data_ready.clone()))
+        .unwrap();
+    let mut pin = data_ready;
+    let temperature = read_temperature(&mut bus, &mut pin).await.unwrap();
+    info!(
+        "temperature: {}.{:02} C",
+        temperature / 100,
+        temperature % 100
+    );
+
+    // SPI loopback
+    let mut spi = SpiLoopback::new();
+    let mut rx = [0; 4];
+    spi.read_write(&mut rx, &[1, 2, 3]).await.unwrap();
+    info!("spi: read {:?}", rx);
+
+    // UARTs connected to each other
+    let (mut uart, mut peer) = Uart::pair().unwrap();
+    uart.write(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    peer.read(&mut buf).await.unwrap();
+    info!("uart: peer read {:?}", std::str::from_utf8(&buf).unwrap());
+
+    // Flash, with NOR semantics
+    let mut flash = RamFlash::new(4096, 4, 1024);
+    flash.write(0, &[0x0f, 0xf0, 0xff, 0x00]).await.unwrap();
+    flash.write(0, &[0xff, 0x3c, 0x00, 0xff]).await.unwrap();
+    let mut data = [0; 4];
+    flash.read(0, &mut data).await.unwrap();
+    info!("flash: read {:02x?} after two writes", data);
+    flash.erase(0).await.unwrap();
+    flash.read(0, &mut data).await.unwrap();
+    info!("flash: read {:02x?} after erasing", data);
+
+    // Random numbers
+    let mut rng = OsRng::new().unwrap();
+    let mut random = [0; 8];
+    rng.fill_bytes(&mut random).await.unwrap();
+    info!("rng: {:02x?}", random);
+}
+
+static EXECUTOR: Forever<Executor> = Forever::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.put(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(run(spawner)).unwrap();
+    });
+}
@@ -6,10 +6,9 @@
 #[path = "../serial_port.rs"]
 mod serial_port;
 
-use async_io::Async;
 use embassy::io::AsyncBufReadExt;
 use embassy::util::Forever;
-use embassy_std::Executor;
+use embassy_std::{AsyncFd, Executor};
 use log::*;
 use nix::sys::termios;
 
@@ -22,17 +21,9 @@ async fn run() {
     let port = SerialPort::new("/dev/ttyACM0", baudrate).unwrap();
     //let port = Spy::new(port);
 
-    // Use async_io's reactor for async IO.
-    // This demonstrates how embassy's executor can drive futures from another IO library.
-    // Essentially, async_io::Async converts from AsRawFd+Read+Write to futures's AsyncRead+AsyncWrite
-    let port = Async::new(port).unwrap();
-
-    // This implements futures's AsyncBufRead based on futures's AsyncRead
-    let port = futures::io::BufReader::new(port);
-
-    // We can then use FromStdIo to convert from futures's AsyncBufRead+AsyncWrite
-    // to embassy's AsyncBufRead+AsyncWrite
-    let mut port = embassy::io::FromStdIo::new(port);
+    // Register the port with the executor's reactor, which wakes this task when the
+    // port is readable. AsyncFd implements embassy's AsyncBufRead+AsyncWrite directly.
+    let mut port = AsyncFd::new(port).unwrap();
 
     info!("Serial opened!");
 
@@ -48,7 +39,6 @@ static EXECUTOR: Forever<Executor> = Forever::new();
 fn main() {
     env_logger::builder()
         .filter_level(log::LevelFilter::Debug)
-        .filter_module("async_io", log::LevelFilter::Info)
         .format_timestamp_nanos()
         .init();
 
@@ -0,0 +1,146 @@
+use embassy::io::{AsyncBufRead, AsyncWrite};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::reactor::{self, Interest, Shared, Source};
+
+/// Size of the read buffer, allocated on the first read through [`AsyncBufRead`].
+const BUF_LEN: usize = 1024;
+
+/// A file descriptor registered with the reactor of the [`Executor`](crate::Executor)
+/// running on this thread, such as a serial port, a pipe or a socket.
+///
+/// It implements [`AsyncBufRead`] and [`AsyncWrite`] by reading and writing the fd
+/// directly, so it can be passed to anything taking an embassy stream.
+/// [`AsyncFd::poll_read_with`] and [`AsyncFd::poll_write_with`] wrap other operations,
+/// such as reading a datagram.
+pub struct AsyncFd<T: AsRawFd> {
+    inner: T,
+    source: Arc<Source>,
+    reactor: Arc<Shared>,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+}
+
+impl<T: AsRawFd> AsyncFd<T> {
+    /// Register `inner`, switching its fd to non-blocking mode.
+    ///
+    /// This must be called from a task, or from the `init` closure passed to
+    /// [`Executor::run`](crate::Executor::run).
+    pub fn new(inner: T) -> io::Result<Self> {
+        let fd = inner.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let reactor = reactor::current()?;
+        let source = reactor.register(fd)?;
+        Ok(Self {
+            inner,
+            source,
+            reactor,
+            buf: Vec::new(),
+            pos: 0,
+            len: 0,
+        })
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Run `f` once the fd is readable, until it no longer fails with `WouldBlock`.
+    pub fn poll_read_with<R>(
+        &self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut(&T) -> io::Result<R>,
+    ) -> Poll<io::Result<R>> {
+        self.source.poll_io(Interest::Read, cx, || f(&self.inner))
+    }
+
+    /// Run `f` once the fd is writable, until it no longer fails with `WouldBlock`.
+    pub fn poll_write_with<R>(
+        &self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut(&T) -> io::Result<R>,
+    ) -> Poll<io::Result<R>> {
+        self.source.poll_io(Interest::Write, cx, || f(&self.inner))
+    }
+}
+
+impl<T: AsRawFd> Drop for AsyncFd<T> {
+    fn drop(&mut self) {
+        self.reactor
+            .deregister(self.inner.as_raw_fd(), &self.source);
+    }
+}
+
+impl<T: AsRawFd> Unpin for AsyncFd<T> {}
+
+impl<T: AsRawFd> AsyncBufRead for AsyncFd<T> {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<embassy::io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.pos == this.len {
+            if this.buf.is_empty() {
+                this.buf.resize(BUF_LEN, 0);
+            }
+            let fd = this.inner.as_raw_fd();
+            let buf = &mut this.buf;
+            let res = this.source.poll_io(Interest::Read, cx, || {
+                let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+                if n == -1 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+            match res {
+                // Zero at the end of the stream.
+                Poll::Ready(Ok(n)) => {
+                    this.pos = 0;
+                    this.len = n;
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(&this.buf[this.pos..this.len]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().pos += amt;
+    }
+}
+
+impl<T: AsRawFd> AsyncWrite for AsyncFd<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<embassy::io::Result<usize>> {
+        let res = self.poll_write_with(cx, |inner| {
+            let fd = inner.as_raw_fd();
+            let n = unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) };
+            if n == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(n as usize)
+            }
+        });
+        res.map_err(|e| e.into())
+    }
+}
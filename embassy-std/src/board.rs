@@ -0,0 +1,247 @@
+//! A simulated board, to run application code on Linux.
+//!
+//! [`init`] sets up the peripherals, which implement the same traits as the ones of a real
+//! board:
+//!
+//! - a console [`Uart`], on the standard input and output,
+//! - a TAP interface, as an [`embassy_net::Device`], with the `tuntap` feature,
+//! - a [`FileFlash`], whose contents persist from one run to the next,
+//! - GPIO [`Pin`]s, which can also be driven through a control socket.
+//!
+//! The control socket is a Unix socket. Each line sent to it is a command for a pin,
+//! answered with a line:
+//!
+//! ```text
+//! <pin> high    set pin <pin> high, answers "ok"
+//! <pin> low     set pin <pin> low, answers "ok"
+//! <pin>         answers "high" or "low"
+//! ```
+//!
+//! For example, to press a button on pin 0:
+//!
+//! ```text
+//! echo "0 high" | socat - UNIX-CONNECT:embassy-board.sock
+//! ```
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use crate::flash::FileFlash;
+use crate::gpio::Pin;
+use crate::uart::Uart;
+
+#[cfg(feature = "tuntap")]
+use crate::tuntap::TunTapDevice;
+
+pub mod config {
+    use std::path::PathBuf;
+
+    #[non_exhaustive]
+    pub struct Config {
+        /// The TAP interface, created beforehand as shown in [`crate::tuntap`].
+        #[cfg(feature = "tuntap")]
+        pub tap_name: String,
+        /// The file holding the flash contents.
+        pub flash_path: PathBuf,
+        pub flash_size: usize,
+        pub flash_write_size: usize,
+        pub flash_erase_size: usize,
+        /// The path of the control socket, replacing any socket already there. Anything
+        /// else already there is left alone, and setting up the board fails.
+        pub control_socket: PathBuf,
+        pub gpio_count: usize,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                #[cfg(feature = "tuntap")]
+                tap_name: "tap0".into(),
+                flash_path: "embassy-flash.bin".into(),
+                // The size of the flash of an nRF52840.
+                flash_size: 1024 * 1024,
+                flash_write_size: 4,
+                flash_erase_size: 4096,
+                control_socket: "embassy-board.sock".into(),
+                gpio_count: 8,
+            }
+        }
+    }
+}
+
+pub struct Peripherals {
+    pub console: Uart<UnixStream>,
+    #[cfg(feature = "tuntap")]
+    pub net: TunTapDevice,
+    pub flash: FileFlash,
+    /// The pins, numbered as on the control socket.
+    pub gpio: Vec<Pin>,
+}
+
+/// Set up the board.
+///
+/// Like [`AsyncFd::new`](crate::AsyncFd::new), this must be called from a task, or from a
+/// future run by an executor.
+///
+/// If this fails, it can be called again, for example with another configuration.
+///
+/// # Panics
+///
+/// Panics if called again once it succeeded, since there's only one console.
+pub fn init(config: config::Config) -> io::Result<Peripherals> {
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+    assert!(
+        !TAKEN.load(Ordering::Acquire),
+        "board::init called more than once"
+    );
+
+    #[cfg(feature = "tuntap")]
+    let net = TunTapDevice::new(&config.tap_name, embassy_net::Medium::Ethernet)?;
+
+    let flash = FileFlash::open(
+        &config.flash_path,
+        config.flash_size,
+        config.flash_write_size,
+        config.flash_erase_size,
+    )?;
+
+    let listener = bind_control_socket(&config.control_socket)?;
+
+    // The threads are only started once nothing else can fail, so that a failed attempt
+    // doesn't leave one reading stdin.
+    let console = console()?;
+    let gpio: Vec<Pin> = (0..config.gpio_count).map(|_| Pin::new()).collect();
+    serve_control_socket(listener, gpio.clone())?;
+
+    TAKEN.store(true, Ordering::Release);
+    Ok(Peripherals {
+        console,
+        #[cfg(feature = "tuntap")]
+        net,
+        flash,
+        gpio,
+    })
+}
+
+/// A UART connected to the standard input and output.
+///
+/// Threads copy between them and the other end of a socket pair, so that stdin and stdout
+/// are left blocking for the rest of the process.
+fn console() -> io::Result<Uart<UnixStream>> {
+    let (uart, peer) = UnixStream::pair()?;
+
+    let mut rx = peer.try_clone()?;
+    thread::Builder::new()
+        .name("embassy-console-rx".into())
+        .spawn(move || {
+            let _ = io::copy(&mut io::stdin(), &mut rx);
+            // Reads of the console end once stdin does.
+            let _ = rx.shutdown(std::net::Shutdown::Write);
+        })?;
+
+    let mut tx = peer;
+    thread::Builder::new()
+        .name("embassy-console-tx".into())
+        .spawn(move || {
+            let mut buf = [0; 1024];
+            let stdout = io::stdout();
+            loop {
+                let n = match tx.read(&mut buf) {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => n,
+                };
+                // Flush each write, since the console isn't always written by lines.
+                let mut stdout = stdout.lock();
+                if stdout.write_all(&buf[..n]).is_err() || stdout.flush().is_err() {
+                    return;
+                }
+            }
+        })?;
+
+    Uart::new(uart)
+}
+
+/// Bind the control socket, replacing a socket left at `path` by an earlier run.
+///
+/// Fails if something other than a socket is at `path`, rather than deleting it.
+fn bind_control_socket(path: &Path) -> io::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and isn't a socket", path.display()),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    UnixListener::bind(path)
+}
+
+fn serve_control_socket(listener: UnixListener, gpio: Vec<Pin>) -> io::Result<()> {
+    thread::Builder::new()
+        .name("embassy-control".into())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let gpio = gpio.clone();
+                let _ = thread::Builder::new()
+                    .name("embassy-control-client".into())
+                    .spawn(move || serve_control_client(stream, &gpio));
+            }
+        })?;
+    Ok(())
+}
+
+fn serve_control_client(stream: UnixStream, gpio: &[Pin]) {
+    let mut reply = match stream.try_clone() {
+        Ok(reply) => reply,
+        Err(_) => return,
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let answer = control_command(&line, gpio).unwrap_or_else(|e| format!("error: {}", e));
+        if writeln!(reply, "{}", answer).is_err() {
+            return;
+        }
+    }
+}
+
+fn control_command(line: &str, gpio: &[Pin]) -> Result<String, String> {
+    let mut words = line.split_whitespace();
+    let pin = match words.next() {
+        Some(pin) => pin,
+        None => return Err("expected a pin".into()),
+    };
+    let pin = pin
+        .parse::<usize>()
+        .ok()
+        .and_then(|pin| gpio.get(pin))
+        .ok_or_else(|| format!("no pin {}", pin))?;
+
+    let answer = match (words.next(), words.next()) {
+        (None, _) if pin.is_set_high() => "high",
+        (None, _) => "low",
+        (Some("high"), None) => {
+            pin.set_high();
+            "ok"
+        }
+        (Some("low"), None) => {
+            pin.set_low();
+            "ok"
+        }
+        _ => return Err("expected `high`, `low` or nothing after the pin".into()),
+    };
+    Ok(answer.into())
+}
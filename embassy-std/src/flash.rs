@@ -0,0 +1,205 @@
+//! A simulated NOR flash, in RAM or in a file.
+
+use embassy::traits::flash::{Error, Flash};
+use std::fs::{File, OpenOptions};
+use std::future::{ready, Ready};
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+/// The value of erased bytes.
+pub const ERASED: u8 = 0xff;
+
+/// A flash memory in RAM, with the semantics of NOR flash: erasing sets a whole page to
+/// [`ERASED`], and writing can only clear bits, so writing over data that isn't erased
+/// combines both.
+///
+/// Reads are byte-aligned, and writes are aligned to `write_size`. Accesses out of range
+/// fail with [`Error::Failed`].
+pub struct RamFlash {
+    memory: Vec<u8>,
+    write_size: usize,
+    erase_size: usize,
+}
+
+impl RamFlash {
+    /// An erased flash of `size` bytes, in pages of `erase_size` bytes, written by
+    /// `write_size` bytes.
+    pub fn new(size: usize, write_size: usize, erase_size: usize) -> Self {
+        assert!(size % erase_size == 0 && erase_size % write_size == 0);
+        Self {
+            memory: vec![ERASED; size],
+            write_size,
+            erase_size,
+        }
+    }
+
+    /// The contents of the whole flash.
+    pub fn contents(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The contents of the whole flash, to change them directly.
+    pub fn contents_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    fn range(&self, address: usize, len: usize) -> Result<std::ops::Range<usize>, Error> {
+        match address.checked_add(len) {
+            Some(end) if end <= self.memory.len() => Ok(address..end),
+            _ => Err(Error::Failed),
+        }
+    }
+
+    fn write_at(&mut self, address: usize, buf: &[u8]) -> Result<(), Error> {
+        if address % self.write_size != 0 {
+            return Err(Error::AddressMisaligned);
+        }
+        if buf.len() % self.write_size != 0 {
+            return Err(Error::BufferMisaligned);
+        }
+        let range = self.range(address, buf.len())?;
+        for (byte, &b) in self.memory[range].iter_mut().zip(buf) {
+            *byte &= b;
+        }
+        Ok(())
+    }
+
+    fn erase_at(&mut self, address: usize) -> Result<(), Error> {
+        if address % self.erase_size != 0 {
+            return Err(Error::AddressMisaligned);
+        }
+        let range = self.range(address, self.erase_size)?;
+        for byte in &mut self.memory[range] {
+            *byte = ERASED;
+        }
+        Ok(())
+    }
+}
+
+impl Flash for RamFlash {
+    type ReadFuture<'a> = Ready<Result<(), Error>>;
+    type WriteFuture<'a> = Ready<Result<(), Error>>;
+    type ErasePageFuture<'a> = Ready<Result<(), Error>>;
+
+    fn read<'a>(&'a mut self, address: usize, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        ready(self.range(address, buf.len()).map(|range| {
+            buf.copy_from_slice(&self.memory[range]);
+        }))
+    }
+
+    fn write<'a>(&'a mut self, address: usize, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+        ready(self.write_at(address, buf))
+    }
+
+    fn erase<'a>(&'a mut self, address: usize) -> Self::ErasePageFuture<'a> {
+        ready(self.erase_at(address))
+    }
+
+    fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    fn read_size(&self) -> usize {
+        1
+    }
+
+    fn write_size(&self) -> usize {
+        self.write_size
+    }
+
+    fn erase_size(&self) -> usize {
+        self.erase_size
+    }
+}
+
+/// A [`RamFlash`] saved to a file, so that its contents persist from one run to the next.
+///
+/// Each write and erase is written through to the file. If that fails, the operation fails
+/// with [`Error::Failed`].
+pub struct FileFlash {
+    flash: RamFlash,
+    file: File,
+}
+
+impl FileFlash {
+    /// Open the flash saved at `path`, or create it erased.
+    ///
+    /// The file is truncated or padded with [`ERASED`] bytes to `size`.
+    pub fn open(
+        path: impl AsRef<Path>,
+        size: usize,
+        write_size: usize,
+        erase_size: usize,
+    ) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+
+        let mut flash = RamFlash::new(size, write_size, erase_size);
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        contents.truncate(size);
+        flash.memory[..contents.len()].copy_from_slice(&contents);
+
+        file.set_len(size as u64)?;
+        file.write_all_at(&flash.memory, 0)?;
+        Ok(Self { flash, file })
+    }
+
+    /// The contents of the whole flash.
+    pub fn contents(&self) -> &[u8] {
+        self.flash.contents()
+    }
+
+    fn save(&self, address: usize, len: usize) -> Result<(), Error> {
+        self.file
+            .write_all_at(&self.flash.memory[address..][..len], address as u64)
+            .map_err(|_| Error::Failed)
+    }
+}
+
+impl Flash for FileFlash {
+    type ReadFuture<'a> = Ready<Result<(), Error>>;
+    type WriteFuture<'a> = Ready<Result<(), Error>>;
+    type ErasePageFuture<'a> = Ready<Result<(), Error>>;
+
+    fn read<'a>(&'a mut self, address: usize, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        self.flash.read(address, buf)
+    }
+
+    fn write<'a>(&'a mut self, address: usize, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+        ready(
+            self.flash
+                .write_at(address, buf)
+                .and_then(|_| self.save(address, buf.len())),
+        )
+    }
+
+    fn erase<'a>(&'a mut self, address: usize) -> Self::ErasePageFuture<'a> {
+        let erase_size = self.flash.erase_size;
+        ready(
+            self.flash
+                .erase_at(address)
+                .and_then(|_| self.save(address, erase_size)),
+        )
+    }
+
+    fn size(&self) -> usize {
+        self.flash.size()
+    }
+
+    fn read_size(&self) -> usize {
+        self.flash.read_size()
+    }
+
+    fn write_size(&self) -> usize {
+        self.flash.write_size()
+    }
+
+    fn erase_size(&self) -> usize {
+        self.flash.erase_size()
+    }
+}
@@ -0,0 +1,210 @@
+//! Simulated GPIO pins.
+
+use embassy::traits::gpio::{
+    WaitForAnyEdge, WaitForFallingEdge, WaitForHigh, WaitForLow, WaitForRisingEdge,
+};
+use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin as FuturePin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// A pin, whose level is set by a test or by the driver using it.
+///
+/// Clones are the same pin, like two ends of a wire: a test keeps one to drive the pin
+/// and check its level, while a driver uses another. Both can set the level, as an input
+/// pin with [`Pin::set_high`] and [`Pin::set_low`], or as an output pin through
+/// [`OutputPin`].
+#[derive(Clone)]
+pub struct Pin {
+    state: Arc<Mutex<PinState>>,
+}
+
+struct PinState {
+    high: bool,
+    /// Counts the edges, so that a wait for an edge sees the edge even if the level changes
+    /// back before it's polled.
+    rising_edges: u32,
+    falling_edges: u32,
+    wakers: Vec<Waker>,
+}
+
+impl Pin {
+    /// A low pin.
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(PinState {
+                high: false,
+                rising_edges: 0,
+                falling_edges: 0,
+                wakers: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn set_high(&self) {
+        self.set(true)
+    }
+
+    pub fn set_low(&self) {
+        self.set(false)
+    }
+
+    pub fn set(&self, high: bool) {
+        let wakers = {
+            let mut state = self.state.lock().unwrap();
+            match (state.high, high) {
+                (false, true) => state.rising_edges = state.rising_edges.wrapping_add(1),
+                (true, false) => state.falling_edges = state.falling_edges.wrapping_add(1),
+                _ => return,
+            }
+            state.high = high;
+            std::mem::take(&mut state.wakers)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub fn is_set_high(&self) -> bool {
+        self.state.lock().unwrap().high
+    }
+
+    fn wait(&self, until: Until) -> PinFuture<'_> {
+        let (rising_edges, falling_edges) = {
+            let state = self.state.lock().unwrap();
+            (state.rising_edges, state.falling_edges)
+        };
+        PinFuture {
+            pin: self,
+            until,
+            rising_edges,
+            falling_edges,
+        }
+    }
+}
+
+impl Default for Pin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Until {
+    High,
+    Low,
+    RisingEdge,
+    FallingEdge,
+    AnyEdge,
+}
+
+/// Waits for a level or an edge of a [`Pin`].
+pub struct PinFuture<'a> {
+    pin: &'a Pin,
+    until: Until,
+    /// The edge counts when the wait started.
+    rising_edges: u32,
+    falling_edges: u32,
+}
+
+impl<'a> Future for PinFuture<'a> {
+    type Output = ();
+
+    fn poll(self: FuturePin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.pin.state.lock().unwrap();
+        let rising = state.rising_edges != self.rising_edges;
+        let falling = state.falling_edges != self.falling_edges;
+        let done = match self.until {
+            Until::High => state.high,
+            Until::Low => !state.high,
+            Until::RisingEdge => rising,
+            Until::FallingEdge => falling,
+            Until::AnyEdge => rising || falling,
+        };
+        if done {
+            return Poll::Ready(());
+        }
+        if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl InputPin for Pin {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.is_set_high())
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_set_high())
+    }
+}
+
+impl OutputPin for Pin {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Pin::set_high(self);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Pin::set_low(self);
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for Pin {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(Pin::is_set_high(self))
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(!Pin::is_set_high(self))
+    }
+}
+
+impl WaitForHigh for Pin {
+    type Future<'a> = PinFuture<'a>;
+
+    fn wait_for_high<'a>(&'a mut self) -> Self::Future<'a> {
+        self.wait(Until::High)
+    }
+}
+
+impl WaitForLow for Pin {
+    type Future<'a> = PinFuture<'a>;
+
+    fn wait_for_low<'a>(&'a mut self) -> Self::Future<'a> {
+        self.wait(Until::Low)
+    }
+}
+
+impl WaitForRisingEdge for Pin {
+    type Future<'a> = PinFuture<'a>;
+
+    fn wait_for_rising_edge<'a>(&'a mut self) -> Self::Future<'a> {
+        self.wait(Until::RisingEdge)
+    }
+}
+
+impl WaitForFallingEdge for Pin {
+    type Future<'a> = PinFuture<'a>;
+
+    fn wait_for_falling_edge<'a>(&'a mut self) -> Self::Future<'a> {
+        self.wait(Until::FallingEdge)
+    }
+}
+
+impl WaitForAnyEdge for Pin {
+    type Future<'a> = PinFuture<'a>;
+
+    fn wait_for_any_edge<'a>(&'a mut self) -> Self::Future<'a> {
+        self.wait(Until::AnyEdge)
+    }
+}
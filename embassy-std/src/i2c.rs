@@ -0,0 +1,164 @@
+//! A simulated I2C bus.
+//!
+//! Devices are attached to an [`I2c`] bus at their address, and modeled by implementing
+//! [`I2cDevice`]. [`RegisterMap`] models the common kind of device whose registers are
+//! written and read after sending a register address.
+
+use embassy::traits::i2c::{I2c as I2cTrait, SevenBitAddress};
+use std::collections::BTreeMap;
+use std::future::{ready, Ready};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// No device is attached at the address.
+    AddressNack,
+    /// The device didn't acknowledge a byte written, or had nothing to read.
+    DataNack,
+}
+
+/// A device attached to an [`I2c`] bus.
+///
+/// Each transaction addressed to the device is passed to it whole. A `write_read` is a
+/// write followed by a read.
+pub trait I2cDevice: Send {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error>;
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error>;
+}
+
+/// A simulated I2C bus, with the devices attached to it.
+pub struct I2c {
+    devices: BTreeMap<SevenBitAddress, Box<dyn I2cDevice>>,
+}
+
+impl I2c {
+    pub fn new() -> Self {
+        Self {
+            devices: BTreeMap::new(),
+        }
+    }
+
+    /// Attach `device` at `address`, replacing any device there.
+    pub fn attach(&mut self, address: SevenBitAddress, device: impl I2cDevice + 'static) {
+        self.devices.insert(address, Box::new(device));
+    }
+
+    fn device(&mut self, address: SevenBitAddress) -> Result<&mut dyn I2cDevice, Error> {
+        match self.devices.get_mut(&address) {
+            Some(device) => Ok(&mut **device),
+            None => Err(Error::AddressNack),
+        }
+    }
+}
+
+impl Default for I2c {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The transactions complete immediately, since the buffers aren't borrowed by the futures.
+impl I2cTrait for I2c {
+    type Error = Error;
+
+    type ReadFuture<'a> = Ready<Result<(), Error>>;
+    type WriteFuture<'a> = Ready<Result<(), Error>>;
+    type WriteReadFuture<'a> = Ready<Result<(), Error>>;
+
+    fn read<'a>(&'a mut self, address: u8, buffer: &mut [u8]) -> Self::ReadFuture<'a> {
+        ready(self.device(address).and_then(|device| device.read(buffer)))
+    }
+
+    fn write<'a>(&'a mut self, address: u8, bytes: &[u8]) -> Self::WriteFuture<'a> {
+        ready(self.device(address).and_then(|device| device.write(bytes)))
+    }
+
+    fn write_read<'a>(
+        &'a mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Self::WriteReadFuture<'a> {
+        ready(self.device(address).and_then(|device| {
+            device.write(bytes)?;
+            device.read(buffer)
+        }))
+    }
+}
+
+/// A device with `len` 8-bit registers.
+///
+/// The first byte written selects a register, and the following bytes are written to it
+/// and the registers after it. Reads start at the selected register, and also advance.
+/// Accessing a register past the end fails with [`Error::DataNack`].
+///
+/// Clones share the registers, so a test keeps one to drive the device and check what a
+/// driver wrote, while another is attached to the bus.
+#[derive(Clone)]
+pub struct RegisterMap {
+    state: Arc<Mutex<RegisterMapState>>,
+}
+
+struct RegisterMapState {
+    registers: Vec<u8>,
+    pointer: usize,
+}
+
+impl RegisterMap {
+    /// A device with `len` registers, set to zero.
+    pub fn new(len: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(RegisterMapState {
+                registers: vec![0; len],
+                pointer: 0,
+            })),
+        }
+    }
+
+    pub fn get(&self, register: usize) -> u8 {
+        self.state.lock().unwrap().registers[register]
+    }
+
+    pub fn set(&self, register: usize, value: u8) {
+        self.state.lock().unwrap().registers[register] = value;
+    }
+
+    /// Run `f` with all the registers.
+    pub fn with<R>(&self, f: impl FnOnce(&mut [u8]) -> R) -> R {
+        f(&mut self.state.lock().unwrap().registers)
+    }
+}
+
+impl I2cDevice for RegisterMap {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let state = &mut *self.state.lock().unwrap();
+        let (&register, data) = match bytes.split_first() {
+            Some(split) => split,
+            // An empty write just probes the address.
+            None => return Ok(()),
+        };
+        if register as usize >= state.registers.len() {
+            return Err(Error::DataNack);
+        }
+        state.pointer = register as usize;
+        for &b in data {
+            let register = state
+                .registers
+                .get_mut(state.pointer)
+                .ok_or(Error::DataNack)?;
+            *register = b;
+            state.pointer += 1;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let state = &mut *self.state.lock().unwrap();
+        for b in buffer {
+            *b = *state.registers.get(state.pointer).ok_or(Error::DataNack)?;
+            state.pointer += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! Simulated interrupts.
+//!
+//! The host has no NVIC, so embassy-std simulates one, with software interrupts `SWI0` to
+//! `SWI7`. Each interrupt has its own thread, which runs the handler whenever the
+//! interrupt is enabled and pended. This is enough to run
+//! [`InterruptExecutor`](embassy::executor::InterruptExecutor)s, which are pended from
+//! other threads, like from interrupts on hardware, and to wait for interrupts with
+//! [`InterruptFuture`](embassy::util::InterruptFuture).
+//!
+//! Priorities are honored as far as threads allow: a handler doesn't start while a handler
+//! of the same or higher priority (lower number) runs. Handlers of higher priority start
+//! while lower ones run, like they would preempt them, but both then run in parallel.
+
+use embassy::interrupt::{Controller, Handler};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, Once};
+use std::thread;
+
+// Re-exports
+pub use embassy::interrupt::{Interrupt, InterruptExt};
+pub use embassy_extras::interrupt::Priority3 as Priority;
+
+const LINES: usize = 8;
+
+const HANDLER: Handler = Handler::new();
+static HANDLERS: [Handler; LINES] = [HANDLER; LINES];
+
+const NOT_TAKEN: AtomicBool = AtomicBool::new(false);
+static TAKEN: [AtomicBool; LINES] = [NOT_TAKEN; LINES];
+
+thread_local! {
+    /// The interrupt whose handler is running on this thread.
+    static ACTIVE_IRQ: Cell<Option<u16>> = Cell::new(None);
+}
+
+macro_rules! irqs {
+    ($($name:ident = $number:expr,)*) => {
+        $(
+            #[allow(non_camel_case_types)]
+            pub struct $name(());
+            unsafe impl Interrupt for $name {
+                type Priority = Priority;
+                fn number(&self) -> u16 {
+                    $number
+                }
+                unsafe fn steal() -> Self {
+                    Self(())
+                }
+                unsafe fn __handler(&self) -> &'static Handler {
+                    &HANDLERS[$number]
+                }
+            }
+
+            unsafe impl embassy::util::Unborrow for $name {
+                type Target = $name;
+                unsafe fn unborrow(self) -> $name {
+                    self
+                }
+            }
+        )*
+    };
+}
+
+irqs!(
+    SWI0 = 0,
+    SWI1 = 1,
+    SWI2 = 2,
+    SWI3 = 3,
+    SWI4 = 4,
+    SWI5 = 5,
+    SWI6 = 6,
+    SWI7 = 7,
+);
+
+/// Take a simulated interrupt, such as `SWI0`. This panics if it was taken before.
+#[macro_export]
+macro_rules! __interrupt_take {
+    ($name:ident) => {
+        $crate::interrupt::__take::<$crate::interrupt::$name>()
+    };
+}
+pub use crate::__interrupt_take as take;
+
+/// Implementation detail of `take!`, do not use.
+#[doc(hidden)]
+pub fn __take<T: Interrupt>() -> T {
+    init();
+    let irq = unsafe { T::steal() };
+    if TAKEN[irq.number() as usize].swap(true, Ordering::AcqRel) {
+        panic!("IRQ Already taken");
+    }
+    irq
+}
+
+/// Make the simulated NVIC the interrupt controller. Called when an interrupt is taken,
+/// and when an [`Executor`](crate::Executor) is created.
+pub(crate) fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| embassy::interrupt::set_controller(&*NVIC));
+}
+
+lazy_static::lazy_static! {
+    static ref NVIC: Nvic = Nvic {
+        lines: Mutex::new([Line::new(); LINES]),
+        changed: Condvar::new(),
+    };
+}
+
+struct Nvic {
+    lines: Mutex<[Line; LINES]>,
+    /// Notified when a line is enabled, pended, or its handler returns.
+    changed: Condvar,
+}
+
+#[derive(Clone, Copy)]
+struct Line {
+    enabled: bool,
+    pending: bool,
+    active: bool,
+    priority: u8,
+    /// Whether the thread running the handler was started.
+    started: bool,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            enabled: false,
+            pending: false,
+            active: false,
+            priority: 0,
+            started: false,
+        }
+    }
+}
+
+impl Nvic {
+    fn update(&self, irq: u16, f: impl FnOnce(&mut Line)) {
+        let mut lines = self.lines.lock().unwrap();
+        f(&mut lines[irq as usize]);
+        self.changed.notify_all();
+    }
+
+    fn get<R>(&self, irq: u16, f: impl FnOnce(&Line) -> R) -> R {
+        f(&self.lines.lock().unwrap()[irq as usize])
+    }
+
+    /// Whether the handler of `irq` may start.
+    fn can_run(lines: &[Line; LINES], irq: usize) -> bool {
+        let line = &lines[irq];
+        line.enabled
+            && line.pending
+            && !lines
+                .iter()
+                .any(|other| other.active && other.priority <= line.priority)
+    }
+
+    fn run(&self, irq: usize) {
+        let mut lines = self.lines.lock().unwrap();
+        loop {
+            while !Self::can_run(&lines, irq) {
+                lines = self.changed.wait(lines).unwrap();
+            }
+            lines[irq].pending = false;
+            lines[irq].active = true;
+            drop(lines);
+
+            let handler = &HANDLERS[irq];
+            let func = handler.func.load(Ordering::Relaxed);
+            let ctx = handler.ctx.load(Ordering::Relaxed);
+            if !func.is_null() {
+                ACTIVE_IRQ.with(|active| active.set(Some(irq as u16)));
+                unsafe {
+                    let func: unsafe fn(*mut ()) = std::mem::transmute(func);
+                    func(ctx);
+                }
+                ACTIVE_IRQ.with(|active| active.set(None));
+            }
+
+            lines = self.lines.lock().unwrap();
+            lines[irq].active = false;
+            // Lower priority handlers may start now.
+            self.changed.notify_all();
+        }
+    }
+}
+
+impl Controller for Nvic {
+    fn enable(&self, irq: u16) {
+        self.update(irq, |line| {
+            line.enabled = true;
+            if !line.started {
+                line.started = true;
+                thread::Builder::new()
+                    .name(format!("embassy-irq-{}", irq))
+                    .spawn(move || NVIC.run(irq as usize))
+                    .unwrap();
+            }
+        })
+    }
+
+    fn disable(&self, irq: u16) {
+        self.update(irq, |line| line.enabled = false)
+    }
+
+    fn is_active(&self, irq: u16) -> bool {
+        self.get(irq, |line| line.active)
+    }
+
+    fn is_enabled(&self, irq: u16) -> bool {
+        self.get(irq, |line| line.enabled)
+    }
+
+    fn is_pending(&self, irq: u16) -> bool {
+        self.get(irq, |line| line.pending)
+    }
+
+    fn pend(&self, irq: u16) {
+        self.update(irq, |line| line.pending = true)
+    }
+
+    fn unpend(&self, irq: u16) {
+        self.update(irq, |line| line.pending = false)
+    }
+
+    fn get_priority(&self, irq: u16) -> u8 {
+        self.get(irq, |line| line.priority)
+    }
+
+    fn set_priority(&self, irq: u16, prio: u8) {
+        self.update(irq, |line| line.priority = prio)
+    }
+
+    fn active_irq(&self) -> Option<u16> {
+        ACTIVE_IRQ.with(|active| active.get())
+    }
+}
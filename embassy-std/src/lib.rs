@@ -1,38 +1,199 @@
+#![feature(generic_associated_types)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
 use embassy::executor::{raw, Spawner};
 use embassy::time::TICKS_PER_SECOND;
 use embassy::time::{Alarm, Clock};
+use std::cell::Cell;
 use std::marker::PhantomData;
-use std::mem::MaybeUninit;
 use std::ptr;
-use std::sync::{Condvar, Mutex};
+use std::sync::{Arc, Condvar, Mutex, Once};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration as StdDuration, Instant as StdInstant};
 
-static mut CLOCK_ZERO: MaybeUninit<StdInstant> = MaybeUninit::uninit();
+mod async_fd;
+pub mod board;
+pub mod flash;
+pub mod gpio;
+pub mod i2c;
+pub mod interrupt;
+mod reactor;
+pub mod rng;
+mod scoped;
+pub mod spi;
+mod testing;
+#[cfg(feature = "tuntap")]
+pub mod tuntap;
+pub mod uart;
+
+pub use async_fd::AsyncFd;
+use reactor::Reactor;
+pub use scoped::{block_on, ScopedExecutor};
+#[doc(hidden)]
+pub use testing::__run_test;
+
+lazy_static::lazy_static! {
+    static ref CLOCK_ZERO: StdInstant = StdInstant::now();
+}
+
+thread_local! {
+    /// The time on this thread, while it runs a test with a mock clock.
+    static MOCK_NOW: Cell<Option<u64>> = Cell::new(None);
+}
+
 struct StdClock;
 impl Clock for StdClock {
     fn now(&self) -> u64 {
-        let zero = unsafe { CLOCK_ZERO.as_ptr().read() };
-        let dur = StdInstant::now().duration_since(zero);
+        if let Some(now) = MOCK_NOW.with(|now| now.get()) {
+            return now;
+        }
+        let dur = StdInstant::now().duration_since(*CLOCK_ZERO);
         dur.as_secs() * (TICKS_PER_SECOND as u64)
             + (dur.subsec_nanos() as u64) * (TICKS_PER_SECOND as u64) / 1_000_000_000
     }
 }
 
-static mut ALARM_AT: u64 = u64::MAX;
+/// Set the clock, once for all executors, so that the time doesn't jump when another one
+/// is created.
+fn init_clock() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe { embassy::time::set_clock(&StdClock) });
+}
+
+/// An alarm, calling its callback from its own thread.
+///
+/// Each executor needs its own alarm. [`Executor`] has one built in, and an
+/// [`InterruptExecutor`](embassy::executor::InterruptExecutor) is given one with
+/// `set_alarm`.
+pub struct StdAlarm {
+    shared: Arc<AlarmShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct AlarmShared {
+    state: Mutex<AlarmState>,
+    changed: Condvar,
+}
+
+struct AlarmState {
+    at: u64,
+    callback: Option<Callback>,
+    closed: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Callback {
+    func: fn(*mut ()),
+    ctx: *mut (),
+}
+
+// The callback may be called from any context, as documented on `Alarm::set_callback`.
+unsafe impl Send for Callback {}
+
+impl StdAlarm {
+    pub fn new() -> Self {
+        init_clock();
+
+        let shared = Arc::new(AlarmShared {
+            state: Mutex::new(AlarmState {
+                at: u64::MAX,
+                callback: None,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("embassy-alarm".into())
+                .spawn(move || shared.run())
+                .unwrap()
+        };
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut AlarmState)) {
+        let mut state = self.shared.state.lock().unwrap();
+        f(&mut state);
+        self.shared.changed.notify_one();
+    }
+}
+
+impl Default for StdAlarm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AlarmShared {
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.closed {
+            if state.at == u64::MAX {
+                state = self.changed.wait(state).unwrap();
+                continue;
+            }
+
+            let now = StdClock.now();
+            if now < state.at {
+                let left = ticks_to_duration(state.at - now);
+                state = self.changed.wait_timeout(state, left).unwrap().0;
+                continue;
+            }
+
+            state.at = u64::MAX;
+            let callback = state.callback;
+            // The callback may set the alarm again.
+            drop(state);
+            if let Some(callback) = callback {
+                (callback.func)(callback.ctx);
+            }
+            state = self.state.lock().unwrap();
+        }
+    }
+}
 
-pub struct StdAlarm;
 impl Alarm for StdAlarm {
-    fn set_callback(&self, _callback: fn(*mut ()), _ctx: *mut ()) {}
+    fn set_callback(&self, callback: fn(*mut ()), ctx: *mut ()) {
+        self.update(|state| {
+            state.callback = Some(Callback {
+                func: callback,
+                ctx,
+            })
+        })
+    }
 
     fn set(&self, timestamp: u64) {
-        unsafe { ALARM_AT = timestamp }
+        self.update(|state| state.at = timestamp)
     }
 
     fn clear(&self) {
-        unsafe { ALARM_AT = u64::MAX }
+        self.update(|state| state.at = u64::MAX)
+    }
+}
+
+impl Drop for StdAlarm {
+    fn drop(&mut self) {
+        self.update(|state| state.closed = true);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
     }
 }
 
+fn ticks_to_duration(ticks: u64) -> StdDuration {
+    StdDuration::new(
+        ticks / (TICKS_PER_SECOND as u64),
+        (ticks % (TICKS_PER_SECOND as u64) * 1_000_000_000 / (TICKS_PER_SECOND as u64)) as u32,
+    )
+}
+
 struct Signaler {
     mutex: Mutex<bool>,
     condvar: Condvar,
@@ -49,29 +210,27 @@ impl Signaler {
     fn wait(&self) {
         let mut signaled = self.mutex.lock().unwrap();
         while !*signaled {
-            let alarm_at = unsafe { ALARM_AT };
-            if alarm_at == u64::MAX {
-                signaled = self.condvar.wait(signaled).unwrap();
-            } else {
-                let now = StdClock.now();
-                if now >= alarm_at {
-                    break;
-                }
-
-                let left = alarm_at - now;
-                let dur = StdDuration::new(
-                    left / (TICKS_PER_SECOND as u64),
-                    (left % (TICKS_PER_SECOND as u64) * 1_000_000_000 / (TICKS_PER_SECOND as u64))
-                        as u32,
-                );
-                let (signaled2, timeout) = self.condvar.wait_timeout(signaled, dur).unwrap();
-                signaled = signaled2;
-                if timeout.timed_out() {
-                    break;
-                }
+            signaled = self.condvar.wait(signaled).unwrap();
+        }
+        *signaled = false;
+    }
+
+    /// Wait until signaled or `deadline`, returning whether signaled.
+    fn wait_until(&self, deadline: StdInstant) -> bool {
+        let mut signaled = self.mutex.lock().unwrap();
+        while !*signaled {
+            let now = StdInstant::now();
+            if now >= deadline {
+                return false;
             }
+            signaled = self
+                .condvar
+                .wait_timeout(signaled, deadline - now)
+                .unwrap()
+                .0;
         }
         *signaled = false;
+        true
     }
 
     fn signal(ctx: *mut ()) {
@@ -86,19 +245,21 @@ pub struct Executor {
     inner: raw::Executor,
     not_send: PhantomData<*mut ()>,
     signaler: Signaler,
+    alarm: StdAlarm,
+    reactor: Reactor,
 }
 
 impl Executor {
     pub fn new() -> Self {
-        unsafe {
-            CLOCK_ZERO.as_mut_ptr().write(StdInstant::now());
-            embassy::time::set_clock(&StdClock);
-        }
+        init_clock();
+        interrupt::init();
 
         Self {
             inner: raw::Executor::new(Signaler::signal, ptr::null_mut()),
             not_send: PhantomData,
             signaler: Signaler::new(),
+            alarm: StdAlarm::new(),
+            reactor: Reactor::new().unwrap(),
         }
     }
 
@@ -106,14 +267,39 @@ impl Executor {
     ///
     /// This function never returns.
     pub fn run(&'static mut self, init: impl FnOnce(Spawner)) -> ! {
-        self.inner.set_signal_ctx(&self.signaler as *const _ as _);
-        self.inner.set_alarm(&StdAlarm);
+        self.run_until(init, || false);
+        unreachable!()
+    }
 
-        init(unsafe { self.inner.spawner() });
+    /// Runs the executor until `condition` returns true.
+    ///
+    /// The condition is checked each time the tasks that were woken have run. To run an
+    /// executor more than once, or without making it `'static`, use a [`ScopedExecutor`].
+    pub fn run_until(
+        &'static mut self,
+        init: impl FnOnce(Spawner),
+        mut condition: impl FnMut() -> bool,
+    ) {
+        let Self {
+            inner,
+            signaler,
+            alarm,
+            reactor,
+            ..
+        } = self;
+        inner.set_signal_ctx(&*signaler as *const _ as _);
+        inner.set_alarm(alarm);
+        let _reactor = reactor.enter();
+
+        let inner: &'static raw::Executor = inner;
+        init(unsafe { inner.spawner() });
 
         loop {
-            unsafe { self.inner.run_queued() };
-            self.signaler.wait();
+            unsafe { inner.run_queued() };
+            if condition() {
+                return;
+            }
+            signaler.wait();
         }
     }
 }
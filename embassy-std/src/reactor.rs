@@ -0,0 +1,321 @@
+//! Wakes tasks when file descriptors become readable or writable.
+//!
+//! Each [`Executor`](crate::Executor) owns a reactor: a thread waiting on an epoll instance
+//! where the fds of its [`AsyncFd`](crate::AsyncFd)s are registered. When an fd becomes
+//! ready, the reactor wakes the tasks waiting for it, which signals the executor like any
+//! other wakeup.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+/// Token of the eventfd used to stop the reactor thread.
+const STOP_TOKEN: u64 = u64::MAX;
+
+thread_local! {
+    /// The reactor of the executor running on this thread.
+    static CURRENT: RefCell<Option<Arc<Shared>>> = RefCell::new(None);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Interest {
+    Read,
+    Write,
+}
+
+pub(crate) struct Reactor {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+pub(crate) struct Shared {
+    epoll: RawFd,
+    /// Written to wake the reactor thread, when it should stop.
+    stop_event: RawFd,
+    stop: AtomicBool,
+    sources: Mutex<HashMap<u64, Arc<Source>>>,
+    next_token: AtomicU64,
+}
+
+impl Reactor {
+    pub fn new() -> io::Result<Self> {
+        let epoll = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        let stop_event =
+            match cvt(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) }) {
+                Ok(fd) => fd,
+                Err(e) => {
+                    unsafe { libc::close(epoll) };
+                    return Err(e);
+                }
+            };
+        // Closes both fds if anything below fails.
+        let shared = Arc::new(Shared {
+            epoll,
+            stop_event,
+            stop: AtomicBool::new(false),
+            sources: Mutex::new(HashMap::new()),
+            next_token: AtomicU64::new(0),
+        });
+
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: STOP_TOKEN,
+        };
+        cvt(unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_ADD, stop_event, &mut event) })?;
+
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("embassy-reactor".into())
+                .spawn(move || shared.run())?
+        };
+
+        Ok(Self {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Make this the reactor used by the `AsyncFd`s created on this thread, until the
+    /// returned guard is dropped.
+    pub fn enter(&self) -> EnterGuard {
+        let previous = CURRENT.with(|c| c.replace(Some(self.shared.clone())));
+        EnterGuard { previous }
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        let one: u64 = 1;
+        unsafe {
+            libc::write(
+                self.shared.stop_event,
+                &one as *const u64 as *const libc::c_void,
+                8,
+            );
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub(crate) struct EnterGuard {
+    previous: Option<Arc<Shared>>,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|c| *c.borrow_mut() = previous);
+    }
+}
+
+/// The reactor of the executor running on this thread.
+pub(crate) fn current() -> io::Result<Arc<Shared>> {
+    CURRENT.with(|c| c.borrow().clone()).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            "no embassy-std executor is running on this thread",
+        )
+    })
+}
+
+impl Shared {
+    fn run(&self) {
+        let mut events: [libc::epoll_event; 64] = unsafe { std::mem::zeroed() };
+        loop {
+            let n =
+                unsafe { libc::epoll_wait(self.epoll, events.as_mut_ptr(), events.len() as _, -1) };
+            if self.stop.load(Ordering::Acquire) {
+                return;
+            }
+            if n < 0 {
+                // Interrupted by a signal.
+                continue;
+            }
+
+            let sources = self.sources.lock().unwrap();
+            for event in &events[..n as usize] {
+                // Copied out, since the struct is packed.
+                let (token, flags) = (event.u64, event.events);
+                // Sources deregistered meanwhile are gone.
+                if let Some(source) = sources.get(&token) {
+                    source.wake(flags);
+                }
+            }
+        }
+    }
+
+    /// Register `fd`, edge-triggered for both reading and writing.
+    pub fn register(&self, fd: RawFd) -> io::Result<Arc<Source>> {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        let source = Arc::new(Source {
+            token,
+            state: Mutex::new(SourceState {
+                read: Direction::new(),
+                write: Direction::new(),
+            }),
+        });
+        self.sources.lock().unwrap().insert(token, source.clone());
+
+        let mut event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP | libc::EPOLLET) as u32,
+            u64: token,
+        };
+        if let Err(e) =
+            cvt(unsafe { libc::epoll_ctl(self.epoll, libc::EPOLL_CTL_ADD, fd, &mut event) })
+        {
+            self.sources.lock().unwrap().remove(&token);
+            return Err(e);
+        }
+        Ok(source)
+    }
+
+    pub fn deregister(&self, fd: RawFd, source: &Source) {
+        unsafe {
+            libc::epoll_ctl(self.epoll, libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut());
+        }
+        self.sources.lock().unwrap().remove(&source.token);
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.stop_event);
+            libc::close(self.epoll);
+        }
+    }
+}
+
+/// The readiness of a registered fd.
+pub(crate) struct Source {
+    token: u64,
+    state: Mutex<SourceState>,
+}
+
+struct SourceState {
+    read: Direction,
+    write: Direction,
+}
+
+struct Direction {
+    /// Whether an operation may succeed. Starts out true, since the fd may be ready
+    /// before it's registered, and epoll only reports changes.
+    ready: bool,
+    /// Counts readiness events, so that readiness isn't cleared because of an operation
+    /// that started before the last event.
+    tick: u64,
+    waker: Option<Waker>,
+}
+
+impl Direction {
+    fn new() -> Self {
+        Self {
+            ready: true,
+            tick: 0,
+            waker: None,
+        }
+    }
+
+    fn set_ready(&mut self) -> Option<Waker> {
+        self.ready = true;
+        self.tick = self.tick.wrapping_add(1);
+        self.waker.take()
+    }
+}
+
+impl SourceState {
+    fn direction(&mut self, interest: Interest) -> &mut Direction {
+        match interest {
+            Interest::Read => &mut self.read,
+            Interest::Write => &mut self.write,
+        }
+    }
+}
+
+impl Source {
+    fn wake(&self, flags: u32) {
+        let flags = flags as libc::c_int;
+        let (read_waker, write_waker) = {
+            let mut state = self.state.lock().unwrap();
+            let mut read_waker = None;
+            let mut write_waker = None;
+            // Errors and hangups are reported to both directions, so the next operation
+            // fails with them.
+            if flags & (libc::EPOLLIN | libc::EPOLLRDHUP | libc::EPOLLHUP | libc::EPOLLERR) != 0 {
+                read_waker = state.read.set_ready();
+            }
+            if flags & (libc::EPOLLOUT | libc::EPOLLHUP | libc::EPOLLERR) != 0 {
+                write_waker = state.write.set_ready();
+            }
+            (read_waker, write_waker)
+        };
+        // Woken outside the lock, since waking may run arbitrary code.
+        if let Some(waker) = read_waker {
+            waker.wake();
+        }
+        if let Some(waker) = write_waker {
+            waker.wake();
+        }
+    }
+
+    /// Wait until an operation in direction `interest` may succeed. Returns the tick to
+    /// pass to [`Source::clear_ready`] if it doesn't.
+    pub fn poll_ready(&self, interest: Interest, cx: &mut Context<'_>) -> Poll<u64> {
+        let mut state = self.state.lock().unwrap();
+        let direction = state.direction(interest);
+        if direction.ready {
+            return Poll::Ready(direction.tick);
+        }
+        match &direction.waker {
+            Some(w) if w.will_wake(cx.waker()) => {}
+            _ => direction.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+
+    /// An operation failed with `WouldBlock`, after `poll_ready` returned `tick`.
+    pub fn clear_ready(&self, interest: Interest, tick: u64) {
+        let mut state = self.state.lock().unwrap();
+        let direction = state.direction(interest);
+        if direction.tick == tick {
+            direction.ready = false;
+        }
+    }
+
+    /// Run `io` once the fd is ready for it, until it no longer fails with `WouldBlock`.
+    pub fn poll_io<R>(
+        &self,
+        interest: Interest,
+        cx: &mut Context<'_>,
+        mut io: impl FnMut() -> io::Result<R>,
+    ) -> Poll<io::Result<R>> {
+        loop {
+            let tick = match self.poll_ready(interest, cx) {
+                Poll::Ready(tick) => tick,
+                Poll::Pending => return Poll::Pending,
+            };
+            match io() {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.clear_ready(interest, tick),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+}
+
+fn cvt(res: libc::c_int) -> io::Result<libc::c_int> {
+    if res == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
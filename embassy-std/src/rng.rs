@@ -0,0 +1,30 @@
+//! Random numbers from the OS.
+
+use embassy::traits::rng::Rng;
+use std::fs::File;
+use std::future::{ready, Ready};
+use std::io::{self, Read};
+
+/// A random number generator reading from `/dev/urandom`.
+pub struct OsRng {
+    urandom: File,
+}
+
+impl OsRng {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            urandom: File::open("/dev/urandom")?,
+        })
+    }
+}
+
+impl Rng for OsRng {
+    type Error = io::Error;
+
+    type RngFuture<'a> = Ready<Result<(), io::Error>>;
+
+    fn fill_bytes<'a>(&'a mut self, dest: &'a mut [u8]) -> Self::RngFuture<'a> {
+        // Never blocks, once the kernel's pool is initialized at boot.
+        ready(self.urandom.read_exact(dest))
+    }
+}
@@ -0,0 +1,297 @@
+//! An executor that runs only while the thread calls it, and returns.
+
+use embassy::executor::{raw, Spawner};
+use embassy::time::{Alarm, Clock};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::pin::Pin;
+use std::time::{Duration as StdDuration, Instant as StdInstant};
+
+use crate::reactor::Reactor;
+use crate::{init_clock, interrupt, ticks_to_duration, Signaler, StdClock, CLOCK_ZERO, MOCK_NOW};
+
+/// The future passed to [`ScopedExecutor::block_on`], with its lifetime erased.
+type LocalFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+/// An executor that runs the tasks only while one of its methods is called, and that
+/// doesn't need to be `'static`.
+///
+/// [`block_on`](Self::block_on) drives a future to completion, and
+/// [`run_until`](Self::run_until) runs the tasks until a condition holds. Both return, so
+/// host tools and tests can use an executor without exiting the process to stop it.
+///
+/// Tasks are still spawned with a `'static` [`Spawner`], since they may outlive the
+/// executor, and they're never polled again once the `ScopedExecutor` is dropped.
+///
+/// # Leaks
+///
+/// The raw executor, along with its alarm and signaler, and the tasks running the futures
+/// passed to `block_on` are leaked, and never freed, even once the `ScopedExecutor` is
+/// dropped: a task, or a waker held by another thread, may still refer to them. Create one
+/// executor per thread and reuse it, as [`block_on`] does, rather than one per future.
+/// Tests are the exception: each `#[embassy::test]` gets an executor of its own, so that
+/// the tasks it spawns stop with it.
+pub struct ScopedExecutor {
+    inner: &'static raw::Executor,
+    signaler: &'static Signaler,
+    alarm: &'static PolledAlarm,
+    /// The tasks running the futures passed to `block_on`, reused once they're done. A task
+    /// may still be queued for a while after it completes, so another one is added if none
+    /// is free.
+    tasks: Vec<&'static raw::Task<LocalFuture>>,
+    reactor: Reactor,
+    mock_clock: bool,
+    /// Set when a task panicked, since the executor may then still hold the future passed
+    /// to `block_on`, which borrows from the stack frame that was unwound.
+    poisoned: bool,
+    not_send: PhantomData<*mut ()>,
+}
+
+impl ScopedExecutor {
+    pub fn new() -> Self {
+        init_clock();
+        interrupt::init();
+
+        let signaler: &'static Signaler = Box::leak(Box::new(Signaler::new()));
+        let alarm: &'static PolledAlarm = Box::leak(Box::new(PolledAlarm {
+            at: Cell::new(u64::MAX),
+            callback: Cell::new(None),
+        }));
+        let inner = Box::leak(Box::new(raw::Executor::new(
+            Signaler::signal,
+            signaler as *const _ as _,
+        )));
+        inner.set_alarm(alarm);
+
+        Self {
+            inner,
+            signaler,
+            alarm,
+            tasks: Vec::new(),
+            reactor: Reactor::new().unwrap(),
+            mock_clock: false,
+            poisoned: false,
+            not_send: PhantomData,
+        }
+    }
+
+    /// Create an executor using a mock clock on this thread, until it's dropped.
+    ///
+    /// The time starts at zero, and jumps to the next timer whenever no task is ready to
+    /// run, so that the tasks never wait for a timer. Only this thread sees the mock time:
+    /// other threads, such as the reactor's or the alarm threads of other executors, keep
+    /// using the real clock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if another executor already uses a mock clock on this thread.
+    pub(crate) fn with_mock_clock() -> Self {
+        let mut executor = Self::new();
+        MOCK_NOW.with(|now| {
+            assert!(
+                now.get().is_none(),
+                "another executor uses a mock clock on this thread"
+            );
+            now.set(Some(0));
+        });
+        executor.mock_clock = true;
+        executor
+    }
+
+    pub fn spawner(&self) -> Spawner {
+        unsafe { self.inner.spawner() }
+    }
+
+    /// Run the tasks until `condition` returns true.
+    ///
+    /// The condition is checked each time the tasks that were woken have run.
+    pub fn run_until(&mut self, condition: impl FnMut() -> bool) {
+        self.run(condition, None)
+    }
+
+    /// Run `future`, along with the tasks, until it completes.
+    ///
+    /// Unlike a task, the future may borrow from the caller.
+    pub fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+        self.block_on_timeout(future, None)
+    }
+
+    /// Like [`block_on`](Self::block_on), panicking if `future` hasn't completed after
+    /// `timeout`.
+    pub(crate) fn block_on_timeout<F: Future>(
+        &mut self,
+        future: F,
+        timeout: Option<StdDuration>,
+    ) -> F::Output {
+        let output = Cell::new(None);
+        let future: Pin<Box<dyn Future<Output = ()> + '_>> =
+            Box::pin(async { output.set(Some(future.await)) });
+        // The task drops the future when it completes, before this returns. If instead the
+        // future panics, or it times out, the executor is poisoned and never polls it again.
+        let future: LocalFuture = unsafe { mem::transmute(future) };
+
+        let mut future = Some(future);
+        let spawner = self.spawner();
+        let spawned = self
+            .tasks
+            .iter()
+            .any(|&task| spawner.spawn(task.spawn(|| future.take().unwrap())).is_ok());
+        if !spawned {
+            let task = Box::leak(Box::new(raw::Task::new()));
+            self.tasks.push(task);
+            spawner
+                .spawn(task.spawn(|| future.take().unwrap()))
+                .unwrap();
+        }
+
+        let mut result = None;
+        self.run(
+            || {
+                result = output.take();
+                result.is_some()
+            },
+            timeout,
+        );
+        result.unwrap()
+    }
+
+    fn run(&mut self, mut condition: impl FnMut() -> bool, timeout: Option<StdDuration>) {
+        assert!(!self.poisoned, "a task panicked on this executor");
+
+        let inner = self.inner;
+        let signaler = self.signaler;
+        let alarm = self.alarm;
+        let deadline = timeout.map(|timeout| StdInstant::now() + timeout);
+        let _reactor = self.reactor.enter();
+        let _poison = PoisonOnPanic(&mut self.poisoned);
+
+        loop {
+            unsafe { inner.run_queued() };
+            if condition() {
+                return;
+            }
+            if let Some(deadline) = deadline {
+                if StdInstant::now() >= deadline {
+                    panic!("timed out after {:?}", timeout.unwrap());
+                }
+            }
+
+            let at = alarm.at.get();
+            if self.mock_clock && at != u64::MAX {
+                // Jump to the alarm, unless a task was woken.
+                if !signaler.wait_until(StdInstant::now()) {
+                    MOCK_NOW.with(|now| now.set(Some(now.get().unwrap().max(at))));
+                }
+            } else {
+                // With a mock clock, only other threads, such as the reactor's, wake tasks.
+                let alarm_at = if at == u64::MAX || self.mock_clock {
+                    None
+                } else {
+                    Some(*CLOCK_ZERO + ticks_to_duration(at))
+                };
+                match (deadline, alarm_at) {
+                    (Some(a), Some(b)) => signaler.wait_until(a.min(b)),
+                    (Some(until), None) | (None, Some(until)) => signaler.wait_until(until),
+                    (None, None) => {
+                        signaler.wait();
+                        true
+                    }
+                };
+            }
+            alarm.poll(StdClock.now());
+        }
+    }
+}
+
+impl Drop for ScopedExecutor {
+    fn drop(&mut self) {
+        if self.mock_clock {
+            MOCK_NOW.with(|now| now.set(None));
+        }
+    }
+}
+
+impl Default for ScopedExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+thread_local! {
+    /// The executor of this thread, used by `block_on`.
+    static THREAD_EXECUTOR: RefCell<Option<ScopedExecutor>> = RefCell::new(None);
+}
+
+/// Call `f` with the [`ScopedExecutor`] of this thread, created the first time.
+///
+/// If a task panicked on it, it's replaced with a new one.
+fn with_thread_executor<R>(f: impl FnOnce(&mut ScopedExecutor) -> R) -> R {
+    THREAD_EXECUTOR.with(|executor| {
+        let mut executor = executor
+            .try_borrow_mut()
+            .expect("block_on called from a future run by block_on");
+        if executor.as_ref().map_or(true, |e| e.poisoned) {
+            *executor = Some(ScopedExecutor::new());
+        }
+        f(executor.as_mut().unwrap())
+    })
+}
+
+/// Run `future` to completion on the [`ScopedExecutor`] of this thread.
+///
+/// The executor is created by the first call on each thread, and reused by the next ones,
+/// since it's leaked as explained on [`ScopedExecutor`]. Tasks spawned by a future keep
+/// running during the next calls.
+///
+/// # Panics
+///
+/// Panics if called from a future that's itself run by `block_on`.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    with_thread_executor(|executor| executor.block_on(future))
+}
+
+struct PoisonOnPanic<'a>(&'a mut bool);
+
+impl<'a> Drop for PoisonOnPanic<'a> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            *self.0 = true;
+        }
+    }
+}
+
+/// An alarm checked by the executor's own thread, between runs of the tasks, instead of
+/// from a thread of its own.
+struct PolledAlarm {
+    at: Cell<u64>,
+    callback: Cell<Option<(fn(*mut ()), *mut ())>>,
+}
+
+impl PolledAlarm {
+    /// Call the callback if the alarm is due at `now`.
+    fn poll(&self, now: u64) {
+        if self.at.get() > now {
+            return;
+        }
+        self.at.set(u64::MAX);
+        if let Some((func, ctx)) = self.callback.get() {
+            func(ctx);
+        }
+    }
+}
+
+impl Alarm for PolledAlarm {
+    fn set_callback(&self, callback: fn(*mut ()), ctx: *mut ()) {
+        self.callback.set(Some((callback, ctx)));
+    }
+
+    fn set(&self, timestamp: u64) {
+        self.at.set(timestamp);
+    }
+
+    fn clear(&self) {
+        self.at.set(u64::MAX);
+    }
+}
@@ -0,0 +1,64 @@
+//! A simulated SPI bus, with MISO tied to MOSI.
+
+use embassy::traits::spi::FullDuplex;
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+
+/// The byte read while nothing is written.
+pub const OVER_READ_CHARACTER: u8 = 0x00;
+
+/// An SPI bus looping back the bytes written, with MISO tied to MOSI.
+///
+/// Each byte read is the byte written in its place. Bytes read past the end of the bytes
+/// written are [`OVER_READ_CHARACTER`]. The bytes written are also kept, to check what a
+/// driver sent.
+pub struct SpiLoopback {
+    written: Vec<u8>,
+}
+
+impl SpiLoopback {
+    pub fn new() -> Self {
+        Self {
+            written: Vec::new(),
+        }
+    }
+
+    /// The bytes written since the last call.
+    pub fn take_written(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.written)
+    }
+}
+
+impl Default for SpiLoopback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FullDuplex<u8> for SpiLoopback {
+    type Error = Infallible;
+
+    type WriteFuture<'a> = Ready<Result<(), Infallible>>;
+    type ReadFuture<'a> = Ready<Result<(), Infallible>>;
+    type WriteReadFuture<'a> = Ready<Result<(), Infallible>>;
+
+    fn read<'a>(&'a mut self, data: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        self.read_write(data, &[])
+    }
+
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> Self::WriteFuture<'a> {
+        self.read_write(&mut [], data)
+    }
+
+    fn read_write<'a>(
+        &'a mut self,
+        read: &'a mut [u8],
+        write: &'a [u8],
+    ) -> Self::WriteReadFuture<'a> {
+        for (i, b) in read.iter_mut().enumerate() {
+            *b = write.get(i).copied().unwrap_or(OVER_READ_CHARACTER);
+        }
+        self.written.extend_from_slice(write);
+        ready(Ok(()))
+    }
+}
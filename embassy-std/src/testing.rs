@@ -0,0 +1,29 @@
+//! The runner behind `#[embassy::test]`.
+
+use embassy::executor::Spawner;
+use std::future::Future;
+use std::time::Duration as StdDuration;
+
+use crate::ScopedExecutor;
+
+/// Run the future returned by `init` on a new executor, until it completes.
+///
+/// Panics if it hasn't completed after `timeout`. With `mock_clock`, the time starts at
+/// zero and jumps to the next timer whenever no task is ready to run.
+///
+/// The executor is dropped when the test ends, even if it fails, so the tasks spawned by
+/// the test never run again and the mock clock doesn't outlive it.
+#[doc(hidden)]
+pub fn __run_test<F, Fut>(init: F, timeout: StdDuration, mock_clock: bool)
+where
+    F: FnOnce(Spawner) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut executor = if mock_clock {
+        ScopedExecutor::with_mock_clock()
+    } else {
+        ScopedExecutor::new()
+    };
+    let future = init(executor.spawner());
+    executor.block_on_timeout(future, Some(timeout));
+}
@@ -0,0 +1,264 @@
+//! Linux TUN/TAP interfaces, as an [`embassy_net::Device`].
+//!
+//! A TAP interface carries Ethernet frames, and a TUN interface carries IP packets. Either
+//! is created beforehand, for example with:
+//!
+//! ```text
+//! sudo ip tuntap add name tap0 mode tap user $USER
+//! sudo ip link set tap0 up
+//! sudo ip addr add 192.168.69.1/24 dev tap0
+//! ```
+
+use embassy_net::{DeviceCapabilities, LinkState, Medium, PacketQueue, RxToken, TxToken};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::task::{Context, Poll, Waker};
+
+use crate::AsyncFd;
+
+const SIOCGIFMTU: libc::c_ulong = 0x8921;
+const TUNSETIFF: libc::c_ulong = 0x400454CA;
+const IFF_TUN: libc::c_int = 0x0001;
+const IFF_TAP: libc::c_int = 0x0002;
+const IFF_NO_PI: libc::c_int = 0x1000;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+
+/// Longest frame handled, for a 1500 byte IP MTU. Interfaces with a larger MTU are used
+/// with this one.
+const MAX_FRAME_LEN: usize = 1500 + ETHERNET_HEADER_LEN;
+
+/// The Ethernet address of a TAP device, unless set with
+/// [`TunTapDevice::set_ethernet_address`].
+pub const DEFAULT_ETHERNET_ADDRESS: [u8; 6] = [0x02, 0x03, 0x04, 0x05, 0x06, 0x07];
+
+#[repr(C)]
+#[derive(Debug)]
+struct ifreq {
+    ifr_name: [libc::c_char; libc::IF_NAMESIZE],
+    ifr_data: libc::c_int, /* ifr_ifindex or ifr_mtu */
+}
+
+fn ifreq_for(name: &str) -> io::Result<ifreq> {
+    let mut ifreq = ifreq {
+        ifr_name: [0; libc::IF_NAMESIZE],
+        ifr_data: 0,
+    };
+    // The name is NUL terminated.
+    if name.len() >= libc::IF_NAMESIZE {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    for (i, byte) in name.as_bytes().iter().enumerate() {
+        ifreq.ifr_name[i] = *byte as libc::c_char
+    }
+    Ok(ifreq)
+}
+
+fn ifreq_ioctl(
+    lower: libc::c_int,
+    ifreq: &mut ifreq,
+    cmd: libc::c_ulong,
+) -> io::Result<libc::c_int> {
+    unsafe {
+        let res = libc::ioctl(lower, cmd as _, ifreq as *mut ifreq);
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(ifreq.ifr_data)
+}
+
+/// An open TUN or TAP interface, in non-blocking mode.
+#[derive(Debug)]
+pub struct TunTap {
+    fd: libc::c_int,
+    medium: Medium,
+    mtu: usize,
+}
+
+impl AsRawFd for TunTap {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl TunTap {
+    /// Open the interface `name`: a TAP interface with [`Medium::Ethernet`], or a TUN
+    /// interface with [`Medium::Ip`].
+    pub fn new(name: &str, medium: Medium) -> io::Result<TunTap> {
+        let mut ifreq = ifreq_for(name)?;
+        let (flags, header_len) = match medium {
+            Medium::Ethernet => (IFF_TAP, ETHERNET_HEADER_LEN),
+            Medium::Ip => (IFF_TUN, 0),
+        };
+
+        unsafe {
+            let fd = libc::open(
+                "/dev/net/tun\0".as_ptr() as *const libc::c_char,
+                libc::O_RDWR | libc::O_NONBLOCK,
+            );
+            if fd == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // Closes the fd if anything below fails.
+            let mut tuntap = TunTap { fd, medium, mtu: 0 };
+
+            ifreq.ifr_data = flags | IFF_NO_PI;
+            ifreq_ioctl(fd, &mut ifreq, TUNSETIFF)?;
+
+            let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, libc::IPPROTO_IP);
+            if socket == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let ip_mtu = ifreq_ioctl(socket, &mut ifreq, SIOCGIFMTU);
+            libc::close(socket);
+            let ip_mtu = ip_mtu? as usize;
+
+            // SIOCGIFMTU returns the IP MTU (typically 1500 bytes.)
+            // smoltcp counts the entire Ethernet frame in the MTU, so add the Ethernet header size to it.
+            tuntap.mtu = ip_mtu + header_len;
+            Ok(tuntap)
+        }
+    }
+
+    pub fn medium(&self) -> Medium {
+        self.medium
+    }
+
+    /// The MTU of the interface, including the Ethernet header for TAP interfaces.
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Read a frame. Fails with `WouldBlock` if none is waiting.
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if len == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(len as usize)
+        }
+    }
+
+    /// Write a frame. Fails with `WouldBlock` if the interface queue is full.
+    pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let len = unsafe { libc::write(self.fd, buf.as_ptr() as *mut libc::c_void, buf.len()) };
+        if len == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(len as usize)
+        }
+    }
+}
+
+impl Drop for TunTap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// A TUN or TAP interface, as a device for the stack.
+pub struct TunTapDevice {
+    rx: PacketQueue<1, MAX_FRAME_LEN>,
+    tx: TunTapTx,
+    waker: Option<Waker>,
+    ethernet_address: [u8; 6],
+}
+
+/// The transmit half of the device, which writes frames straight to the interface.
+struct TunTapTx {
+    tuntap: AsyncFd<TunTap>,
+    buf: [u8; MAX_FRAME_LEN],
+}
+
+impl TunTapDevice {
+    /// Open the interface `name`: a TAP interface with [`Medium::Ethernet`], or a TUN
+    /// interface with [`Medium::Ip`].
+    ///
+    /// Like [`AsyncFd::new`], this must be called from a task, or from the `init` closure
+    /// passed to [`Executor::run`](crate::Executor::run).
+    pub fn new(name: &str, medium: Medium) -> io::Result<TunTapDevice> {
+        let tuntap = AsyncFd::new(TunTap::new(name, medium)?)?;
+        Ok(Self {
+            rx: PacketQueue::new(),
+            tx: TunTapTx {
+                tuntap,
+                buf: [0; MAX_FRAME_LEN],
+            },
+            waker: None,
+            ethernet_address: DEFAULT_ETHERNET_ADDRESS,
+        })
+    }
+
+    /// Set the Ethernet address used on a TAP interface. It's applied when the stack is
+    /// created.
+    pub fn set_ethernet_address(&mut self, address: [u8; 6]) {
+        self.ethernet_address = address;
+    }
+}
+
+impl TxToken for TunTapTx {
+    fn consume(&mut self, len: usize, f: &mut dyn FnMut(&mut [u8])) {
+        let buf = match self.buf.get_mut(..len) {
+            Some(buf) => buf,
+            None => return,
+        };
+        f(buf);
+        // If the interface can't take the frame, it's dropped, as if lost on the wire.
+        let _ = self.tuntap.get_ref().write(buf);
+    }
+}
+
+impl embassy_net::Device for TunTapDevice {
+    fn receive(&mut self) -> Option<(&mut dyn RxToken, &mut dyn TxToken)> {
+        // The stack registers its waker before polling the device.
+        let waker = self.waker.as_ref()?;
+        let mut cx = Context::from_waker(waker);
+        while self.rx.is_empty() {
+            let buf = self.rx.back_buf().unwrap();
+            match self
+                .tx
+                .tuntap
+                .poll_read_with(&mut cx, |tuntap| tuntap.read(&mut buf[..]))
+            {
+                Poll::Ready(Ok(n)) => self.rx.push(n),
+                // The reactor wakes the stack once the fd is readable again.
+                Poll::Ready(Err(_)) | Poll::Pending => return None,
+            }
+        }
+        let rx: &mut dyn RxToken = &mut self.rx;
+        let tx: &mut dyn TxToken = &mut self.tx;
+        Some((rx, tx))
+    }
+
+    fn transmit(&mut self) -> Option<&mut dyn TxToken> {
+        let tx: &mut dyn TxToken = &mut self.tx;
+        Some(tx)
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        match &self.waker {
+            Some(w) if w.will_wake(waker) => {}
+            _ => self.waker = Some(waker.clone()),
+        }
+    }
+
+    fn capabilities(&mut self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = self.tx.tuntap.get_ref().mtu().min(MAX_FRAME_LEN);
+        caps.medium = self.tx.tuntap.get_ref().medium();
+        caps
+    }
+
+    fn link_state(&mut self) -> LinkState {
+        LinkState::Up
+    }
+
+    fn ethernet_address(&mut self) -> [u8; 6] {
+        self.ethernet_address
+    }
+}
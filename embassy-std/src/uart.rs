@@ -0,0 +1,102 @@
+//! A simulated UART, on a pty, a serial port, or a socket pair.
+
+use embassy::io::{AsyncBufReadExt, AsyncWriteExt};
+use embassy::traits::uart::{Error, Read, ReadUntilIdle, Write};
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+use crate::AsyncFd;
+
+/// A UART on a file descriptor.
+///
+/// Reads wait for the bytes to arrive. [`ReadUntilIdle`] returns the bytes received so
+/// far, once at least one arrived.
+///
+/// Like [`AsyncFd`], a UART must be created from a task, or from the `init` closure
+/// passed to [`Executor::run`](crate::Executor::run).
+pub struct Uart<T: AsRawFd> {
+    fd: AsyncFd<T>,
+}
+
+impl<T: AsRawFd> Uart<T> {
+    pub fn new(inner: T) -> io::Result<Self> {
+        Ok(Self {
+            fd: AsyncFd::new(inner)?,
+        })
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.fd.get_ref()
+    }
+}
+
+impl Uart<File> {
+    /// Open the serial port or pty at `path`, in raw mode.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+
+        unsafe {
+            let mut termios = std::mem::zeroed();
+            if libc::tcgetattr(file.as_raw_fd(), &mut termios) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(file.as_raw_fd(), libc::TCSANOW, &termios) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Self::new(file)
+    }
+}
+
+impl Uart<UnixStream> {
+    /// Two UARTs connected to each other, such as a driver's and the simulated device's.
+    pub fn pair() -> io::Result<(Self, Self)> {
+        let (a, b) = UnixStream::pair()?;
+        Ok((Self::new(a)?, Self::new(b)?))
+    }
+}
+
+impl<T: AsRawFd> Read for Uart<T> {
+    #[rustfmt::skip]
+    type ReadFuture<'a> where Self: 'a = impl Future<Output = Result<(), Error>> + 'a;
+
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        async move { self.fd.read_exact(buf).await.map_err(|_| Error::Other) }
+    }
+}
+
+impl<T: AsRawFd> ReadUntilIdle for Uart<T> {
+    #[rustfmt::skip]
+    type ReadUntilIdleFuture<'a> where Self: 'a = impl Future<Output = Result<usize, Error>> + 'a;
+
+    fn read_until_idle<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadUntilIdleFuture<'a> {
+        async move {
+            let empty = buf.is_empty();
+            let n = self.fd.read(buf).await.map_err(|_| Error::Other)?;
+            if n == 0 && !empty {
+                // The other end was closed.
+                return Err(Error::Other);
+            }
+            Ok(n)
+        }
+    }
+}
+
+impl<T: AsRawFd> Write for Uart<T> {
+    #[rustfmt::skip]
+    type WriteFuture<'a> where Self: 'a = impl Future<Output = Result<(), Error>> + 'a;
+
+    fn write<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+        async move { self.fd.write_all(buf).await.map_err(|_| Error::Other) }
+    }
+}
@@ -0,0 +1,111 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+use embassy::executor::Spawner;
+use embassy::time::{Duration, Instant, Timer};
+use embassy::util::Signal;
+use std::panic::catch_unwind;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static PONG: Signal<u32> = Signal::new();
+static ENDLESS_TICKS: AtomicU32 = AtomicU32::new(0);
+
+#[embassy::task]
+async fn pong(value: u32) {
+    Timer::after(Duration::from_millis(10)).await;
+    PONG.signal(value + 1);
+}
+
+#[embassy::task]
+async fn endless_tick() {
+    loop {
+        Timer::after(Duration::from_millis(1)).await;
+        ENDLESS_TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[embassy::test]
+async fn runs_async_fn() {
+    let value = async { 42 }.await;
+    assert_eq!(value, 42);
+}
+
+#[embassy::test]
+async fn spawns_tasks(spawner: Spawner) {
+    spawner.spawn(pong(41)).unwrap();
+    assert_eq!(PONG.wait().await, 42);
+}
+
+#[embassy::test(mock_clock)]
+async fn mock_clock_skips_timers() {
+    let start = Instant::now();
+    let real_start = std::time::Instant::now();
+    Timer::after(Duration::from_secs(60)).await;
+    assert_eq!(
+        Instant::now().duration_since(start),
+        Duration::from_secs(60)
+    );
+    assert!(real_start.elapsed() < std::time::Duration::from_secs(10));
+}
+
+#[embassy::test(timeout_secs = 1)]
+#[should_panic(expected = "timed out")]
+async fn times_out() {
+    core::future::pending::<()>().await;
+}
+
+#[embassy::test]
+#[should_panic(expected = "boom")]
+async fn reports_panics() {
+    Timer::after(Duration::from_millis(1)).await;
+    panic!("boom");
+}
+
+#[test]
+fn tasks_stop_with_the_test() {
+    embassy_std::__run_test(
+        |spawner| async move {
+            spawner.spawn(endless_tick()).unwrap();
+            while ENDLESS_TICKS.load(Ordering::Relaxed) < 3 {
+                Timer::after(Duration::from_millis(1)).await;
+            }
+        },
+        std::time::Duration::from_secs(10),
+        false,
+    );
+
+    let ticks = ENDLESS_TICKS.load(Ordering::Relaxed);
+    embassy_std::__run_test(
+        |_| Timer::after(Duration::from_millis(20)),
+        std::time::Duration::from_secs(10),
+        false,
+    );
+    assert_eq!(ENDLESS_TICKS.load(Ordering::Relaxed), ticks);
+}
+
+#[test]
+fn mock_clock_ends_with_the_test() {
+    let start = Instant::now();
+    embassy_std::__run_test(
+        |_| Timer::after(Duration::from_secs(60)),
+        std::time::Duration::from_secs(10),
+        true,
+    );
+    let result = catch_unwind(|| {
+        embassy_std::__run_test(
+            |_| async {
+                Timer::after(Duration::from_secs(60)).await;
+                panic!("boom");
+            },
+            std::time::Duration::from_secs(10),
+            true,
+        )
+    });
+    assert!(result.is_err());
+
+    // Back to the real clock, which didn't jump.
+    let elapsed = Instant::now().duration_since(start);
+    assert!(elapsed < Duration::from_secs(10));
+}
@@ -0,0 +1,25 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+use embassy::util::InterruptFuture;
+use embassy_std::interrupt::{self, Interrupt, InterruptExt, SWI5};
+use std::thread;
+use std::time::Duration;
+
+#[embassy::test]
+async fn interrupt_future_completes_when_pended() {
+    let mut irq = interrupt::take!(SWI5);
+
+    for _ in 0..3 {
+        let future = InterruptFuture::new(&mut irq);
+        // Pended from another thread, like by a peripheral.
+        thread::spawn(|| {
+            thread::sleep(Duration::from_millis(10));
+            unsafe { SWI5::steal() }.pend();
+        });
+        future.await;
+    }
+    assert!(!irq.is_enabled());
+}
@@ -0,0 +1,93 @@
+#![feature(generic_associated_types)]
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+use embassy::traits::i2c::I2c as _;
+use embassy::traits::spi::FullDuplex;
+use embassy::traits::uart::{self, Read, ReadUntilIdle, Write};
+use embassy_std::i2c::{self, I2c, RegisterMap};
+use embassy_std::spi::{SpiLoopback, OVER_READ_CHARACTER};
+use embassy_std::uart::Uart;
+
+const SENSOR_ADDRESS: u8 = 0x48;
+
+#[embassy::test]
+async fn i2c_register_map() {
+    let sensor = RegisterMap::new(4);
+    let mut bus = I2c::new();
+    bus.attach(SENSOR_ADDRESS, sensor.clone());
+
+    // The first byte selects the register, and the rest are written from there on.
+    bus.write(SENSOR_ADDRESS, &[1, 0xaa, 0xbb]).await.unwrap();
+    sensor.with(|registers| assert_eq!(registers, &[0, 0xaa, 0xbb, 0]));
+
+    sensor.set(3, 0xcc);
+    let mut buf = [0; 3];
+    bus.write_read(SENSOR_ADDRESS, &[1], &mut buf)
+        .await
+        .unwrap();
+    assert_eq!(buf, [0xaa, 0xbb, 0xcc]);
+
+    // Reads carry on from where the last one stopped.
+    bus.write(SENSOR_ADDRESS, &[0]).await.unwrap();
+    let mut buf = [0; 2];
+    bus.read(SENSOR_ADDRESS, &mut buf).await.unwrap();
+    assert_eq!(buf, [0, 0xaa]);
+    bus.read(SENSOR_ADDRESS, &mut buf).await.unwrap();
+    assert_eq!(buf, [0xbb, 0xcc]);
+
+    assert_eq!(
+        bus.read(SENSOR_ADDRESS, &mut buf).await,
+        Err(i2c::Error::DataNack)
+    );
+    assert_eq!(
+        bus.write(SENSOR_ADDRESS, &[4, 0]).await,
+        Err(i2c::Error::DataNack)
+    );
+    assert_eq!(
+        bus.write(SENSOR_ADDRESS + 1, &[0]).await,
+        Err(i2c::Error::AddressNack)
+    );
+}
+
+#[embassy::test]
+async fn spi_loopback() {
+    let mut spi = SpiLoopback::new();
+
+    let mut read = [0xff; 4];
+    spi.read_write(&mut read, &[1, 2, 3]).await.unwrap();
+    assert_eq!(read, [1, 2, 3, OVER_READ_CHARACTER]);
+
+    spi.write(&[4, 5]).await.unwrap();
+    let mut read = [0xff; 2];
+    spi.read(&mut read).await.unwrap();
+    assert_eq!(read, [OVER_READ_CHARACTER; 2]);
+
+    assert_eq!(spi.take_written(), [1, 2, 3, 4, 5]);
+    assert!(spi.take_written().is_empty());
+}
+
+#[embassy::test]
+async fn uart_pair() {
+    let (mut driver, mut device) = Uart::pair().unwrap();
+
+    driver.write(b"AT\r\n").await.unwrap();
+    let mut buf = [0; 4];
+    device.read(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"AT\r\n");
+
+    device.write(b"OK\r\n").await.unwrap();
+    let mut buf = [0; 16];
+    let n = driver.read_until_idle(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"OK\r\n");
+
+    // Reads fail once the other end is gone.
+    drop(device);
+    assert_eq!(
+        driver.read_until_idle(&mut buf).await,
+        Err(uart::Error::Other)
+    );
+    assert_eq!(driver.read(&mut buf).await, Err(uart::Error::Other));
+}
@@ -0,0 +1,193 @@
+#![feature(min_type_alias_impl_trait)]
+#![feature(impl_trait_in_bindings)]
+#![feature(type_alias_impl_trait)]
+#![allow(incomplete_features)]
+
+use embassy::time::{Duration, Timer};
+use embassy_std::{block_on, ScopedExecutor};
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+static BACKGROUND_TICKS: AtomicU32 = AtomicU32::new(0);
+
+#[embassy::task]
+async fn tick() {
+    loop {
+        Timer::after(Duration::from_millis(1)).await;
+        TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[embassy::task]
+async fn background_tick() {
+    loop {
+        Timer::after(Duration::from_millis(1)).await;
+        BACKGROUND_TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// What was done with a [`Probe`].
+#[derive(Default)]
+struct Record {
+    polls: AtomicU32,
+    drops: AtomicU32,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// A future borrowing from the frame of its caller, which never completes. It keeps its
+/// waker, so that it can be woken once the caller returned.
+struct Probe<'a> {
+    frame: &'a mut u32,
+    record: &'static Record,
+    panic: bool,
+}
+
+impl<'a> Future for Probe<'a> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        *this.frame += 1;
+        this.record.polls.fetch_add(1, Ordering::Relaxed);
+        *this.record.waker.lock().unwrap() = Some(cx.waker().clone());
+        if this.panic {
+            panic!("boom");
+        }
+        Poll::Pending
+    }
+}
+
+impl<'a> Drop for Probe<'a> {
+    fn drop(&mut self) {
+        self.record.drops.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Wake the probe after its caller returned, and check that it's neither polled nor
+/// dropped again, since its frame is gone.
+fn assert_left_alone(record: &Record) {
+    assert_eq!(record.polls.load(Ordering::Relaxed), 1);
+    record.waker.lock().unwrap().take().unwrap().wake();
+    block_on(Timer::after(Duration::from_millis(10)));
+    assert_eq!(record.polls.load(Ordering::Relaxed), 1);
+    assert_eq!(record.drops.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn block_on_returns_output() {
+    let answer = block_on(async {
+        Timer::after(Duration::from_millis(1)).await;
+        42
+    });
+    assert_eq!(answer, 42);
+}
+
+#[test]
+fn block_on_borrows_locals() {
+    let mut values = Vec::new();
+    block_on(async {
+        for i in 0..3 {
+            Timer::after(Duration::from_millis(1)).await;
+            values.push(i);
+        }
+    });
+    assert_eq!(values, [0, 1, 2]);
+}
+
+#[test]
+fn block_on_repeatedly() {
+    for i in 0..100 {
+        assert_eq!(block_on(async { i }), i);
+    }
+}
+
+#[test]
+fn run_until_runs_tasks() {
+    let mut executor = ScopedExecutor::new();
+    executor.spawner().spawn(tick()).unwrap();
+    executor.run_until(|| TICKS.load(Ordering::Relaxed) >= 3);
+    assert!(TICKS.load(Ordering::Relaxed) >= 3);
+}
+
+#[test]
+fn tasks_run_across_block_on() {
+    let mut executor = ScopedExecutor::new();
+    executor.block_on(async {});
+    executor.spawner().spawn(background_tick()).unwrap();
+
+    let start = BACKGROUND_TICKS.load(Ordering::Relaxed);
+    executor.block_on(Timer::after(Duration::from_millis(20)));
+    let middle = BACKGROUND_TICKS.load(Ordering::Relaxed);
+    assert!(middle > start);
+
+    executor.block_on(Timer::after(Duration::from_millis(20)));
+    assert!(BACKGROUND_TICKS.load(Ordering::Relaxed) > middle);
+}
+
+#[test]
+#[should_panic(expected = "block_on called from a future run by block_on")]
+fn nested_block_on_panics() {
+    block_on(async {
+        block_on(async {});
+    });
+}
+
+#[test]
+fn panic_poisons_executor() {
+    let mut executor = ScopedExecutor::new();
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        executor.block_on(async { panic!("boom") });
+    }));
+    assert!(result.is_err());
+
+    let result = catch_unwind(AssertUnwindSafe(|| executor.block_on(async {})));
+    let message = result.unwrap_err();
+    let message = message.downcast_ref::<&str>().copied().unwrap_or_default();
+    assert_eq!(message, "a task panicked on this executor");
+}
+
+#[test]
+fn block_on_recovers_from_panic() {
+    let result = catch_unwind(|| block_on(async { panic!("boom") }));
+    assert!(result.is_err());
+    assert_eq!(block_on(async { 42 }), 42);
+}
+
+#[test]
+fn panicked_future_is_left_alone() {
+    let record: &'static Record = Box::leak(Box::default());
+    let result = catch_unwind(|| {
+        let mut frame = 0;
+        block_on(Probe {
+            frame: &mut frame,
+            record,
+            panic: true,
+        });
+    });
+    assert!(result.is_err());
+    assert_left_alone(record);
+}
+
+#[test]
+fn timed_out_future_is_left_alone() {
+    let record: &'static Record = Box::leak(Box::default());
+    let result = catch_unwind(|| {
+        let mut frame = 0;
+        embassy_std::__run_test(
+            |_| Probe {
+                frame: &mut frame,
+                record,
+                panic: false,
+            },
+            std::time::Duration::from_millis(10),
+            false,
+        );
+    });
+    assert!(result.is_err());
+    assert_left_alone(record);
+}
@@ -133,17 +133,30 @@ impl Executor {
             cortex_m::asm::wfe();
         }
     }
-}
 
-fn pend_by_number(n: u16) {
-    #[derive(Clone, Copy)]
-    struct N(u16);
-    unsafe impl cortex_m::interrupt::InterruptNumber for N {
-        fn number(self) -> u16 {
-            self.0
+    /// Runs the executor until `condition` returns true.
+    ///
+    /// The condition is checked each time the tasks that were woken have run, so it
+    /// should depend on state changed by the tasks.
+    pub fn run_until(
+        &'static mut self,
+        init: impl FnOnce(Spawner),
+        mut condition: impl FnMut() -> bool,
+    ) {
+        init(unsafe { self.inner.spawner() });
+
+        loop {
+            unsafe { self.inner.run_queued() };
+            if condition() {
+                return;
+            }
+            cortex_m::asm::wfe();
         }
     }
-    cortex_m::peripheral::NVIC::pend(N(n))
+}
+
+fn pend_by_number(n: u16) {
+    crate::interrupt::nvic::pend(n)
 }
 
 pub struct InterruptExecutor<I: Interrupt> {
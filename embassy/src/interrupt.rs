@@ -1,5 +1,4 @@
 use core::ptr;
-use cortex_m::peripheral::NVIC;
 
 use atomic_polyfill::{compiler_fence, AtomicPtr, Ordering};
 
@@ -22,14 +21,152 @@ impl Handler {
     }
 }
 
+#[cfg(not(feature = "std"))]
 #[derive(Clone, Copy)]
-pub(crate) struct NrWrap(pub(crate) u16);
+struct NrWrap(u16);
+#[cfg(not(feature = "std"))]
 unsafe impl cortex_m::interrupt::InterruptNumber for NrWrap {
     fn number(self) -> u16 {
         self.0
     }
 }
 
+/// An interrupt controller, on hosts without an NVIC.
+///
+/// With the `std` feature, interrupts are managed by the controller set with
+/// [`set_controller`] instead of the NVIC. `embassy-std` provides a simulated one.
+#[cfg(feature = "std")]
+pub trait Controller: Sync {
+    fn enable(&self, irq: u16);
+    fn disable(&self, irq: u16);
+    fn is_active(&self, irq: u16) -> bool;
+    fn is_enabled(&self, irq: u16) -> bool;
+    fn is_pending(&self, irq: u16) -> bool;
+    fn pend(&self, irq: u16);
+    fn unpend(&self, irq: u16);
+    fn get_priority(&self, irq: u16) -> u8;
+    fn set_priority(&self, irq: u16, prio: u8);
+    /// The interrupt whose handler is running on the calling thread, if any.
+    fn active_irq(&self) -> Option<u16>;
+}
+
+/// The controller, boxed to fit in an `AtomicPtr`, since it's read from the threads of
+/// all the executors and interrupts. The box is never freed.
+#[cfg(feature = "std")]
+static CONTROLLER: AtomicPtr<&'static dyn Controller> = AtomicPtr::new(ptr::null_mut());
+
+/// Sets the interrupt controller.
+///
+/// It can only be set once, since other threads may be using it at any time. This panics
+/// if a controller was set before.
+#[cfg(feature = "std")]
+pub fn set_controller(controller: &'static dyn Controller) {
+    let controller = Box::into_raw(Box::new(controller));
+    let res = CONTROLLER.compare_exchange(
+        ptr::null_mut(),
+        controller,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+    if res.is_err() {
+        drop(unsafe { Box::from_raw(controller) });
+        panic!("interrupt controller already set");
+    }
+}
+
+/// Interrupt operations by number, on the NVIC or on the controller.
+#[cfg(not(feature = "std"))]
+pub(crate) mod nvic {
+    use super::NrWrap;
+    use cortex_m::peripheral::{scb, NVIC, SCB};
+
+    pub unsafe fn enable(irq: u16) {
+        NVIC::unmask(NrWrap(irq))
+    }
+    pub fn disable(irq: u16) {
+        NVIC::mask(NrWrap(irq))
+    }
+    #[cfg(not(armv6m))]
+    pub fn is_active(irq: u16) -> bool {
+        NVIC::is_active(NrWrap(irq))
+    }
+    pub fn is_enabled(irq: u16) -> bool {
+        NVIC::is_enabled(NrWrap(irq))
+    }
+    pub fn is_pending(irq: u16) -> bool {
+        NVIC::is_pending(NrWrap(irq))
+    }
+    pub fn pend(irq: u16) {
+        NVIC::pend(NrWrap(irq))
+    }
+    pub fn unpend(irq: u16) {
+        NVIC::unpend(NrWrap(irq))
+    }
+    pub fn get_priority(irq: u16) -> u8 {
+        NVIC::get_priority(NrWrap(irq))
+    }
+    pub unsafe fn set_priority(irq: u16, prio: u8) {
+        cortex_m::peripheral::Peripherals::steal()
+            .NVIC
+            .set_priority(NrWrap(irq), prio)
+    }
+    /// The interrupt whose handler is running. Must be called from an interrupt handler.
+    pub fn active_irq() -> u16 {
+        match SCB::vect_active() {
+            scb::VectActive::Interrupt { irqn } => irqn as u16,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Interrupt operations by number, on the NVIC or on the controller.
+#[cfg(feature = "std")]
+pub(crate) mod nvic {
+    use super::{Controller, CONTROLLER};
+    use crate::fmt::*;
+    use atomic_polyfill::Ordering;
+
+    fn controller() -> &'static dyn Controller {
+        let controller = CONTROLLER.load(Ordering::Acquire);
+        if controller.is_null() {
+            panic!("No interrupt controller set");
+        }
+        unsafe { *controller }
+    }
+
+    pub unsafe fn enable(irq: u16) {
+        controller().enable(irq)
+    }
+    pub fn disable(irq: u16) {
+        controller().disable(irq)
+    }
+    pub fn is_active(irq: u16) -> bool {
+        controller().is_active(irq)
+    }
+    pub fn is_enabled(irq: u16) -> bool {
+        controller().is_enabled(irq)
+    }
+    pub fn is_pending(irq: u16) -> bool {
+        controller().is_pending(irq)
+    }
+    pub fn pend(irq: u16) {
+        controller().pend(irq)
+    }
+    pub fn unpend(irq: u16) {
+        controller().unpend(irq)
+    }
+    pub fn get_priority(irq: u16) -> u8 {
+        controller().get_priority(irq)
+    }
+    pub unsafe fn set_priority(irq: u16, prio: u8) {
+        controller().set_priority(irq, prio)
+    }
+    /// The interrupt whose handler is running. Must be called from an interrupt handler.
+    pub fn active_irq() -> u16 {
+        unwrap!(controller().active_irq())
+    }
+}
+
 pub unsafe trait Interrupt {
     type Priority: From<u8> + Into<u8> + Copy;
     fn number(&self) -> u16;
@@ -80,53 +217,49 @@ impl<T: Interrupt + ?Sized> InterruptExt for T {
     fn enable(&self) {
         compiler_fence(Ordering::SeqCst);
         unsafe {
-            NVIC::unmask(NrWrap(self.number()));
+            nvic::enable(self.number());
         }
     }
 
     #[inline]
     fn disable(&self) {
-        NVIC::mask(NrWrap(self.number()));
+        nvic::disable(self.number());
         compiler_fence(Ordering::SeqCst);
     }
 
     #[inline]
     #[cfg(not(armv6m))]
     fn is_active(&self) -> bool {
-        NVIC::is_active(NrWrap(self.number()))
+        nvic::is_active(self.number())
     }
 
     #[inline]
     fn is_enabled(&self) -> bool {
-        NVIC::is_enabled(NrWrap(self.number()))
+        nvic::is_enabled(self.number())
     }
 
     #[inline]
     fn is_pending(&self) -> bool {
-        NVIC::is_pending(NrWrap(self.number()))
+        nvic::is_pending(self.number())
     }
 
     #[inline]
     fn pend(&self) {
-        NVIC::pend(NrWrap(self.number()))
+        nvic::pend(self.number())
     }
 
     #[inline]
     fn unpend(&self) {
-        NVIC::unpend(NrWrap(self.number()))
+        nvic::unpend(self.number())
     }
 
     #[inline]
     fn get_priority(&self) -> Self::Priority {
-        Self::Priority::from(NVIC::get_priority(NrWrap(self.number())))
+        Self::Priority::from(nvic::get_priority(self.number()))
     }
 
     #[inline]
     fn set_priority(&self, prio: Self::Priority) {
-        unsafe {
-            cortex_m::peripheral::Peripherals::steal()
-                .NVIC
-                .set_priority(NrWrap(self.number()), prio.into())
-        }
+        unsafe { nvic::set_priority(self.number(), prio.into()) }
     }
 }
@@ -1,16 +1,13 @@
 use core::cell::UnsafeCell;
 use core::future::Future;
 use core::mem;
-use core::ptr;
+use core::ptr::{self, NonNull};
 use core::task::{Context, Poll, Waker};
-use cortex_m::peripheral::NVIC;
-use cortex_m::peripheral::{scb, SCB};
 use executor::raw::TaskHeader;
-use ptr::NonNull;
 
 use crate::executor;
 use crate::fmt::panic;
-use crate::interrupt::{Interrupt, InterruptExt};
+use crate::interrupt::{nvic, Interrupt, InterruptExt};
 
 /// Synchronization primitive. Allows creating awaitable signals that may be passed between tasks.
 ///
@@ -91,16 +88,13 @@ pub fn wake_on_interrupt(interrupt: &mut impl Interrupt, waker: &Waker) {
 }
 
 unsafe fn irq_wake_handler(ctx: *mut ()) {
+    // Disabled before waking, since with `std` the task may be polled on another thread
+    // while the handler still runs.
+    nvic::disable(nvic::active_irq());
+
     if let Some(task) = NonNull::new(ctx as *mut TaskHeader) {
         executor::raw::wake_task(task);
     }
-
-    let irq = match SCB::vect_active() {
-        scb::VectActive::Interrupt { irqn } => irqn,
-        _ => unreachable!(),
-    };
-
-    NVIC::mask(crate::interrupt::NrWrap(irq as u16));
 }
 
 // ==========
@@ -48,6 +48,46 @@ impl WakerRegistration {
     }
 }
 
+/// Utility struct to register and wake multiple wakers.
+///
+/// Up to `N` tasks can wait at once. If more tasks register, all the registered
+/// ones are woken to make room, so they can reregister if they're still interested.
+#[derive(Debug)]
+pub struct MultiWakerRegistration<const N: usize> {
+    wakers: [Option<NonNull<TaskHeader>>; N],
+}
+
+impl<const N: usize> MultiWakerRegistration<N> {
+    pub const fn new() -> Self {
+        Self { wakers: [None; N] }
+    }
+
+    /// Register a waker. Does nothing if it is already registered.
+    pub fn register(&mut self, w: &Waker) {
+        let w = unsafe { task_from_waker(w) };
+        if self.wakers.iter().any(|w2| *w2 == Some(w)) {
+            return;
+        }
+
+        if let Some(slot) = self.wakers.iter_mut().find(|w2| w2.is_none()) {
+            *slot = Some(w);
+            return;
+        }
+
+        self.wake();
+        self.wakers[0] = Some(w);
+    }
+
+    /// Wake all the registered wakers, if any.
+    pub fn wake(&mut self) {
+        for w in self.wakers.iter_mut() {
+            if let Some(w) = w.take() {
+                unsafe { wake_task(w) }
+            }
+        }
+    }
+}
+
 pub struct AtomicWaker {
     waker: AtomicPtr<TaskHeader>,
 }
@@ -48,6 +48,50 @@ impl WakerRegistration {
     }
 }
 
+/// Utility struct to register and wake multiple wakers.
+///
+/// Up to `N` tasks can wait at once. If more tasks register, all the registered
+/// ones are woken to make room, so they can reregister if they're still interested.
+#[derive(Debug)]
+pub struct MultiWakerRegistration<const N: usize> {
+    wakers: [Option<Waker>; N],
+}
+
+impl<const N: usize> MultiWakerRegistration<N> {
+    pub const fn new() -> Self {
+        const NONE: Option<Waker> = None;
+        Self { wakers: [NONE; N] }
+    }
+
+    /// Register a waker. Does nothing if it is already registered.
+    pub fn register(&mut self, w: &Waker) {
+        if self
+            .wakers
+            .iter()
+            .any(|w2| matches!(w2, Some(w2) if w2.will_wake(w)))
+        {
+            return;
+        }
+
+        if let Some(slot) = self.wakers.iter_mut().find(|w2| w2.is_none()) {
+            *slot = Some(w.clone());
+            return;
+        }
+
+        self.wake();
+        self.wakers[0] = Some(w.clone());
+    }
+
+    /// Wake all the registered wakers, if any.
+    pub fn wake(&mut self) {
+        for w in self.wakers.iter_mut() {
+            if let Some(w) = w.take() {
+                w.wake()
+            }
+        }
+    }
+}
+
 /// Utility struct to register and wake a waker.
 pub struct AtomicWaker {
     waker: Mutex<Cell<Option<Waker>>>,